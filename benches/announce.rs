@@ -0,0 +1,81 @@
+//! Allocations and time spent processing the initial announce snapshot of a large tree.
+//! Run with `cargo bench --bench announce`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink};
+
+const TOPICS: usize = 5_000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+struct Discard;
+
+impl ConnectionSink for Discard {
+    type Error = String;
+
+    fn send_text(&mut self, _: String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn send_binary(&mut self, _: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn event(&mut self, _: ConnectionEvent) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let frames: Vec<String> = (0..TOPICS)
+        .map(|i| {
+            format!(
+                r#"{{"method":"announce","params":{{"name":"/Robot/Subsystem{}/Value{}","id":{},"type":"double","properties":{{}}}}}}"#,
+                i / 50,
+                i % 50,
+                i
+            )
+        })
+        .collect();
+    // The second pass is a reconnect: the same tree announced again.
+    let mut core = ConnectionCore::new();
+    for pass in ["initial", "reconnect"] {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for frame in frames.iter() {
+            core.on_text(&mut Discard, frame).unwrap();
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!(
+            "{} announces ({}): {:?}, {} allocations ({:.1} per announce)",
+            TOPICS,
+            pass,
+            elapsed,
+            allocations,
+            allocations as f64 / TOPICS as f64
+        );
+        core.on_disconnect(&mut Discard).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Time per value of a 1 kB `double[]` updated at 50 Hz with and without diff mode, where each
+//! update changes a few elements as an LED buffer does. Run with `cargo bench --bench diff`.
+
+use std::time::Instant;
+
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink, Nt4Data};
+
+/// 128 doubles, 1 kB.
+const ELEMENTS: usize = 128;
+/// A minute of updates at 50 Hz.
+const VALUES: i64 = 50 * 60;
+const PERIOD_US: i64 = 20_000;
+
+/// Counts the elements delivered, so the work on them is not optimized away.
+#[derive(Default)]
+struct Count {
+    elements: usize,
+}
+
+impl ConnectionSink for Count {
+    type Error = String;
+
+    fn send_text(&mut self, _: String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn send_binary(&mut self, _: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), String> {
+        let len = |data: &Nt4Data| match data {
+            Nt4Data::DoubleArray(x) => x.len(),
+            _ => 0,
+        };
+        self.elements += match event {
+            ConnectionEvent::Value { data, .. } => len(&data),
+            ConnectionEvent::Diff { diff, .. } => {
+                diff.changes.iter().map(|x| len(&x.values)).sum::<usize>() + diff.full.as_ref().map_or(0, len)
+            }
+            _ => 0,
+        };
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut values = vec![0.0_f64; ELEMENTS];
+    let frames: Vec<Vec<u8>> = (0..VALUES)
+        .map(|i| {
+            // A pixel that moves along the strip, and one at the end that blinks.
+            let at = i as usize % (ELEMENTS - 1);
+            values[at] = 1.0;
+            values[(at + ELEMENTS - 2) % (ELEMENTS - 1)] = 0.0;
+            values[ELEMENTS - 1] = (i % 2) as f64;
+            rmp_serde::to_vec(&(1_i32, i * PERIOD_US, 17_u8, &values)).unwrap()
+        })
+        .collect();
+    let announce = r#"{"method":"announce","params":{"name":"/LEDs","id":1,"type":"double[]","properties":{}}}"#;
+    for diff in [false, true] {
+        let mut core = ConnectionCore::new();
+        let mut sink = Count::default();
+        core.set_diff_mode("/LEDs", diff);
+        core.on_text(&mut sink, announce).unwrap();
+        let start = Instant::now();
+        for frame in frames.iter() {
+            core.on_binary(&mut sink, frame).unwrap();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{} values of {} bytes ({}): {:?}, {:.2} µs per value, {} elements delivered",
+            VALUES,
+            ELEMENTS * 8,
+            if diff { "diff" } else { "full" },
+            elapsed,
+            elapsed.as_secs_f64() * 1e6 / VALUES as f64,
+            sink.elements
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Time per keystroke of `search_topics` over a large announce table.
+//! Run with `cargo bench --bench search`.
+
+use std::time::Instant;
+
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink};
+
+const TOPICS: usize = 10_000;
+const ROUNDS: u32 = 20;
+
+struct Discard;
+
+impl ConnectionSink for Discard {
+    type Error = String;
+
+    fn send_text(&mut self, _: String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn send_binary(&mut self, _: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn event(&mut self, _: ConnectionEvent) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut core = ConnectionCore::new();
+    for i in 0..TOPICS {
+        let ty = if i % 3 == 0 { "boolean" } else { "double" };
+        let frame = format!(
+            r#"{{"method":"announce","params":{{"name":"/SmartDashboard/Subsystem{}/Motor{}/Velocity","id":{},"type":"{}","properties":{{}}}}}}"#,
+            i / 100,
+            i % 100,
+            i,
+            ty
+        );
+        core.on_text(&mut Discard, &frame).unwrap();
+    }
+    // Typing a query one character at a time.
+    let query = "sub42motvel";
+    for end in 1..=query.len() {
+        let start = Instant::now();
+        let mut found = 0;
+        for _ in 0..ROUNDS {
+            found = core.search_topics(&query[..end], 20, None, false).len();
+        }
+        println!("{:>12}: {:?} per search over {} topics, {} found", &query[..end], start.elapsed() / ROUNDS, TOPICS, found);
+    }
+}
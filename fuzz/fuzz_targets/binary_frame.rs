@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink};
+
+struct Discard;
+
+impl ConnectionSink for Discard {
+    type Error = String;
+
+    fn send_text(&mut self, _: String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn send_binary(&mut self, _: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn event(&mut self, _: ConnectionEvent) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut core = ConnectionCore::new();
+    // Announce a topic so values for id 1 are delivered rather than only buffered.
+    let _ = core.on_text(
+        &mut Discard,
+        r#"{"method":"announce","params":{"name":"/fuzz","id":1,"type":"double","properties":{}}}"#,
+    );
+    let _ = core.on_binary(&mut Discard, data);
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink};
+
+struct Discard;
+
+impl ConnectionSink for Discard {
+    type Error = String;
+
+    fn send_text(&mut self, _: String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn send_binary(&mut self, _: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn event(&mut self, _: ConnectionEvent) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(data) = std::str::from_utf8(data) {
+        let mut core = ConnectionCore::new();
+        // Twice, so re-announces and unannounces of known topics are reached too.
+        let _ = core.on_text(&mut Discard, data);
+        let _ = core.on_text(&mut Discard, data);
+    }
+});
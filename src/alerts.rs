@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+
+use crate::types::Nt4Data;
+
+/// How the elements of an array value are reduced to the one number an [`AlertSpec`] checks.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reduction {
+    Min,
+    Max,
+    Mean,
+}
+
+/// When the numeric topic an alert is added for is out of range, see
+/// [`crate::ConnectionCore::add_alert`]. Times are in milliseconds of server time, measured between
+/// the timestamps of received values.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub above: Option<f64>,
+    /// How long the value has to stay out of range before the alert goes off.
+    #[serde(default)]
+    pub for_ms: f64,
+    /// How far back into range the value has to come for the alert to clear.
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// For array values, which are not checked without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reduce: Option<Reduction>,
+    /// The alert also goes off when no value was received for longer than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_ms: Option<f64>,
+}
+
+impl AlertSpec {
+    fn check(&self, name: &str) -> Result<(), String> {
+        if self.below.is_none() && self.above.is_none() && self.missing_ms.is_none() {
+            return Err(format!("alert {:?} has neither below, above nor missing_ms", name));
+        }
+        if self.below.is_some_and(f64::is_nan) || self.above.is_some_and(f64::is_nan) {
+            return Err(format!("alert {:?} has a NaN threshold", name));
+        }
+        if let (Some(below), Some(above)) = (self.below, self.above) {
+            if below > above {
+                return Err(format!("alert {:?} is below {} and above {}, so always on", name, below, above));
+            }
+        }
+        let fields = [("for_ms", Some(self.for_ms)), ("hysteresis", Some(self.hysteresis)), ("missing_ms", self.missing_ms)];
+        for (field, x) in fields {
+            if let Some(x) = x.filter(|x| !(x.is_finite() && *x >= 0.0)) {
+                return Err(format!("alert {:?} has a {} of {}", name, field, x));
+            }
+        }
+        Ok(())
+    }
+
+    /// The number checked for `data`, if it has one.
+    fn number(&self, data: &Nt4Data) -> Option<f64> {
+        let reduce = |values: &mut dyn ExactSizeIterator<Item = f64>| {
+            let len = values.len();
+            match self.reduce? {
+                _ if len == 0 => None,
+                Reduction::Min => values.reduce(f64::min),
+                Reduction::Max => values.reduce(f64::max),
+                Reduction::Mean => Some(values.sum::<f64>() / len as f64),
+            }
+        };
+        let number = match data {
+            Nt4Data::Double(x) => Some(*x),
+            Nt4Data::Float(x) => Some(*x as f64),
+            Nt4Data::Int(x) => Some(*x as f64),
+            Nt4Data::DoubleArray(x) => reduce(&mut x.iter().copied()),
+            Nt4Data::FloatArray(x) => reduce(&mut x.iter().map(|&x| x as f64)),
+            Nt4Data::IntArray(x) => reduce(&mut x.iter().map(|&x| x as f64)),
+            _ => None,
+        };
+        number.filter(|x| !x.is_nan())
+    }
+
+    fn out_of_range(&self, x: f64) -> bool {
+        self.below.is_some_and(|below| x < below) || self.above.is_some_and(|above| x > above)
+    }
+
+    fn back_in_range(&self, x: f64) -> bool {
+        self.below.is_none_or(|below| x >= below + self.hysteresis)
+            && self.above.is_none_or(|above| x <= above - self.hysteresis)
+    }
+}
+
+/// An alert going off or clearing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    /// The topic the alert is for.
+    pub name: String,
+    pub active: bool,
+    /// The number that decided it, `None` when data went missing or the alert cleared on a reset.
+    pub value: Option<f64>,
+    /// The server time the value went out of range or data went missing, or the alert cleared.
+    pub since_us: i64,
+}
+
+/// An alert as listed by [`crate::ConnectionCore::alerts`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertState {
+    pub name: String,
+    #[serde(flatten)]
+    pub spec: AlertSpec,
+    pub active: bool,
+}
+
+#[derive(Debug)]
+struct Alert {
+    spec: AlertSpec,
+    active: bool,
+    /// Whether the alert went off because data went missing.
+    missing: bool,
+    last: Option<f64>,
+    /// The server time of the last value received, or the first server time seen without one.
+    seen: Option<i64>,
+    /// The server time the value went out of range, while not yet active.
+    out_since: Option<i64>,
+}
+
+impl Alert {
+    fn new(spec: AlertSpec) -> Self {
+        Self { spec, active: false, missing: false, last: None, seen: None, out_since: None }
+    }
+
+    /// Whether the alert goes off by the server clock reaching `server_time`, and as of when.
+    fn advance(&mut self, server_time: i64) -> Option<(Option<f64>, i64)> {
+        if self.active {
+            return None;
+        }
+        if let (Some(out_since), Some(x)) = (self.out_since, self.last) {
+            if server_time.saturating_sub(out_since) >= ms_to_us(self.spec.for_ms) {
+                self.active = true;
+                return Some((Some(x), out_since));
+            }
+        }
+        let seen = *self.seen.get_or_insert(server_time);
+        let missing_ms = self.spec.missing_ms?;
+        if server_time.saturating_sub(seen) > ms_to_us(missing_ms) {
+            self.active = true;
+            self.missing = true;
+            self.out_since = None;
+            return Some((None, seen));
+        }
+        None
+    }
+
+    /// Whether a value stamped `timestamp` sets the alert off or clears it.
+    fn observe(&mut self, timestamp: i64, data: &Nt4Data) -> Option<(bool, Option<f64>, i64)> {
+        self.seen = Some(timestamp);
+        let x = self.spec.number(data);
+        self.last = x.or(self.last);
+        if self.active {
+            // Data that went missing is back, and only a number out of range keeps the alert on.
+            let cleared = match x {
+                Some(x) => self.spec.back_in_range(x) || self.missing && !self.spec.out_of_range(x),
+                None => self.missing,
+            };
+            if !cleared {
+                return None;
+            }
+            self.active = false;
+            self.missing = false;
+            self.out_since = None;
+            return Some((false, x, timestamp));
+        }
+        let x = x?;
+        if !self.spec.out_of_range(x) {
+            self.out_since = None;
+            return None;
+        }
+        self.out_since.get_or_insert(timestamp);
+        self.advance(timestamp).map(|(value, since)| (true, value, since))
+    }
+}
+
+fn ms_to_us(ms: f64) -> i64 {
+    (ms * 1e3) as i64
+}
+
+/// Every alert by topic name.
+#[derive(Debug, Default)]
+pub struct Alerts {
+    alerts: BTreeMap<String, Alert>,
+}
+
+impl Alerts {
+    /// Adds an alert for `name`, replacing any it had.
+    pub fn add(&mut self, name: &str, spec: AlertSpec) -> Result<(), String> {
+        spec.check(name)?;
+        self.alerts.insert(name.to_string(), Alert::new(spec));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.alerts.remove(name).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alerts.is_empty()
+    }
+
+    /// Sorted by name.
+    pub fn list(&self) -> Vec<AlertState> {
+        let alerts = self.alerts.iter();
+        alerts.map(|(name, x)| AlertState { name: name.clone(), spec: x.spec.clone(), active: x.active }).collect()
+    }
+
+    /// Every spec as a JSON object keyed by topic name, for [`Alerts::import`].
+    pub fn export(&self) -> Result<String, String> {
+        let specs: BTreeMap<&String, &AlertSpec> = self.alerts.iter().map(|(name, x)| (name, &x.spec)).collect();
+        serde_json::to_string(&specs).map_err(|x| x.to_string())
+    }
+
+    /// Replaces every alert with those in `json`, as produced by [`Alerts::export`].
+    pub fn import(&mut self, json: &str) -> Result<(), String> {
+        let specs: BTreeMap<String, AlertSpec> = serde_json::from_str(json).map_err(|x| x.to_string())?;
+        for (name, spec) in &specs {
+            spec.check(name)?;
+        }
+        self.alerts = specs.into_iter().map(|(name, spec)| (name, Alert::new(spec))).collect();
+        Ok(())
+    }
+
+    /// The alerts set off by the server clock reaching `server_time`, other than that of `except`.
+    fn advance_except(&mut self, server_time: i64, except: Option<&str>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for (name, alert) in &mut self.alerts {
+            if Some(name.as_str()) == except {
+                continue;
+            }
+            if let Some((value, since_us)) = alert.advance(server_time) {
+                events.push(AlertEvent { name: name.clone(), active: true, value, since_us });
+            }
+        }
+        events
+    }
+
+    pub fn advance(&mut self, server_time: i64) -> Vec<AlertEvent> {
+        self.advance_except(server_time, None)
+    }
+
+    /// The alerts set off or cleared by a value of `name` stamped `timestamp`, including those set
+    /// off by the server clock reaching `timestamp` first.
+    pub fn observe(&mut self, name: &str, timestamp: i64, data: &Nt4Data) -> Vec<AlertEvent> {
+        // The value itself ends any gap in the data of `name`.
+        let mut events = self.advance_except(timestamp, Some(name));
+        if let Some(alert) = self.alerts.get_mut(name) {
+            if let Some((active, value, since_us)) = alert.observe(timestamp, data) {
+                events.push(AlertEvent { name: name.to_string(), active, value, since_us });
+            }
+        }
+        events
+    }
+
+    /// Forgets every value and clears every alert, returning those that were active, e.g. when the
+    /// connection drops or the server clock restarts. `server_time` is the last server time known.
+    pub fn reset(&mut self, server_time: i64) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for (name, alert) in &mut self.alerts {
+            if alert.active {
+                events.push(AlertEvent { name: name.clone(), active: false, value: None, since_us: server_time });
+            }
+            *alert = Alert::new(alert.spec.clone());
+        }
+        events
+    }
+
+    pub fn clear(&mut self) {
+        self.alerts.clear();
+    }
+}
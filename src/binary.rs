@@ -1,14 +1,15 @@
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BinaryDataFrame {
     pub topic_id: i32,
+    /// Unsigned on the wire, so a negative timestamp cannot be serialized.
     pub timestamp: i64,
     pub data: crate::types::Nt4Data,
 }
 
 impl BinaryDataFrame {
     pub fn timesync(time: i64) -> Self {
-        Self { topic_id: -1, timestamp: 0, data: crate::types::Nt4Data::Int(time) }
+        Self { topic_id: crate::reserved_ids::TIMESYNC_TOPIC_ID, timestamp: 0, data: crate::types::Nt4Data::Int(time) }
     }
 }
 
@@ -16,12 +17,14 @@ impl serde::Serialize for BinaryDataFrame {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer {
-        use serde::ser::SerializeTuple;
+        use serde::ser::{Error, SerializeTuple};
 
         let data_type = self.data.get_id();
+        let timestamp = u64::try_from(self.timestamp)
+            .map_err(|_| S::Error::custom(format!("timestamp {} is negative", self.timestamp)))?;
         serializer.serialize_tuple(4).and_then(|mut s| {
             s.serialize_element(&self.topic_id)?;
-            s.serialize_element(&self.timestamp)?;
+            s.serialize_element(&timestamp)?;
             s.serialize_element(&data_type)?;
             s.serialize_element(&self.data)?;
             s.end()
@@ -34,23 +37,43 @@ impl<'de> serde::Deserialize<'de> for BinaryDataFrame {
     where
         D: serde::Deserializer<'de>,
     {
-        let (topic_id, timestamp, data_type, data) =
-            <(i32, i64, u8, crate::types::Nt4Data) as serde::Deserialize>::deserialize(
-                deserializer,
-            )?;
-        if data.get_id() != data_type {
-            Err(<D::Error as serde::de::Error>::custom(format!(
-                "data type does not match type id: got id for {:?}, expected id for {:?}",
-                data.get_name(),
-                crate::types::Nt4TypeId::from_id(data_type)
-                    .map_err(|x| <D::Error as serde::de::Error>::custom(x))?
-            )))
-        } else {
-            Ok(Self {
-                topic_id,
-                timestamp,
-                data,
-            })
+        struct FrameVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FrameVisitor {
+            type Value = BinaryDataFrame;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an array of [topic id, timestamp, data type, data]")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                use serde::de::Error;
+
+                let topic_id: i32 = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                // Some simulators send a signed integer rather than an unsigned one, both are accepted.
+                let timestamp: i64 = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                let data_type: u8 = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+                let ty = crate::types::Nt4TypeId::from_id(data_type).map_err(A::Error::custom)?;
+                let data = seq
+                    .next_element_seed(crate::wire::WireDataSeed(ty))?
+                    .ok_or_else(|| A::Error::invalid_length(3, &self))?;
+                Ok(BinaryDataFrame {
+                    topic_id,
+                    timestamp,
+                    data,
+                })
+            }
         }
+
+        deserializer.deserialize_tuple(4, FrameVisitor)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+
+use crate::binary::BinaryDataFrame;
+
+/// Decodes the frame of `message` at `*position` and moves `position` past it.
+pub fn next_frame(message: &[u8], position: &mut usize) -> Result<BinaryDataFrame, String> {
+    let mut rest = &message[*position..];
+    let frame = serde::Deserialize::deserialize(&mut rmp_serde::Deserializer::new(&mut rest))
+        .map_err(|x| format!("{:?}", x))?;
+    *position = message.len() - rest.len();
+    Ok(frame)
+}
+
+/// Binary messages being processed a few frames at a time, see
+/// [`crate::ConnectionCore::on_binary_chunked`]. Frames are only ever taken from the oldest message,
+/// so values are delivered in the order they were received whichever handle is continued.
+#[derive(Debug, Default)]
+pub struct ChunkedMessages {
+    /// Oldest first, by handle, with the position of the next frame.
+    queue: VecDeque<(u32, Vec<u8>, usize)>,
+    next_handle: u32,
+}
+
+impl ChunkedMessages {
+    /// Queues `message`, returning its handle. An empty message is done right away.
+    pub fn push(&mut self, message: Vec<u8>) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        if !message.is_empty() {
+            self.queue.push_back((handle, message, 0));
+        }
+        handle
+    }
+
+    /// Whether the message of `handle` still has frames left.
+    pub fn is_pending(&self, handle: u32) -> bool {
+        self.queue.iter().any(|(x, _, _)| *x == handle)
+    }
+
+    /// The next frame of the oldest message, which is dropped once it is done or fails to decode.
+    pub fn next_frame(&mut self) -> Option<Result<BinaryDataFrame, String>> {
+        let (_, message, position) = self.queue.front_mut()?;
+        let frame = next_frame(message, position);
+        if frame.is_err() || *position >= message.len() {
+            self.queue.pop_front();
+        }
+        Some(frame)
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
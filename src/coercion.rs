@@ -0,0 +1,58 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::types::Nt4TypeId;
+
+/// The types topics are expected to have whatever the server announces, by topic name or, for
+/// keys ending in `/`, by prefix, see [`crate::ConnectionCore::set_type_coercions`].
+#[derive(Debug, Default)]
+pub struct TypeCoercions {
+    table: BTreeMap<String, Nt4TypeId>,
+    /// Topics whose last value could not be converted, so a run of failures is warned about once.
+    failing: HashSet<String>,
+}
+
+impl TypeCoercions {
+    pub fn set(&mut self, table: BTreeMap<String, Nt4TypeId>) {
+        self.table = table;
+        self.failing.clear();
+    }
+
+    /// The type expected for `name`: that of its own entry, or else of the longest prefix it is under.
+    pub fn expected(&self, name: &str) -> Option<Nt4TypeId> {
+        if self.table.is_empty() {
+            return None;
+        }
+        if let Some(&ty) = self.table.get(name) {
+            return Some(ty);
+        }
+        self.table
+            .iter()
+            .filter(|(key, _)| key.ends_with('/') && name.starts_with(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, &ty)| ty)
+    }
+
+    /// Notes that a value of `name` could not be converted, returning whether it starts a run.
+    pub fn failed(&mut self, name: &str) -> bool {
+        self.failing.insert(name.to_string())
+    }
+
+    /// A value of `name` was converted, or needed no conversion, ending its run of failures.
+    pub fn converted(&mut self, name: &str) {
+        if !self.failing.is_empty() {
+            self.failing.remove(name);
+        }
+    }
+
+    /// The table as a JSON object of type names.
+    pub fn export(&self) -> Result<String, String> {
+        serde_json::to_string(&self.table).map_err(|x| x.to_string())
+    }
+
+    /// Replaces the table with the one in `json`, as produced by [`TypeCoercions::export`].
+    pub fn import(&mut self, json: &str) -> Result<(), String> {
+        let table = serde_json::from_str(json).map_err(|x| x.to_string())?;
+        self.set(table);
+        Ok(())
+    }
+}
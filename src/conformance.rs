@@ -0,0 +1,338 @@
+use std::{cell::RefCell, rc::Rc};
+
+use js_sys::Function;
+use serde_bytes::ByteBuf;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    connection::{ConnectionCore, ConnectionEvent, ConnectionSink},
+    instant,
+    self_test::{MAX_RTT_US, STAGE_TIMEOUT_US},
+    types::{Nt4Data, PartialProperties, Properties, SubscriptionOptions},
+};
+
+/// Conformance topics are published under this prefix, followed by a unique run name.
+pub const PREFIX: &str = "/nt4-wasm/conformance/";
+
+/// How long a whole conformance run may take.
+pub const TIMEOUT_MS: f64 = (STAGE_TIMEOUT_US / 1000) as f64;
+
+/// The values sent and expected back bit-exact, one topic each.
+fn vectors() -> Vec<Nt4Data> {
+    vec![
+        Nt4Data::Boolean(true),
+        Nt4Data::Double(-0.1 - 0.2),
+        Nt4Data::Int(i64::MIN + 1),
+        Nt4Data::Float(1.5e-10),
+        Nt4Data::String("nt4 ✓ \u{0} \"quoted\"".to_string()),
+        Nt4Data::Raw(ByteBuf::from((0..=255).collect::<Vec<u8>>())),
+        Nt4Data::BooleanArray(vec![true, false, true]),
+        Nt4Data::DoubleArray(vec![-0.0, f64::MIN_POSITIVE, 1e300]),
+        Nt4Data::IntArray(vec![0, -1, i64::MAX]),
+        Nt4Data::StringArray(vec![String::new(), "a".repeat(300)]),
+    ]
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConformanceCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+    /// Since the run started.
+    pub elapsed_ms: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConformanceReport {
+    pub passed: bool,
+    pub checks: Vec<ConformanceCheck>,
+}
+
+/// Something for the transport to send to the server.
+#[derive(Debug)]
+pub enum Outgoing {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Default)]
+struct Collect {
+    outgoing: Vec<Outgoing>,
+    events: Vec<ConnectionEvent>,
+}
+
+impl ConnectionSink for Collect {
+    type Error = String;
+
+    fn send_text(&mut self, data: String) -> Result<(), String> {
+        self.outgoing.push(Outgoing::Text(data));
+        Ok(())
+    }
+
+    fn send_binary(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.outgoing.push(Outgoing::Binary(data));
+        Ok(())
+    }
+
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), String> {
+        self.events.push(event);
+        Ok(())
+    }
+}
+
+struct Vector {
+    name: String,
+    data: Nt4Data,
+    pubuid: i32,
+    topic_id: Option<i32>,
+}
+
+/// A scripted run against a server on a connection of its own: a timesync, then one topic per
+/// [`vectors`] value published, announced, sent and echoed back through a subscription, then a
+/// property update that the server must acknowledge. Every check is in the report, in that order,
+/// failed with "timed out" if it did not happen before [`Conformance::on_timeout`].
+pub struct Conformance {
+    core: ConnectionCore,
+    started: f64,
+    prefix: String,
+    checks: Vec<ConformanceCheck>,
+    /// Indices into `checks` not yet decided.
+    pending: Vec<usize>,
+    vectors: Vec<Vector>,
+    subuid: Option<i32>,
+    finished: bool,
+}
+
+impl Default for Conformance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Conformance {
+    pub fn new() -> Self {
+        let started = instant::now();
+        let mut names = vec!["timesync".to_string()];
+        let data = vectors();
+        names.extend(data.iter().map(|x| format!("announce {}", x.get_name())));
+        names.extend(data.iter().map(|x| format!("echo {}", x.get_name())));
+        names.push("properties ack".to_string());
+        let checks: Vec<ConformanceCheck> = names
+            .into_iter()
+            .map(|name| ConformanceCheck { name, passed: false, detail: String::new(), elapsed_ms: 0.0 })
+            .collect();
+        Self {
+            core: ConnectionCore::new(),
+            started,
+            prefix: format!("{}{}/", PREFIX, (started * 1000.0) as u64),
+            pending: (0..checks.len()).collect(),
+            checks,
+            vectors: Vec::new(),
+            subuid: None,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn report(&self) -> ConformanceReport {
+        ConformanceReport { passed: self.checks.iter().all(|x| x.passed), checks: self.checks.clone() }
+    }
+
+    fn decide(&mut self, name: &str, passed: bool, detail: String) {
+        let Some(i) = self.pending.iter().position(|&i| self.checks[i].name == name) else {
+            return;
+        };
+        let check = &mut self.checks[self.pending.remove(i)];
+        check.passed = passed;
+        check.detail = detail;
+        check.elapsed_ms = instant::now() - self.started;
+    }
+
+    pub fn start(&mut self) -> Result<Vec<Outgoing>, String> {
+        let mut sink = Collect::default();
+        self.core.timesync(&mut sink)?;
+        self.handle(sink)
+    }
+
+    pub fn on_text(&mut self, data: &str) -> Result<Vec<Outgoing>, String> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+        // The core applies property updates silently, so look for the acknowledgement first.
+        if let Ok(message) = serde_json::from_str::<serde_json::Value>(data) {
+            let params = &message["params"];
+            let first = self.vectors.first().map(|x| x.name.as_str());
+            if message["method"] == "properties" && params["name"].as_str() == first {
+                let ack = params["ack"] == true;
+                self.decide("properties ack", ack, format!("update {}, ack {}", params["update"], params["ack"]));
+            }
+        }
+        let mut sink = Collect::default();
+        self.core.on_text(&mut sink, data)?;
+        self.handle(sink)
+    }
+
+    pub fn on_binary(&mut self, data: &[u8]) -> Result<Vec<Outgoing>, String> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+        let mut sink = Collect::default();
+        self.core.on_binary(&mut sink, data)?;
+        self.handle(sink)
+    }
+
+    /// Fails every check still pending and cleans up.
+    pub fn on_timeout(&mut self) -> Result<Vec<Outgoing>, String> {
+        for i in std::mem::take(&mut self.pending) {
+            self.checks[i].detail = format!("timed out after {} ms", TIMEOUT_MS);
+            self.checks[i].elapsed_ms = instant::now() - self.started;
+        }
+        self.handle(Collect::default())
+    }
+
+    fn handle(&mut self, mut sink: Collect) -> Result<Vec<Outgoing>, String> {
+        while !sink.events.is_empty() {
+            for event in std::mem::take(&mut sink.events) {
+                self.event(&mut sink, event)?;
+            }
+        }
+        if self.pending.is_empty() && !self.finished {
+            self.finished = true;
+            // Best-effort, the report is what matters.
+            if let Some(subuid) = self.subuid {
+                let _ = self.core.unsubscribe(&mut sink, subuid);
+            }
+            for vector in self.vectors.iter() {
+                let _ = self.core.unpublish(&mut sink, vector.pubuid);
+            }
+        }
+        Ok(sink.outgoing)
+    }
+
+    fn event(&mut self, sink: &mut Collect, event: ConnectionEvent) -> Result<(), String> {
+        match event {
+            ConnectionEvent::Ready if self.subuid.is_none() => {
+                let rtt = self.core.rtt().unwrap_or_default();
+                self.decide("timesync", rtt <= MAX_RTT_US, format!("round trip {} µs", rtt));
+                let options = SubscriptionOptions {
+                    periodic: std::time::Duration::from_millis(10),
+                    all: true,
+                    topicsonly: false,
+                    prefix: true,
+                };
+                self.subuid = Some(self.core.subscribe(sink, &self.prefix, options)?);
+                for (i, data) in vectors().into_iter().enumerate() {
+                    let name = format!("{}{}", self.prefix, i);
+                    let pubuid = self.core.publish(sink, &name, data.get_type_id(), Properties::default())?;
+                    self.vectors.push(Vector { name, data, pubuid, topic_id: None });
+                }
+            },
+            ConnectionEvent::Announce { id, topic } => {
+                let Some(i) = self.vectors.iter().position(|x| *x.name == *topic.name) else {
+                    return Ok(());
+                };
+                let vector = &mut self.vectors[i];
+                let expected = vector.data.get_type_id();
+                let name = format!("announce {}", vector.data.get_name());
+                vector.topic_id = Some(id);
+                let (pubuid, data) = (vector.pubuid, vector.data.clone());
+                self.decide(&name, topic.ty == expected, format!("announced as {}", topic.ty.get_name()));
+                self.core.send_data(sink, pubuid, data, None)?;
+                if i == 0 {
+                    let update = PartialProperties::single("conformance", serde_json::Value::Bool(true))?;
+                    self.core.set_properties(sink, &topic.name, update)?;
+                }
+            },
+            ConnectionEvent::Value { topic_id, data, .. } => {
+                let Some(vector) = self.vectors.iter().find(|x| x.topic_id == Some(topic_id)) else {
+                    return Ok(());
+                };
+                let encode = |x: &Nt4Data| rmp_serde::to_vec(x).unwrap_or_default();
+                let exact = encode(&data) == encode(&vector.data);
+                let name = format!("echo {}", vector.data.get_name());
+                let detail = if exact { "bit-exact".to_string() } else { format!("got {:?}", data) };
+                self.decide(&name, exact, detail);
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// Sends everything the run produced, in order.
+fn send(outgoing: Vec<Outgoing>, send_text: &Function, send_binary: &Function) -> Result<(), JsValue> {
+    for frame in outgoing {
+        match frame {
+            Outgoing::Text(text) => send_text.call1(&JsValue::NULL, &JsValue::from(text))?,
+            Outgoing::Binary(data) => send_binary.call1(&JsValue::NULL, &js_sys::Uint8Array::from(&data[..]))?,
+        };
+    }
+    Ok(())
+}
+
+#[doc = " run_conformance(function send_text, function send_binary, function feed) -> Promise<{passed, checks}>\n"]
+#[doc = " Checks that a server speaks NT4 as this library expects, resolving after 5 s at the latest with every check."]
+#[doc = " @param {function} feed - called once with `(on_text, on_binary)`, the functions to pass every received frame to."]
+#[wasm_bindgen(skip_jsdoc)]
+pub fn run_conformance(send_text: Function, send_binary: Function, feed: Function) -> Result<js_sys::Promise, JsValue> {
+    let mut resolvers = None;
+    let promise = js_sys::Promise::new(&mut |resolve, reject| resolvers = Some((resolve, reject)));
+    let Some((resolve, reject)) = resolvers else {
+        return Err(js_sys::JsString::from("the promise executor was not called").into());
+    };
+    let run = Rc::new(RefCell::new(Conformance::new()));
+    // Hands every frame to the run, and resolves (or rejects on error) once it is over.
+    let step = {
+        let run = run.clone();
+        move |f: &dyn Fn(&mut Conformance) -> Result<Vec<Outgoing>, String>| {
+            let Ok(mut conformance) = run.try_borrow_mut() else {
+                return;
+            };
+            if conformance.is_finished() {
+                return;
+            }
+            let result = f(&mut conformance)
+                .map_err(|x| JsValue::from(js_sys::JsString::from(x)))
+                .and_then(|outgoing| send(outgoing, &send_text, &send_binary));
+            // Nothing is left to report a failure to settle the promise to.
+            let _ = match result {
+                Err(error) => {
+                    conformance.finished = true;
+                    reject.call1(&JsValue::NULL, &error)
+                },
+                Ok(()) if conformance.is_finished() => {
+                    let report = serde::Serialize::serialize(
+                        &conformance.report(),
+                        &serde_wasm_bindgen::Serializer::json_compatible(),
+                    );
+                    report.map_err(JsValue::from).and_then(|report| resolve.call1(&JsValue::NULL, &report))
+                },
+                Ok(()) => Ok(JsValue::UNDEFINED),
+            };
+        }
+    };
+    let step = Rc::new(step);
+    let on_text = {
+        let step = step.clone();
+        Closure::<dyn Fn(String)>::new(move |data: String| step(&|x| x.on_text(&data)))
+    };
+    let on_binary = {
+        let step = step.clone();
+        Closure::<dyn Fn(Vec<u8>)>::new(move |data: Vec<u8>| step(&|x| x.on_binary(&data)))
+    };
+    let on_timeout = {
+        let step = step.clone();
+        Closure::once_into_js(move || step(&|x| x.on_timeout()))
+    };
+    feed.call2(&JsValue::NULL, on_text.as_ref(), on_binary.as_ref())?;
+    // The feed functions may be called for as long as the socket lives, after the run they do nothing.
+    on_text.forget();
+    on_binary.forget();
+    let set_timeout: Function = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))?.dyn_into()?;
+    set_timeout.call2(&JsValue::NULL, &on_timeout, &JsValue::from_f64(TIMEOUT_MS))?;
+    step(&|x| x.start());
+    Ok(promise)
+}
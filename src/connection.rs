@@ -0,0 +1,3216 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+
+use chrono::Duration;
+use serde_bytes::ByteBuf;
+use serde_json::json;
+
+use crate::{
+    alerts::{AlertEvent, AlertSpec, AlertState, Alerts},
+    binary::BinaryDataFrame,
+    chunked::{self, ChunkedMessages},
+    coercion::TypeCoercions,
+    diff::{self, ArrayDiff},
+    filter::TopicFilter,
+    fetch::Fetches,
+    frame_schema,
+    groups::{GroupValues, Groups},
+    hydration::Hydration,
+    instant::Instant,
+    interpolation::{InterpolatedSample, InterpolationMode, InterpolationOptions, Interpolations},
+    journal::{Journal, JournalEvent, JournalKind},
+    log_channel::{LogChannel, LogOptions},
+    manifest::{Manifest, ManifestDrift, ManifestStrictness, ManifestTopic},
+    metrics::{self, Counters, Metrics, ReportMetrics},
+    metadata,
+    mismatch::{self, TypeMismatches},
+    own_topics::{OwnTopics, TopicKey, TopicRecord},
+    pause::Pause,
+    pending,
+    persistent::{self, ExportStage, ImportReport, ImportResult, Importing, PersistentBackup, PersistentEntry, PersistentTask},
+    profiles::SubscriptionProfiles,
+    quarantine::{self, Quarantine, QuarantinedFrame, QuarantinedInput},
+    reserved_ids::{self, TopicIdClass},
+    retention::{Retention, RetentionOptions, RetentionStats, SWEEP_BUDGET},
+    search::{self, TopicMatch},
+    self_test::{self, SelfTest, SelfTestFailure, SelfTestReport, SelfTestStage},
+    snapshot::{self, ConnectionSnapshot, ConnectionState, Snapshot, SnapshotValue, TopicSnapshot},
+    sync::{Delta, SyncLog},
+    text::*,
+    time_reset::{TimeResetDetector, TimeResetOptions},
+    topic_list::{self, Change, TopicEntry, TopicOrder, TopicsDiff},
+    trajectory::{Trajectories, Trajectory, TrajectoryOptions},
+    types::*,
+    validation::{self, Validator},
+    watch::{WatchResult, WatchSpec, Watches},
+    widgets::{self, WidgetDescriptor, Widgets},
+    wire::{self, WireOptions},
+};
+
+/// Something received from the server that the owner of a [`ConnectionCore`] is told about.
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    Announce { id: i32, topic: Topic },
+    Unannounce { id: i32, name: String },
+    /// `name` was announced again with a different type than the last time it was seen.
+    TypeChanged { name: String, old: Nt4TypeId, new: Nt4TypeId },
+    /// `name` was announced again under the same id with different properties, or the server
+    /// sent an update of its properties.
+    PropertiesChanged { id: i32, name: String, properties: Properties },
+    /// A timesync response was received.
+    Ready,
+    Unready,
+    /// `type_mismatch` is set when `data` is not of the announced type of the topic and could not
+    /// be converted to it without loss. `local_echo` is set for a value we sent ourselves, see
+    /// [`ConnectionCore::set_local_echo`].
+    Value { topic_id: i32, timestamp: i64, data: Nt4Data, type_mismatch: bool, local_echo: bool },
+    /// A value of a topic in diff mode, see [`ConnectionCore::set_diff_mode`].
+    Diff { topic_id: i32, timestamp: i64, ty: Nt4TypeId, diff: ArrayDiff },
+    /// Something the user is likely to have gotten wrong, such as subscribing to filtered out topics.
+    Warning(String),
+    /// The outcome of [`ConnectionCore::start_self_test`].
+    SelfTest(Result<SelfTestReport, SelfTestFailure>),
+    /// A set of values of the group made by [`ConnectionCore::subscribe_group`].
+    Group { id: i32, values: GroupValues },
+    /// The first value received for the fetch made by [`ConnectionCore::start_fetch`], or why there was none.
+    Fetched { id: i32, result: Result<(i64, Nt4Data), String> },
+    /// The outcome of [`ConnectionCore::start_persistent_export`].
+    PersistentExport(Result<PersistentBackup, String>),
+    /// The outcome of [`ConnectionCore::start_persistent_import`].
+    PersistentImport(ImportReport),
+    /// Received timestamps jumped backwards as the server's clock restarted, see
+    /// [`ConnectionCore::set_server_time_reset_options`]. Both are server times in µs.
+    ServerTimeReset { old_estimate: i64, new_estimate: i64 },
+    /// A watch added with [`ConnectionCore::add_watch`] passed or failed.
+    Watch(WatchResult),
+    /// An alert added with [`ConnectionCore::add_alert`] went off or cleared.
+    Alert(AlertEvent),
+}
+
+impl From<BinaryDataFrame> for ConnectionEvent {
+    fn from(frame: BinaryDataFrame) -> Self {
+        Self::Value {
+            topic_id: frame.topic_id,
+            timestamp: frame.timestamp,
+            data: frame.data,
+            type_mismatch: false,
+            local_echo: false,
+        }
+    }
+}
+
+/// Carries the frames a [`ConnectionCore`] sends and the events it emits.
+/// Errors returned here are passed straight back out of the core method that caused them.
+pub trait ConnectionSink {
+    type Error: From<String> + From<UnknownUid> + From<InternalError>;
+
+    fn send_text(&mut self, data: String) -> Result<(), Self::Error>;
+    fn send_binary(&mut self, data: Vec<u8>) -> Result<(), Self::Error>;
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), Self::Error>;
+
+    /// The largest message the transport carries, for those with a limit such as WebRTC data
+    /// channels. Values that only happen to be sent together, such as log lines, are split over as
+    /// many binary messages as it takes. Atomic sends are never split, so one that does not fit, a
+    /// single value or a text message over it is an error rather than sent. Nothing has to be
+    /// reassembled on the way in: every value in a binary message is a msgpack array of its own,
+    /// so messages split anywhere between values decode the same.
+    fn max_frame_bytes(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The table a uid was not found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UidKind {
+    Subscription,
+    Publication,
+}
+
+/// A subuid or pubuid that was never allocated, or has already been released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownUid {
+    pub kind: UidKind,
+    pub uid: i32,
+}
+
+impl std::fmt::Display for UnknownUid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            UidKind::Subscription => write!(f, "{} is not an active subscription", self.uid),
+            UidKind::Publication => write!(f, "{} is not an active publication", self.uid),
+        }
+    }
+}
+
+impl From<UnknownUid> for String {
+    fn from(x: UnknownUid) -> Self {
+        x.to_string()
+    }
+}
+
+/// A panic caught while processing a frame, which is kept in [`ConnectionCore::quarantined`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternalError {
+    pub message: String,
+}
+
+impl std::fmt::Display for InternalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "internal error: {}", self.message)
+    }
+}
+
+impl From<InternalError> for String {
+    fn from(x: InternalError) -> Self {
+        x.to_string()
+    }
+}
+
+/// Where the timestamps of values sent with [`ConnectionCore::send_data`],
+/// [`ConnectionCore::send_atomic`] and [`ConnectionCore::send_struct_of_values`] come from.
+/// Values the connection sends on its own, such as momentaries, are always stamped live.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampMode {
+    /// The local clock plus the timesync offset. Timestamps may not be given.
+    #[default]
+    Live,
+    /// The given server timestamp, as is. Timestamps must be given.
+    Passthrough,
+    /// The given timestamp plus [`ConnectionCore::set_timestamp_offset`], ignoring the timesync
+    /// offset, e.g. to line a replayed log up with the current server time.
+    FixedOffset,
+}
+
+/// How values are put on the wire by [`ConnectionCore`]. Either is accepted when receiving.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    /// Binary frames of msgpack, as in NT4.
+    #[default]
+    MsgPack,
+    /// Text frames of `{"method": "values", "params": [[id, timestamp, type, value], ...]}`, for
+    /// bridges that cannot carry binary frames. Doubles that are not finite cannot be sent.
+    Json,
+}
+
+/// Names of topics that are no longer announced are forgotten once more than this many are known.
+const KNOWN_TYPES_LIMIT: usize = 16_384;
+
+/// Shortest time between two updates of the client metadata topics.
+const CLIENT_METADATA_DEBOUNCE_US: i64 = 100_000;
+
+/// See [`ConnectionCore::set_max_properties_bytes`].
+pub const DEFAULT_MAX_PROPERTIES_BYTES: usize = 16 * 1024;
+
+/// See [`ConnectionCore::set_max_topics`].
+pub const DEFAULT_MAX_TOPICS: usize = 100_000;
+
+/// The topics of [`ConnectionCore::publish_client_metadata`].
+#[derive(Debug)]
+struct ClientMetadata {
+    subscriptions: i32,
+    publications: i32,
+    dirty: bool,
+    last_sent: Option<i64>,
+}
+
+/// The protocol state behind `Nt4Connection`, without any JS types, so it can run natively.
+#[derive(Debug)]
+pub struct ConnectionCore {
+    start_time: Instant,
+    offs: i64,
+    rtt: Option<i64>,
+    uid_cnt: i32,
+    ready: bool,
+    topics: HashMap<i32, Topic>,
+    topic_ids: HashMap<Arc<str>, i32>,
+    known_types: HashMap<Arc<str>, Nt4TypeId>,
+    known_types_limit: usize,
+    pending: pending::PendingValues,
+    pretty_text_frames: bool,
+    /// Whether text frames that [`frame_schema::check_client_frame`] finds wrong are errors rather
+    /// than warnings, which debug builds give.
+    debug_validation: bool,
+    publications: HashMap<i32, Topic>,
+    subscriptions: HashMap<i32, SubscribeParams>,
+    background_mode: bool,
+    background_periodic: std::time::Duration,
+    always_fast: HashSet<String>,
+    publishers: metadata::Publishers,
+    closed: bool,
+    validators: validation::Validators,
+    cache: HashMap<i32, (i64, Nt4Data)>,
+    own_topics: OwnTopics,
+    /// Properties of announced topics, as of their announce.
+    properties: HashMap<i32, Properties>,
+    fire_duplicate_announces: bool,
+    idempotent: bool,
+    /// Largest JSON encoding of the properties sent with a publish or setproperties.
+    max_properties_bytes: usize,
+    subscription_profiles: SubscriptionProfiles,
+    trajectories: Trajectories,
+    groups: Groups,
+    retention: Retention,
+    topic_filter: Option<TopicFilter>,
+    /// Names by id of announced topics dropped by the topic filter, whose values are dropped too.
+    filtered: HashMap<i32, String>,
+    filtered_count: u64,
+    /// Most topics held in the announce table, see [`ConnectionCore::set_max_topics`].
+    max_topics: usize,
+    /// Ids of announced topics dropped for being over `max_topics`, whose values are dropped too.
+    over_limit: HashSet<i32>,
+    over_limit_count: u64,
+    /// Reserved topic ids frames were received for, each warned about once.
+    reserved_warned: HashSet<i32>,
+    momentaries: HashMap<i32, bool>,
+    log_channels: BTreeMap<i32, LogChannel>,
+    quarantine: Quarantine,
+    counters: Counters,
+    interpolations: Interpolations,
+    widgets: Widgets,
+    booleans_sent: HashMap<i32, (i64, bool)>,
+    time_reset: TimeResetDetector,
+    diff_modes: HashMap<String, diff::DiffState>,
+    client_metadata_prefix: Option<String>,
+    client_metadata: Option<ClientMetadata>,
+    timestamp_mode: TimestampMode,
+    value_encoding: ValueEncoding,
+    wire_options: WireOptions,
+    timestamp_offset: i64,
+    self_test: Option<SelfTest>,
+    persistent: Option<PersistentTask>,
+    pause: Option<Pause>,
+    fetches: Fetches,
+    journal: Journal,
+    /// Topics seeded by [`ConnectionCore::hydrate`] that are not settled yet.
+    hydration: Option<Hydration>,
+    chunked: ChunkedMessages,
+    type_mismatches: TypeMismatches,
+    coercions: TypeCoercions,
+    manifest: Option<Manifest>,
+    watches: Watches,
+    alerts: Alerts,
+    sync: SyncLog,
+}
+
+impl Default for ConnectionCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionCore {
+    pub fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            offs: 0,
+            rtt: None,
+            uid_cnt: 0,
+            ready: false,
+            topics: HashMap::new(),
+            topic_ids: HashMap::new(),
+            known_types: HashMap::new(),
+            known_types_limit: KNOWN_TYPES_LIMIT,
+            pending: pending::PendingValues::default(),
+            pretty_text_frames: false,
+            debug_validation: false,
+            publications: HashMap::new(),
+            subscriptions: HashMap::new(),
+            background_mode: false,
+            background_periodic: std::time::Duration::from_secs(1),
+            always_fast: HashSet::new(),
+            publishers: metadata::Publishers::default(),
+            closed: false,
+            validators: validation::Validators::default(),
+            cache: HashMap::new(),
+            properties: HashMap::new(),
+            fire_duplicate_announces: false,
+            idempotent: false,
+            max_properties_bytes: DEFAULT_MAX_PROPERTIES_BYTES,
+            subscription_profiles: SubscriptionProfiles::default(),
+            trajectories: Trajectories::default(),
+            groups: Groups::default(),
+            retention: Retention::default(),
+            topic_filter: None,
+            filtered: HashMap::new(),
+            filtered_count: 0,
+            max_topics: DEFAULT_MAX_TOPICS,
+            over_limit: HashSet::new(),
+            over_limit_count: 0,
+            reserved_warned: HashSet::new(),
+            momentaries: HashMap::new(),
+            log_channels: BTreeMap::new(),
+            quarantine: Quarantine::default(),
+            counters: Counters::default(),
+            interpolations: Interpolations::default(),
+            widgets: Widgets::default(),
+            own_topics: OwnTopics::default(),
+            sync: SyncLog::default(),
+            booleans_sent: HashMap::new(),
+            time_reset: TimeResetDetector::default(),
+            diff_modes: HashMap::new(),
+            client_metadata_prefix: None,
+            client_metadata: None,
+            timestamp_mode: TimestampMode::Live,
+            value_encoding: ValueEncoding::MsgPack,
+            wire_options: WireOptions::default(),
+            timestamp_offset: 0,
+            self_test: None,
+            persistent: None,
+            pause: None,
+            fetches: Fetches::default(),
+            journal: Journal::default(),
+            hydration: None,
+            chunked: ChunkedMessages::default(),
+            type_mismatches: TypeMismatches::default(),
+            coercions: TypeCoercions::default(),
+            manifest: None,
+            watches: Watches::default(),
+            alerts: Alerts::default(),
+        }
+    }
+
+    /// Microseconds on the local clock used for outgoing timestamps.
+    pub fn now(&mut self) -> Result<i64, String> {
+        let now = Duration::from_std(Instant::now().duration_since(self.start_time))
+            .map_err(|x| format!("{:?}", x))?;
+        Ok(if let Some(us) = now.num_microseconds() {
+            us
+        } else {
+            self.start_time = Instant::now();
+            let now = Duration::from_std(Instant::now().duration_since(self.start_time))
+                .map_err(|x| format!("{:?}", x))?;
+            now.num_microseconds().ok_or("the local clock overflowed")?
+        })
+    }
+
+    /// Offset from the local clock to the server clock, from the last timesync.
+    pub fn offset(&self) -> i64 {
+        self.offs
+    }
+
+    pub fn check_open(&self) -> Result<(), String> {
+        if self.closed {
+            Err("connection closed".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn new_uid(&mut self) -> i32 {
+        let next = self.uid_cnt;
+        self.uid_cnt += 1;
+        next
+    }
+
+    /// Allocates subuids and pubuids from `base` on, so that the ids of a follower, see
+    /// [`ConnectionCore::apply_delta`], do not collide with the leader's when it forwards the
+    /// follower's frames. Fails once an id has been allocated.
+    pub fn set_uid_base(&mut self, base: i32) -> Result<(), String> {
+        if self.uid_cnt != 0 {
+            return Err("ids have already been allocated".to_string());
+        }
+        if base < 0 {
+            return Err(format!("the uid base must not be negative, not {}", base));
+        }
+        self.uid_cnt = base;
+        Ok(())
+    }
+
+    fn encode_text(&self, data: &ClientToServerTextDataFrame) -> Result<String, String> {
+        if self.pretty_text_frames {
+            serde_json::to_string_pretty(data)
+        } else {
+            serde_json::to_string(data)
+        }
+        .map_err(|x| format!("{:?}", x))
+    }
+
+    fn send_text<S: ConnectionSink>(&self, sink: &mut S, data: &ClientToServerTextDataFrame) -> Result<(), S::Error> {
+        let data = self.encode_text(data)?;
+        if self.debug_validation || cfg!(debug_assertions) {
+            if let Err(problem) = frame_schema::check_client_frame(&data) {
+                let problem = format!("invalid text frame, {}: {}", problem, data);
+                if self.debug_validation {
+                    return Err(problem.into());
+                }
+                sink.event(ConnectionEvent::Warning(problem))?;
+            }
+        }
+        let len = data.len();
+        if let Some(limit) = sink.max_frame_bytes().filter(|&limit| len > limit) {
+            return Err(format!("a {} byte text message is over the {} byte frame limit", len, limit).into());
+        }
+        sink.send_text(data)?;
+        metrics::bump(&self.counters.frames_tx, 1);
+        metrics::bump(&self.counters.bytes_tx, len);
+        Ok(())
+    }
+
+    /// Sends `frames` in one message, or in as many as [`ConnectionSink::max_frame_bytes`] requires
+    /// if `split`.
+    fn send_values<S: ConnectionSink>(&self, sink: &mut S, frames: &[BinaryDataFrame], split: bool) -> Result<(), S::Error> {
+        match self.value_encoding {
+            ValueEncoding::MsgPack => {
+                let messages = match sink.max_frame_bytes() {
+                    Some(limit) => wire::write_frames_split(frames, &self.wire_options, limit)?,
+                    None => vec![wire::write_frames(frames, &self.wire_options)?],
+                };
+                if !split && messages.len() > 1 {
+                    return Err(format!(
+                        "{} values sent together are {} bytes encoded, over the {} byte frame limit",
+                        frames.len(),
+                        messages.iter().map(Vec::len).sum::<usize>(),
+                        sink.max_frame_bytes().unwrap_or_default()
+                    )
+                    .into());
+                }
+                for data in messages {
+                    let len = data.len();
+                    sink.send_binary(data)?;
+                    metrics::bump(&self.counters.frames_tx, 1);
+                    metrics::bump(&self.counters.bytes_tx, len);
+                }
+                Ok(())
+            },
+            ValueEncoding::Json => {
+                // JSON has no NaN or infinity, serde_json would quietly write them as null.
+                let finite = |frame: &&BinaryDataFrame| match &frame.data {
+                    Nt4Data::Double(x) => x.is_finite(),
+                    Nt4Data::Float(x) => x.is_finite(),
+                    Nt4Data::DoubleArray(x) => x.iter().all(|x| x.is_finite()),
+                    Nt4Data::FloatArray(x) => x.iter().all(|x| x.is_finite()),
+                    _ => true,
+                };
+                if let Some(frame) = frames.iter().find(|frame| !finite(frame)) {
+                    return Err(format!("{:?} of topic {} cannot be sent as json", frame.data, frame.topic_id).into());
+                }
+                self.send_text(sink, &ClientToServerTextDataFrame::Values(frames.to_vec()))
+            },
+        }
+    }
+
+    fn send_subscription<S: ConnectionSink>(&self, sink: &mut S, params: &SubscribeParams) -> Result<(), S::Error> {
+        let mut params = params.clone();
+        if self.background_mode && !params.topics.iter().any(|x| self.always_fast.contains(x)) {
+            params.options.periodic = params.options.periodic.max(self.background_periodic);
+        }
+        self.send_text(sink, &ClientToServerTextDataFrame::Subscribe(params))
+    }
+
+    fn resend_subscriptions<S: ConnectionSink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        for params in self.subscriptions.values() {
+            self.send_subscription(sink, params)?;
+        }
+        Ok(())
+    }
+
+    /// Reuses the name of a topic seen before, so announces after a reconnect share the names
+    /// already held instead of allocating new ones.
+    fn intern(&self, name: String) -> Arc<str> {
+        match self.known_types.get_key_value(name.as_str()) {
+            Some((name, _)) => name.clone(),
+            None => name.into(),
+        }
+    }
+
+    /// Forgets the types of topics that are no longer announced once too many names are known,
+    /// so a type change across such a gap is no longer reported.
+    fn evict_known_types(&mut self) {
+        if self.known_types.len() <= self.known_types_limit {
+            return;
+        }
+        // Names of announced topics are also held by the announce table.
+        self.known_types.retain(|name, _| Arc::strong_count(name) > 1);
+        self.known_types_limit = KNOWN_TYPES_LIMIT.max(self.known_types.len() * 2);
+    }
+
+    /// Bookkeeping for values of announced topics, before they are delivered. Metadata that does not
+    /// decode is only a warning, the value is still delivered.
+    fn observe<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &BinaryDataFrame) -> Result<(), S::Error> {
+        if let Some(topic) = self.topics.get(&data_frame.topic_id) {
+            if let Err(problem) = self.publishers.update(&topic.name, &data_frame.data) {
+                sink.event(ConnectionEvent::Warning(problem))?;
+            }
+            self.trajectories.observe(&topic.name, data_frame.timestamp, &data_frame.data);
+            self.interpolations.observe(&topic.name, data_frame.timestamp, &data_frame.data);
+            self.cache.insert(data_frame.topic_id, (data_frame.timestamp, data_frame.data.clone()));
+            self.sync.valued(data_frame.topic_id);
+            if let Some(hydration) = &mut self.hydration {
+                hydration.refresh(data_frame.topic_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// The diff to deliver instead of `data_frame` if its topic is in diff mode. Must be called
+    /// before [`ConnectionCore::observe`] replaces the cached previous value.
+    fn diff(&mut self, data_frame: &BinaryDataFrame) -> Option<ArrayDiff> {
+        let topic = self.topics.get(&data_frame.topic_id)?;
+        let state = self.diff_modes.get_mut(&*topic.name)?;
+        if !diff::is_array(&data_frame.data) {
+            return None;
+        }
+        let base_revision = state.revision;
+        state.revision += 1;
+        let previous = self
+            .cache
+            .get(&data_frame.topic_id)
+            .filter(|_| !std::mem::take(&mut state.full_requested));
+        Some(match previous.and_then(|(_, previous)| diff::changes(previous, &data_frame.data)) {
+            Some(changes) => ArrayDiff {
+                base_revision: Some(base_revision),
+                revision: state.revision,
+                changes,
+                full: None,
+            },
+            None => ArrayDiff::full(state.revision, data_frame.data.clone()),
+        })
+    }
+
+    /// Moves everything stamped on the old server clock onto the new one, except the values of
+    /// the `jumped` topics which already are, and resynchronizes.
+    fn server_time_reset<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        now: i64,
+        new_estimate: i64,
+        jumped: &[i32],
+    ) -> Result<(), S::Error> {
+        // The same bounds as a timesync answered the moment it was sent.
+        let Ok(offs) = timesync_offset(now, new_estimate, now) else {
+            let warning = format!("ignoring a server time reset to an implausible {} µs", new_estimate);
+            return sink.event(ConnectionEvent::Warning(warning));
+        };
+        let old_estimate = now + self.offs;
+        let delta = new_estimate - old_estimate;
+        for (id, (timestamp, _)) in self.cache.iter_mut() {
+            if !jumped.contains(id) {
+                *timestamp = timestamp.saturating_add(delta);
+            }
+        }
+        for (timestamp, _) in self.booleans_sent.values_mut() {
+            *timestamp = timestamp.saturating_add(delta);
+        }
+        self.trajectories.clear_points();
+        self.interpolations.clear_samples();
+        self.groups.clear_values();
+        self.watches.reset();
+        for event in self.alerts.reset(old_estimate) {
+            sink.event(ConnectionEvent::Alert(event))?;
+        }
+        // Close enough for live timestamps until the timesync response arrives.
+        self.offs = offs;
+        sink.event(ConnectionEvent::ServerTimeReset { old_estimate, new_estimate })?;
+        self.timesync(sink)
+    }
+
+    /// Forgets the announced topic `id` and tells the sink it is gone.
+    fn remove_topic<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        let Some(topic) = self.topics.remove(&id) else {
+            return Ok(());
+        };
+        if self.topic_ids.get(&topic.name) == Some(&id) {
+            self.topic_ids.remove(&topic.name);
+        }
+        self.cache.remove(&id);
+        self.own_topics.unannounced(id);
+        self.sync.removed(id, &topic.name);
+        self.properties.remove(&id);
+        self.widgets.unannounce(&topic.name)?;
+        if let Some(hydration) = &mut self.hydration {
+            hydration.remove(id);
+        }
+        if self_test::is_test_topic(&topic.name) {
+            return Ok(());
+        }
+        self.announce_event(sink, ConnectionEvent::Unannounce { id, name: topic.name.to_string() })
+    }
+
+    /// Journals an announce, unannounce, type or properties change, and passes it on unless
+    /// announces are paused.
+    fn announce_event<S: ConnectionSink>(&mut self, sink: &mut S, event: ConnectionEvent) -> Result<(), S::Error> {
+        let server_time = self.now()? + self.offs;
+        let entry = match &event {
+            ConnectionEvent::Announce { id, topic } => {
+                Some((JournalKind::Announce, topic.name.to_string(), json!({"id": id, "type": topic.ty})))
+            },
+            ConnectionEvent::Unannounce { id, name } => Some((JournalKind::Unannounce, name.clone(), json!({"id": id}))),
+            ConnectionEvent::PropertiesChanged { name, properties, .. } => Some((
+                JournalKind::Properties,
+                name.clone(),
+                serde_json::to_value(properties).map_err(|x| format!("{:?}", x))?,
+            )),
+            ConnectionEvent::TypeChanged { name, old, new } => {
+                Some((JournalKind::TypeChange, name.clone(), json!({"old": old, "new": new})))
+            },
+            _ => None,
+        };
+        if let Some((kind, name, detail)) = entry {
+            self.journal.record(kind, &name, detail, server_time);
+        }
+        if self.pause.as_ref().is_some_and(Pause::announces_paused) {
+            return Ok(());
+        }
+        sink.event(event)
+    }
+
+    fn value_event(data_frame: BinaryDataFrame, diff: Option<ArrayDiff>, type_mismatch: bool, local_echo: bool) -> ConnectionEvent {
+        match diff {
+            Some(diff) => ConnectionEvent::Diff {
+                topic_id: data_frame.topic_id,
+                timestamp: data_frame.timestamp,
+                ty: data_frame.data.get_type_id(),
+                diff,
+            },
+            None => ConnectionEvent::Value {
+                topic_id: data_frame.topic_id,
+                timestamp: data_frame.timestamp,
+                data: data_frame.data,
+                type_mismatch,
+                local_echo,
+            },
+        }
+    }
+
+    /// The type values of `topic` are delivered as: the one set by [`Self::set_type_coercions`],
+    /// or else the announced one.
+    fn expected_type(&self, topic: &Topic) -> Nt4TypeId {
+        self.coercions.expected(&topic.name).unwrap_or(topic.ty)
+    }
+
+    /// Whether `data` is not of the expected type of `topic_id`.
+    fn is_type_mismatch(&self, topic_id: i32, data: &Nt4Data) -> bool {
+        self.topics.get(&topic_id).is_some_and(|topic| self.expected_type(topic) != data.get_type_id())
+    }
+
+    /// `data_frame` with its value converted to the announced type of its topic if that loses
+    /// nothing, and whether it is left of another type. Values that cannot be are counted, and
+    /// warned about once a run of them is long enough. Topics with a type set by
+    /// [`Self::set_type_coercions`] are converted to that one instead, even with loss.
+    fn coerce_to_announced<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        mut data_frame: BinaryDataFrame,
+    ) -> Result<(BinaryDataFrame, bool), S::Error> {
+        let Some(topic) = self.topics.get(&data_frame.topic_id) else {
+            return Ok((data_frame, false));
+        };
+        if let Some(expected) = self.coercions.expected(&topic.name) {
+            if data_frame.data.get_type_id() == expected {
+                self.coercions.converted(&topic.name);
+                return Ok((data_frame, false));
+            }
+            return match data_frame.data.convert_to(expected) {
+                Ok((data, lossy)) => {
+                    data_frame.data = data;
+                    self.coercions.converted(&topic.name);
+                    Ok((data_frame, lossy))
+                },
+                Err(data) => {
+                    if self.coercions.failed(&topic.name) {
+                        sink.event(ConnectionEvent::Warning(format!(
+                            "{} got a value of type {} that cannot be converted to {}",
+                            topic.name,
+                            data.get_name(),
+                            expected.get_name()
+                        )))?;
+                    }
+                    data_frame.data = data;
+                    Ok((data_frame, true))
+                },
+            };
+        }
+        if data_frame.data.get_type_id() == topic.ty {
+            self.type_mismatches.matched(&topic.name);
+            return Ok((data_frame, false));
+        }
+        match mismatch::coerce(data_frame.data, topic.ty) {
+            Ok(data) => {
+                data_frame.data = data;
+                self.type_mismatches.matched(&topic.name);
+                Ok((data_frame, false))
+            },
+            Err(data) => {
+                if self.type_mismatches.mismatched(&topic.name) {
+                    sink.event(ConnectionEvent::Warning(format!(
+                        "{} got {} values of type {} in a row but is announced as {}, it may have to be re-announced or resubscribed to",
+                        topic.name,
+                        self.type_mismatches.warn_after,
+                        data.get_name(),
+                        topic.ty.get_name()
+                    )))?;
+                }
+                data_frame.data = data;
+                Ok((data_frame, true))
+            },
+        }
+    }
+
+    /// Delivers a value of an announced topic.
+    fn receive<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: BinaryDataFrame) -> Result<(), S::Error> {
+        if self.topics.get(&data_frame.topic_id).is_some_and(|topic| self_test::is_test_topic(&topic.name)) {
+            return self.self_test_value(sink, data_frame.topic_id, &data_frame.data);
+        }
+        // A value we sent, coming back after it was delivered as a local echo.
+        if self.own_topics.received(data_frame.topic_id)
+            && self.cache.get(&data_frame.topic_id).is_some_and(|(timestamp, _)| *timestamp == data_frame.timestamp)
+        {
+            return Ok(());
+        }
+        let (data_frame, type_mismatch) = self.coerce_to_announced(sink, data_frame)?;
+        if let Some(previous) = self.cache.get(&data_frame.topic_id).map(|(timestamp, _)| *timestamp) {
+            let now = self.now()?;
+            let valued = self.cache.len();
+            let reset = self.time_reset.observe(data_frame.topic_id, previous, data_frame.timestamp, now, valued);
+            if let Some((new_estimate, jumped)) = reset {
+                self.server_time_reset(sink, now, new_estimate, &jumped)?;
+            }
+        }
+        let diff = if self.pause.is_some() || type_mismatch { None } else { self.diff(&data_frame) };
+        self.observe(sink, &data_frame)?;
+        let sets = self.group_sets(&data_frame)?;
+        let watched = match self.topics.get(&data_frame.topic_id) {
+            Some(topic) if !self.watches.is_empty() => {
+                self.watches.observe(&topic.name, data_frame.timestamp, &data_frame.data)
+            },
+            _ => Vec::new(),
+        };
+        let alerted = match self.topics.get(&data_frame.topic_id) {
+            Some(topic) if !self.alerts.is_empty() => {
+                self.alerts.observe(&topic.name, data_frame.timestamp, &data_frame.data)
+            },
+            _ => Vec::new(),
+        };
+        self.fetched(sink, &data_frame)?;
+        if let Some(pause) = &mut self.pause {
+            pause.valued(data_frame.topic_id);
+            // Whatever is delivered next has to be a full snapshot, the owner missed the values in between.
+            if let Some(state) = self.topics.get(&data_frame.topic_id).and_then(|x| self.diff_modes.get_mut(&*x.name)) {
+                state.full_requested = true;
+            }
+        } else {
+            sink.event(Self::value_event(data_frame, diff, type_mismatch, false))?;
+            for (id, values) in sets {
+                sink.event(ConnectionEvent::Group { id, values })?;
+            }
+        }
+        for result in watched {
+            sink.event(ConnectionEvent::Watch(result))?;
+        }
+        for event in alerted {
+            sink.event(ConnectionEvent::Alert(event))?;
+        }
+        if matches!(self.persistent, Some(PersistentTask::Export(ExportStage::Values { .. }))) {
+            self.check_persistent(sink)?;
+        }
+        Ok(())
+    }
+
+    /// The sets of groups completed by `data_frame` or timed out.
+    fn group_sets(&mut self, data_frame: &BinaryDataFrame) -> Result<Vec<(i32, GroupValues)>, String> {
+        let now = self.now()?;
+        let mut sets = match self.topics.get(&data_frame.topic_id) {
+            Some(topic) => self.groups.observe(&topic.name, data_frame.timestamp, &data_frame.data, now),
+            None => Vec::new(),
+        };
+        sets.extend(self.groups.poll(now));
+        Ok(sets)
+    }
+
+    fn flush_pending<S: ConnectionSink>(&mut self, sink: &mut S, topic_id: i32) -> Result<(), S::Error> {
+        let now = self.now()?;
+        self.pending.expire(now);
+        for (_, data_frame) in self.pending.take(topic_id) {
+            self.receive(sink, data_frame)?;
+        }
+        Ok(())
+    }
+
+    fn publication_id(&self, name: &str) -> Result<i32, String> {
+        self.publications
+            .iter()
+            .find(|(_, topic)| &*topic.name == name)
+            .map(|(id, _)| *id)
+            .ok_or_else(|| format!("{:?} is not published", name))
+    }
+
+    /// `data` as the type the topic `topic_id` is published as, for values that are all checked
+    /// before any is sent. Numbers convert when nothing is lost, as JS has no separate ints.
+    fn published_type(&self, topic_id: i32, data: Nt4Data) -> Result<Nt4Data, String> {
+        let Some(topic) = self.publications.get(&topic_id) else {
+            return Err(format!("topic id {} is not published", topic_id));
+        };
+        if data.get_type_id() == topic.ty {
+            return Ok(data);
+        }
+        let got = data.get_name();
+        match data.convert_to(topic.ty) {
+            Ok((data, false)) => Ok(data),
+            _ => Err(format!("{} is published as {}, a {} value does not fit it", topic.name, topic.ty.get_name(), got)),
+        }
+    }
+
+    /// The server timestamp for values sent by the user with `timestamp` given, or `None` to stamp them live.
+    fn outgoing_timestamp(&self, timestamp: Option<i64>) -> Result<Option<i64>, String> {
+        let timestamp = match (self.timestamp_mode, timestamp) {
+            (TimestampMode::Live, None) => Ok(None),
+            (TimestampMode::Live, Some(_)) => Err("timestamps can only be given in passthrough or fixed_offset mode".to_string()),
+            (_, None) => Err("a timestamp is required in passthrough and fixed_offset mode".to_string()),
+            (TimestampMode::Passthrough, Some(t)) => Ok(Some(t)),
+            (TimestampMode::FixedOffset, Some(t)) => t
+                .checked_add(self.timestamp_offset)
+                .map(Some)
+                .ok_or_else(|| format!("timestamp {} overflows with offset {}", t, self.timestamp_offset)),
+        }?;
+        match timestamp {
+            Some(t) if t < 0 => Err(format!("timestamp {} is negative, NT4 timestamps are unsigned", t)),
+            timestamp => Ok(timestamp),
+        }
+    }
+
+    /// Sends `values` in one message, stamped with `timestamp` or live if it is `None`. With `split`
+    /// they may go in several to stay under [`ConnectionSink::max_frame_bytes`].
+    fn send_frames<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        values: Vec<(i32, Nt4Data)>,
+        timestamp: Option<i64>,
+        split: bool,
+    ) -> Result<(), S::Error> {
+        let now = self.now()?;
+        // Before the first timesync the offset may be far enough off to go below zero.
+        let timestamp = timestamp.unwrap_or((now + self.offs).max(0));
+        let mut topics = Vec::with_capacity(values.len());
+        for (topic_id, _) in values.iter() {
+            let Some(topic) = self.publications.get(topic_id) else {
+                return Err(UnknownUid { kind: UidKind::Publication, uid: *topic_id }.into());
+            };
+            topics.push((*topic_id, topic));
+        }
+        // Checked together, so a topic sent twice in one message is checked against its earlier value.
+        let named = topics.iter().zip(values).map(|((_, topic), (_, data))| (&*topic.name, data));
+        let checked = self.validators.check_all(named, now)?;
+        let mut frames = Vec::with_capacity(checked.len());
+        for ((topic_id, topic), data) in topics.into_iter().zip(checked) {
+            let data = self.coerce_outgoing(&topic.name, topic.ty, data)?;
+            frames.push(BinaryDataFrame { data, timestamp, topic_id });
+        }
+        self.send_values(sink, &frames, split)?;
+        for frame in frames.iter() {
+            if let Some(topic) = self.publications.get(&frame.topic_id) {
+                self.validators.record(&topic.name, &frame.data, now);
+            }
+        }
+        if self.own_topics.local_echo {
+            self.local_echo(sink, frames)?;
+        }
+        Ok(())
+    }
+
+    /// Caches the values just sent to topics the server announced with our pubuid, and delivers
+    /// them if we are subscribed, see [`Self::set_local_echo`].
+    fn local_echo<S: ConnectionSink>(&mut self, sink: &mut S, frames: Vec<BinaryDataFrame>) -> Result<(), S::Error> {
+        for frame in frames {
+            let Some(id) = self.own_topics.id_of(frame.topic_id) else {
+                continue;
+            };
+            let Some(name) = self.topics.get(&id).map(|x| x.name.clone()) else {
+                continue;
+            };
+            // Self tests time the server sending the value back.
+            if self_test::is_test_topic(&name) {
+                continue;
+            }
+            let frame = BinaryDataFrame { topic_id: id, ..frame };
+            let subscribed = self.subscribed_to(&name);
+            let diff = if self.pause.is_some() || !subscribed { None } else { self.diff(&frame) };
+            self.observe(sink, &frame)?;
+            self.own_topics.echoed(id);
+            if !subscribed {
+                continue;
+            }
+            if let Some(pause) = &mut self.pause {
+                pause.valued(id);
+                if let Some(state) = self.diff_modes.get_mut(&*name) {
+                    state.full_requested = true;
+                }
+            } else {
+                sink.event(Self::value_event(frame, diff, false, true))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a subscription asks for the values of `name`.
+    fn subscribed_to(&self, name: &str) -> bool {
+        self.subscriptions.values().filter(|params| !params.options.topicsonly).any(|params| {
+            let prefix = params.options.prefix;
+            params.topics.iter().any(|x| if prefix { name.starts_with(x.as_str()) } else { name == x })
+        })
+    }
+
+    /// `data` for the published topic `name`, converted to the type the server announced it as if
+    /// [`Self::set_type_coercions`] covers it, or else the type it was published as.
+    fn coerce_outgoing(&self, name: &str, published: Nt4TypeId, data: Nt4Data) -> Result<Nt4Data, String> {
+        if self.coercions.expected(name).is_none() {
+            return Ok(data);
+        }
+        let announced = self.topic_ids.get(name).and_then(|id| self.topics.get(id));
+        let ty = announced.map_or(published, |topic| topic.ty);
+        if data.get_type_id() == ty {
+            return Ok(data);
+        }
+        data.convert_to(ty)
+            .map(|(data, _)| data)
+            .map_err(|data| format!("{} is a {} topic, a {} value cannot be converted to it", name, ty.get_name(), data.get_name()))
+    }
+
+    fn unknown_release<E: From<UnknownUid>>(&self, kind: UidKind, uid: i32) -> Result<(), E> {
+        if self.idempotent {
+            Ok(())
+        } else {
+            Err(UnknownUid { kind, uid }.into())
+        }
+    }
+
+    fn send_boolean<S: ConnectionSink>(&mut self, sink: &mut S, pubuid: i32, value: bool) -> Result<(), S::Error> {
+        self.send_frames(sink, vec![(pubuid, Nt4Data::Boolean(value))], None, false)?;
+        let timestamp = self.now()? + self.offs;
+        self.booleans_sent.insert(pubuid, (timestamp, value));
+        Ok(())
+    }
+
+    /// The most recent of the last boolean received for `name` and the last one we sent to it.
+    fn boolean_state(&self, name: &str) -> bool {
+        let received = self
+            .topics
+            .iter()
+            .filter(|(_, topic)| &*topic.name == name)
+            .filter_map(|(id, _)| self.cache.get(id))
+            .filter_map(|(t, data)| data.as_boolean().map(|x| (*t, *x)));
+        let sent = self
+            .publications
+            .iter()
+            .filter(|(_, topic)| &*topic.name == name)
+            .filter_map(|(id, _)| self.booleans_sent.get(id).copied());
+        received.chain(sent).max_by_key(|(t, _)| *t).map(|(_, x)| x).unwrap_or(false)
+    }
+
+    fn set_momentary<S: ConnectionSink>(&mut self, sink: &mut S, id: i32, held: bool) -> Result<(), S::Error> {
+        self.check_open()?;
+        if !self.momentaries.contains_key(&id) {
+            return Err(format!("{} is not a momentary", id).into());
+        }
+        self.send_boolean(sink, id, held)?;
+        self.momentaries.insert(id, held);
+        Ok(())
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = (&i32, &Topic)> {
+        self.topics.iter()
+    }
+
+    /// Every announced topic, sorted by name.
+    pub fn sorted_topics(&self) -> Vec<&Topic> {
+        let mut topics: Vec<&Topic> = self.topics.values().collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        topics
+    }
+
+    /// Fails with [`UnknownUid`] and sends nothing if `id` is not an active subscription,
+    /// unless [`ConnectionCore::set_idempotent`].
+    pub fn unsubscribe<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        self.check_open()?;
+        if !self.subscriptions.contains_key(&id) {
+            return self.unknown_release(UidKind::Subscription, id);
+        }
+        self.send_text(sink, &ClientToServerTextDataFrame::Unsubscribe(UnsubscribeParams { subuid: id }))?;
+        self.subscriptions.remove(&id);
+        self.subscription_profiles.untrack(id);
+        self.groups.remove(id);
+        self.client_metadata_changed(sink);
+        Ok(())
+    }
+
+    pub fn subscribe<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        path: &str,
+        options: SubscriptionOptions,
+    ) -> Result<i32, S::Error> {
+        self.subscribe_topics(sink, vec![path.to_string()], options)
+    }
+
+    fn subscribe_topics<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        topics: Vec<String>,
+        options: SubscriptionOptions,
+    ) -> Result<i32, S::Error> {
+        self.check_open()?;
+        for topic in &topics {
+            self.check_manifest(sink, topic, |manifest| manifest.check_subscribe(topic, options.prefix))?;
+        }
+        let id = self.new_uid();
+        let params = SubscribeParams { topics, subuid: id, options };
+        self.send_subscription(sink, &params)?;
+        self.warn_if_filtered(sink, &params)?;
+        self.subscriptions.insert(id, params);
+        self.client_metadata_changed(sink);
+        Ok(id)
+    }
+
+    /// Subscribes to every topic in `names` and delivers their values together as
+    /// [`ConnectionEvent::Group`]s, paired as described on [`Groups`]. Values are also delivered
+    /// one by one as usual. Timeouts are checked whenever a value arrives and on every timesync.
+    /// Returns the subuid, which is also the id of the group.
+    pub fn subscribe_group<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        names: Vec<String>,
+        window_us: i64,
+        timeout_us: i64,
+    ) -> Result<i32, S::Error> {
+        self.check_open()?;
+        if names.is_empty() {
+            return Err("a group needs at least one topic".to_string().into());
+        }
+        let options = SubscriptionOptions {
+            periodic: std::time::Duration::from_millis(100),
+            all: true,
+            topicsonly: false,
+            prefix: false,
+        };
+        let id = self.subscribe_topics(sink, names.clone(), options)?;
+        let now = self.now()?;
+        self.groups.add(id, names, window_us, timeout_us, now);
+        Ok(id)
+    }
+
+    /// How a restart of the server's clock is detected, see [`ConnectionEvent::ServerTimeReset`].
+    pub fn set_server_time_reset_options(&mut self, options: TimeResetOptions) {
+        self.time_reset.options = options;
+        self.time_reset.clear();
+    }
+
+    /// Registers (or replaces) the profile `name` for [`ConnectionCore::subscribe_with_profile`].
+    /// `plot`, `display` and `browse` are registered by default.
+    pub fn register_subscription_profile(&mut self, name: &str, options: SubscriptionOptions) {
+        self.subscription_profiles.register(name, options);
+    }
+
+    pub fn subscribe_with_profile<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        path: &str,
+        profile: &str,
+    ) -> Result<i32, S::Error> {
+        let options = self.subscription_profiles.get(profile)?.clone();
+        let id = self.subscribe(sink, path, options)?;
+        self.subscription_profiles.track(id, profile);
+        Ok(id)
+    }
+
+    /// Replaces the options of profile `name` and of every active subscription made from it, which
+    /// keep their subuids. Returns how many subscriptions were updated.
+    pub fn update_all_with_profile<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        name: &str,
+        options: SubscriptionOptions,
+    ) -> Result<usize, S::Error> {
+        self.check_open()?;
+        self.subscription_profiles.get(name)?;
+        self.subscription_profiles.register(name, options.clone());
+        let subuids = self.subscription_profiles.subscriptions_of(name);
+        for subuid in subuids.iter() {
+            let Some(params) = self.subscriptions.get_mut(subuid) else {
+                continue;
+            };
+            params.options = options.clone();
+            // Subscribing again with the same subuid updates the subscription in place.
+            let params = params.clone();
+            self.send_subscription(sink, &params)?;
+        }
+        self.client_metadata_changed(sink);
+        Ok(subuids.len())
+    }
+
+    /// Every subscription profile as a JSON object keyed by name, for [`ConnectionCore::import_subscription_profiles`].
+    pub fn export_subscription_profiles(&self) -> Result<String, String> {
+        self.subscription_profiles.export()
+    }
+
+    pub fn import_subscription_profiles(&mut self, json: &str) -> Result<(), String> {
+        self.subscription_profiles.import(json)
+    }
+
+    /// As [`ConnectionCore::unsubscribe`], for publications.
+    pub fn unpublish<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        self.check_open()?;
+        if !self.publications.contains_key(&id) {
+            return self.unknown_release(UidKind::Publication, id);
+        }
+        self.send_text(sink, &ClientToServerTextDataFrame::Unpublish(UnpublishParams { pubuid: id }))?;
+        self.publications.remove(&id);
+        self.own_topics.unpublished(id);
+        self.momentaries.remove(&id);
+        self.log_channels.remove(&id);
+        self.booleans_sent.remove(&id);
+        self.client_metadata_changed(sink);
+        Ok(())
+    }
+
+    pub fn publish<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        name: &str,
+        ty: Nt4TypeId,
+        properties: Properties,
+    ) -> Result<i32, S::Error> {
+        self.check_open()?;
+        self.check_properties_size(name, &properties)?;
+        self.check_manifest(sink, name, |manifest| manifest.check_publish(name, ty))?;
+        let id = self.new_uid();
+        self.send_text(
+            sink,
+            &ClientToServerTextDataFrame::Publish(PublishParams {
+                name: name.to_string(),
+                properties,
+                pubuid: id,
+                ty,
+            }),
+        )?;
+        self.publications.insert(id, Topic { name: name.into(), ty });
+        self.client_metadata_changed(sink);
+        Ok(id)
+    }
+
+    /// Warns about or fails on what `check` finds wrong with using `name`, as the manifest asks.
+    /// Server meta topics and self test topics are never in a manifest, so they are not checked.
+    fn check_manifest<S: ConnectionSink>(
+        &self,
+        sink: &mut S,
+        name: &str,
+        check: impl FnOnce(&Manifest) -> Option<String>,
+    ) -> Result<(), S::Error> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        if name.starts_with('$') || self_test::is_test_topic(name) {
+            return Ok(());
+        }
+        match (check(manifest), manifest.strictness) {
+            (None, _) => Ok(()),
+            (Some(problem), ManifestStrictness::Warn) => sink.event(ConnectionEvent::Warning(problem)),
+            (Some(problem), ManifestStrictness::Error) => Err(problem.into()),
+        }
+    }
+
+    /// Checks publishes and subscribes from now on against `topics`: using a topic missing from
+    /// them, or publishing one with another type, warns or fails as `strictness` says. Replaces
+    /// the previous manifest.
+    pub fn load_manifest(&mut self, topics: Vec<ManifestTopic>, strictness: ManifestStrictness) -> Result<(), String> {
+        self.manifest = Some(Manifest::new(topics, strictness)?);
+        Ok(())
+    }
+
+    /// The topics of the manifest, sorted by name.
+    pub fn manifest_topics(&self) -> Vec<ManifestTopic> {
+        self.manifest.iter().flat_map(|manifest| manifest.topics().cloned()).collect()
+    }
+
+    /// How the announced topics differ from the manifest, by name. Server meta, self test and
+    /// [hidden](widgets::is_hidden) topics are not expected in it.
+    pub fn manifest_drift(&self) -> Vec<ManifestDrift> {
+        let Some(manifest) = &self.manifest else {
+            return Vec::new();
+        };
+        let listed = |name: &str| !name.starts_with('$') && !self_test::is_test_topic(name) && !widgets::is_hidden(name);
+        manifest.drift(self.topics.values(), listed)
+    }
+
+    pub fn set_properties<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        name: &str,
+        update: PartialProperties,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        self.check_properties_size(name, &update)?;
+        // Applied locally right away, the server's update that follows then changes nothing
+        // unless someone else changed the same keys in between.
+        if let Some(properties) = self.topic_ids.get(name).and_then(|id| self.properties.get_mut(id)) {
+            update.apply_to(properties);
+        }
+        self.send_text(
+            sink,
+            &ClientToServerTextDataFrame::SetProperties(SetPropertiesParams {
+                name: name.to_string(),
+                update,
+            }),
+        )
+    }
+
+    fn check_properties_size(&self, name: &str, properties: &impl serde::Serialize) -> Result<(), String> {
+        let size = serde_json::to_vec(properties).map_err(|x| format!("{:?}", x))?.len();
+        if size > self.max_properties_bytes {
+            return Err(format!(
+                "properties of {:?} are {} bytes, over the limit of {}",
+                name, size, self.max_properties_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    /// Publishes and property updates whose properties encode to more than `bytes` of JSON fail
+    /// instead of being sent. [`DEFAULT_MAX_PROPERTIES_BYTES`] by default.
+    pub fn set_max_properties_bytes(&mut self, bytes: usize) {
+        self.max_properties_bytes = bytes;
+    }
+
+    /// Sets (or with `null` deletes) the single property `key` of `name`, leaving every other
+    /// property as the server has it, rather than as it was when last read.
+    pub fn update_topic_property<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        name: &str,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<(), S::Error> {
+        let update = PartialProperties::single(key, value)?;
+        self.set_properties(sink, name, update)
+    }
+
+    /// The properties of `name` as last announced or updated, if it is announced.
+    pub fn topic_properties(&self, name: &str) -> Option<&Properties> {
+        self.topic_ids.get(name).and_then(|id| self.properties.get(id))
+    }
+
+    pub fn timesync<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        self.check_self_test(sink)?;
+        self.check_persistent(sink)?;
+        self.check_fetches(sink)?;
+        self.check_hydration(sink)?;
+        let now = self.now()?;
+        self.send_values(sink, &[BinaryDataFrame::timesync(now)], false)?;
+        self.flush_logs(sink)?;
+        for (id, values) in self.groups.poll(now) {
+            sink.event(ConnectionEvent::Group { id, values })?;
+        }
+        Ok(())
+    }
+
+    /// Checks run whenever a frame arrives, before it is processed.
+    fn frame_arrived<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        // Best-effort, a debounced metadata update must not fail frame processing.
+        let _ = self.flush_client_metadata(sink, false);
+        self.sweep_if_due()?;
+        self.check_self_test(sink)?;
+        self.check_persistent(sink)?;
+        self.check_fetches(sink)?;
+        self.check_hydration(sink)
+    }
+
+    /// Processes every value of a binary message, after whatever is left of the messages passed to
+    /// [`ConnectionCore::on_binary_chunked`], so that values are still delivered in order. A panic
+    /// is returned as an [`InternalError`], see [`ConnectionCore::quarantined`].
+    pub fn on_binary<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &[u8]) -> Result<(), S::Error> {
+        self.received(data_frame.len());
+        self.guarded(sink, |core, sink| core.process_binary(sink, data_frame), || {
+            QuarantinedInput::Binary(ByteBuf::from(data_frame))
+        })
+    }
+
+    fn process_binary<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &[u8]) -> Result<(), S::Error> {
+        self.frame_arrived(sink)?;
+        self.process_chunked(sink, None, usize::MAX)?;
+        let mut position = 0;
+        while position < data_frame.len() {
+            let frame = chunked::next_frame(data_frame, &mut position).map_err(|x| self.decode_error(x))?;
+            self.on_value(sink, frame)?;
+        }
+        Ok(())
+    }
+
+    /// Queues a binary message to be processed at most `budget` values at a time, starting now, so
+    /// a huge one does not block for long. Returns the handle to pass to
+    /// [`ConnectionCore::continue_processing`]. A message that fails to decode is dropped from
+    /// the failing value on, with the error returned by the call that got to it.
+    pub fn on_binary_chunked<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        data_frame: Vec<u8>,
+        budget: usize,
+    ) -> Result<u32, S::Error> {
+        self.check_open()?;
+        self.received(data_frame.len());
+        let handle = self.chunked.push(data_frame);
+        self.continue_processing(sink, handle, budget)?;
+        Ok(handle)
+    }
+
+    /// Processes at most `budget` more values, stopping early once the message of `handle` is done,
+    /// and returns whether it is. Messages queued before it are finished first.
+    pub fn continue_processing<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        handle: u32,
+        budget: usize,
+    ) -> Result<bool, S::Error> {
+        self.frame_arrived(sink)?;
+        self.process_chunked(sink, Some(handle), budget)?;
+        Ok(!self.chunked.is_pending(handle))
+    }
+
+    /// Processes at most `budget` queued values, oldest first, until the message of `handle` is done.
+    fn process_chunked<S: ConnectionSink>(&mut self, sink: &mut S, handle: Option<u32>, budget: usize) -> Result<(), S::Error> {
+        for _ in 0..budget {
+            if handle.is_some_and(|x| !self.chunked.is_pending(x)) {
+                break;
+            }
+            let Some(frame) = self.chunked.next_frame() else {
+                break;
+            };
+            let frame = frame.map_err(|x| self.decode_error(x))?;
+            self.on_value(sink, frame)?;
+        }
+        Ok(())
+    }
+
+    /// A value received in either [`ValueEncoding`].
+    fn on_value<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: BinaryDataFrame) -> Result<(), S::Error> {
+        match reserved_ids::classify_topic_id(data_frame.topic_id) {
+            TopicIdClass::Normal => metrics::bump(&self.counters.values_rx, 1),
+            TopicIdClass::Timesync => {
+                let Some(local_time) = data_frame.data.as_int() else {
+                    return Err(format!("Invalid timesync dataframe: {:?}", data_frame).into());
+                };
+                let now = self.now()?;
+                self.offs = timesync_offset(*local_time, data_frame.timestamp, now)?;
+                self.rtt = Some(now - local_time);
+                self.ready = true;
+                if let Some(hydration) = &mut self.hydration {
+                    hydration.ready(now);
+                }
+                sink.event(ConnectionEvent::Ready)?;
+                self.flush_logs(sink)?;
+                // The response is stamped with the server clock, which may decide watches waiting on it
+                // and set off alerts.
+                for result in self.watches.advance(data_frame.timestamp) {
+                    sink.event(ConnectionEvent::Watch(result))?;
+                }
+                for event in self.alerts.advance(data_frame.timestamp) {
+                    sink.event(ConnectionEvent::Alert(event))?;
+                }
+                return Ok(());
+            },
+            TopicIdClass::ReservedUnknown(id) => {
+                if !self.reserved_warned.insert(id) {
+                    return Ok(());
+                }
+                return sink.event(ConnectionEvent::Warning(format!(
+                    "ignoring values for reserved topic id {}",
+                    id
+                )));
+            },
+        }
+        if self.topics.contains_key(&data_frame.topic_id) {
+            self.receive(sink, data_frame)
+        } else if self.filtered.contains_key(&data_frame.topic_id) || self.over_limit.contains(&data_frame.topic_id) {
+            Ok(())
+        } else {
+            let now = self.now()?;
+            self.pending.push(now, data_frame);
+            Ok(())
+        }
+    }
+
+    /// A panic is returned as an [`InternalError`], see [`ConnectionCore::quarantined`].
+    pub fn on_text<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &str) -> Result<(), S::Error> {
+        self.received(data_frame.len());
+        self.guarded(sink, |core, sink| core.process_text(sink, data_frame), || {
+            QuarantinedInput::Text(data_frame.to_string())
+        })
+    }
+
+    fn received(&self, len: usize) {
+        metrics::bump(&self.counters.frames_rx, 1);
+        metrics::bump(&self.counters.bytes_rx, len);
+    }
+
+    fn decode_error(&self, error: String) -> String {
+        metrics::bump(&self.counters.decode_errors, 1);
+        error
+    }
+
+    /// Runs `process`, turning a panic into an [`InternalError`] and quarantining the frame. The
+    /// connection stays usable, though whatever `process` was partway through is left as it was.
+    /// Panics only unwind where the target supports it: in wasm32-unknown-unknown they abort.
+    fn guarded<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        process: impl FnOnce(&mut Self, &mut S) -> Result<(), S::Error>,
+        input: impl FnOnce() -> QuarantinedInput,
+    ) -> Result<(), S::Error> {
+        let payload = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| process(self, sink))) {
+            Ok(result) => return result,
+            Err(payload) => payload,
+        };
+        let message = quarantine::panic_message(&*payload);
+        metrics::bump(&self.counters.internal_errors, 1);
+        self.quarantine.push(message.clone(), input());
+        Err(InternalError { message }.into())
+    }
+
+    /// The last frames whose processing panicked, oldest first, with the panic message.
+    pub fn quarantined(&self) -> Vec<QuarantinedFrame> {
+        self.quarantine.frames().cloned().collect()
+    }
+
+    pub fn clear_quarantine(&mut self) {
+        self.quarantine.clear();
+    }
+
+    fn process_text<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &str) -> Result<(), S::Error> {
+        self.frame_arrived(sink)?;
+        let data_frame: ServerToClientTextDataFrame =
+            serde_json::from_str(data_frame).map_err(|x| self.decode_error(format!("{:?}", x)))?;
+        match data_frame {
+            ServerToClientTextDataFrame::Announce(ann) => self.announce(sink, ann),
+            ServerToClientTextDataFrame::Unannounce(unann) => self.unannounce(sink, unann),
+            ServerToClientTextDataFrame::Properties(props) => {
+                let Some(&id) = self.topic_ids.get(props.name.as_str()) else {
+                    return Ok(());
+                };
+                let Some(properties) = self.properties.get_mut(&id) else {
+                    return Ok(());
+                };
+                let old = properties.clone();
+                for (key, value) in props.update {
+                    properties.apply(&key, value)?;
+                }
+                if *properties == old || self_test::is_test_topic(&props.name) {
+                    return Ok(());
+                }
+                let properties = properties.clone();
+                self.sync.announced(id);
+                self.announce_event(sink, ConnectionEvent::PropertiesChanged { id, name: props.name, properties })
+            },
+            ServerToClientTextDataFrame::Values(frames) => {
+                for frame in frames {
+                    self.on_value(sink, frame)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn announce<S: ConnectionSink>(&mut self, sink: &mut S, ann: AnnounceParams) -> Result<(), S::Error> {
+        self.persistent_announced(sink, &ann)?;
+        if self.topic_filter.as_ref().is_some_and(|filter| !filter.allows(&ann.name)) {
+            if self.filtered.insert(ann.id, ann.name.clone()).is_none() {
+                self.filtered_count += 1;
+            }
+            self.pending.take(ann.id);
+            return self.remove_topic(sink, ann.id);
+        }
+        self.filtered.remove(&ann.id);
+        if self.over_topic_limit(&ann) {
+            return self.drop_over_limit(sink, ann.id);
+        }
+        self.over_limit.remove(&ann.id);
+        let name = self.intern(ann.name);
+        let duplicate = self.topic_ids.get(&name) == Some(&ann.id);
+        if !duplicate {
+            // A new id for a known name, or a new name for a known id, replaces the old topic.
+            if let Some(old_id) = self.topic_ids.get(&name).copied() {
+                self.remove_topic(sink, old_id)?;
+            }
+            if self.topics.contains_key(&ann.id) {
+                self.remove_topic(sink, ann.id)?;
+            }
+        }
+        self.topic_ids.insert(name.clone(), ann.id);
+        self.topics.insert(ann.id, Topic { name: name.clone(), ty: ann.ty });
+        self.sync.announced(ann.id);
+        if let Some(pubuid) = ann.pubuid.filter(|x| self.publications.get(x).is_some_and(|x| x.name == name)) {
+            self.own_topics.correlate(pubuid, ann.id);
+        }
+        self.widgets.announce(&name, ann.ty, self.topics.values())?;
+        if let Some(hydration) = &mut self.hydration {
+            hydration.confirm(ann.id);
+        }
+        let old_properties = self.properties.insert(ann.id, ann.properties.clone());
+        let mut type_changed = false;
+        if let Some(old) = self.known_types.insert(name.clone(), ann.ty) {
+            if old != ann.ty {
+                type_changed = true;
+                self.announce_event(sink, ConnectionEvent::TypeChanged { name: name.to_string(), old, new: ann.ty })?;
+            }
+        }
+        self.evict_known_types();
+        if !duplicate || type_changed {
+            self.cache.remove(&ann.id);
+            if let Some(hydration) = &mut self.hydration {
+                hydration.refresh(ann.id);
+            }
+        }
+        if self_test::is_test_topic(&name) {
+            self.self_test_announced(sink, ann.id, &name, ann.properties.retained)?;
+            return self.flush_pending(sink, ann.id);
+        }
+        if duplicate {
+            if old_properties.as_ref() != Some(&ann.properties) {
+                self.announce_event(
+                    sink,
+                    ConnectionEvent::PropertiesChanged {
+                        id: ann.id,
+                        name: name.to_string(),
+                        properties: ann.properties,
+                    },
+                )?;
+            }
+            if !self.fire_duplicate_announces {
+                return self.flush_pending(sink, ann.id);
+            }
+        }
+        self.announce_event(sink, ConnectionEvent::Announce { id: ann.id, topic: Topic { name, ty: ann.ty } })?;
+        self.flush_pending(sink, ann.id)
+    }
+
+    fn unannounce<S: ConnectionSink>(&mut self, sink: &mut S, unann: UnannounceParams) -> Result<(), S::Error> {
+        if self.filtered.remove(&unann.id).is_some() || self.over_limit.remove(&unann.id) {
+            return Ok(());
+        }
+        self.publishers.remove(&unann.name);
+        if self.topics.contains_key(&unann.id) {
+            return self.remove_topic(sink, unann.id);
+        }
+        self.cache.remove(&unann.id);
+        self.own_topics.unannounced(unann.id);
+        self.properties.remove(&unann.id);
+        if self_test::is_test_topic(&unann.name) {
+            return Ok(());
+        }
+        self.announce_event(sink, ConnectionEvent::Unannounce { id: unann.id, name: unann.name })
+    }
+
+    pub fn on_disconnect<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        if let Some(test) = &self.self_test {
+            let failure = test.fail("disconnected");
+            self.finish_self_test(sink, Err(failure))?;
+        }
+        self.abort_persistent(sink, "disconnected")?;
+        for id in self.fetches.take_all() {
+            self.finish_fetch(sink, id, Err("disconnected".to_string()))?;
+        }
+        // Best-effort, there may be no socket left to send on.
+        let _ = self.release_all_momentaries(sink);
+        metrics::bump(&self.counters.disconnects, 1);
+        self.ready = false;
+        self.topics.clear();
+        self.topic_ids.clear();
+        self.widgets.clear();
+        self.filtered.clear();
+        self.over_limit.clear();
+        self.cache.clear();
+        self.own_topics.clear();
+        self.sync.reset();
+        self.properties.clear();
+        self.pending.clear();
+        self.publishers.clear();
+        self.time_reset.clear();
+        self.groups.clear_values();
+        self.hydration = None;
+        self.chunked.clear();
+        self.watches.reset();
+        let server_time = self.now()? + self.offs;
+        let cleared = self.alerts.reset(server_time);
+        for channel in self.log_channels.values_mut() {
+            channel.republish = true;
+        }
+        sink.event(ConnectionEvent::Unready)?;
+        for event in cleared {
+            sink.event(ConnectionEvent::Alert(event))?;
+        }
+        Ok(())
+    }
+
+    /// `timestamp` is required or forbidden depending on the [`TimestampMode`].
+    pub fn send_data<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        topic_id: i32,
+        data: Nt4Data,
+        timestamp: Option<i64>,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        let timestamp = self.outgoing_timestamp(timestamp)?;
+        self.send_frames(sink, vec![(topic_id, data)], timestamp, false)
+    }
+
+    /// Sends every entry in one binary message with a shared timestamp, or nothing if any entry is invalid
+    /// or the message would be over [`ConnectionSink::max_frame_bytes`].
+    pub fn send_atomic<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        entries: Vec<AtomicEntry>,
+        timestamp: Option<i64>,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        let timestamp = self.outgoing_timestamp(timestamp)?;
+        let mut frames = Vec::with_capacity(entries.len());
+        for (i, entry) in entries.into_iter().enumerate() {
+            let data = self.published_type(entry.topic_id, entry.data).map_err(|x| format!("entry {}: {}", i, x))?;
+            frames.push((entry.topic_id, data));
+        }
+        self.send_frames(sink, frames, timestamp, false)
+    }
+
+    /// Sends each `(key, value)` to the published topic `<prefix>/<key>`, as in [`ConnectionCore::send_atomic`].
+    pub fn send_struct_of_values<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        prefix: &str,
+        values: Vec<(String, Nt4Data)>,
+        timestamp: Option<i64>,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        let timestamp = self.outgoing_timestamp(timestamp)?;
+        let prefix = prefix.trim_end_matches('/');
+        let mut frames = Vec::with_capacity(values.len());
+        for (key, data) in values {
+            let topic_id = self.publication_id(&format!("{}/{}", prefix, key))?;
+            frames.push((topic_id, self.published_type(topic_id, data)?));
+        }
+        self.send_frames(sink, frames, timestamp, false)
+    }
+
+    /// Unsubscribes and unpublishes everything, ignoring send failures, then forgets all state.
+    pub fn close<S: ConnectionSink>(&mut self, sink: &mut S) {
+        if self.closed {
+            return;
+        }
+        if let Some(test) = &self.self_test {
+            let failure = test.fail("connection closed");
+            let _ = self.finish_self_test(sink, Err(failure));
+        }
+        let _ = self.abort_persistent(sink, "connection closed");
+        for id in self.fetches.take_all() {
+            let _ = self.finish_fetch(sink, id, Err("connection closed".to_string()));
+        }
+        self.client_metadata = None;
+        let _ = self.release_all_momentaries(sink);
+        let subscriptions = self
+            .subscriptions
+            .keys()
+            .map(|&subuid| ClientToServerTextDataFrame::Unsubscribe(UnsubscribeParams { subuid }));
+        let publications = self
+            .publications
+            .keys()
+            .map(|&pubuid| ClientToServerTextDataFrame::Unpublish(UnpublishParams { pubuid }));
+        for data in subscriptions.chain(publications) {
+            let _ = self.send_text(sink, &data);
+        }
+        self.closed = true;
+        self.ready = false;
+        self.topics.clear();
+        self.topic_ids.clear();
+        self.widgets.clear();
+        self.filtered.clear();
+        self.over_limit.clear();
+        self.known_types.clear();
+        self.pending.clear();
+        self.publications.clear();
+        self.subscriptions.clear();
+        self.subscription_profiles.clear_subscriptions();
+        self.always_fast.clear();
+        self.publishers.clear();
+        self.cache.clear();
+        self.own_topics.clear();
+        self.sync.reset();
+        self.properties.clear();
+        self.trajectories.clear_all();
+        self.interpolations.clear();
+        self.groups.clear();
+        self.momentaries.clear();
+        self.log_channels.clear();
+        self.booleans_sent.clear();
+        self.diff_modes.clear();
+        self.pause = None;
+        self.hydration = None;
+        self.chunked.clear();
+        self.type_mismatches.clear();
+        self.watches.clear();
+        self.alerts.clear();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn set_topic_validator(&mut self, name: &str, validator: Validator) {
+        self.validators.set(name, validator);
+    }
+
+    pub fn remove_topic_validator(&mut self, name: &str) {
+        self.validators.remove(name);
+    }
+
+    pub fn export_validators(&self) -> Result<String, String> {
+        self.validators.export()
+    }
+
+    pub fn import_validators(&mut self, json: &str) -> Result<(), String> {
+        self.validators.import(json)
+    }
+
+    /// Publishes `name` as a non-retained boolean that is only `true` while held.
+    pub fn momentary<S: ConnectionSink>(&mut self, sink: &mut S, name: &str) -> Result<i32, S::Error> {
+        let id = self.publish(sink, name, Nt4TypeId::Boolean, Properties::default())?;
+        self.momentaries.insert(id, false);
+        Ok(id)
+    }
+
+    pub fn press<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        self.set_momentary(sink, id, true)
+    }
+
+    pub fn release<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        self.set_momentary(sink, id, false)
+    }
+
+    /// Publishes `name` as a non-retained string for messages sent with [`Self::log`] within the
+    /// limits of `options`.
+    pub fn log_channel<S: ConnectionSink>(&mut self, sink: &mut S, name: &str, options: LogOptions) -> Result<i32, S::Error> {
+        options.check()?;
+        let id = self.publish(sink, name, Nt4TypeId::String, Properties::default())?;
+        let now = self.now()?;
+        self.log_channels.insert(id, LogChannel::new(options, now));
+        Ok(id)
+    }
+
+    /// Queues `message` on the log channel `id`, prefixed with the server time as `[hh:mm:ss.mmm] `,
+    /// and sends what the limits allow. Messages over the queue limit are dropped and counted in
+    /// a single `… N messages dropped` line.
+    pub fn log<S: ConnectionSink>(&mut self, sink: &mut S, id: i32, message: &str) -> Result<(), S::Error> {
+        self.check_open()?;
+        let server_time = self.now()? + self.offs;
+        let Some(channel) = self.log_channels.get_mut(&id) else {
+            return Err(format!("{} is not a log channel", id).into());
+        };
+        channel.push(server_time, message);
+        self.flush_log_channel(sink, id)
+    }
+
+    /// Sends what the limits of every log channel allow, also done by [`Self::timesync`]. Nothing
+    /// is sent until the connection is ready, and queued messages are kept until they are.
+    pub fn flush_logs<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        let ids: Vec<i32> = self.log_channels.keys().copied().collect();
+        for id in ids {
+            self.flush_log_channel(sink, id)?;
+        }
+        Ok(())
+    }
+
+    fn flush_log_channel<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        if !self.ready {
+            return Ok(());
+        }
+        let now = self.now()?;
+        let (Some(channel), Some(topic)) = (self.log_channels.get_mut(&id), self.publications.get(&id)) else {
+            return Ok(());
+        };
+        if channel.republish {
+            let publish = ClientToServerTextDataFrame::Publish(PublishParams {
+                name: topic.name.to_string(),
+                properties: Properties::default(),
+                pubuid: id,
+                ty: topic.ty,
+            });
+            self.send_text(sink, &publish)?;
+        }
+        let Some(channel) = self.log_channels.get_mut(&id) else {
+            return Ok(());
+        };
+        channel.republish = false;
+        let lines = channel.sendable(now);
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let values = lines.iter().map(|line| (id, Nt4Data::String(line.clone()))).collect();
+        self.send_frames(sink, values, None, true)?;
+        if let Some(channel) = self.log_channels.get_mut(&id) {
+            channel.sent(&lines);
+        }
+        Ok(())
+    }
+
+    /// Messages of the log channel `id` waiting to be sent, and dropped so far.
+    pub fn log_counts(&self, id: i32) -> Option<(usize, u64)> {
+        self.log_channels.get(&id).map(|channel| (channel.queued(), channel.dropped()))
+    }
+
+    /// Sends `false` for every held momentary, returning the last error if any send failed.
+    pub fn release_all_momentaries<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        let held: Vec<i32> = self.momentaries.iter().filter(|(_, held)| **held).map(|(id, _)| *id).collect();
+        let mut result = Ok(());
+        for id in held {
+            self.momentaries.insert(id, false);
+            if let Err(x) = self.send_boolean(sink, id, false) {
+                result = Err(x);
+            }
+        }
+        result
+    }
+
+    /// Publishes the inverse of the most recent value of the boolean topic `name` and returns it.
+    pub fn toggle<S: ConnectionSink>(&mut self, sink: &mut S, name: &str) -> Result<bool, S::Error> {
+        self.check_open()?;
+        let id = match self.publication_id(name) {
+            Ok(id) => id,
+            Err(_) => self.publish(sink, name, Nt4TypeId::Boolean, Properties { retained: true, ..Default::default() })?,
+        };
+        let value = !self.boolean_state(name);
+        self.send_boolean(sink, id, value)?;
+        Ok(value)
+    }
+
+    /// Delivers array values of `name` as [`ConnectionEvent::Diff`] against the previous value.
+    /// A full snapshot is sent first, after a reconnect or re-announce, when the length or type
+    /// changes, and after [`ConnectionCore::request_full`].
+    pub fn set_diff_mode(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.diff_modes.entry(name.to_string()).or_default();
+        } else {
+            self.diff_modes.remove(name);
+        }
+    }
+
+    pub fn request_full(&mut self, name: &str) -> Result<(), String> {
+        self.diff_modes
+            .get_mut(name)
+            .map(|state| state.full_requested = true)
+            .ok_or_else(|| format!("{:?} is not in diff mode", name))
+    }
+
+    /// Sets the prefix under which [`ConnectionCore::publish_client_metadata`] publishes
+    /// `<prefix>/subscriptions` and `<prefix>/publications`, e.g. `/Dashboards/<client name>`.
+    /// Takes effect the next time it is enabled.
+    pub fn set_client_metadata_prefix(&mut self, prefix: &str) {
+        self.client_metadata_prefix = Some(prefix.trim_end_matches('/').to_string());
+    }
+
+    /// Publishes our subscription and publication tables as msgpack topics, in the shape of the
+    /// server's `$clientsub$` and `$clientpub$` topics, and keeps them up to date.
+    pub fn publish_client_metadata<S: ConnectionSink>(&mut self, sink: &mut S, enabled: bool) -> Result<(), S::Error> {
+        self.check_open()?;
+        if enabled == self.client_metadata.is_some() {
+            return Ok(());
+        }
+        if let Some(metadata) = self.client_metadata.take() {
+            self.unpublish(sink, metadata.subscriptions)?;
+            return self.unpublish(sink, metadata.publications);
+        }
+        let Some(prefix) = self.client_metadata_prefix.clone() else {
+            return Err("set_client_metadata_prefix must be called before publishing client metadata"
+                .to_string()
+                .into());
+        };
+        let properties = || Properties { retained: true, ..Default::default() };
+        let subscriptions = self.publish(sink, &format!("{}/subscriptions", prefix), Nt4TypeId::MsgPack, properties())?;
+        let publications = self.publish(sink, &format!("{}/publications", prefix), Nt4TypeId::MsgPack, properties())?;
+        self.client_metadata = Some(ClientMetadata { subscriptions, publications, dirty: true, last_sent: None });
+        self.flush_client_metadata(sink, true)
+    }
+
+    fn client_metadata_changed<S: ConnectionSink>(&mut self, sink: &mut S) {
+        if let Some(metadata) = &mut self.client_metadata {
+            metadata.dirty = true;
+            // Best-effort, the table change itself already succeeded.
+            let _ = self.flush_client_metadata(sink, false);
+        }
+    }
+
+    /// Sends the client metadata if it changed, at most once per [`CLIENT_METADATA_DEBOUNCE_US`]
+    /// unless `force`d. Changes held back by the debounce go out with a later call.
+    fn flush_client_metadata<S: ConnectionSink>(&mut self, sink: &mut S, force: bool) -> Result<(), S::Error> {
+        let Some(metadata) = &self.client_metadata else {
+            return Ok(());
+        };
+        if !metadata.dirty {
+            return Ok(());
+        }
+        let ids @ [subscriptions_id, publications_id] = [metadata.subscriptions, metadata.publications];
+        let last_sent = metadata.last_sent;
+        let now = self.now()?;
+        if !force && last_sent.is_some_and(|t| now - t < CLIENT_METADATA_DEBOUNCE_US) {
+            return Ok(());
+        }
+        let subscriptions = metadata::encode_subscriptions(self.subscriptions.values())?;
+        let publications =
+            metadata::encode_publications(self.publications.iter().filter(|(id, _)| !ids.contains(id)))?;
+        self.send_frames(
+            sink,
+            vec![
+                (subscriptions_id, Nt4Data::MsgPack(ByteBuf::from(subscriptions))),
+                (publications_id, Nt4Data::MsgPack(ByteBuf::from(publications))),
+            ],
+            None,
+            true,
+        )?;
+        if let Some(metadata) = &mut self.client_metadata {
+            metadata.dirty = false;
+            metadata.last_sent = Some(now);
+        }
+        Ok(())
+    }
+
+    pub fn set_timestamp_mode(&mut self, mode: TimestampMode) {
+        self.timestamp_mode = mode;
+    }
+
+    /// How values, including timesync requests, are sent from now on.
+    pub fn set_value_encoding(&mut self, encoding: ValueEncoding) {
+        self.value_encoding = encoding;
+    }
+
+    /// How values in binary frames are encoded from now on, for servers that only accept some
+    /// encodings. Values are received in any reasonable encoding regardless.
+    pub fn set_wire_options(&mut self, options: WireOptions) {
+        self.wire_options = options;
+    }
+
+    /// The delta added to given timestamps in [`TimestampMode::FixedOffset`], in µs.
+    pub fn set_timestamp_offset(&mut self, offset_us: i64) {
+        self.timestamp_offset = offset_us;
+    }
+
+    /// Drops announced topics that `filter` does not allow, and their values, from now on: they are
+    /// not stored, cached or passed to the sink. Topics already announced that the new filter drops are
+    /// unannounced. If the new filter allows topics the old one dropped, every subscription is briefly
+    /// repeated as `topicsonly` so the server announces them again.
+    pub fn set_topic_filter<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        filter: Option<TopicFilter>,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        self.topic_filter = filter;
+        let filtered = self.filtered.len();
+        self.filtered.retain(|_, name| self.topic_filter.as_ref().is_some_and(|filter| !filter.allows(name)));
+        let readmitted = self.filtered.len() < filtered;
+        if let Some(filter) = &self.topic_filter {
+            let excluded: Vec<(i32, String)> = self
+                .topics
+                .iter()
+                .filter(|(_, topic)| !filter.allows(&topic.name))
+                .map(|(id, topic)| (*id, topic.name.to_string()))
+                .collect();
+            for (id, name) in excluded {
+                self.remove_topic(sink, id)?;
+                self.filtered.insert(id, name);
+                self.filtered_count += 1;
+            }
+        }
+        if readmitted {
+            let mut subscriptions: Vec<SubscribeParams> = self.subscriptions.values().cloned().collect();
+            subscriptions.sort_by_key(|x| x.subuid);
+            for mut params in subscriptions {
+                params.subuid = self.new_uid();
+                params.options.topicsonly = true;
+                self.send_text(sink, &ClientToServerTextDataFrame::Subscribe(params.clone()))?;
+                self.send_text(sink, &ClientToServerTextDataFrame::Unsubscribe(UnsubscribeParams { subuid: params.subuid }))?;
+            }
+        }
+        let mut subscriptions: Vec<SubscribeParams> = self.subscriptions.values().cloned().collect();
+        subscriptions.sort_by_key(|x| x.subuid);
+        for params in subscriptions {
+            self.warn_if_filtered(sink, &params)?;
+        }
+        Ok(())
+    }
+
+    pub fn configure_retention(&mut self, options: RetentionOptions) {
+        self.retention.options = options;
+    }
+
+    pub fn retention_stats(&self) -> &RetentionStats {
+        &self.retention.stats
+    }
+
+    /// Trims trajectory points and evicts idle cached values as configured with
+    /// [`ConnectionCore::configure_retention`], at local time `now`. Looks at no more than
+    /// [`SWEEP_BUDGET`] of them, continuing where the last sweep stopped. Returns how many were
+    /// reclaimed.
+    pub fn sweep(&mut self, now: i64) -> u64 {
+        self.retention.swept(now);
+        let server_now = now + self.offs;
+        let mut budget = SWEEP_BUDGET;
+        let mut reclaimed = 0;
+        if let Some(max_age_s) = self.retention.options.history_max_age_s {
+            let trimmed = self.trajectories.trim_older_than(server_now - (max_age_s * 1e6) as i64, budget);
+            budget -= trimmed;
+            self.retention.stats.history_trimmed += trimmed as u64;
+            reclaimed += trimmed as u64;
+        }
+        let Some(max_idle_s) = self.retention.options.cache_max_idle_s else {
+            return reclaimed;
+        };
+        let oldest = server_now - (max_idle_s * 1e6) as i64;
+        if self.retention.queue.is_empty() {
+            self.retention.queue = self.cache.keys().copied().collect();
+        }
+        for _ in 0..budget {
+            let Some(id) = self.retention.queue.pop() else {
+                break;
+            };
+            let idle = self.cache.get(&id).is_some_and(|(timestamp, _)| *timestamp < oldest);
+            let kept = self.properties.get(&id).is_some_and(|x| x.retained || x.persistent);
+            if idle && !kept {
+                self.cache.remove(&id);
+                self.retention.stats.cache_evicted += 1;
+                reclaimed += 1;
+            }
+        }
+        reclaimed
+    }
+
+    fn sweep_if_due(&mut self) -> Result<(), String> {
+        let now = self.now()?;
+        if self.retention.due(now) {
+            self.sweep(now);
+        }
+        Ok(())
+    }
+
+    /// Number of announces dropped by the topic filter.
+    /// Whether `ann` introduces a topic the announce table has no room for.
+    fn over_topic_limit(&self, ann: &AnnounceParams) -> bool {
+        self.topics.len() >= self.max_topics
+            && !self.topics.contains_key(&ann.id)
+            && !self.topic_ids.contains_key(ann.name.as_str())
+    }
+
+    /// Drops the announce of `id` for being over the topic limit, warning when the limit starts
+    /// dropping announces. At most `max_topics` dropped ids are remembered to drop their values,
+    /// values of the others wait for their announce like those of any unknown id.
+    fn drop_over_limit<S: ConnectionSink>(&mut self, sink: &mut S, id: i32) -> Result<(), S::Error> {
+        self.over_limit_count += 1;
+        self.pending.take(id);
+        let first = self.over_limit.is_empty();
+        if self.over_limit.len() < self.max_topics {
+            self.over_limit.insert(id);
+        }
+        if !first {
+            return Ok(());
+        }
+        sink.event(ConnectionEvent::Warning(format!(
+            "the limit of {} topics is reached, announces of new topics are dropped",
+            self.max_topics
+        )))
+    }
+
+    /// Announces of new topics beyond `max_topics` held topics are counted by
+    /// [`Self::over_limit_topic_count`], warned about and otherwise dropped, as are their values.
+    /// Topics already held stay, even beyond a lowered limit. [`DEFAULT_MAX_TOPICS`] by default.
+    pub fn set_max_topics(&mut self, max_topics: usize) {
+        self.max_topics = max_topics;
+    }
+
+    /// Whether the announce table is full, so announces of new topics are being dropped.
+    pub fn is_topic_limit_reached(&self) -> bool {
+        self.topics.len() >= self.max_topics
+    }
+
+    pub fn over_limit_topic_count(&self) -> u64 {
+        self.over_limit_count
+    }
+
+    /// Every announce, unannounce, properties and type change journaled after `seq`, see
+    /// [`Journal::since`]. Events are journaled even while announces are paused, and are only
+    /// dropped past [`Self::set_journal_capacity`] or by [`Self::clear_journal`].
+    pub fn events_since(&self, seq: u64) -> Vec<JournalEvent> {
+        self.journal.since(seq)
+    }
+
+    /// The number of the last event journaled, 0 before the first.
+    pub fn latest_seq(&self) -> u64 {
+        self.journal.latest_seq()
+    }
+
+    pub fn clear_journal(&mut self) {
+        self.journal.clear();
+    }
+
+    /// The announced topics and cached values that changed since `since_seq`, for
+    /// [`ConnectionCore::apply_delta`] on a follower that shares this connection instead of
+    /// opening its own, e.g. in another browser tab. Only the latest value of each topic is
+    /// included. The delta is full, holding every topic and value, the first time, for 0, and
+    /// whenever the changes since `since_seq` are no longer all known, as after a disconnect.
+    /// Encoded as a version byte and named msgpack, with topics and values sorted by id, so the
+    /// same changes always encode the same.
+    pub fn export_delta(&mut self, since_seq: u64) -> Result<Vec<u8>, String> {
+        self.check_open()?;
+        let server_time_us = self.now()? + self.offs;
+        let topics = self.topics.iter().filter(|(_, topic)| !self_test::is_test_topic(&topic.name));
+        let delta = self.sync.delta(since_seq, topics, &self.properties, &self.cache, self.ready, server_time_us);
+        delta.encode()
+    }
+
+    /// Applies a delta from [`ConnectionCore::export_delta`] as if the leader's server had sent
+    /// it: topics are announced, changed and unannounced, values delivered, and the connection is
+    /// ready when the leader is, on the leader's server clock. Values whose cached timestamp they
+    /// already have are skipped, so overlapping deltas are fine. Returns false for a delta that
+    /// ends at or before [`ConnectionCore::applied_delta_seq`], as when one arrives twice or late.
+    /// Fails without changing anything on a delta that starts after it, as when one went missing.
+    pub fn apply_delta<S: ConnectionSink>(&mut self, sink: &mut S, data: &[u8]) -> Result<bool, S::Error> {
+        let mut applied = false;
+        self.guarded(
+            sink,
+            |core, sink| {
+                applied = core.process_delta(sink, data)?;
+                Ok(())
+            },
+            || QuarantinedInput::Binary(ByteBuf::from(data)),
+        )?;
+        Ok(applied)
+    }
+
+    fn process_delta<S: ConnectionSink>(&mut self, sink: &mut S, data: &[u8]) -> Result<bool, S::Error> {
+        self.check_open()?;
+        let delta = Delta::decode(data)?;
+        if delta.to_seq <= self.sync.applied {
+            return Ok(false);
+        }
+        if !delta.full && delta.from_seq > self.sync.applied {
+            return Err(format!(
+                "a delta since seq {} cannot be applied after seq {}, export the changes since {} instead",
+                delta.from_seq, self.sync.applied, self.sync.applied
+            )
+            .into());
+        }
+        self.frame_arrived(sink)?;
+        if self.ready && !delta.ready {
+            self.on_disconnect(sink)?;
+        }
+        if delta.ready && !self.ready {
+            let local_time = self.now()?;
+            let response = BinaryDataFrame {
+                topic_id: reserved_ids::TIMESYNC_TOPIC_ID,
+                timestamp: delta.server_time_us,
+                data: Nt4Data::Int(local_time),
+            };
+            self.on_value(sink, response)?;
+        }
+        if delta.full {
+            let kept: HashSet<i32> = delta.announced.iter().map(|x| x.id).collect();
+            let mut gone: Vec<UnannounceParams> = self
+                .topics
+                .iter()
+                .filter(|(id, _)| !kept.contains(id))
+                .map(|(&id, topic)| UnannounceParams { name: topic.name.to_string(), id })
+                .collect();
+            gone.sort_by_key(|x| x.id);
+            for unann in gone {
+                self.unannounce(sink, unann)?;
+            }
+        }
+        for unann in delta.unannounced {
+            self.unannounce(sink, unann)?;
+        }
+        for ann in delta.announced {
+            self.announce(sink, ann)?;
+        }
+        for frame in delta.values {
+            if self.cache.get(&frame.topic_id).is_some_and(|(timestamp, _)| *timestamp == frame.timestamp) {
+                continue;
+            }
+            self.on_value(sink, frame)?;
+        }
+        self.sync.applied = delta.to_seq;
+        Ok(true)
+    }
+
+    /// The `to_seq` of the last delta applied by [`ConnectionCore::apply_delta`], to ask the
+    /// leader for the changes since. 0 before the first.
+    pub fn applied_delta_seq(&self) -> u64 {
+        self.sync.applied
+    }
+
+    /// Keeps the last `capacity` events, 1024 by default.
+    pub fn set_journal_capacity(&mut self, capacity: usize) {
+        self.journal.capacity = capacity;
+        self.journal.trim();
+    }
+
+    /// The topics announced, unannounced or changed after `seq`, folded from [`Self::events_since`]
+    /// and sorted by `order`. A topic announced and unannounced in between is left out, one
+    /// unannounced and announced again is changed. Every topic instead if the journal no longer
+    /// reaches back to `seq`, or `seq` is ahead of it. Self test topics are left out, and so are
+    /// [hidden](widgets::is_hidden) ones unless `include_hidden`.
+    pub fn topics_diff(&self, seq: u64, order: TopicOrder, include_hidden: bool) -> TopicsDiff {
+        let listed = |name: &str| include_hidden || !widgets::is_hidden(name);
+        let latest_seq = self.journal.latest_seq();
+        let events = self.journal.since(seq);
+        let changes = if seq <= latest_seq { topic_list::fold(&events) } else { None };
+        let Some(changes) = changes else {
+            let mut topics: Vec<_> = self
+                .topics
+                .values()
+                .filter(|topic| !self_test::is_test_topic(&topic.name) && listed(&topic.name))
+                .filter_map(|topic| self.topic_entry(&topic.name))
+                .collect();
+            topic_list::sort(&mut topics, order);
+            return TopicsDiff {
+                resync_required: true,
+                added: Vec::new(),
+                removed: Vec::new(),
+                changed: Vec::new(),
+                topics: Some(topics),
+                seq: latest_seq,
+            };
+        };
+        let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+        for (name, change) in changes.into_iter().filter(|(name, _)| listed(name)) {
+            match (self.topic_entry(name), change) {
+                (Some(entry), Change::Added) => added.push(entry),
+                (Some(entry), Change::Existing) => changed.push(entry),
+                (None, Change::Existing) => removed.push(name.to_string()),
+                (None, Change::Added) => {},
+            }
+        }
+        topic_list::sort(&mut added, order);
+        topic_list::sort(&mut changed, order);
+        TopicsDiff { resync_required: false, added, removed, changed, topics: None, seq: latest_seq }
+    }
+
+    fn topic_entry(&self, name: &str) -> Option<TopicEntry> {
+        let &id = self.topic_ids.get(name)?;
+        let topic = self.topics.get(&id)?;
+        Some(TopicEntry {
+            id,
+            name: name.to_string(),
+            ty: topic.ty,
+            properties: self.properties.get(&id).cloned(),
+            last_update_us: self.cache.get(&id).map(|(timestamp, _)| *timestamp),
+        })
+    }
+
+    pub fn filtered_topic_count(&self) -> u64 {
+        self.filtered_count
+    }
+
+    fn warn_if_filtered<S: ConnectionSink>(&self, sink: &mut S, params: &SubscribeParams) -> Result<(), S::Error> {
+        let Some(filter) = &self.topic_filter else {
+            return Ok(());
+        };
+        for path in params.topics.iter().filter(|x| filter.excludes_all(x, params.options.prefix)) {
+            sink.event(ConnectionEvent::Warning(format!(
+                "subscription {} to {:?} only matches topics dropped by the topic filter",
+                params.subuid, path
+            )))?;
+        }
+        Ok(())
+    }
+
+    /// Starts keeping a decimated trail of the `double[3]` poses received for `name`.
+    pub fn track_trajectory(&mut self, name: &str, options: TrajectoryOptions) {
+        self.trajectories.track(name, options);
+    }
+
+    pub fn untrack_trajectory(&mut self, name: &str) {
+        self.trajectories.untrack(name);
+    }
+
+    /// Starts keeping the last two numeric values of `name`, starting from the cached one, for
+    /// [`Self::sample_interpolated`]. Fails if `name` is announced with a type `mode` cannot blend.
+    pub fn enable_interpolation(&mut self, name: &str, mode: InterpolationMode, options: InterpolationOptions) -> Result<(), String> {
+        let id = self.topic_ids.get(name).copied();
+        let ty = id.and_then(|id| self.topics.get(&id)).map(|topic| topic.ty);
+        let cached = id.and_then(|id| self.cache.get(&id)).map(|(timestamp, data)| (*timestamp, data));
+        self.interpolations.enable(name, ty, mode, options, cached)
+    }
+
+    pub fn disable_interpolation(&mut self, name: &str) {
+        self.interpolations.disable(name);
+    }
+
+    /// The value of `name` at `server_time`, blended between the two samples around it. Before the
+    /// older one it is the older one, past the latest it carries on along the last change for up
+    /// to [`InterpolationOptions::max_extrapolation_ms`]. Allocates nothing.
+    pub fn sample_interpolated(&mut self, name: &str, server_time: i64) -> Result<Option<InterpolatedSample<'_>>, String> {
+        self.interpolations.sample(name, server_time)
+    }
+
+    pub fn trajectory(&self, name: &str) -> Option<&Trajectory> {
+        self.trajectories.get(name)
+    }
+
+    pub fn clear_trajectory(&mut self, name: &str) -> Result<(), String> {
+        self.trajectories.clear(name)
+    }
+
+    /// Whether releasing a uid that is not active is silently ignored instead of failing with [`UnknownUid`].
+    pub fn set_idempotent(&mut self, idempotent: bool) {
+        self.idempotent = idempotent;
+    }
+
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent
+    }
+
+    pub fn is_active_subscription(&self, id: i32) -> bool {
+        self.subscriptions.contains_key(&id)
+    }
+
+    pub fn is_active_publication(&self, id: i32) -> bool {
+        self.publications.contains_key(&id)
+    }
+
+    /// Whether values sent to topics we publish are cached and delivered to the subscriptions that
+    /// cover them as soon as they are sent, flagged with `local_echo`, for servers that never send a
+    /// client its own values. The server sending one back with the same timestamp is then ignored.
+    /// Only topics announced with our pubuid are echoed, so not values sent before their announce.
+    pub fn set_local_echo(&mut self, enabled: bool) {
+        self.own_topics.local_echo = enabled;
+    }
+
+    /// The topic `key` refers to, with its server id and our pubuid if any, whichever key it is
+    /// looked up by. A topic announced with our pubuid is reported with that pubuid, else with
+    /// any of our publications of the same name.
+    pub fn topic_record(&self, key: TopicKey) -> Option<TopicRecord> {
+        let (name, pubuid) = match key {
+            TopicKey::Name(name) => (name, None),
+            TopicKey::Id(id) => (self.topics.get(&id)?.name.to_string(), self.own_topics.pubuid_of(id)),
+            TopicKey::Pubuid(pubuid) => (self.publications.get(&pubuid)?.name.to_string(), Some(pubuid)),
+        };
+        let id = self.topic_ids.get(name.as_str()).copied();
+        let pubuid = pubuid
+            .or_else(|| id.and_then(|id| self.own_topics.pubuid_of(id)))
+            .or_else(|| self.publication_id(&name).ok());
+        let ty = id
+            .and_then(|id| self.topics.get(&id))
+            .or_else(|| pubuid.and_then(|pubuid| self.publications.get(&pubuid)))?
+            .ty;
+        let local_echo = id.is_some_and(|id| self.own_topics.is_echo(id));
+        Some(TopicRecord { name, id, pubuid, ty, local_echo })
+    }
+
+    /// Whether an announce repeating the name and id of an announced topic is passed on as
+    /// [`ConnectionEvent::Announce`] again. Such announces are always checked for type and property changes.
+    pub fn set_fire_duplicate_announces(&mut self, fire: bool) {
+        self.fire_duplicate_announces = fire;
+    }
+
+    /// Stops passing on values, diffs and group sets, and with `announces` also announces,
+    /// unannounces and type and property changes, while still processing every frame: the value
+    /// cache, histories and trajectories keep updating and timesync keeps running. Only which
+    /// topics got values is kept, so a pause of any length costs at most one entry per topic.
+    pub fn pause_delivery(&mut self, announces: bool) -> Result<(), String> {
+        if self.pause.is_some() {
+            return Err("delivery is already paused".to_string());
+        }
+        let topics = announces.then(|| {
+            self.topics
+                .iter()
+                .filter(|(_, topic)| !self_test::is_test_topic(&topic.name))
+                .map(|(id, topic)| (*id, (topic.clone(), self.properties.get(id).cloned().unwrap_or_default())))
+                .collect()
+        });
+        self.pause = Some(Pause::new(topics));
+        Ok(())
+    }
+
+    /// Ends a pause, doing nothing if delivery is not paused. Paused announces are caught up on as
+    /// the changes from the topics as of the pause to the current ones. With `catch_up`, the latest
+    /// value of every topic that got one during the pause is then delivered, otherwise delivery
+    /// just goes on with the next value received. Topics in diff mode start over from a full snapshot
+    /// either way. Group sets completed during the pause are not delivered.
+    pub fn resume_delivery<S: ConnectionSink>(&mut self, sink: &mut S, catch_up: bool) -> Result<(), S::Error> {
+        let Some(pause) = self.pause.take() else {
+            return Ok(());
+        };
+        for event in pause.announce_events(&self.topics, &self.properties) {
+            sink.event(event)?;
+        }
+        if !catch_up {
+            return Ok(());
+        }
+        for topic_id in pause.into_valued() {
+            let Some((timestamp, data)) = self.cache.get(&topic_id).cloned() else {
+                continue;
+            };
+            let type_mismatch = self.is_type_mismatch(topic_id, &data);
+            let data_frame = BinaryDataFrame { topic_id, timestamp, data };
+            let diff = if type_mismatch { None } else { self.diff(&data_frame) };
+            let local_echo = self.own_topics.is_echo(topic_id);
+            sink.event(Self::value_event(data_frame, diff, type_mismatch, local_echo))?;
+        }
+        Ok(())
+    }
+
+    pub fn is_delivery_paused(&self) -> bool {
+        self.pause.is_some()
+    }
+
+    pub fn set_pretty_text_frames(&mut self, pretty: bool) {
+        self.pretty_text_frames = pretty;
+    }
+
+    /// Checks every text frame sent against [`frame_schema::check_client_frame`], failing on one
+    /// that is wrong instead of sending it. Debug builds check them anyway, and only warn.
+    pub fn set_debug_validation(&mut self, enabled: bool) {
+        self.debug_validation = enabled;
+    }
+
+    pub fn set_background_mode<S: ConnectionSink>(&mut self, sink: &mut S, enabled: bool) -> Result<(), S::Error> {
+        self.check_open()?;
+        if self.background_mode == enabled {
+            return Ok(());
+        }
+        self.background_mode = enabled;
+        self.resend_subscriptions(sink)
+    }
+
+    pub fn set_background_periodic<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        periodic: std::time::Duration,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        self.background_periodic = periodic;
+        if self.background_mode {
+            self.resend_subscriptions(sink)?;
+        }
+        Ok(())
+    }
+
+    pub fn always_fast<S: ConnectionSink>(&mut self, sink: &mut S, name: &str) -> Result<(), S::Error> {
+        self.check_open()?;
+        if self.always_fast.insert(name.to_string()) && self.background_mode {
+            self.resend_subscriptions(sink)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_pending_unannounced_limits(&mut self, per_topic: usize, total: usize, max_age_us: i64) {
+        self.pending.per_topic_limit = per_topic;
+        self.pending.total_limit = total;
+        self.pending.max_age_us = max_age_us;
+    }
+
+    pub fn pending_unannounced_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn dropped_unannounced_count(&self) -> u64 {
+        self.pending.dropped()
+    }
+
+    /// Announced topics matching `query`, see [`search::search`]. [Hidden](widgets::is_hidden)
+    /// topics are only searched with `include_hidden`.
+    pub fn search_topics(&self, query: &str, limit: usize, only: Option<Nt4TypeId>, include_hidden: bool) -> Vec<TopicMatch> {
+        let topics = self.topics.values().filter(|topic| include_hidden || !widgets::is_hidden(&topic.name));
+        search::search(topics, query, limit, only)
+    }
+
+    /// The widgets whose path starts with `prefix`: each subtable with a `.type` entry, with the
+    /// values of its `.type`, `.name` and `.controllable` entries and the topics it holds. Kept up
+    /// to date as topics are announced, so only the values are looked up here.
+    pub fn widget_descriptors(&self, prefix: &str) -> Vec<WidgetDescriptor> {
+        self.widgets.descriptors(prefix, |name| {
+            let id = self.topic_ids.get(name)?;
+            self.cache.get(id).map(|(_, data)| data)
+        })
+    }
+
+    pub fn publishers_of(&self, topic: &str) -> Vec<String> {
+        self.publishers.publishers_of(topic)
+    }
+
+    pub fn topics_of_client(&self, client: &str) -> Vec<String> {
+        self.publishers.topics_of_client(client)
+    }
+
+    /// Every metric of the connection, from the frame counters and each part with metrics of its
+    /// own, plus the gauges below. Counters keep counting across reconnects.
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics::default();
+        let parts: [&dyn ReportMetrics; 5] =
+            [&self.counters, &self.pending, &self.retention, &self.type_mismatches, &self.journal];
+        for part in parts {
+            part.report(&mut metrics);
+        }
+        metrics.gauge("ready", "1 once timesync has completed since connecting, else 0.", f64::from(u8::from(self.ready)));
+        metrics.gauge("topics_announced", "Topics currently announced.", self.topics.len() as f64);
+        metrics.gauge("subscriptions", "Active subscriptions.", self.subscriptions.len() as f64);
+        metrics.gauge("publications", "Active publications.", self.publications.len() as f64);
+        metrics.gauge("cached_values", "Topics with a cached last value.", self.cache.len() as f64);
+        metrics.gauge("offset_us", "Server clock minus local clock, in µs.", self.offs as f64);
+        if let Some(rtt) = self.rtt {
+            metrics.gauge("rtt_us", "Round-trip time of the last timesync, in µs.", rtt as f64);
+        }
+        let queued = self.log_channels.values().map(LogChannel::queued).sum::<usize>();
+        metrics.gauge("log_messages_queued", "Log channel messages waiting to be sent.", queued as f64);
+        metrics
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Every announced topic with its properties and last value, and the state of the connection.
+    /// Self test topics are left out.
+    pub fn snapshot(&mut self) -> Result<Snapshot, String> {
+        let server_time_us = self.now()? + self.offs;
+        let topics = self
+            .topics
+            .iter()
+            .filter(|(_, topic)| !self_test::is_test_topic(&topic.name))
+            .map(|(&id, topic)| {
+                let cached = self.cache.get(&id);
+                let snapshot = TopicSnapshot {
+                    id,
+                    ty: topic.ty,
+                    properties: self.properties.get(&id).cloned(),
+                    value: cached.map(|(_, data)| SnapshotValue::new(data)),
+                    timestamp_us: cached.map(|(timestamp, _)| *timestamp),
+                    staleness_us: cached.map(|(timestamp, _)| server_time_us.saturating_sub(*timestamp)),
+                };
+                (topic.name.to_string(), snapshot)
+            })
+            .collect();
+        Ok(Snapshot {
+            version: snapshot::VERSION,
+            connection: ConnectionSnapshot {
+                state: if self.closed {
+                    ConnectionState::Closed
+                } else if self.ready {
+                    ConnectionState::Ready
+                } else {
+                    ConnectionState::Connecting
+                },
+                server_time_us,
+                offset_us: self.offs,
+                rtt_us: self.rtt,
+                subscriptions: self.subscriptions.len(),
+                publications: self.publications.len(),
+                pending_unannounced: self.pending.len(),
+                dropped_unannounced: self.pending.dropped(),
+                topics_over_limit: self.over_limit_count,
+            },
+            topics,
+        })
+    }
+
+    /// Round-trip time of the last timesync, in microseconds.
+    pub fn rtt(&self) -> Option<i64> {
+        self.rtt
+    }
+
+    /// Seeds a connection that has no topics yet with the topics, properties and values of
+    /// `snapshot`, e.g. one taken on another machine to render with before the socket connects.
+    /// Seeded values are stale until a live value of their topic arrives, see
+    /// [`ConnectionCore::is_stale`]; raw values summarized in the snapshot are left out. Nothing is
+    /// emitted until [`ConnectionCore::replay_seeded`]. Live announces are reconciled with the
+    /// seeded topics as duplicate announces are: the same name under the same id keeps the seeded
+    /// topic and its value, anything else replaces it. Seeded topics not announced within
+    /// `settle_us` of the connection being ready are unannounced. A disconnect drops them all.
+    pub fn hydrate(&mut self, snapshot: Snapshot, settle_us: i64) -> Result<(), String> {
+        self.check_open()?;
+        if snapshot.version != snapshot::VERSION {
+            return Err(format!("snapshot version {} is not {}", snapshot.version, snapshot::VERSION));
+        }
+        if !self.topics.is_empty() || self.hydration.is_some() {
+            return Err("only a connection without topics can be seeded".to_string());
+        }
+        let (mut seeded, mut valued) = (BTreeSet::new(), BTreeSet::new());
+        for (name, topic) in snapshot.topics {
+            if self.topics.contains_key(&topic.id) {
+                return Err(format!("topic id {} is in the snapshot twice", topic.id));
+            }
+            let name = self.intern(name);
+            self.topic_ids.insert(name.clone(), topic.id);
+            self.known_types.insert(name.clone(), topic.ty);
+            self.topics.insert(topic.id, Topic { name: name.clone(), ty: topic.ty });
+            self.widgets.announce(&name, topic.ty, self.topics.values())?;
+            self.properties.insert(topic.id, topic.properties.unwrap_or_default());
+            self.sync.announced(topic.id);
+            if let (Some(SnapshotValue::Value(data)), Some(timestamp)) = (topic.value, topic.timestamp_us) {
+                self.cache.insert(topic.id, (timestamp, data));
+                self.sync.valued(topic.id);
+                valued.insert(topic.id);
+            }
+            seeded.insert(topic.id);
+        }
+        self.hydration = Some(Hydration::new(seeded, valued, settle_us));
+        Ok(())
+    }
+
+    /// Emits an announce for every topic seeded by [`ConnectionCore::hydrate`] that has not been
+    /// replaced since, then a value for each of those with a stale value, both in id order.
+    pub fn replay_seeded<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        let Some(hydration) = &self.hydration else {
+            return Ok(());
+        };
+        let stale = hydration.stale().clone();
+        let mut announced: Vec<(i32, Topic)> = self.topics.iter().map(|(&id, topic)| (id, topic.clone())).collect();
+        announced.sort_unstable_by_key(|(id, _)| *id);
+        for (id, topic) in announced {
+            self.announce_event(sink, ConnectionEvent::Announce { id, topic })?;
+        }
+        for topic_id in stale {
+            if let Some((timestamp, data)) = self.cache.get(&topic_id).cloned() {
+                let type_mismatch = self.is_type_mismatch(topic_id, &data);
+                sink.event(ConnectionEvent::Value { topic_id, timestamp, data, type_mismatch, local_echo: false })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `spec` against every value received from now on, replacing any watch with the same
+    /// `id`, and emits [`ConnectionEvent::Watch`] whenever it starts passing or failing. Timing is
+    /// judged on server timestamps of values and timesync responses only, never on when frames
+    /// arrive. Nothing is subscribed to. A disconnect or server clock restart forgets the values
+    /// and outcomes seen so far.
+    pub fn add_watch(&mut self, id: &str, spec: WatchSpec) -> Result<(), String> {
+        self.watches.add(id, spec)
+    }
+
+    /// Whether there was a watch `id` to remove.
+    pub fn remove_watch(&mut self, id: &str) -> bool {
+        self.watches.remove(id)
+    }
+
+    /// Checks every number received for `name` against `spec`, replacing any alert it had, and
+    /// emits [`ConnectionEvent::Alert`] whenever the alert goes off or clears. Like watches, timing
+    /// is judged on server timestamps only and nothing is subscribed to. A disconnect or server
+    /// clock restart clears every alert that is on.
+    pub fn add_alert(&mut self, name: &str, spec: AlertSpec) -> Result<(), String> {
+        self.alerts.add(name, spec)
+    }
+
+    /// Whether `name` had an alert to remove.
+    pub fn remove_alert(&mut self, name: &str) -> bool {
+        self.alerts.remove(name)
+    }
+
+    pub fn alerts(&self) -> Vec<AlertState> {
+        self.alerts.list()
+    }
+
+    /// Every alert spec as a JSON object keyed by topic name, for [`Self::import_alerts`].
+    pub fn export_alerts(&self) -> Result<String, String> {
+        self.alerts.export()
+    }
+
+    /// Replaces every alert with those in `json`, leaving them as they are if any is invalid.
+    pub fn import_alerts(&mut self, json: &str) -> Result<(), String> {
+        self.alerts.import(json)
+    }
+
+    /// How many values of `name` were not of its announced type and could not be converted to it.
+    /// Has values of each topic named in `table`, or under a key ending in `/`, delivered as the
+    /// type given whatever type the server announces it as, converting them even with loss. Such
+    /// values are flagged as type mismatches, as are values that cannot be converted, which are
+    /// delivered as they came and warned about once per run. Values sent to these topics are
+    /// converted to the type the server announced instead. Replaces the previous table.
+    pub fn set_type_coercions(&mut self, table: BTreeMap<String, Nt4TypeId>) {
+        self.coercions.set(table);
+    }
+
+    /// The table of [`Self::set_type_coercions`] as a JSON object, for [`Self::import_type_coercions`].
+    pub fn export_type_coercions(&self) -> Result<String, String> {
+        self.coercions.export()
+    }
+
+    pub fn import_type_coercions(&mut self, json: &str) -> Result<(), String> {
+        self.coercions.import(json)
+    }
+
+    pub fn type_mismatch_count(&self, name: &str) -> u64 {
+        self.type_mismatches.count(name)
+    }
+
+    /// How many such values in a row make a [`ConnectionEvent::Warning`], 10 by default.
+    pub fn set_type_mismatch_warn_after(&mut self, count: u32) {
+        self.type_mismatches.warn_after = count;
+    }
+
+    /// Whether the cached value of `name` still is the one seeded by [`ConnectionCore::hydrate`].
+    pub fn is_stale(&self, name: &str) -> bool {
+        let id = self.topic_ids.get(name);
+        id.is_some_and(|&id| self.hydration.as_ref().is_some_and(|x| x.is_stale(id)))
+    }
+
+    /// Evicts seeded topics the live server has not announced once the settle window is over.
+    fn check_hydration<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        if self.hydration.is_none() {
+            return Ok(());
+        }
+        let now = self.now()?;
+        let unsettled = self.hydration.as_mut().map(|x| x.take_unsettled(now)).unwrap_or_default();
+        for id in unsettled {
+            self.remove_topic(sink, id)?;
+        }
+        if self.hydration.as_ref().is_some_and(Hydration::is_done) {
+            self.hydration = None;
+        }
+        Ok(())
+    }
+
+    /// Publishes a uniquely named retained test topic under [`self_test::PREFIX`], subscribes to it,
+    /// sends a value once it is announced and waits for that value to come back, then checks the
+    /// timesync round-trip time. The outcome is emitted as [`ConnectionEvent::SelfTest`] after the
+    /// topic has been made non-retained, unsubscribed and unpublished. Each stage times out after
+    /// [`self_test::STAGE_TIMEOUT_US`], checked whenever a frame arrives or a timesync is sent.
+    pub fn start_self_test<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        if self.self_test.is_some() {
+            return Err("a self test is already running".to_string().into());
+        }
+        if !self.ready {
+            let failure = SelfTestFailure {
+                stage: SelfTestStage::Timesync,
+                message: "no timesync response has been received".to_string(),
+            };
+            return sink.event(ConnectionEvent::SelfTest(Err(failure)));
+        }
+        let now = self.now()?;
+        let name = format!("{}{}-{}", self_test::PREFIX, now, self.uid_cnt);
+        let pubuid = self.publish(sink, &name, Nt4TypeId::Double, Properties { retained: true, ..Default::default() })?;
+        self.self_test = Some(SelfTest {
+            name: name.clone(),
+            pubuid,
+            subuids: Vec::new(),
+            stage: SelfTestStage::Announce,
+            started: now,
+            stage_started: now,
+            topic_id: None,
+            value: 0.0,
+            announce_us: 0,
+            warnings: Vec::new(),
+        });
+        let subuid = self.subscribe(sink, &name, Self::self_test_options())?;
+        if let Some(test) = &mut self.self_test {
+            test.subuids.push(subuid);
+        }
+        Ok(())
+    }
+
+    fn self_test_options() -> SubscriptionOptions {
+        SubscriptionOptions {
+            periodic: std::time::Duration::from_millis(10),
+            all: true,
+            topicsonly: false,
+            prefix: false,
+        }
+    }
+
+    fn self_test_announced<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        id: i32,
+        name: &str,
+        retained: bool,
+    ) -> Result<(), S::Error> {
+        let now = self.now()?;
+        let Some(test) = self
+            .self_test
+            .as_mut()
+            .filter(|test| test.stage == SelfTestStage::Announce && test.name == name)
+        else {
+            return Ok(());
+        };
+        test.topic_id = Some(id);
+        test.announce_us = now - test.started;
+        if !retained {
+            test.warnings.push("the server did not announce the test topic as retained".to_string());
+        }
+        test.stage = SelfTestStage::Echo;
+        test.stage_started = now;
+        test.value = now as f64;
+        let (pubuid, value, name) = (test.pubuid, test.value, test.name.clone());
+        self.send_frames(sink, vec![(pubuid, Nt4Data::Double(value))], None, false)?;
+        // A new subscription is sent the current value, in case the server does not echo values to their publisher.
+        let subuid = self.subscribe(sink, &name, Self::self_test_options())?;
+        if let Some(test) = &mut self.self_test {
+            test.subuids.push(subuid);
+        }
+        Ok(())
+    }
+
+    fn self_test_value<S: ConnectionSink>(&mut self, sink: &mut S, topic_id: i32, data: &Nt4Data) -> Result<(), S::Error> {
+        let now = self.now()?;
+        let Some(test) = self
+            .self_test
+            .as_mut()
+            .filter(|test| test.stage == SelfTestStage::Echo && test.topic_id == Some(topic_id))
+        else {
+            return Ok(());
+        };
+        if data.as_double() != Some(&test.value) {
+            return Ok(());
+        }
+        let echo_us = now - test.stage_started;
+        test.stage = SelfTestStage::Timesync;
+        let result = match self.rtt {
+            None => Err(test.fail("no round-trip time has been measured")),
+            Some(rtt) if rtt > self_test::MAX_RTT_US => {
+                Err(test.fail(format!("timesync round-trip time of {} µs is implausibly slow", rtt)))
+            },
+            Some(rtt) => {
+                if rtt > self_test::SLOW_RTT_US {
+                    test.warnings.push(format!("timesync round-trip time of {} µs is slow", rtt));
+                }
+                Ok(SelfTestReport {
+                    announce_ms: test.announce_us as f64 / 1000.0,
+                    echo_ms: echo_us as f64 / 1000.0,
+                    rtt_us: rtt,
+                    offset_us: self.offs,
+                    warnings: std::mem::take(&mut test.warnings),
+                })
+            },
+        };
+        self.finish_self_test(sink, result)
+    }
+
+    /// Fails the self test if its current stage has taken too long.
+    fn check_self_test<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        let Some(stage_started) = self.self_test.as_ref().map(|test| test.stage_started) else {
+            return Ok(());
+        };
+        if self.now()? - stage_started <= self_test::STAGE_TIMEOUT_US {
+            return Ok(());
+        }
+        let failure = self.self_test.as_ref().map(|test| {
+            test.fail(format!("timed out after {} ms", self_test::STAGE_TIMEOUT_US / 1000))
+        });
+        match failure {
+            Some(failure) => self.finish_self_test(sink, Err(failure)),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the test topic and reports `result`.
+    fn finish_self_test<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        result: Result<SelfTestReport, SelfTestFailure>,
+    ) -> Result<(), S::Error> {
+        if let Some(test) = self.self_test.take() {
+            // Best-effort, the socket may be gone. Clearing `retained` lets the server delete the topic once unpublished.
+            let update = PartialProperties { retained: Some(false), ..Default::default() };
+            let _ = self.set_properties(sink, &test.name, update);
+            for subuid in test.subuids {
+                let _ = self.unsubscribe(sink, subuid);
+                self.subscriptions.remove(&subuid);
+            }
+            let _ = self.unpublish(sink, test.pubuid);
+            self.publications.remove(&test.pubuid);
+        }
+        sink.event(ConnectionEvent::SelfTest(result))
+    }
+
+    /// Backs up every persistent topic: subscribes to all topics without values until no announce
+    /// arrived for [`persistent::SETTLE_US`], then to the values of the persistent ones. The backup
+    /// is emitted as [`ConnectionEvent::PersistentExport`] once every value arrived or after
+    /// [`persistent::TIMEOUT_US`], listing those that did not as missing. Announces settling and
+    /// timeouts are checked whenever a frame arrives or a timesync is sent.
+    pub fn start_persistent_export<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        self.check_open()?;
+        if self.persistent.is_some() {
+            return Err("a persistent export or import is already running".to_string().into());
+        }
+        let options = SubscriptionOptions {
+            periodic: std::time::Duration::from_millis(100),
+            all: false,
+            topicsonly: true,
+            prefix: true,
+        };
+        let subuid = self.subscribe(sink, "", options)?;
+        let last_announce = self.now()?;
+        self.persistent = Some(PersistentTask::Export(ExportStage::Announces { subuid, last_announce }));
+        Ok(())
+    }
+
+    /// Restores `entries` as persistent topics: publishes each with `persistent: true`, also set
+    /// as a property in case the topic exists, sends its value and waits for its announce. An entry
+    /// fails on its own, without stopping the others, if its value does not match its type or
+    /// encodes to more than [`persistent::MAX_VALUE_BYTES`], if the topic has another type, or if
+    /// it is not announced as persistent within [`persistent::TIMEOUT_US`]. The report is emitted
+    /// as [`ConnectionEvent::PersistentImport`], and the entries are unpublished again.
+    pub fn start_persistent_import<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        entries: Vec<PersistentEntry>,
+    ) -> Result<(), S::Error> {
+        self.check_open()?;
+        if self.persistent.is_some() {
+            return Err("a persistent export or import is already running".to_string().into());
+        }
+        let started = self.now()?;
+        let mut results = Vec::with_capacity(entries.len());
+        let mut waiting = Vec::new();
+        for (index, entry) in entries.into_iter().enumerate() {
+            let error = match self.check_import(&entry, started) {
+                Ok((data, properties)) => {
+                    let pubuid = self.publish(sink, &entry.name, entry.ty, properties)?;
+                    let update = PartialProperties { persistent: Some(true), ..Default::default() };
+                    self.set_properties(sink, &entry.name, update)?;
+                    self.send_frames(sink, vec![(pubuid, data)], None, false)?;
+                    waiting.push(Importing { index, name: entry.name.clone(), ty: entry.ty, pubuid });
+                    None
+                },
+                Err(error) => Some(error),
+            };
+            results.push(ImportResult { name: entry.name, error });
+        }
+        self.persistent = Some(PersistentTask::Import { started, results, waiting });
+        self.check_persistent(sink)
+    }
+
+    /// The value and properties to publish `entry` with, or why it cannot be imported.
+    fn check_import(&self, entry: &PersistentEntry, now: i64) -> Result<(Nt4Data, Properties), String> {
+        let data = entry.data()?;
+        let size = rmp_serde::to_vec(&data).map_err(|x| format!("{:?}", x))?.len();
+        if size > persistent::MAX_VALUE_BYTES {
+            return Err(format!("the value is {} bytes, over the limit of {}", size, persistent::MAX_VALUE_BYTES));
+        }
+        if let Some(topic) = self.topic_ids.get(entry.name.as_str()).and_then(|id| self.topics.get(id)) {
+            if topic.ty != entry.ty {
+                return Err(format!("the topic is a {}, not a {}", topic.ty.get_name(), entry.ty.get_name()));
+            }
+        }
+        if self.publication_id(&entry.name).is_ok() {
+            return Err("the topic is already published by this client".to_string());
+        }
+        let data = self.validators.check(&entry.name, data, now)?;
+        let properties = Properties { persistent: true, ..entry.properties.clone() };
+        self.check_properties_size(&entry.name, &properties)?;
+        Ok((data, properties))
+    }
+
+    fn persistent_announced<S: ConnectionSink>(&mut self, sink: &mut S, ann: &AnnounceParams) -> Result<(), S::Error> {
+        let now = self.now()?;
+        match &mut self.persistent {
+            Some(PersistentTask::Export(ExportStage::Announces { last_announce, .. })) => {
+                *last_announce = now;
+                Ok(())
+            },
+            Some(PersistentTask::Import { results, waiting, .. }) => {
+                let Some(i) = waiting
+                    .iter()
+                    .position(|x| x.name == ann.name && ann.pubuid.is_none_or(|pubuid| pubuid == x.pubuid))
+                else {
+                    return Ok(());
+                };
+                let importing = waiting.remove(i);
+                results[importing.index].error = if ann.ty != importing.ty {
+                    Some(format!("announced as a {}, not a {}", ann.ty.get_name(), importing.ty.get_name()))
+                } else if !ann.properties.persistent {
+                    Some("announced without the persistent property".to_string())
+                } else {
+                    None
+                };
+                // Persistent topics outlive their publishers.
+                let _ = self.unpublish(sink, importing.pubuid);
+                self.check_persistent(sink)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves a running export or import on once its announces settled, its values arrived or it timed out.
+    fn check_persistent<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        let now = self.now()?;
+        match &self.persistent {
+            Some(PersistentTask::Export(ExportStage::Announces { subuid, last_announce })) => {
+                if now - last_announce < persistent::SETTLE_US {
+                    return Ok(());
+                }
+                let subuid = *subuid;
+                let properties: BTreeMap<String, Properties> = self
+                    .topics
+                    .iter()
+                    .filter(|(id, topic)| {
+                        !self_test::is_test_topic(&topic.name) && self.properties.get(id).is_some_and(|x| x.persistent)
+                    })
+                    .map(|(id, topic)| (topic.name.to_string(), self.properties[id].clone()))
+                    .collect();
+                self.persistent = None;
+                self.unsubscribe(sink, subuid)?;
+                let options = SubscriptionOptions {
+                    periodic: std::time::Duration::from_millis(100),
+                    all: false,
+                    topicsonly: false,
+                    prefix: false,
+                };
+                let subuid = self.subscribe_topics(sink, properties.keys().cloned().collect(), options)?;
+                self.persistent =
+                    Some(PersistentTask::Export(ExportStage::Values { subuid, started: now, properties }));
+                self.check_persistent(sink)
+            },
+            Some(PersistentTask::Export(ExportStage::Values { started, properties, .. })) => {
+                let value = |name: &str| self.topic_ids.get(name).and_then(|id| self.cache.get(id));
+                if now - started <= persistent::TIMEOUT_US && !properties.keys().all(|name| value(name).is_some()) {
+                    return Ok(());
+                }
+                let mut backup = PersistentBackup { version: persistent::VERSION, entries: Vec::new(), missing: Vec::new() };
+                for (name, properties) in properties {
+                    match value(name) {
+                        Some((_, data)) => backup.entries.push(PersistentEntry::new(name, properties, data)?),
+                        None => backup.missing.push(name.clone()),
+                    }
+                }
+                self.finish_persistent(sink, Ok(backup))
+            },
+            Some(PersistentTask::Import { started, waiting, .. }) => {
+                if !waiting.is_empty() && now - started <= persistent::TIMEOUT_US {
+                    return Ok(());
+                }
+                let message = format!("not announced within {} ms", persistent::TIMEOUT_US / 1000);
+                self.abort_persistent(sink, &message)
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Ends a running export with `message` as the error, or a running import with it as the error
+    /// of every entry still waiting for its announce.
+    fn abort_persistent<S: ConnectionSink>(&mut self, sink: &mut S, message: &str) -> Result<(), S::Error> {
+        match self.persistent.take() {
+            Some(task @ PersistentTask::Export(_)) => {
+                self.persistent = Some(task);
+                self.finish_persistent(sink, Err(message.to_string()))
+            },
+            Some(PersistentTask::Import { mut results, waiting, .. }) => {
+                for importing in waiting {
+                    results[importing.index].error = Some(message.to_string());
+                    // Best-effort, the socket may be gone.
+                    let _ = self.unpublish(sink, importing.pubuid);
+                }
+                let failed = results.iter().filter(|x| x.error.is_some()).count();
+                let report = ImportReport { restored: results.len() - failed, failed, results };
+                sink.event(ConnectionEvent::PersistentImport(report))
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Unsubscribes what the running export subscribed to and reports `result`.
+    fn finish_persistent<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        result: Result<PersistentBackup, String>,
+    ) -> Result<(), S::Error> {
+        if let Some(PersistentTask::Export(ExportStage::Announces { subuid, .. } | ExportStage::Values { subuid, .. })) =
+            self.persistent.take()
+        {
+            // Best-effort, the socket may be gone.
+            let _ = self.unsubscribe(sink, subuid);
+        }
+        sink.event(ConnectionEvent::PersistentExport(result))
+    }
+
+    /// The cached value of `name` if it is at most `max_age_us` old on the server clock.
+    pub fn fresh_value(&mut self, name: &str, max_age_us: i64) -> Result<Option<(i64, Nt4Data)>, String> {
+        let server_time = self.now()? + self.offs;
+        let id = self.topic_ids.get(name).filter(|&&id| !self.hydration.as_ref().is_some_and(|x| x.is_stale(id)));
+        let cached = id.and_then(|id| self.cache.get(id));
+        Ok(cached.filter(|(timestamp, _)| server_time.saturating_sub(*timestamp) <= max_age_us).cloned())
+    }
+
+    /// Asks the server for the current value of `name` by subscribing to it under a temporary id,
+    /// which the server answers with the value it has. The first value of `name` received from
+    /// then on, through any subscription, is emitted as [`ConnectionEvent::Fetched`] under the
+    /// returned id, or an error if none arrived within `timeout_us`, checked whenever a frame
+    /// arrives or a timesync is sent. The temporary subscription is dropped either way, and is
+    /// never renewed on reconnect.
+    pub fn start_fetch<S: ConnectionSink>(&mut self, sink: &mut S, name: &str, timeout_us: i64) -> Result<i32, S::Error> {
+        let options = SubscriptionOptions {
+            periodic: std::time::Duration::from_millis(10),
+            all: false,
+            topicsonly: false,
+            prefix: false,
+        };
+        let id = self.subscribe(sink, name, options)?;
+        let now = self.now()?;
+        self.fetches.add(id, name, now + timeout_us);
+        Ok(id)
+    }
+
+    fn fetched<S: ConnectionSink>(&mut self, sink: &mut S, data_frame: &BinaryDataFrame) -> Result<(), S::Error> {
+        if self.fetches.is_empty() {
+            return Ok(());
+        }
+        let Some(topic) = self.topics.get(&data_frame.topic_id) else {
+            return Ok(());
+        };
+        for id in self.fetches.take_topic(&topic.name.clone()) {
+            self.finish_fetch(sink, id, Ok((data_frame.timestamp, data_frame.data.clone())))?;
+        }
+        Ok(())
+    }
+
+    fn check_fetches<S: ConnectionSink>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        if self.fetches.is_empty() {
+            return Ok(());
+        }
+        let now = self.now()?;
+        for (id, name) in self.fetches.take_expired(now) {
+            self.finish_fetch(sink, id, Err(format!("no value of {:?} arrived in time", name)))?;
+        }
+        Ok(())
+    }
+
+    fn finish_fetch<S: ConnectionSink>(
+        &mut self,
+        sink: &mut S,
+        id: i32,
+        result: Result<(i64, Nt4Data), String>,
+    ) -> Result<(), S::Error> {
+        if self.unsubscribe(sink, id).is_err() {
+            // Forgotten even if the unsubscribe could not be sent, so it is not renewed on reconnect.
+            self.subscriptions.remove(&id);
+            self.subscription_profiles.untrack(id);
+        }
+        sink.event(ConnectionEvent::Fetched { id, result })
+    }
+}
+
+/// Largest clock offset accepted from a timesync response, about ten years.
+const MAX_TIME_OFFSET_US: i64 = 10 * 365 * 24 * 60 * 60 * 1_000_000;
+
+/// Offset from the local clock to the server clock, given the local time echoed back by the
+/// server, the server time it was answered at, and the local time the answer arrived.
+pub fn timesync_offset(local_time: i64, server_time: i64, now: i64) -> Result<i64, String> {
+    if local_time > now {
+        return Err(format!(
+            "Invalid timesync response: echoed time {} is after the current time {}",
+            local_time, now
+        ));
+    }
+    now.checked_sub(local_time)
+        .and_then(|rtt| server_time.checked_sub(rtt / 2))
+        .and_then(|x| x.checked_sub(local_time))
+        .filter(|x| x.unsigned_abs() <= MAX_TIME_OFFSET_US as u64)
+        .ok_or_else(|| {
+            format!(
+                "Invalid timesync response: server time {} is implausibly far from local time {}",
+                server_time, local_time
+            )
+        })
+}
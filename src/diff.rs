@@ -0,0 +1,85 @@
+use crate::types::Nt4Data;
+
+/// A run of changed elements, `values` being the new elements from `start` on.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ArrayChange {
+    pub start: usize,
+    pub values: Nt4Data,
+}
+
+/// An array update relative to `base_revision`, or a full snapshot in `full` when there is
+/// nothing to diff against.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ArrayDiff {
+    pub base_revision: Option<u64>,
+    pub revision: u64,
+    pub changes: Vec<ArrayChange>,
+    pub full: Option<Nt4Data>,
+}
+
+/// Per-topic diff mode bookkeeping; the previous value itself is the connection's value cache.
+#[derive(Debug, Default)]
+pub struct DiffState {
+    pub revision: u64,
+    pub full_requested: bool,
+}
+
+impl ArrayDiff {
+    pub fn full(revision: u64, data: Nt4Data) -> Self {
+        Self { base_revision: None, revision, changes: Vec::new(), full: Some(data) }
+    }
+}
+
+pub fn is_array(data: &Nt4Data) -> bool {
+    matches!(
+        data,
+        Nt4Data::BooleanArray(_)
+            | Nt4Data::DoubleArray(_)
+            | Nt4Data::IntArray(_)
+            | Nt4Data::FloatArray(_)
+            | Nt4Data::StringArray(_)
+    )
+}
+
+/// The changed runs between two arrays of the same type and length, or `None` if they can
+/// only be described by a full snapshot. Numbers are compared bit for bit, so a NaN that stays
+/// NaN is unchanged while `0.0` becoming `-0.0` is a change.
+pub fn changes(old: &Nt4Data, new: &Nt4Data) -> Option<Vec<ArrayChange>> {
+    match (old, new) {
+        (Nt4Data::BooleanArray(a), Nt4Data::BooleanArray(b)) => runs(a, b, bool::eq, Nt4Data::BooleanArray),
+        (Nt4Data::DoubleArray(a), Nt4Data::DoubleArray(b)) => {
+            runs(a, b, |a, b| a.to_bits() == b.to_bits(), Nt4Data::DoubleArray)
+        }
+        (Nt4Data::IntArray(a), Nt4Data::IntArray(b)) => runs(a, b, i64::eq, Nt4Data::IntArray),
+        (Nt4Data::FloatArray(a), Nt4Data::FloatArray(b)) => {
+            runs(a, b, |a, b| a.to_bits() == b.to_bits(), Nt4Data::FloatArray)
+        }
+        (Nt4Data::StringArray(a), Nt4Data::StringArray(b)) => runs(a, b, String::eq, Nt4Data::StringArray),
+        _ => None,
+    }
+}
+
+fn runs<T: Clone>(
+    old: &[T],
+    new: &[T],
+    same: fn(&T, &T) -> bool,
+    wrap: fn(Vec<T>) -> Nt4Data,
+) -> Option<Vec<ArrayChange>> {
+    if old.len() != new.len() {
+        return None;
+    }
+    let mut changes = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        if same(&old[i], &new[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < new.len() && !same(&old[i], &new[i]) {
+            i += 1;
+        }
+        changes.push(ArrayChange { start, values: wrap(new[start..i].to_vec()) });
+    }
+    Some(changes)
+}
@@ -0,0 +1,224 @@
+use std::fmt::Write;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    reserved_ids::{self, TopicIdClass},
+    types::Nt4TypeId,
+};
+
+/// Arrays and maps nested deeper than this are not walked, which bounds the recursion on hostile input.
+const MAX_DEPTH: usize = 32;
+
+/// Array items and string characters shown in a decoded value before it is cut short.
+const MAX_SHOWN: usize = 8;
+
+/// A decoded msgpack element, just enough of it to summarize a frame.
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    Str(String),
+    Bin(Vec<u8>),
+    Array(Vec<Value>),
+    Map(usize),
+    Ext(i8, usize),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(x) => write!(f, "{}", x),
+            Self::Int(x) => write!(f, "{}", x),
+            Self::Float(x) => write!(f, "{}", x),
+            Self::Str(x) if x.chars().count() > MAX_SHOWN * 8 => {
+                write!(f, "{:?}…", x.chars().take(MAX_SHOWN * 8).collect::<String>())
+            },
+            Self::Str(x) => write!(f, "{:?}", x),
+            Self::Bin(x) => {
+                write!(f, "<{} bytes", x.len())?;
+                for byte in x.iter().take(MAX_SHOWN * 2) {
+                    write!(f, " {:02x}", byte)?;
+                }
+                if x.len() > MAX_SHOWN * 2 {
+                    write!(f, " …")?;
+                }
+                write!(f, ">")
+            },
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().take(MAX_SHOWN).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                if items.len() > MAX_SHOWN {
+                    write!(f, ", … {} more", items.len() - MAX_SHOWN)?;
+                }
+                write!(f, "]")
+            },
+            Self::Map(len) => write!(f, "{{{} entries}}", len),
+            Self::Ext(ty, len) => write!(f, "<ext {}, {} bytes>", ty, len),
+        }
+    }
+}
+
+/// A read-only msgpack walker that writes one line per element as it goes, so whatever was read
+/// before malformed or truncated input is still explained.
+struct Walker<'a> {
+    data: &'a [u8],
+    pos: usize,
+    lines: String,
+}
+
+impl<'a> Walker<'a> {
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8], String> {
+        let data = self.data;
+        match data.get(self.pos..self.pos.saturating_add(n)) {
+            Some(bytes) => {
+                self.pos += n;
+                Ok(bytes)
+            },
+            None => Err(format!(
+                "truncated: {} needs {} bytes at byte {}, only {} left",
+                what,
+                n,
+                self.pos,
+                data.len() - self.pos
+            )),
+        }
+    }
+
+    fn uint(&mut self, n: usize, what: &str) -> Result<u64, String> {
+        Ok(self.take(n, what)?.iter().fold(0, |x, &byte| x << 8 | byte as u64))
+    }
+
+    fn line(&mut self, start: usize, depth: usize, marker: &str, detail: impl std::fmt::Display) {
+        let range = format!("{}..{}", start, self.pos);
+        let _ = writeln!(self.lines, "  {:<12}{:indent$}{}  {}", range, "", marker, detail, indent = depth * 2);
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Value, String> {
+        let start = self.pos;
+        let marker = self.take(1, "a type marker")?[0];
+        let (name, value) = match marker {
+            0x00..=0x7f => ("positive fixint", Value::Int(marker as i128)),
+            0xe0..=0xff => ("negative fixint", Value::Int(marker as i8 as i128)),
+            0xc0 => ("nil", Value::Nil),
+            0xc2 | 0xc3 => ("bool", Value::Bool(marker == 0xc3)),
+            0xcc..=0xcf => {
+                let n = 1 << (marker - 0xcc);
+                let name = ["uint8", "uint16", "uint32", "uint64"][(marker - 0xcc) as usize];
+                (name, Value::Int(self.uint(n, name)? as i128))
+            },
+            0xd0..=0xd3 => {
+                let n = 1 << (marker - 0xd0);
+                let name = ["int8", "int16", "int32", "int64"][(marker - 0xd0) as usize];
+                let x = self.uint(n, name)?;
+                // Sign-extend from the top bit of the `n` bytes.
+                let shift = 64 - 8 * n;
+                (name, Value::Int(((x << shift) as i64 >> shift) as i128))
+            },
+            0xca => ("float32", Value::Float(f32::from_bits(self.uint(4, "float32")? as u32) as f64)),
+            0xcb => ("float64", Value::Float(f64::from_bits(self.uint(8, "float64")?))),
+            0xa0..=0xbf | 0xd9..=0xdb => {
+                let (name, len) = match marker {
+                    0xd9 => ("str8", self.uint(1, "str8 length")?),
+                    0xda => ("str16", self.uint(2, "str16 length")?),
+                    0xdb => ("str32", self.uint(4, "str32 length")?),
+                    _ => ("fixstr", (marker & 0x1f) as u64),
+                };
+                let bytes = self.take(len as usize, name)?;
+                (name, Value::Str(String::from_utf8_lossy(bytes).into_owned()))
+            },
+            0xc4..=0xc6 => {
+                let name = ["bin8", "bin16", "bin32"][(marker - 0xc4) as usize];
+                let len = self.uint(1 << (marker - 0xc4), name)?;
+                (name, Value::Bin(self.take(len as usize, name)?.to_vec()))
+            },
+            0xd4..=0xd8 | 0xc7..=0xc9 => {
+                let (name, len) = match marker {
+                    0xc7 => ("ext8", self.uint(1, "ext8 length")?),
+                    0xc8 => ("ext16", self.uint(2, "ext16 length")?),
+                    0xc9 => ("ext32", self.uint(4, "ext32 length")?),
+                    _ => ("fixext", 1 << (marker - 0xd4)),
+                };
+                let ty = self.take(1, name)?[0] as i8;
+                self.take(len as usize, name)?;
+                (name, Value::Ext(ty, len as usize))
+            },
+            0x90..=0x9f | 0xdc | 0xdd | 0x80..=0x8f | 0xde | 0xdf => {
+                let (name, len, map) = match marker {
+                    0xdc => ("array16", self.uint(2, "array16 length")?, false),
+                    0xdd => ("array32", self.uint(4, "array32 length")?, false),
+                    0xde => ("map16", self.uint(2, "map16 length")?, true),
+                    0xdf => ("map32", self.uint(4, "map32 length")?, true),
+                    0x80..=0x8f => ("fixmap", (marker & 0x0f) as u64, true),
+                    _ => ("fixarray", (marker & 0x0f) as u64, false),
+                };
+                self.line(start, depth, name, format_args!("{} {}", len, if map { "entries" } else { "items" }));
+                if depth >= MAX_DEPTH {
+                    return Err(format!("nested deeper than {} at byte {}", MAX_DEPTH, start));
+                }
+                let count = if map { len * 2 } else { len };
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    items.push(self.element(depth + 1)?);
+                }
+                return Ok(if map { Value::Map(len as usize) } else { Value::Array(items) });
+            },
+            0xc1 => return Err(format!("invalid type marker c1 at byte {}", start)),
+        };
+        self.line(start, depth, name, &value);
+        Ok(value)
+    }
+}
+
+/// One line about a whole frame, e.g. `topic 14 @ 1234567 µs double 3.25`, if it is one.
+fn summarize(frame: &Value) -> Option<String> {
+    let Value::Array(items) = frame else {
+        return None;
+    };
+    let [Value::Int(topic_id), Value::Int(timestamp), Value::Int(ty), value] = items.as_slice() else {
+        return None;
+    };
+    let topic = match reserved_ids::classify_topic_id(i32::try_from(*topic_id).ok()?) {
+        TopicIdClass::Timesync => "timesync".to_string(),
+        _ => format!("topic {}", topic_id),
+    };
+    let ty = match u8::try_from(*ty).ok().and_then(|x| Nt4TypeId::from_id(x).ok()) {
+        Some(ty) => ty.get_name().to_string(),
+        None => format!("unknown type {}", ty),
+    };
+    Some(format!("{} @ {} µs {} {}", topic, timestamp, ty, value))
+}
+
+#[doc = " explain_binary_frame(Uint8Array bytes) -> string\n"]
+#[doc = " An annotated dump of one or more msgpack binary frames, explaining malformed input up to where it goes wrong."]
+#[wasm_bindgen(skip_jsdoc)]
+pub fn explain_binary_frame(bytes: &[u8]) -> String {
+    let mut walker = Walker { data: bytes, pos: 0, lines: String::new() };
+    let mut out = String::new();
+    let mut index = 0;
+    while walker.pos < bytes.len() {
+        let start = walker.pos;
+        let result = walker.element(0);
+        let summary = match &result {
+            Ok(frame) => summarize(frame).unwrap_or_else(|| "not a data frame".to_string()),
+            Err(error) => error.clone(),
+        };
+        let _ = writeln!(out, "frame {}, bytes {}..{}: {}", index, start, walker.pos, summary);
+        out.push_str(&std::mem::take(&mut walker.lines));
+        if result.is_err() {
+            break;
+        }
+        index += 1;
+    }
+    if bytes.is_empty() {
+        out.push_str("empty\n");
+    }
+    out
+}
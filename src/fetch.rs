@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+/// A value asked for by [`crate::ConnectionCore::start_fetch`].
+#[derive(Debug)]
+struct Fetch {
+    name: String,
+    /// Local time after which the fetch fails.
+    deadline: i64,
+}
+
+/// Running fetches by the id of their temporary subscription.
+#[derive(Debug, Default)]
+pub struct Fetches {
+    fetches: BTreeMap<i32, Fetch>,
+}
+
+impl Fetches {
+    pub fn add(&mut self, id: i32, name: &str, deadline: i64) {
+        self.fetches.insert(id, Fetch { name: name.to_string(), deadline });
+    }
+
+    /// Ends every fetch of `name`, returning their ids.
+    pub fn take_topic(&mut self, name: &str) -> Vec<i32> {
+        let ids: Vec<i32> = self.fetches.iter().filter(|(_, x)| x.name == name).map(|(id, _)| *id).collect();
+        for id in &ids {
+            self.fetches.remove(id);
+        }
+        ids
+    }
+
+    /// Ends every fetch past its deadline at local time `now`, returning their ids and names.
+    pub fn take_expired(&mut self, now: i64) -> Vec<(i32, String)> {
+        let ids: Vec<i32> = self.fetches.iter().filter(|(_, x)| now > x.deadline).map(|(id, _)| *id).collect();
+        ids.into_iter().filter_map(|id| self.fetches.remove(&id).map(|x| (id, x.name))).collect()
+    }
+
+    /// Ends every fetch, returning their ids.
+    pub fn take_all(&mut self) -> Vec<i32> {
+        std::mem::take(&mut self.fetches).into_keys().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fetches.is_empty()
+    }
+}
@@ -0,0 +1,40 @@
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicFilterMode {
+    /// Only topics under one of the prefixes are kept.
+    Include,
+    /// Topics under any of the prefixes are dropped.
+    Exclude,
+}
+
+/// Which announced topics a connection keeps, decided by name at announce time.
+#[derive(Debug, Clone)]
+pub struct TopicFilter {
+    pub prefixes: Vec<String>,
+    pub mode: TopicFilterMode,
+}
+
+impl TopicFilter {
+    pub fn allows(&self, name: &str) -> bool {
+        let matched = self.prefixes.iter().any(|x| name.starts_with(x.as_str()));
+        match self.mode {
+            TopicFilterMode::Include => matched,
+            TopicFilterMode::Exclude => !matched,
+        }
+    }
+
+    /// Whether every topic a subscription to `path` could match is filtered out.
+    pub fn excludes_all(&self, path: &str, prefix: bool) -> bool {
+        if !prefix {
+            return !self.allows(path);
+        }
+        match self.mode {
+            TopicFilterMode::Include => !self
+                .prefixes
+                .iter()
+                .any(|x| path.starts_with(x.as_str()) || x.starts_with(path)),
+            TopicFilterMode::Exclude => self.prefixes.iter().any(|x| path.starts_with(x.as_str())),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use serde_json::{Map, Value};
+
+use crate::types::Nt4TypeId;
+
+/// The prefix of subscription options other than those NT4 defines, `periodic`, `all`,
+/// `topicsonly` and `prefix`, which servers ignore.
+pub const CUSTOM_OPTION_PREFIX: &str = "x-";
+
+/// The properties NT4 defines as booleans.
+const FLAGS: [&str; 3] = ["persistent", "retained", "cached"];
+
+/// Checks a text frame a client sends against what NT4 requires of it: a `method` of `publish`,
+/// `unpublish`, `setproperties`, `subscribe` or `unsubscribe` (or our own `values`), and its
+/// `params` with exactly the keys the method takes, of the types it takes. Names of topics
+/// published or updated cannot be empty, uids are 32-bit integers and types are NT4 type names.
+/// Properties may hold anything, but `persistent`, `retained` and `cached` are booleans. It works
+/// on the JSON as sent, so a misnamed or mistyped field shows up here rather than as a frame the
+/// server silently ignores.
+pub fn check_client_frame(text: &str) -> Result<(), String> {
+    let frame: Value = serde_json::from_str(text).map_err(|x| format!("frame is not JSON: {}", x))?;
+    let frame = keys(&frame, "frame", &["method", "params"], &[])?;
+    let method = frame["method"].as_str().ok_or("method must be a string")?;
+    let params = &frame["params"];
+    let checked = match method {
+        "publish" => publish(params),
+        "unpublish" => keys(params, "params", &["pubuid"], &[]).and_then(|x| uid(x, "pubuid")),
+        "setproperties" => set_properties(params),
+        "subscribe" => subscribe(params),
+        "unsubscribe" => keys(params, "params", &["subuid"], &[]).and_then(|x| uid(x, "subuid")),
+        "values" => values(params),
+        _ => return Err(format!("method {:?} is not an NT4 client method", method)),
+    };
+    checked.map_err(|x| format!("{} frame: {}", method, x))
+}
+
+/// `value` as an object with every key of `required`, and otherwise only keys of `optional`.
+fn keys<'a>(
+    value: &'a Value,
+    what: &str,
+    required: &[&str],
+    optional: &[&str],
+) -> Result<&'a Map<String, Value>, String> {
+    let map = value.as_object().ok_or_else(|| format!("{} must be an object", what))?;
+    if let Some(key) = required.iter().find(|&&key| !map.contains_key(key)) {
+        return Err(format!("{} has no {:?}", what, key));
+    }
+    let known = |key: &&String| required.contains(&key.as_str()) || optional.contains(&key.as_str());
+    if let Some(key) = map.keys().find(|key| !known(key)) {
+        return Err(format!("{} has an unknown key {:?}", what, key));
+    }
+    Ok(map)
+}
+
+fn name(params: &Map<String, Value>) -> Result<(), String> {
+    match params["name"].as_str() {
+        Some(name) if !name.is_empty() => Ok(()),
+        _ => Err("name must be a non-empty string".to_string()),
+    }
+}
+
+fn uid(params: &Map<String, Value>, key: &str) -> Result<(), String> {
+    match params[key].as_i64() {
+        Some(uid) if i32::try_from(uid).is_ok() => Ok(()),
+        _ => Err(format!("{} must be a 32-bit integer, not {}", key, params[key])),
+    }
+}
+
+/// The boolean properties of `what`, where `null` deletes one in an update.
+fn flags(properties: &Map<String, Value>, what: &str, nullable: bool) -> Result<(), String> {
+    for key in FLAGS {
+        match properties.get(key) {
+            None | Some(Value::Bool(_)) => {},
+            Some(Value::Null) if nullable => {},
+            Some(x) => return Err(format!("{}.{} must be a boolean, not {}", what, key, x)),
+        }
+    }
+    Ok(())
+}
+
+fn publish(params: &Value) -> Result<(), String> {
+    let params = keys(params, "params", &["name", "pubuid", "type", "properties"], &[])?;
+    name(params)?;
+    uid(params, "pubuid")?;
+    if serde_json::from_value::<Nt4TypeId>(params["type"].clone()).is_err() {
+        return Err(format!("type must be an NT4 type name, not {}", params["type"]));
+    }
+    let properties = params["properties"].as_object().ok_or("properties must be an object")?;
+    flags(properties, "properties", false)
+}
+
+fn set_properties(params: &Value) -> Result<(), String> {
+    let params = keys(params, "params", &["name", "update"], &[])?;
+    name(params)?;
+    let update = params["update"].as_object().ok_or("update must be an object")?;
+    flags(update, "update", true)
+}
+
+fn subscribe(params: &Value) -> Result<(), String> {
+    let params = keys(params, "params", &["topics", "subuid"], &["options"])?;
+    let topics = params["topics"].as_array().ok_or("topics must be an array")?;
+    // An empty name is fine here: as a prefix it matches every topic.
+    if let Some(topic) = topics.iter().find(|x| !x.is_string()) {
+        return Err(format!("topics must be strings, not {}", topic));
+    }
+    uid(params, "subuid")?;
+    let Some(options) = params.get("options") else {
+        return Ok(());
+    };
+    let options = options.as_object().ok_or("options must be an object")?;
+    for (key, value) in options {
+        let valid = match key.as_str() {
+            "periodic" => value.as_f64().is_some_and(|x| x >= 0.0),
+            "all" | "topicsonly" | "prefix" => value.is_boolean(),
+            key if key.starts_with(CUSTOM_OPTION_PREFIX) => true,
+            _ => {
+                let prefix = CUSTOM_OPTION_PREFIX;
+                return Err(format!("options has an unknown key {:?}, custom ones start with {:?}", key, prefix));
+            },
+        };
+        if !valid {
+            return Err(format!("options.{} cannot be {}", key, value));
+        }
+    }
+    Ok(())
+}
+
+/// Our own `[id, timestamp, type, value]` arrays, as in binary frames.
+fn values(params: &Value) -> Result<(), String> {
+    let values = params.as_array().ok_or("params must be an array")?;
+    for (i, value) in values.iter().enumerate() {
+        let id = |x: &Value| x.as_i64().is_some_and(|id| i32::try_from(id).is_ok());
+        let valid = value.as_array().is_some_and(|x| x.len() == 4 && id(&x[0]) && x[1].is_u64() && x[2].is_u64());
+        if !valid {
+            return Err(format!("params[{}] must be [id, timestamp, type, value], not {}", i, value));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+};
+
+use crate::types::Nt4Data;
+
+/// Values kept per member to match against the slowest one.
+const HISTORY: usize = 32;
+
+/// One value per member of a group, in the order of its names.
+#[derive(Debug, Clone)]
+pub struct GroupValues {
+    /// The server timestamp and value of each member, `None` for members without a value yet.
+    pub values: Vec<Option<(i64, Nt4Data)>>,
+    /// Whether the values are within the window of each other, rather than the freshest ones
+    /// delivered on a timeout.
+    pub complete: bool,
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    values: VecDeque<(i64, Nt4Data)>,
+}
+
+impl Member {
+    /// The mean server time between the kept values, `None` below two values.
+    fn interval(&self) -> Option<i64> {
+        let (first, last) = (self.values.front()?.0, self.values.back()?.0);
+        (self.values.len() >= 2).then(|| (last - first) / (self.values.len() as i64 - 1))
+    }
+
+    /// The kept value nearest to `timestamp`, or `None` while a later value could still be nearer.
+    fn nearest(&self, timestamp: i64) -> Option<&(i64, Nt4Data)> {
+        if self.values.back()?.0 < timestamp {
+            return None;
+        }
+        self.values.iter().min_by_key(|(t, _)| t.abs_diff(timestamp))
+    }
+}
+
+/// Topics delivered together as a consistent set, see [`Groups`].
+#[derive(Debug)]
+struct Group {
+    members: Vec<Member>,
+    window_us: i64,
+    timeout_us: i64,
+    /// The timestamp of the slowest member's value that the others are being matched to.
+    anchor: Option<i64>,
+    /// Local time of the last delivered set, or of when the group was made.
+    last_delivered: i64,
+}
+
+impl Group {
+    /// The member with the longest interval between values, where fewer than two values count as
+    /// slowest and earlier members win ties.
+    fn slowest(&self) -> usize {
+        let slowness = |(i, member): (usize, &Member)| (Reverse(member.interval().unwrap_or(i64::MAX)), i);
+        self.members.iter().enumerate().min_by_key(|&x| slowness(x)).map_or(0, |(i, _)| i)
+    }
+
+    fn observe(&mut self, member: usize, timestamp: i64, data: &Nt4Data, now: i64) -> Option<GroupValues> {
+        let values = &mut self.members[member].values;
+        values.push_back((timestamp, data.clone()));
+        if values.len() > HISTORY {
+            values.pop_front();
+        }
+        if member == self.slowest() {
+            // Replaces a set still waiting for the others, which the timeout covers.
+            self.anchor = Some(timestamp);
+        }
+        self.try_match(now)
+    }
+
+    fn try_match(&mut self, now: i64) -> Option<GroupValues> {
+        let anchor = self.anchor?;
+        let mut values = Vec::with_capacity(self.members.len());
+        for member in &self.members {
+            let (timestamp, data) = member.nearest(anchor)?;
+            if timestamp.abs_diff(anchor) > self.window_us.unsigned_abs() {
+                // Every later value is further away, so this set can never be complete.
+                self.anchor = None;
+                return None;
+            }
+            values.push(Some((*timestamp, data.clone())));
+        }
+        self.anchor = None;
+        self.last_delivered = now;
+        Some(GroupValues { values, complete: true })
+    }
+
+    fn timeout(&mut self, now: i64) -> Option<GroupValues> {
+        if now - self.last_delivered < self.timeout_us || self.members.iter().all(|x| x.values.is_empty()) {
+            return None;
+        }
+        self.last_delivered = now;
+        let values = self.members.iter().map(|x| x.values.back().cloned()).collect();
+        Some(GroupValues { values, complete: false })
+    }
+}
+
+/// Topics whose values are delivered as a set, by group id.
+///
+/// Sets are matched against the slowest member, the one with the longest mean interval between
+/// its values: each of its values is paired with the value of every other member nearest to it in
+/// server time, out of the last few kept. A set is delivered as complete once every member has a
+/// value at or after it, if all the nearest ones are within the window. Faster members thus never
+/// hold a set back, and their values in between are skipped. When no set was delivered for the
+/// timeout, such as while a member is not publishing, the freshest value of each member is
+/// delivered as incomplete instead, and again after every further timeout.
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: BTreeMap<i32, Group>,
+}
+
+impl Groups {
+    pub fn add(&mut self, id: i32, names: Vec<String>, window_us: i64, timeout_us: i64, now: i64) {
+        let members = names.into_iter().map(|name| Member { name, values: VecDeque::new() }).collect();
+        self.groups.insert(id, Group { members, window_us, timeout_us, anchor: None, last_delivered: now });
+    }
+
+    pub fn remove(&mut self, id: i32) -> bool {
+        self.groups.remove(&id).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+    }
+
+    /// Forgets every kept value, such as when their timestamps are no longer comparable to new ones.
+    pub fn clear_values(&mut self) {
+        for group in self.groups.values_mut() {
+            group.anchor = None;
+            for member in group.members.iter_mut() {
+                member.values.clear();
+            }
+        }
+    }
+
+    /// Records a value of `name` received at local time `now`, returning the sets it completes.
+    pub fn observe(&mut self, name: &str, timestamp: i64, data: &Nt4Data, now: i64) -> Vec<(i32, GroupValues)> {
+        let mut delivered = Vec::new();
+        for (id, group) in self.groups.iter_mut() {
+            for member in 0..group.members.len() {
+                if group.members[member].name == name {
+                    delivered.extend(group.observe(member, timestamp, data, now).map(|x| (*id, x)));
+                }
+            }
+        }
+        delivered
+    }
+
+    /// The incomplete sets of groups that timed out by local time `now`.
+    pub fn poll(&mut self, now: i64) -> Vec<(i32, GroupValues)> {
+        self.groups.iter_mut().filter_map(|(id, group)| group.timeout(now).map(|x| (*id, x))).collect()
+    }
+}
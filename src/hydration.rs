@@ -0,0 +1,63 @@
+use std::collections::BTreeSet;
+
+/// See [`crate::ConnectionCore::hydrate`].
+pub const DEFAULT_SETTLE_US: i64 = 2_000_000;
+
+/// The topics a connection was seeded with from a snapshot, until the live server has had its say.
+#[derive(Debug)]
+pub struct Hydration {
+    /// Seeded topics the live server has not announced yet.
+    unconfirmed: BTreeSet<i32>,
+    /// Topics whose cached value still is the one from the snapshot.
+    stale: BTreeSet<i32>,
+    settle_us: i64,
+    /// Local time after which unconfirmed topics are evicted, set once the connection is ready.
+    deadline: Option<i64>,
+}
+
+impl Hydration {
+    pub fn new(seeded: BTreeSet<i32>, valued: BTreeSet<i32>, settle_us: i64) -> Self {
+        Self { unconfirmed: seeded, stale: valued, settle_us, deadline: None }
+    }
+
+    /// The live server announced `id`.
+    pub fn confirm(&mut self, id: i32) {
+        self.unconfirmed.remove(&id);
+    }
+
+    /// A live value of `id` arrived.
+    pub fn refresh(&mut self, id: i32) {
+        self.stale.remove(&id);
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        self.unconfirmed.remove(&id);
+        self.stale.remove(&id);
+    }
+
+    pub fn is_stale(&self, id: i32) -> bool {
+        self.stale.contains(&id)
+    }
+
+    pub fn stale(&self) -> &BTreeSet<i32> {
+        &self.stale
+    }
+
+    /// Starts the settle window at local time `now`, if it has not started yet.
+    pub fn ready(&mut self, now: i64) {
+        self.deadline.get_or_insert(now + self.settle_us);
+    }
+
+    /// Ends the settle window if it is over at local time `now`, returning the topics to evict.
+    pub fn take_unsettled(&mut self, now: i64) -> Vec<i32> {
+        match self.deadline {
+            Some(deadline) if now > deadline => std::mem::take(&mut self.unconfirmed).into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether nothing is left to track: every topic is confirmed or evicted, and every value refreshed.
+    pub fn is_done(&self) -> bool {
+        self.unconfirmed.is_empty() && self.stale.is_empty()
+    }
+}
@@ -1,29 +1,18 @@
 use std::{time::Duration, ops::*};
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Instant(Duration);
 
-impl Ord for Instant {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other)
-            .expect("an instant should never be NaN or Inf.")
-    }
-}
-impl Eq for Instant {}
-
 impl Instant {
     #[inline]
     pub fn now() -> Self {
         Instant(duration_from_f64(now()))
     }
 
+    /// Zero when `earlier` is later than `self`, as with `std::time::Instant`.
     #[inline]
     pub fn duration_since(&self, earlier: Instant) -> Duration {
-        assert!(
-            earlier.0 <= self.0,
-            "`earlier` cannot be later than `self`."
-        );
-        self.0 - earlier.0
+        self.0.saturating_sub(earlier.0)
     }
 }
 
@@ -73,13 +62,27 @@ fn duration_from_f64(millis: f64) -> Duration {
         + Duration::from_nanos((millis.fract() * 1.0e6) as u64)
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn now() -> f64 {
-    let now = {
-        use wasm_bindgen::prelude::*;
-        js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
-            .expect("failed to get performance from global object")
-            .unchecked_into::<web_sys::Performance>()
-            .now()
-    };
-    now
+    use std::sync::OnceLock;
+    static START: OnceLock<std::time::Instant> = OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+/// `performance.now()`, or `Date.now()` in runtimes without `performance`, chosen once so the
+/// clock never changes under a running connection.
+#[cfg(target_arch = "wasm32")]
+pub fn now() -> f64 {
+    use wasm_bindgen::prelude::*;
+    thread_local! {
+        static PERFORMANCE: Option<web_sys::Performance> =
+            js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
+                .ok()
+                .filter(|x| x.is_object())
+                .map(JsCast::unchecked_into);
+    }
+    PERFORMANCE.with(|performance| match performance {
+        Some(performance) => performance.now(),
+        None => js_sys::Date::now(),
+    })
 }
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::types::{Nt4Data, Nt4TypeId};
+
+/// See [`InterpolationOptions::max_extrapolation_ms`].
+pub const DEFAULT_MAX_EXTRAPOLATION_MS: f64 = 100.0;
+
+/// How the values of a topic are blended, see [`crate::ConnectionCore::enable_interpolation`].
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpolationMode {
+    /// Each number on its own, for numbers and arrays of numbers.
+    #[serde(rename = "linear")]
+    Linear,
+    /// `[x, y, theta]`, with theta taking the shorter way around.
+    #[serde(rename = "pose2d")]
+    Pose2d,
+}
+
+impl InterpolationMode {
+    fn accepts(self, ty: Nt4TypeId) -> bool {
+        match self {
+            Self::Linear => matches!(
+                ty,
+                Nt4TypeId::Int
+                    | Nt4TypeId::Float
+                    | Nt4TypeId::Double
+                    | Nt4TypeId::IntArray
+                    | Nt4TypeId::FloatArray
+                    | Nt4TypeId::DoubleArray
+            ),
+            Self::Pose2d => matches!(ty, Nt4TypeId::FloatArray | Nt4TypeId::DoubleArray),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct InterpolationOptions {
+    /// Whether theta is in degrees, as `Field2d` publishes it, rather than radians. Only for `pose2d`.
+    pub degrees: bool,
+    /// How far past the latest sample values are carried on along the last change, after which
+    /// they stay put.
+    pub max_extrapolation_ms: f64,
+}
+
+impl Default for InterpolationOptions {
+    fn default() -> Self {
+        Self { degrees: false, max_extrapolation_ms: DEFAULT_MAX_EXTRAPOLATION_MS }
+    }
+}
+
+/// A value of an interpolated topic at some server time, borrowed from the topic's buffer.
+#[derive(Debug, PartialEq)]
+pub struct InterpolatedSample<'a> {
+    pub values: &'a [f64],
+    /// Whether the topic is a single number rather than an array.
+    pub scalar: bool,
+    /// Whether the time is past the latest sample, so the values are a guess.
+    pub extrapolated: bool,
+}
+
+#[derive(Debug)]
+struct Sample {
+    timestamp: i64,
+    values: Vec<f64>,
+}
+
+/// The last two samples of one topic, with buffers that are reused from one value to the next.
+#[derive(Debug)]
+struct Interpolated {
+    mode: InterpolationMode,
+    options: InterpolationOptions,
+    previous: Option<Sample>,
+    latest: Option<Sample>,
+    scalar: bool,
+    out: Vec<f64>,
+}
+
+impl Interpolated {
+    fn observe(&mut self, timestamp: i64, data: &Nt4Data) {
+        let (len, scalar) = match data {
+            Nt4Data::Int(_) | Nt4Data::Float(_) | Nt4Data::Double(_) => (1, true),
+            Nt4Data::IntArray(x) => (x.len(), false),
+            Nt4Data::FloatArray(x) => (x.len(), false),
+            Nt4Data::DoubleArray(x) => (x.len(), false),
+            _ => return,
+        };
+        let latest_timestamp = self.latest.as_ref().map(|x| x.timestamp);
+        if (self.mode == InterpolationMode::Pose2d && len != 3) || latest_timestamp.is_some_and(|x| timestamp < x) {
+            return;
+        }
+        // A value stamped the same as the latest one replaces it.
+        let replace = latest_timestamp == Some(timestamp);
+        let reused = if replace { self.latest.take() } else { self.previous.take() };
+        let mut values = reused.map(|x| x.values).unwrap_or_default();
+        values.clear();
+        match data {
+            Nt4Data::Int(x) => values.push(*x as f64),
+            Nt4Data::Float(x) => values.push(*x as f64),
+            Nt4Data::Double(x) => values.push(*x),
+            Nt4Data::IntArray(x) => values.extend(x.iter().map(|&x| x as f64)),
+            Nt4Data::FloatArray(x) => values.extend(x.iter().map(|&x| x as f64)),
+            Nt4Data::DoubleArray(x) => values.extend_from_slice(x),
+            _ => {},
+        }
+        if !replace {
+            self.previous = self.latest.take();
+        }
+        self.latest = Some(Sample { timestamp, values });
+        self.scalar = scalar;
+    }
+
+    fn sample(&mut self, time: i64) -> Option<InterpolatedSample<'_>> {
+        let latest = self.latest.as_ref()?;
+        let extrapolated = time > latest.timestamp;
+        self.out.clear();
+        let previous = self.previous.as_ref().filter(|x| x.values.len() == latest.values.len());
+        match previous {
+            Some(previous) if time > previous.timestamp => {
+                let limit = latest.timestamp.saturating_add((self.options.max_extrapolation_ms * 1e3) as i64);
+                let span = (latest.timestamp - previous.timestamp) as f64;
+                let t = (time.min(limit) - previous.timestamp) as f64 / span;
+                let from = previous.values.iter();
+                self.out.extend(from.zip(&latest.values).map(|(a, b)| a + (b - a) * t));
+                if self.mode == InterpolationMode::Pose2d {
+                    let turn = if self.options.degrees { 360.0 } else { std::f64::consts::TAU };
+                    let change = (latest.values[2] - previous.values[2] + turn / 2.0).rem_euclid(turn) - turn / 2.0;
+                    self.out[2] = previous.values[2] + change * t;
+                }
+            },
+            Some(previous) => self.out.extend_from_slice(&previous.values),
+            None => self.out.extend_from_slice(&latest.values),
+        }
+        Some(InterpolatedSample { values: &self.out, scalar: self.scalar, extrapolated })
+    }
+}
+
+/// The topics being interpolated, by name.
+#[derive(Debug, Default)]
+pub struct Interpolations {
+    topics: HashMap<String, Interpolated>,
+}
+
+impl Interpolations {
+    /// Starts (or restarts) interpolating `name`, announced as `ty` if it is, from `cached`, its
+    /// latest value if any.
+    pub fn enable(
+        &mut self,
+        name: &str,
+        ty: Option<Nt4TypeId>,
+        mode: InterpolationMode,
+        options: InterpolationOptions,
+        cached: Option<(i64, &Nt4Data)>,
+    ) -> Result<(), String> {
+        if let Some(ty) = ty.filter(|&ty| !mode.accepts(ty)) {
+            let expected = match mode {
+                InterpolationMode::Linear => "numbers and arrays of numbers",
+                InterpolationMode::Pose2d => "float[] and double[] poses",
+            };
+            return Err(format!("{:?} is a {} topic, only {} can be interpolated", name, ty.get_name(), expected));
+        }
+        if !(options.max_extrapolation_ms.is_finite() && options.max_extrapolation_ms >= 0.0) {
+            return Err(format!("max_extrapolation_ms must be 0 or more, not {}", options.max_extrapolation_ms));
+        }
+        let mut interpolated = Interpolated { mode, options, previous: None, latest: None, scalar: false, out: Vec::new() };
+        if let Some((timestamp, data)) = cached {
+            interpolated.observe(timestamp, data);
+        }
+        self.topics.insert(name.to_string(), interpolated);
+        Ok(())
+    }
+
+    pub fn disable(&mut self, name: &str) {
+        self.topics.remove(name);
+    }
+
+    pub fn observe(&mut self, name: &str, timestamp: i64, data: &Nt4Data) {
+        if let Some(interpolated) = self.topics.get_mut(name) {
+            interpolated.observe(timestamp, data);
+        }
+    }
+
+    /// The value of `name` at server time `time`, `None` before its first numeric value.
+    pub fn sample(&mut self, name: &str, time: i64) -> Result<Option<InterpolatedSample<'_>>, String> {
+        match self.topics.get_mut(name) {
+            Some(interpolated) => Ok(interpolated.sample(time)),
+            None => Err(format!("{:?} is not interpolated", name)),
+        }
+    }
+
+    /// Forgets every sample, keeping the topics, e.g. when the server clock restarts.
+    pub fn clear_samples(&mut self) {
+        for interpolated in self.topics.values_mut() {
+            interpolated.previous = None;
+            interpolated.latest = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.topics.clear();
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use crate::metrics::{Metrics, ReportMetrics};
+
+/// Events kept by default, see [`crate::ConnectionCore::set_journal_capacity`].
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalKind {
+    Announce,
+    Unannounce,
+    Properties,
+    TypeChange,
+    /// Stands in for events dropped or cleared before they were read, so the reader has to fall
+    /// back to a full snapshot.
+    Gap,
+}
+
+/// One topic lifecycle event, see [`crate::ConnectionCore::events_since`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct JournalEvent {
+    pub seq: u64,
+    pub kind: JournalKind,
+    pub name: String,
+    /// `{id, type}` for announces, `{id}` for unannounces, the properties for property changes,
+    /// `{old, new}` for type changes and `{missed}` for gaps.
+    pub detail: serde_json::Value,
+    /// When the event was recorded, on the server clock. `None` for gaps.
+    pub server_time: Option<i64>,
+}
+
+/// The last topic lifecycle events, numbered from 1 without reuse. The oldest are dropped past
+/// the capacity.
+#[derive(Debug)]
+pub struct Journal {
+    events: VecDeque<JournalEvent>,
+    latest_seq: u64,
+    pub capacity: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self { events: VecDeque::new(), latest_seq: 0, capacity: DEFAULT_CAPACITY }
+    }
+}
+
+impl Journal {
+    pub fn record(&mut self, kind: JournalKind, name: &str, detail: serde_json::Value, server_time: i64) {
+        self.latest_seq += 1;
+        self.events.push_back(JournalEvent {
+            seq: self.latest_seq,
+            kind,
+            name: name.to_string(),
+            detail,
+            server_time: Some(server_time),
+        });
+        self.trim();
+    }
+
+    /// Every event after `seq`, oldest first, led by a gap if any of them are no longer kept.
+    pub fn since(&self, seq: u64) -> Vec<JournalEvent> {
+        let first_kept = self.events.front().map_or(self.latest_seq + 1, |x| x.seq);
+        let mut events = Vec::new();
+        if seq + 1 < first_kept {
+            events.push(JournalEvent {
+                seq: first_kept - 1,
+                kind: JournalKind::Gap,
+                name: String::new(),
+                detail: serde_json::json!({ "missed": first_kept - 1 - seq }),
+                server_time: None,
+            });
+        }
+        events.extend(self.events.iter().filter(|x| x.seq > seq).cloned());
+        events
+    }
+
+    pub fn latest_seq(&self) -> u64 {
+        self.latest_seq
+    }
+
+    /// Drops every event, keeping the numbering, so readers behind see a gap.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn trim(&mut self) {
+        while self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+}
+
+impl ReportMetrics for Journal {
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.counter("topic_events_total", "Announces, unannounces, properties and type changes.", self.latest_seq);
+    }
+}
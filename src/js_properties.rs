@@ -0,0 +1,155 @@
+use js_sys::{Array, Object, Reflect};
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    connection::{ConnectionEvent, ConnectionSink},
+    types::{PartialProperties, Properties},
+};
+
+/// Objects nested deeper than this are rejected, cyclic or not.
+const MAX_DEPTH: usize = 64;
+
+const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+
+/// Converts JS property values to JSON, naming the offending key path in every error and
+/// collecting a warning for every function or `undefined` skipped on the way.
+#[derive(Default)]
+pub struct Converter {
+    warnings: Vec<String>,
+    /// The objects and arrays being converted, outermost first, to detect cycles.
+    ancestors: Vec<JsValue>,
+}
+
+impl Converter {
+    /// `None` for a value that is skipped.
+    pub fn value(&mut self, path: &str, value: &JsValue) -> Result<Option<Value>, String> {
+        if value.is_undefined() || value.is_function() {
+            let what = if value.is_function() { "a function" } else { "undefined" };
+            self.warnings.push(format!("property {:?} is {} and was skipped", path, what));
+            return Ok(None);
+        }
+        if value.is_null() {
+            return Ok(Some(Value::Null));
+        }
+        if let Some(x) = value.as_bool() {
+            return Ok(Some(Value::Bool(x)));
+        }
+        if let Some(x) = value.as_string() {
+            return Ok(Some(Value::String(x)));
+        }
+        if value.is_bigint() {
+            let number = match i64::try_from(value.clone()) {
+                Ok(x) => serde_json::Number::from(x),
+                Err(_) => u64::try_from(value.clone())
+                    .map(serde_json::Number::from)
+                    .map_err(|_| format!("property {:?} is a BigInt out of the 64-bit range", path))?,
+            };
+            return Ok(Some(Value::Number(number)));
+        }
+        if let Some(x) = value.as_f64() {
+            // Whole numbers stay integers, as `JSON.stringify` writes them, including those past
+            // `Number.MAX_SAFE_INTEGER` that fit 64 bits.
+            let number = if x.fract() == 0.0 && (-TWO_POW_63..TWO_POW_63).contains(&x) {
+                serde_json::Number::from(x as i64)
+            } else if x.fract() == 0.0 && (0.0..2.0 * TWO_POW_63).contains(&x) {
+                serde_json::Number::from(x as u64)
+            } else {
+                serde_json::Number::from_f64(x)
+                    .ok_or_else(|| format!("property {:?} is {}, which is not JSON", path, x))?
+            };
+            return Ok(Some(Value::Number(number)));
+        }
+        if !value.is_object() {
+            let ty = value.js_typeof().as_string().unwrap_or_default();
+            return Err(format!("property {:?} is a {}, which is not JSON", path, ty));
+        }
+        if self.ancestors.iter().any(|x| Object::is(x, value)) {
+            return Err(format!("property {:?} refers back to an object containing it", path));
+        }
+        if self.ancestors.len() >= MAX_DEPTH {
+            return Err(format!("property {:?} is nested deeper than {} levels", path, MAX_DEPTH));
+        }
+        self.ancestors.push(value.clone());
+        let converted = if Array::is_array(value) {
+            self.array(path, &Array::from(value)).map(Value::Array)
+        } else {
+            self.object(path, value).map(Value::Object)
+        };
+        self.ancestors.pop();
+        converted.map(Some)
+    }
+
+    fn array(&mut self, path: &str, array: &Array) -> Result<Vec<Value>, String> {
+        let mut items = Vec::with_capacity(array.length() as usize);
+        for (i, item) in array.iter().enumerate() {
+            // Like `JSON.stringify`, skipped items become null rather than shifting the rest.
+            items.push(self.value(&format!("{}[{}]", path, i), &item)?.unwrap_or(Value::Null));
+        }
+        Ok(items)
+    }
+
+    /// The own enumerable properties of `object`, which is `path` ("" at the top level).
+    fn object(&mut self, path: &str, object: &JsValue) -> Result<Map<String, Value>, String> {
+        let mut map = Map::new();
+        for key in Object::keys(object.unchecked_ref::<Object>()).iter() {
+            let key = key.as_string().unwrap_or_default();
+            let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            let value = Reflect::get(object, &JsValue::from_str(&key))
+                .map_err(|_| format!("property {:?} cannot be read", path))?;
+            if let Some(value) = self.value(&path, &value)? {
+                map.insert(key, value);
+            }
+        }
+        Ok(map)
+    }
+
+    /// The top-level `properties` object, or none at all.
+    fn top_level(&mut self, properties: &JsValue) -> Result<Map<String, Value>, String> {
+        if properties.is_undefined() || properties.is_null() {
+            return Ok(Map::new());
+        }
+        if !properties.is_object() || Array::is_array(properties) {
+            return Err("properties must be an object".to_string());
+        }
+        self.ancestors.push(properties.clone());
+        let map = self.object("", properties);
+        self.ancestors.pop();
+        map
+    }
+
+    pub fn properties(&mut self, properties: &JsValue) -> Result<Properties, String> {
+        let mut map = self.top_level(properties)?;
+        let mut flag = |key: &str| match map.remove(key) {
+            None => Ok(false),
+            Some(Value::Bool(x)) => Ok(x),
+            Some(x) => Err(format!("property {:?} must be a boolean, not {}", key, x)),
+        };
+        Ok(Properties { persistent: flag("persistent")?, retained: flag("retained")?, extra: map })
+    }
+
+    pub fn partial_properties(&mut self, update: &JsValue) -> Result<PartialProperties, String> {
+        let mut map = self.top_level(update)?;
+        let mut flag = |key: &str| match map.remove(key) {
+            None => Ok(None),
+            Some(Value::Bool(x)) => Ok(Some(x)),
+            // Deleting a flag clears it, as in `Properties::apply`.
+            Some(Value::Null) => Ok(Some(false)),
+            Some(x) => Err(format!("property {:?} must be a boolean or null, not {}", key, x)),
+        };
+        Ok(PartialProperties { persistent: flag("persistent")?, retained: flag("retained")?, extra: map })
+    }
+}
+
+/// Runs a conversion, passing its warnings to `sink`.
+pub fn convert<S: ConnectionSink, T>(
+    sink: &mut S,
+    f: impl FnOnce(&mut Converter) -> Result<T, String>,
+) -> Result<T, S::Error> {
+    let mut converter = Converter::default();
+    let converted = f(&mut converter)?;
+    for warning in converter.warnings {
+        sink.event(ConnectionEvent::Warning(warning))?;
+    }
+    Ok(converted)
+}
@@ -1,65 +1,247 @@
-use chrono::Duration;
+//! NT4 client connections for the browser, with the protocol handled by [`ConnectionCore`].
+//!
+//! Frames are only processed inside calls into a connection, so methods that return a promise need
+//! the socket hooked up to `on_text` and `on_binary` while pending, and check their timeouts there
+//! and in `timesync`. Watches, alerts and groups judge time on server timestamps, in µs, rather
+//! than on when frames arrive, and timestamps past `Number.MAX_SAFE_INTEGER` are `BigInt`s.
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use instant::Instant;
+// Not gated on target_arch = "wasm32": wasm-bindgen and js-sys build on native targets too, which keeps the JS API
+// covered by native cargo check, clippy and tests, and only calling into JS needs a wasm runtime.
 use js_sys::JsString;
 use wasm_bindgen::prelude::*;
 
+mod alerts;
 mod binary;
+mod chunked;
+mod coercion;
+mod conformance;
+mod connection;
+mod diff;
+mod explain;
+mod fetch;
+mod filter;
+mod frame_schema;
+mod groups;
+mod hydration;
+mod instant;
+mod interpolation;
+mod journal;
+mod js_properties;
+mod log_channel;
+mod manifest;
+mod metadata;
+mod metrics;
+mod mismatch;
+mod multiplexer;
+mod own_topics;
+mod ownership;
+mod pause;
+mod pending;
+mod persistent;
+mod profiles;
+mod quarantine;
+mod reserved_ids;
+mod retention;
+mod scenario;
+mod search;
+mod self_test;
+mod send_policy;
+#[cfg(feature = "server")]
+mod server;
+mod share;
+mod snapshot;
+mod sync;
+#[cfg(feature = "tcp-transport")]
+mod tcp;
+mod telemetry;
 mod text;
+mod time_reset;
+mod topic_list;
+mod trajectory;
 mod types;
-mod instant;
+mod validation;
+mod virtual_client;
+mod watch;
+mod widgets;
+mod wire;
 
-use text::*;
-use types::*;
+pub use alerts::{AlertEvent, AlertSpec, AlertState, Reduction};
+pub use conformance::{run_conformance, Conformance, ConformanceCheck, ConformanceReport, Outgoing};
+pub use connection::{
+    timesync_offset, ConnectionCore, ConnectionEvent, ConnectionSink, InternalError, TimestampMode, UidKind, UnknownUid,
+    ValueEncoding,
+};
+pub use diff::{ArrayChange, ArrayDiff};
+pub use explain::explain_binary_frame;
+pub use filter::{TopicFilter, TopicFilterMode};
+pub use frame_schema::{check_client_frame, CUSTOM_OPTION_PREFIX};
+pub use groups::GroupValues;
+pub use interpolation::{InterpolatedSample, InterpolationMode, InterpolationOptions};
+pub use journal::{JournalEvent, JournalKind};
+pub use log_channel::LogOptions;
+pub use manifest::{generate_dts, ManifestDrift, ManifestStrictness, ManifestTopic};
+pub use metrics::{Metric, MetricKind, Metrics};
+pub use multiplexer::Nt4Multiplexer;
+pub use ownership::ValueOwnership;
+pub use own_topics::{TopicKey, TopicRecord};
+pub use persistent::{ImportReport, ImportResult, PersistentBackup, PersistentEntry};
+pub use quarantine::{QuarantinedFrame, QuarantinedInput};
+pub use reserved_ids::{classify_topic_id, TopicIdClass, TIMESYNC_TOPIC_ID};
+pub use retention::{RetentionOptions, RetentionStats, SWEEP_BUDGET};
+pub use scenario::{Nt4Scenario, Scenario};
+pub use search::TopicMatch;
+pub use self_test::{SelfTestFailure, SelfTestReport, SelfTestStage};
+pub use share::{decode_share_state, encode_share_state};
+pub use snapshot::{ConnectionSnapshot, ConnectionState, Snapshot, SnapshotValue, TopicSnapshot};
+#[cfg(feature = "server")]
+pub use server::{ClientId, Nt4Server, ServerCore, ServerFrame};
+#[cfg(feature = "tcp-transport")]
+pub use tcp::{Nt4Event, Nt4TcpClient};
+pub use time_reset::TimeResetOptions;
+pub use topic_list::{TopicEntry, TopicOrder, TopicsDiff};
+pub use trajectory::{Trajectory, TrajectoryOptions};
+pub use types::{AtomicEntry, Nt4Data, Nt4TypeId, PartialProperties, Properties, SubscriptionOptions, Topic};
+pub use validation::{AllowedValue, ValidationMode, Validator};
+pub use virtual_client::Nt4VirtualClient;
+pub use watch::{Comparison, WatchResult, WatchSpec};
+pub use widgets::{is_hidden, WidgetChild, WidgetDescriptor};
+pub use wire::{BooleanEncoding, RawEncoding, StringEncoding, WireOptions};
+
+use send_policy::SendPolicies;
+use virtual_client::{Router, VirtualClients};
 
 #[wasm_bindgen]
 pub struct Nt4Connection {
-    send_binary_fn: Option<js_sys::Function>,
-    send_text_fn: Option<js_sys::Function>,
-    announce_fn: Option<js_sys::Function>,
-    unannounce_fn: Option<js_sys::Function>,
-    ready_fn: Option<js_sys::Function>,
-    unready_fn: Option<js_sys::Function>,
-    on_data_fn: Option<js_sys::Function>,
-    start_time: Instant,
-    offs: i64,
-    uid_cnt: i32,
+    inner: Rc<RefCell<Inner>>,
+}
+
+/// Everything behind a [`Nt4Connection`], shared with its [`Nt4VirtualClient`]s.
+struct Inner {
+    callbacks: Callbacks,
+    core: ConnectionCore,
+    virtual_clients: VirtualClients,
+    send_policies: SendPolicies,
+}
+
+impl Inner {
+    /// The core, and the sink that sends its frames and routes its events to us and our virtual clients.
+    fn split(&mut self) -> (&mut ConnectionCore, Router<'_>) {
+        (
+            &mut self.core,
+            Router { callbacks: &mut self.callbacks, virtual_clients: &mut self.virtual_clients },
+        )
+    }
+}
+
+/// Fails if `inner` is already in use further up the stack, e.g. when a virtual client's callback
+/// calls into its connection.
+fn borrow_inner(inner: &RefCell<Inner>) -> Result<std::cell::RefMut<'_, Inner>, JsValue> {
+    let inner = inner.try_borrow_mut();
+    Ok(inner.map_err(|_| JsString::from("connection is busy: it cannot be used from inside its own callbacks"))?)
+}
+
+/// Runs `f` with the core and its sink, or fails like [`borrow_inner`].
+fn with_core<T>(
+    inner: &RefCell<Inner>,
+    f: impl FnOnce(&mut ConnectionCore, &mut Router) -> Result<T, JsValue>,
+) -> Result<T, JsValue> {
+    let mut inner = borrow_inner(inner)?;
+    let (core, mut sink) = inner.split();
+    f(core, &mut sink)
 }
 
 macro_rules! set_fns {
     ($($name:ident),* $(,)?) => {
         paste::paste! {
+            /// The JS side of a [`Nt4Connection`], adapted to the core as a [`ConnectionSink`].
+            #[derive(Default)]
+            struct Callbacks {
+                $(
+                    $name: Option<js_sys::Function>,
+                )*
+                /// Set through `set_send_text_fn`, which first sends what is in `pending_text`.
+                send_text_fn: Option<js_sys::Function>,
+                /// Text frames sent before `send_text_fn` was set, oldest first.
+                pending_text: Vec<String>,
+                include_type_in_callback: bool,
+                value_ownership: ValueOwnership,
+                lender: ownership::Lender,
+                /// `resolve` and `reject` of the promise returned by `self_test`.
+                self_test: Option<(js_sys::Function, js_sys::Function)>,
+                /// `resolve` and `reject` of the promise returned by `export_persistent` or `import_persistent`.
+                persistent: Option<(js_sys::Function, js_sys::Function)>,
+                /// The callbacks of `subscribe_group`, by group id.
+                groups: HashMap<i32, js_sys::Function>,
+                /// `resolve` and `reject` of the promises returned by `fetch_value`, by fetch id.
+                fetches: HashMap<i32, (js_sys::Function, js_sys::Function)>,
+                /// The callback of `set_metrics_fn`.
+                metrics: Option<MetricsCallback>,
+                max_frame_bytes: Option<usize>,
+                /// What a follower sends, for `drain_outbound`, in place of the socket callbacks.
+                outbound: Option<Vec<JsValue>>,
+                /// The timer of `set_telemetry_endpoint`.
+                telemetry: Option<telemetry::Telemetry>,
+            }
+
+            impl Callbacks {
+                fn release(&mut self) {
+                    $(
+                        self.$name = None;
+                    )*
+                    self.send_text_fn = None;
+                    self.pending_text.clear();
+                    self.self_test = None;
+                    self.persistent = None;
+                    self.groups.clear();
+                    self.fetches.clear();
+                    self.metrics = None;
+                    self.telemetry = None;
+                }
+            }
+
             #[wasm_bindgen]
             impl Nt4Connection {
                 #[wasm_bindgen(constructor)]
                 pub fn new() -> Nt4Connection {
                     Self {
-                        $(
-                            $name: None,
-                        )*
-                        start_time: Instant::now(),
-                        offs: 0,
-                        uid_cnt: 0,
+                        inner: Rc::new(RefCell::new(Inner {
+                            callbacks: Callbacks::default(),
+                            core: ConnectionCore::new(),
+                            virtual_clients: VirtualClients::default(),
+                            send_policies: SendPolicies::default(),
+                        })),
                     }
                 }
                 $(
                     pub fn [<set_ $name>](&mut self, f: js_sys::Function) {
-                        self.$name = Some(f);
+                        self.inner.borrow_mut().callbacks.$name = Some(f);
                     }
                 )*
             }
+
+            impl Default for Nt4Connection {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
         }
     };
 }
 
 set_fns! {
     send_binary_fn,
-    send_text_fn,
     announce_fn,
     unannounce_fn,
     ready_fn,
     unready_fn,
     on_data_fn,
+    type_changed_fn,
+    properties_changed_fn,
+    warning_fn,
+    server_time_reset_fn,
+    watch_fn,
+    alert_fn,
 }
 
 macro_rules! expect_available {
@@ -82,67 +264,457 @@ macro_rules! expect_available {
     };
 }
 
-impl Nt4Connection {
-    fn now(&mut self) -> Result<i64, JsValue> {
-        let now = Duration::from_std(Instant::now().duration_since(self.start_time))
-            .map_err(|x| JsString::from(format!("{:?}", x)))?;
-        Ok(if let Some(us) = now.num_microseconds() {
-            us
-        } else {
-            self.start_time = Instant::now();
-            let now = Duration::from_std(Instant::now().duration_since(self.start_time))
-                .map_err(|x| JsString::from(format!("{:?}", x)))?;
-            now.num_microseconds().unwrap()
-        })
+impl ConnectionSink for Callbacks {
+    type Error = JsValue;
+
+    fn send_text(&mut self, data: String) -> Result<(), JsValue> {
+        if let Some(outbound) = &mut self.outbound {
+            outbound.push(JsString::from(data).into());
+            return Ok(());
+        }
+        match &self.send_text_fn {
+            Some(send_text_fn) => {
+                send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
+            },
+            None => self.pending_text.push(data),
+        }
+        Ok(())
+    }
+
+    fn send_binary(&mut self, data: Vec<u8>) -> Result<(), JsValue> {
+        if let Some(outbound) = &mut self.outbound {
+            outbound.push(js_sys::Uint8Array::from(&data[..]).into());
+            return Ok(());
+        }
+        expect_available! { self send_binary_fn {
+            let data = serde_wasm_bindgen::to_value(&data)?;
+            send_binary_fn.call1(&JsValue::NULL, &data)?;
+            Ok(())
+        } }
+    }
+
+    fn max_frame_bytes(&self) -> Option<usize> {
+        self.max_frame_bytes
+    }
+
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), JsValue> {
+        match event {
+            ConnectionEvent::Announce { topic, .. } => expect_available! { self announce_fn {
+                let data = serde_wasm_bindgen::to_value(&topic)?;
+                announce_fn.call1(&JsValue::NULL, &data)?;
+                Ok(())
+            } },
+            ConnectionEvent::Unannounce { name, .. } => expect_available! { self unannounce_fn {
+                unannounce_fn.call1(&JsValue::NULL, &JsString::from(name))?;
+                Ok(())
+            } },
+            ConnectionEvent::TypeChanged { name, old, new } => {
+                if let Some(type_changed_fn) = &self.type_changed_fn {
+                    type_changed_fn.call3(
+                        &JsValue::NULL,
+                        &JsString::from(name),
+                        &JsString::from(old.get_name()),
+                        &JsString::from(new.get_name()),
+                    )?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::PropertiesChanged { name, properties, .. } => {
+                if let Some(properties_changed_fn) = &self.properties_changed_fn {
+                    let properties = to_js(&properties)?;
+                    properties_changed_fn.call2(&JsValue::NULL, &JsString::from(name), &properties)?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::Ready => expect_available! { self ready_fn {
+                ready_fn.call0(&JsValue::NULL)?;
+                Ok(())
+            } },
+            ConnectionEvent::Unready => expect_available! { self unready_fn {
+                unready_fn.call0(&JsValue::NULL)?;
+                Ok(())
+            } },
+            ConnectionEvent::Value { topic_id, timestamp, data, type_mismatch, local_echo } => {
+                if self.value_ownership == ValueOwnership::Borrow {
+                    let mut lender = std::mem::take(&mut self.lender);
+                    let lent = lender.lend(&data, |value| {
+                        self.deliver(topic_id, timestamp, value, data.get_name(), type_mismatch, local_echo)
+                    });
+                    self.lender = lender;
+                    if let Some(result) = lent {
+                        return result;
+                    }
+                }
+                let value = serde_wasm_bindgen::to_value(&data)?;
+                self.deliver(topic_id, timestamp, value, data.get_name(), type_mismatch, local_echo)
+            },
+            ConnectionEvent::Diff { topic_id, timestamp, ty, diff } => {
+                let value = serde::Serialize::serialize(&diff, &serde_wasm_bindgen::Serializer::json_compatible())?;
+                self.deliver(topic_id, timestamp, value, ty.get_name(), false, false)
+            },
+            ConnectionEvent::Warning(message) => {
+                match &self.warning_fn {
+                    Some(warning_fn) => {
+                        warning_fn.call1(&JsValue::NULL, &JsString::from(message))?;
+                    },
+                    None => web_sys::console::warn_1(&JsString::from(message)),
+                }
+                Ok(())
+            },
+            ConnectionEvent::SelfTest(result) => {
+                if let Some((resolve, reject)) = self.self_test.take() {
+                    match result {
+                        Ok(report) => resolve.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&report)?)?,
+                        Err(failure) => reject.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&failure)?)?,
+                    };
+                }
+                Ok(())
+            },
+            ConnectionEvent::PersistentExport(result) => {
+                if let Some((resolve, reject)) = self.persistent.take() {
+                    match result {
+                        Ok(backup) => resolve.call1(
+                            &JsValue::NULL,
+                            &to_js(&backup)?,
+                        )?,
+                        Err(message) => reject.call1(&JsValue::NULL, &JsString::from(message))?,
+                    };
+                }
+                Ok(())
+            },
+            ConnectionEvent::Fetched { id, result } => {
+                if let Some((resolve, reject)) = self.fetches.remove(&id) {
+                    match result {
+                        Ok((timestamp, data)) => resolve.call1(&JsValue::NULL, &fetched_value(timestamp, &data, false)?)?,
+                        Err(message) => reject.call1(&JsValue::NULL, &JsString::from(message))?,
+                    };
+                }
+                Ok(())
+            },
+            ConnectionEvent::PersistentImport(report) => {
+                if let Some((resolve, _)) = self.persistent.take() {
+                    resolve.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&report)?)?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::Group { id, values } => {
+                if let Some(f) = self.groups.get(&id) {
+                    let members = js_sys::Array::new();
+                    for value in values.values {
+                        members.push(&match value {
+                            Some((timestamp, data)) => {
+                                let member = js_sys::Object::new();
+                                js_sys::Reflect::set(&member, &"timestamp".into(), &JsValue::from(timestamp))?;
+                                js_sys::Reflect::set(&member, &"value".into(), &serde_wasm_bindgen::to_value(&data)?)?;
+                                member.into()
+                            },
+                            None => JsValue::NULL,
+                        });
+                    }
+                    f.call2(&JsValue::NULL, &members, &JsValue::from(values.complete))?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::ServerTimeReset { old_estimate, new_estimate } => {
+                if let Some(server_time_reset_fn) = &self.server_time_reset_fn {
+                    server_time_reset_fn.call2(&JsValue::NULL, &JsValue::from(old_estimate), &JsValue::from(new_estimate))?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::Watch(result) => {
+                if let Some(watch_fn) = &self.watch_fn {
+                    let details =
+                        serde::Serialize::serialize(&result.details, &serde_wasm_bindgen::Serializer::json_compatible())?;
+                    watch_fn.call3(&JsValue::NULL, &JsString::from(result.id), &JsValue::from(result.passed), &details)?;
+                }
+                Ok(())
+            },
+            ConnectionEvent::Alert(event) => {
+                if let Some(alert_fn) = &self.alert_fn {
+                    let value = event.value.map_or(JsValue::NULL, JsValue::from);
+                    let active = JsValue::from(event.active);
+                    alert_fn.call4(&JsValue::NULL, &JsString::from(event.name), &active, &value, &JsValue::from(event.since_us))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Callbacks {
+    /// Sets `send_text_fn` and sends it the text frames sent before, in order. If one throws, it and
+    /// the frames after it are kept for the next `send_text_fn`.
+    fn set_send_text_fn(&mut self, f: js_sys::Function) -> Result<(), JsValue> {
+        let pending = std::mem::take(&mut self.pending_text);
+        let mut pending = pending.into_iter();
+        while let Some(data) = pending.next() {
+            if let Err(error) = f.call1(&JsValue::NULL, &JsString::from(data.as_str())) {
+                self.pending_text = std::iter::once(data).chain(pending).collect();
+                return Err(error);
+            }
+        }
+        self.send_text_fn = Some(f);
+        Ok(())
+    }
+
+    /// Calls `on_data_fn(topic_id, timestamp, data)`, with the type name if asked for, or always with
+    /// it and `true` for a value that is not of the announced type, and then `true` again for a
+    /// local echo.
+    fn deliver(
+        &self,
+        topic_id: i32,
+        timestamp: i64,
+        data: JsValue,
+        ty: &str,
+        type_mismatch: bool,
+        local_echo: bool,
+    ) -> Result<(), JsValue> {
+        expect_available! { self on_data_fn {
+            let topic_id = JsValue::from(topic_id);
+            let timestamp = JsValue::from(timestamp);
+            if local_echo {
+                let type_mismatch = JsValue::from(type_mismatch);
+                on_data_fn.call6(&JsValue::NULL, &topic_id, &timestamp, &data, &JsString::from(ty), &type_mismatch, &JsValue::TRUE)?;
+            } else if type_mismatch {
+                on_data_fn.call5(&JsValue::NULL, &topic_id, &timestamp, &data, &JsString::from(ty), &JsValue::TRUE)?;
+            } else if self.include_type_in_callback {
+                on_data_fn.call4(&JsValue::NULL, &topic_id, &timestamp, &data, &JsString::from(ty))?;
+            } else {
+                on_data_fn.call3(&JsValue::NULL, &topic_id, &timestamp, &data)?;
+            }
+            Ok(())
+        } }
     }
+}
+
+/// `metrics_fn`, called by `emit_metrics` at most once per `interval_ms` of the caller's clock.
+struct MetricsCallback {
+    f: js_sys::Function,
+    interval_ms: f64,
+    last_ms: Option<f64>,
+}
+
+/// A JS `Error` with `kind: "UnknownUid"` and the `uid`, so callers can tell it apart from other failures.
+impl From<UnknownUid> for JsValue {
+    fn from(x: UnknownUid) -> Self {
+        let error = js_sys::Error::new(&x.to_string());
+        let _ = js_sys::Reflect::set(&error, &JsValue::from_str("kind"), &JsValue::from_str("UnknownUid"));
+        let _ = js_sys::Reflect::set(&error, &JsValue::from_str("uid"), &JsValue::from(x.uid));
+        error.into()
+    }
+}
+
+/// A JS `Error` with `kind: "InternalError"`, for a panic caught while processing a frame.
+impl From<InternalError> for JsValue {
+    fn from(x: InternalError) -> Self {
+        let error = js_sys::Error::new(&x.to_string());
+        let _ = js_sys::Reflect::set(&error, &JsValue::from_str("kind"), &JsValue::from_str("InternalError"));
+        error.into()
+    }
+}
+
+/// What a promise of `fetch_value` resolves with.
+fn fetched_value(timestamp: i64, data: &Nt4Data, cached: bool) -> Result<JsValue, JsValue> {
+    let value = js_sys::Object::new();
+    js_sys::Reflect::set(&value, &"timestamp".into(), &JsValue::from(timestamp))?;
+    js_sys::Reflect::set(&value, &"value".into(), &serde_wasm_bindgen::to_value(data)?)?;
+    js_sys::Reflect::set(&value, &"cached".into(), &JsValue::from(cached))?;
+    Ok(value.into())
+}
+
+/// `x` as JSON would have it, with plain objects and `null`s, and integer properties past
+/// [`MAX_SAFE_INTEGER`] as `BigInt`s, see [`types::for_js`].
+fn to_js(x: &impl serde::Serialize) -> Result<JsValue, serde_wasm_bindgen::Error> {
+    types::for_js(|| x.serialize(&serde_wasm_bindgen::Serializer::json_compatible()))
+}
+
+/// Largest integer a JS number holds exactly, `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// A value from JS to send. A `Uint8Array` is a raw value, which the untagged [`types::Nt4Data`]
+/// cannot take from `serde_wasm_bindgen` itself.
+fn js_data(data: JsValue) -> Result<types::Nt4Data, serde_wasm_bindgen::Error> {
+    match data.dyn_ref::<js_sys::Uint8Array>() {
+        Some(bytes) => Ok(types::Nt4Data::Raw(bytes.to_vec().into())),
+        None => serde_wasm_bindgen::from_value(data),
+    }
+}
+
+/// A timestamp from JS in whole microseconds, `undefined`/`null` for none. Numbers beyond
+/// [`MAX_SAFE_INTEGER`] may already have been rounded, so those must be passed as a `BigInt`.
+fn timestamp_us(timestamp: &JsValue) -> Result<Option<i64>, JsValue> {
+    if timestamp.is_undefined() || timestamp.is_null() {
+        return Ok(None);
+    }
+    if timestamp.is_bigint() {
+        return i64::try_from(timestamp.clone())
+            .map(Some)
+            .map_err(|_| JsString::from("Invalid timestamp: BigInt out of the 64-bit range").into());
+    }
+    match timestamp.as_f64() {
+        Some(x) if x.is_finite() && x.fract() == 0.0 && x.abs() <= MAX_SAFE_INTEGER => Ok(Some(x as i64)),
+        Some(x) if x.is_finite() && x.fract() == 0.0 => Err(JsString::from(format!(
+            "Imprecise timestamp: {} is beyond Number.MAX_SAFE_INTEGER, pass it as a BigInt",
+            x
+        ))
+        .into()),
+        _ => Err(JsString::from(format!("Invalid timestamp: {:?}", timestamp)).into()),
+    }
+}
 
-    fn new_uid(&mut self) -> i32 {
-        let next = self.uid_cnt;
-        self.uid_cnt += 1;
-        next
+impl Nt4Connection {
+    pub(crate) fn topics(&self) -> Vec<Topic> {
+        self.inner.borrow().core.topics().map(|(_, topic)| topic.clone()).collect()
     }
 }
 
 #[wasm_bindgen]
 impl Nt4Connection {
+    #[doc = " set_send_text_fn(function send_text_fn)\n"]
+    #[doc = " Sets the function text frames are sent with, first sending those kept from calls made before it was set."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_send_text_fn(&mut self, f: js_sys::Function) -> Result<(), JsValue> {
+        borrow_inner(&self.inner)?.callbacks.set_send_text_fn(f)
+    }
+
     #[doc = " unsubscribe(int id)\n"]
+    #[doc = " Throws an `Error` with `kind: \"UnknownUid\"` if `id` is not an active subscription, unless {@link set_idempotent}."]
     #[doc = " @param {number} id - topic id recieved from a {@link subscribe} call."]
     #[wasm_bindgen(skip_jsdoc)]
     pub fn unsubscribe(&mut self, id: i32) -> Result<(), JsValue> {
-        expect_available! { self send_text_fn {
-            let data = text::ClientToServerTextDataFrame::Unsubscribe(UnsubscribeParams { subuid: id });
-            let data = serde_json::to_string(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
+        with_core(&self.inner, |core, sink| {
+            core.unsubscribe(sink, id)?;
+            sink.callbacks.groups.remove(&id);
             Ok(())
-        } }
+        })
     }
 
     pub fn subscribe(&mut self, path: &str, options: JsValue) -> Result<i32, JsValue> {
         let options = serde_wasm_bindgen::from_value(options)?;
-        expect_available! { self send_text_fn {
-            let id = self.new_uid();
-            let data = text::ClientToServerTextDataFrame::Subscribe(
-                SubscribeParams {
-                    topics: vec![path.to_string()],
-                    subuid: id,
-                    options,
-                },
-            );
-            let data = serde_json::to_string(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
+        with_core(&self.inner, |core, sink| core.subscribe(sink, path, options))
+    }
+
+    #[doc = " subscribe_group(string[] names, number window_us, function f, number? timeout_us) -> int\n"]
+    #[doc = " Calls `f(members, complete)` with the values of `names` nearest in server time to each value of the slowest one."]
+    #[doc = " @param {number} window_us - how close in server time the members must be for `complete` to be `true`."]
+    #[doc = " @param {number} [timeout_us] - after which `f` gets the freshest values with `complete` `false`, 1 s by default."]
+    #[doc = " @returns {number} the subuid, for {@link unsubscribe}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn subscribe_group(
+        &mut self,
+        names: Vec<String>,
+        window_us: f64,
+        f: js_sys::Function,
+        timeout_us: Option<f64>,
+    ) -> Result<i32, JsValue> {
+        let timeout_us = timeout_us.unwrap_or(1e6) as i64;
+        with_core(&self.inner, |core, sink| {
+            let id = core.subscribe_group(sink, names, window_us as i64, timeout_us)?;
+            sink.callbacks.groups.insert(id, f);
             Ok(id)
-        } }
+        })
+    }
+
+    #[doc = " add_watch(string id, object spec)\n"]
+    #[doc = " Calls `watch_fn(id, passed, details)` whenever the condition `spec` starts passing or failing, replacing any watch `id`."]
+    #[doc = " @param {object} spec - `{kind: \"compare\", topic, op, value}`, `{kind: \"compare_topics\", left, op, right}`, `{kind: \"within_after_change\", trigger, topic, op, value, within_ms}` or `{kind: \"stays_in_range\", topic, min, max, for_ms}`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn add_watch(&mut self, id: &str, spec: JsValue) -> Result<(), JsValue> {
+        let spec = serde_wasm_bindgen::from_value(spec)?;
+        self.inner.borrow_mut().core.add_watch(id, spec).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " remove_watch(string id) -> bool\n"]
+    #[doc = " Stops checking the watch `id`, returning whether there was one."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn remove_watch(&mut self, id: &str) -> bool {
+        self.inner.borrow_mut().core.remove_watch(id)
+    }
+
+    #[doc = " add_alert(string name, {below?, above?, for_ms?, hysteresis?, reduce?, missing_ms?} spec)\n"]
+    #[doc = " Calls `alert_fn(name, active, value, since_us)` when the numeric topic `name` goes out of range for `for_ms`, and back."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn add_alert(&mut self, name: &str, spec: JsValue) -> Result<(), JsValue> {
+        let spec = serde_wasm_bindgen::from_value(spec)?;
+        self.inner.borrow_mut().core.add_alert(name, spec).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " remove_alert(string name) -> bool\n"]
+    #[doc = " Stops checking `name`, returning whether it had an alert."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn remove_alert(&mut self, name: &str) -> bool {
+        self.inner.borrow_mut().core.remove_alert(name)
+    }
+
+    #[doc = " alerts() -> Array<{name, below?, above?, for_ms, hysteresis, reduce?, missing_ms?, active}>\n"]
+    #[doc = " Every alert of {@link add_alert}, sorted by name."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn alerts(&self) -> Result<JsValue, JsValue> {
+        let alerts = self.inner.borrow().core.alerts();
+        Ok(to_js(&alerts)?)
+    }
+
+    #[doc = " Every alert spec as a JSON object keyed by topic name, for {@link import_alerts}."]
+    pub fn export_alerts(&self) -> Result<String, JsValue> {
+        self.inner.borrow().core.export_alerts().map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " import_alerts(string json)\n"]
+    #[doc = " Replaces every alert with those in `json`, as produced by {@link export_alerts}, or throws and changes nothing."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn import_alerts(&mut self, json: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.import_alerts(json).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " set_server_time_reset_options(object options)\n"]
+    #[doc = " Tunes how a restart of the server's clock is detected, calling `server_time_reset_fn(old_estimate, new_estimate)`."]
+    #[doc = " @param {object} options - `{enabled: true, threshold_s: 5, window_s: 1, min_topics: 3}` by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_server_time_reset_options(&mut self, options: JsValue) -> Result<(), JsValue> {
+        let options = serde_wasm_bindgen::from_value(options)?;
+        self.inner.borrow_mut().core.set_server_time_reset_options(options);
+        Ok(())
+    }
+
+    #[doc = " register_subscription_profile(string name, object options)\n"]
+    #[doc = " Registers (or replaces) named subscribe options for {@link subscribe_with_profile}; `plot`, `display` and `browse` exist by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn register_subscription_profile(&mut self, name: &str, options: JsValue) -> Result<(), JsValue> {
+        let options = serde_wasm_bindgen::from_value(options)?;
+        self.inner.borrow_mut().core.register_subscription_profile(name, options);
+        Ok(())
+    }
+
+    #[doc = " subscribe_with_profile(string path, string profile) -> number\n"]
+    #[doc = " As {@link subscribe}, with the options of a profile registered by {@link register_subscription_profile}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn subscribe_with_profile(&mut self, path: &str, profile: &str) -> Result<i32, JsValue> {
+        with_core(&self.inner, |core, sink| core.subscribe_with_profile(sink, path, profile))
+    }
+
+    #[doc = " update_all_with_profile(string profile, object options) -> number\n"]
+    #[doc = " Replaces the options of `profile` and updates every active subscription made from it in place."]
+    #[doc = " @returns {number} how many subscriptions were updated."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn update_all_with_profile(&mut self, profile: &str, options: JsValue) -> Result<usize, JsValue> {
+        let options = serde_wasm_bindgen::from_value(options)?;
+        with_core(&self.inner, |core, sink| core.update_all_with_profile(sink, profile, options))
+    }
+
+    #[doc = " Every subscription profile as a JSON object keyed by name, for {@link import_subscription_profiles}."]
+    pub fn export_subscription_profiles(&self) -> Result<String, JsValue> {
+        self.inner.borrow().core.export_subscription_profiles().map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " import_subscription_profiles(string json)\n"]
+    #[doc = " Registers every profile in `json`, as produced by {@link export_subscription_profiles}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn import_subscription_profiles(&mut self, json: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.import_subscription_profiles(json).map_err(|x| JsString::from(x).into())
     }
 
     pub fn unpublish(&mut self, id: i32) -> Result<(), JsValue> {
-        expect_available! { self send_text_fn {
-            let data = text::ClientToServerTextDataFrame::Unpublish(UnpublishParams {
-                pubuid: id
-            });
-            let data = serde_json::to_string(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
-            Ok(())
-        } }
+        with_core(&self.inner, |core, sink| core.unpublish(sink, id))
     }
 
     pub fn publish(
@@ -151,112 +723,1042 @@ impl Nt4Connection {
         ty: JsValue,
         properties: JsValue,
     ) -> Result<i32, JsValue> {
-        let ty = serde_wasm_bindgen::from_value(ty)?;
-        let properties = serde_wasm_bindgen::from_value(properties)?;
-        expect_available! { self send_text_fn {
-            let id = self.new_uid();
-            let data = text::ClientToServerTextDataFrame::Publish(PublishParams {
-                name: name.to_string(),
-                properties,
-                pubuid: id,
-                ty,
-            });
-            let data = serde_json::to_string(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
-            Ok(id)
-        } }
+        let ty: Nt4TypeId = serde_wasm_bindgen::from_value(ty)?;
+        with_core(&self.inner, |core, sink| {
+            let properties = js_properties::convert(sink, |x| x.properties(&properties))?;
+            core.publish(sink, name, ty, properties)
+        })
     }
 
     pub fn set_properties(&mut self, name: &str, update: JsValue) -> Result<(), JsValue> {
-        let update = serde_wasm_bindgen::from_value(update)?;
-        expect_available! { self send_text_fn {
-            let data = text::ClientToServerTextDataFrame::SetProperties(SetPropertiesParams {
-                name: name.to_string(),
-                update
-            });
-            let data = serde_json::to_string(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            send_text_fn.call1(&JsValue::NULL, &JsString::from(data))?;
-            Ok(())
-        } }
+        with_core(&self.inner, |core, sink| {
+            let update = js_properties::convert(sink, |x| x.partial_properties(&update))?;
+            core.set_properties(sink, name, update)
+        })
+    }
+
+    #[doc = " update_topic_property(string name, string key, any value)\n"]
+    #[doc = " Sets the single property `key` of `name`, or deletes it when `value` is `null`, by sending only that key."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn update_topic_property(&mut self, name: &str, key: &str, value: JsValue) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| {
+            // Unlike inside an object, a skipped value has nothing to fall back to.
+            let value = js_properties::convert(sink, |x| x.value(key, &value))?
+                .ok_or_else(|| JsString::from(format!("property {:?} cannot be a function or undefined", key)))?;
+            core.update_topic_property(sink, name, key, value)
+        })
+    }
+
+    #[doc = " set_max_properties_bytes(number bytes)\n"]
+    #[doc = " Makes {@link publish}, {@link set_properties} and {@link update_topic_property} throw on properties over `bytes` of JSON, 16 KiB by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_max_properties_bytes(&mut self, bytes: usize) {
+        self.inner.borrow_mut().core.set_max_properties_bytes(bytes);
+    }
+
+    #[doc = " get_topic_properties(string name) -> object?\n"]
+    #[doc = " Every property of `name`, or `undefined` if it is not announced; integers past `Number.MAX_SAFE_INTEGER` are `BigInt`s."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn get_topic_properties(&self, name: &str) -> Result<JsValue, JsValue> {
+        match self.inner.borrow().core.topic_properties(name) {
+            Some(properties) => Ok(to_js(properties)?),
+            None => Ok(JsValue::UNDEFINED),
+        }
     }
 
     pub fn timesync(&mut self) -> Result<(), JsValue> {
-        expect_available! { self send_binary_fn {
-            let now = self.now()?;
-            let data = binary::BinaryDataFrame::timesync(now);
-            let data = rmp_serde::to_vec(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            let data = serde_wasm_bindgen::to_value(&data)?;
-            send_binary_fn.call1(&JsValue::NULL, &data)?;
-            Ok(())
-        } }
+        with_core(&self.inner, |core, sink| core.timesync(sink))
     }
 
     pub fn on_binary(&mut self, data_frame: Vec<u8>) -> Result<(), JsValue> {
-        let data_frame: binary::BinaryDataFrame =
-            rmp_serde::from_slice(&data_frame).map_err(|x| JsString::from(format!("{:?}", x)))?;
-        expect_available! { self on_data_fn, ready_fn {
-            if data_frame.topic_id == -1 {
-                if let Some(local_time) = data_frame.data.as_int() {
-                    let local_time = Duration::microseconds(*local_time);
-                    let server_time = Duration::microseconds(data_frame.timestamp);
-                    let now = Duration::microseconds(self.now()?);
-                    let rtt_2 = (now - local_time) / 2;
-                    self.offs = (server_time - rtt_2 - local_time).num_microseconds().unwrap();
-                    ready_fn.call0(&JsValue::NULL)?;
-                    Ok(())
-                } else {
-                    Err(JsString::from(format!("Invalid timesync dataframe: {:?}", data_frame)).into())
-                }
-            } else {
-                let data = serde_wasm_bindgen::to_value(&data_frame.data)?;
-                on_data_fn.call3(&JsValue::NULL, &JsValue::from(data_frame.topic_id), &JsValue::from(data_frame.timestamp), &data)?;
-                Ok(())
-            }
-        }}
+        with_core(&self.inner, |core, sink| core.on_binary(sink, &data_frame))
+    }
+
+    #[doc = " on_binary_chunked(Uint8Array data_frame, number budget_frames) -> number\n"]
+    #[doc = " Like {@link on_binary}, but processes at most `budget_frames` values now."]
+    #[doc = " @returns {number} a handle for {@link continue_processing} to go on with the rest."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn on_binary_chunked(&mut self, data_frame: Vec<u8>, budget_frames: usize) -> Result<u32, JsValue> {
+        with_core(&self.inner, |core, sink| core.on_binary_chunked(sink, data_frame, budget_frames))
+    }
+
+    #[doc = " continue_processing(number handle, number budget_frames) -> {done}\n"]
+    #[doc = " Processes at most `budget_frames` more values of the message of `handle` from {@link on_binary_chunked}."]
+    #[doc = " @returns {object} `{done}`, `true` once no values are left."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn continue_processing(&mut self, handle: u32, budget_frames: usize) -> Result<JsValue, JsValue> {
+        let done = with_core(&self.inner, |core, sink| core.continue_processing(sink, handle, budget_frames))?;
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"done".into(), &JsValue::from(done))?;
+        Ok(result.into())
     }
 
     pub fn on_text(&mut self, data_frame: String) -> Result<(), JsValue> {
-        let data_frame: text::ServerToClientTextDataFrame = serde_json::from_str(&data_frame).map_err(|x| JsString::from(format!("{:?}", x)))?;
-        match data_frame {
-            text::ServerToClientTextDataFrame::Announce(ann) => {
-                expect_available! { self announce_fn {
-                    let data = serde_wasm_bindgen::to_value(&Topic { name: ann.name, ty: ann.ty })?;
-                    announce_fn.call1(&JsValue::NULL, &data)?;
-                    Ok(())
-                } }
-            },
-            text::ServerToClientTextDataFrame::Unannounce(unann) => {
-                expect_available! { self unannounce_fn {
-                    let data = JsString::from(unann.name);
-                    unannounce_fn.call1(&JsValue::NULL, &data)?;
-                    Ok(())
-                } }
-            },
-            text::ServerToClientTextDataFrame::Properties(_) => {
-                /* IDK what happens here */
-                Ok(())
-            },
-            
-        }
+        with_core(&self.inner, |core, sink| core.on_text(sink, &data_frame))
+    }
+
+    #[doc = " quarantined() -> Array<{message, text} | {message, binary}>\n"]
+    #[doc = " The last 16 frames whose processing panicked, oldest first, with the panic message, for bug reports."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn quarantined(&self) -> Result<JsValue, JsValue> {
+        let frames = self.inner.borrow().core.quarantined();
+        Ok(serde::Serialize::serialize(&frames, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    pub fn clear_quarantine(&mut self) {
+        self.inner.borrow_mut().core.clear_quarantine();
     }
 
     pub fn on_disconnect(&mut self) -> Result<(), JsValue> {
-        expect_available! { self unready_fn {
-            unready_fn.call0(&JsValue::NULL)?;
-            Ok(())
-        } }
+        with_core(&self.inner, |core, sink| core.on_disconnect(sink))
     }
 
-    pub fn send_data(&mut self, topic_id: i32, data: JsValue) -> Result<(), JsValue> {
-        let inner_data: types::Nt4Data = serde_wasm_bindgen::from_value(data)?;
-        expect_available! { self send_binary_fn {
-            let now = self.now()?;
-            let data = binary::BinaryDataFrame { data: inner_data, timestamp: now + self.offs, topic_id };
-            let data = rmp_serde::to_vec(&data).map_err(|x| JsString::from(format!("{:?}", x)))?;
-            let data = serde_wasm_bindgen::to_value(&data)?;
-            send_binary_fn.call1(&JsValue::NULL, &data)?;
+    #[doc = " send_data(int topic_id, any data, (number | bigint)? timestamp)\n"]
+    #[doc = " @param {number | bigint} [timestamp] - server time in µs, as {@link set_timestamp_mode} requires."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn send_data(&mut self, topic_id: i32, data: JsValue, timestamp: JsValue) -> Result<(), JsValue> {
+        let Some(data) = self.apply_send_policies(data, "")? else {
+            return Ok(());
+        };
+        let inner_data = js_data(data)?;
+        let timestamp = timestamp_us(&timestamp)?;
+        with_core(&self.inner, |core, sink| core.send_data(sink, topic_id, inner_data, timestamp))
+    }
+
+    #[doc = " send_atomic(Array<{topic_id: number, data: any}> entries, (number | bigint)? timestamp)\n"]
+    #[doc = " Sends every value in a single binary message with one shared timestamp, either all of them or none."]
+    #[doc = " @param {number | bigint} [timestamp] - as in {@link send_data}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn send_atomic(&mut self, entries: JsValue, timestamp: JsValue) -> Result<(), JsValue> {
+        if !js_sys::Array::is_array(&entries) {
+            return Err(JsString::from("entries must be an array").into());
+        }
+        let mut sanitized = Vec::new();
+        for (i, entry) in entries.unchecked_ref::<js_sys::Array>().iter().enumerate() {
+            let data = js_sys::Reflect::get(&entry, &"data".into())?;
+            let Some(data) = self.apply_send_policies(data, &format!("entry {}: ", i))? else {
+                return Ok(());
+            };
+            let topic_id = js_sys::Reflect::get(&entry, &"topic_id".into())?;
+            let topic_id = serde_wasm_bindgen::from_value(topic_id)?;
+            sanitized.push(types::AtomicEntry { topic_id, data: js_data(data)? });
+        }
+        let entries = sanitized;
+        let timestamp = timestamp_us(&timestamp)?;
+        with_core(&self.inner, |core, sink| core.send_atomic(sink, entries, timestamp))
+    }
+
+    #[doc = " send_struct_of_values(string prefix, object values, (number | bigint)? timestamp)\n"]
+    #[doc = " Sends each `values[key]` to the published topic `<prefix>/<key>`, as in {@link send_atomic}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn send_struct_of_values(
+        &mut self,
+        prefix: &str,
+        values: js_sys::Object,
+        timestamp: JsValue,
+    ) -> Result<(), JsValue> {
+        let timestamp = timestamp_us(&timestamp)?;
+        let mut entries = Vec::new();
+        for entry in js_sys::Object::entries(&values).iter() {
+            let entry: js_sys::Array = entry.unchecked_into();
+            let key = entry.get(0).as_string().unwrap_or_default();
+            let Some(data) = self.apply_send_policies(entry.get(1), &format!("{}: ", key))? else {
+                return Ok(());
+            };
+            let data = js_data(data)
+                .map_err(|x| JsString::from(format!("{}: {}", key, x)))?;
+            entries.push((key, data));
+        }
+        with_core(&self.inner, |core, sink| core.send_struct_of_values(sink, prefix, entries, timestamp))
+    }
+
+    #[doc = " set_send_policies({nan_policy?, null_policy?} policies)\n"]
+    #[doc = " What {@link send_data} and the like do with a NaN, or a `null`, in a number or an array of numbers."]
+    #[doc = " @param {object} policies - each `\"error\"`, `\"skip_message\"`, `{replace: number}` or, for NaN only, `\"allow\"`; `{nan_policy: \"allow\", null_policy: \"error\"}` by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_send_policies(&mut self, policies: JsValue) -> Result<(), JsValue> {
+        let policies: SendPolicies = serde_wasm_bindgen::from_value(policies)?;
+        policies.check().map_err(JsString::from)?;
+        self.inner.borrow_mut().send_policies = policies;
+        Ok(())
+    }
+
+    /// `data` with the send policies applied, `None` if the message is to be skipped. Errors start with `context`.
+    fn apply_send_policies(&self, data: JsValue, context: &str) -> Result<Option<JsValue>, JsValue> {
+        let policies = self.inner.borrow().send_policies;
+        policies.apply(data).map_err(|x| JsString::from(format!("{}{}", context, x)).into())
+    }
+
+    #[doc = " close()\n"]
+    #[doc = " Unsubscribes and unpublishes everything, best-effort, then drops every callback; call it before `free()`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn close(&mut self) {
+        let inner = &mut *self.inner.borrow_mut();
+        let (core, mut sink) = inner.split();
+        core.close(&mut sink);
+        inner.callbacks.release();
+        inner.virtual_clients.clear();
+    }
+
+    #[doc = " virtual_client(string name) -> Nt4VirtualClient\n"]
+    #[doc = " A client for one widget that shares this connection's socket and timesync but has its own ids and callbacks."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn virtual_client(&mut self, name: &str) -> Result<Nt4VirtualClient, JsValue> {
+        let id = with_core(&self.inner, |core, sink| {
+            core.check_open()?;
+            Ok(sink.virtual_clients.add(name))
+        })?;
+        Ok(Nt4VirtualClient { id, inner: self.inner.clone() })
+    }
+
+    #[doc = " self_test() -> Promise<{announce_ms, echo_ms, rtt_us, offset_us, warnings}>\n"]
+    #[doc = " Checks that a retained topic round-trips through the server, rejecting with `{stage, message}` on failure or after 5 s."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn self_test(&mut self) -> Result<js_sys::Promise, JsValue> {
+        let mut resolvers = None;
+        let promise = js_sys::Promise::new(&mut |resolve, reject| resolvers = Some((resolve, reject)));
+        with_core(&self.inner, |core, sink| {
+            if sink.callbacks.self_test.is_some() {
+                return Err(JsString::from("a self test is already running").into());
+            }
+            sink.callbacks.self_test = resolvers;
+            let started = core.start_self_test(sink);
+            if started.is_err() {
+                sink.callbacks.self_test = None;
+            }
+            started
+        })?;
+        Ok(promise)
+    }
+
+    #[doc = " fetch_value(string name, number timeout_ms, number? max_age_ms) -> Promise<{timestamp, value, cached}>\n"]
+    #[doc = " The current value of `name`, without touching its subscriptions, or a cached one at most `max_age_ms` old."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn fetch_value(&mut self, name: &str, timeout_ms: f64, max_age_ms: Option<f64>) -> Result<js_sys::Promise, JsValue> {
+        let mut resolvers = None;
+        let promise = js_sys::Promise::new(&mut |resolve, reject| resolvers = Some((resolve, reject)));
+        let Some((resolve, reject)) = resolvers else {
+            return Err(JsString::from("the promise was not set up").into());
+        };
+        with_core(&self.inner, |core, sink| {
+            if let Some(max_age_ms) = max_age_ms {
+                if let Some((timestamp, data)) = core.fresh_value(name, (max_age_ms * 1000.0) as i64)? {
+                    resolve.call1(&JsValue::NULL, &fetched_value(timestamp, &data, true)?)?;
+                    return Ok(());
+                }
+            }
+            let id = core.start_fetch(sink, name, (timeout_ms * 1000.0) as i64)?;
+            sink.callbacks.fetches.insert(id, (resolve, reject));
             Ok(())
-        } }
+        })?;
+        Ok(promise)
+    }
+
+    #[doc = " export_persistent() -> Promise<{version, entries: [{name, type, properties, value}], missing}>\n"]
+    #[doc = " Backs up every persistent topic on the server, with the topics still without a value after 5 s in `missing`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn export_persistent(&mut self) -> Result<js_sys::Promise, JsValue> {
+        self.start_persistent(|core, sink| core.start_persistent_export(sink))
+    }
+
+    #[doc = " import_persistent({version, entries, missing?} backup) -> Promise<{restored, failed, results: [{name, error}]}>\n"]
+    #[doc = " Restores a backup from {@link export_persistent}, publishing each entry as persistent and unpublishing it again."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn import_persistent(&mut self, backup: JsValue) -> Result<js_sys::Promise, JsValue> {
+        let backup: PersistentBackup = serde_wasm_bindgen::from_value(backup)?;
+        if backup.version != persistent::VERSION {
+            return Err(JsString::from(format!(
+                "persistent backup version {} is not supported, only {}",
+                backup.version,
+                persistent::VERSION
+            ))
+            .into());
+        }
+        self.start_persistent(|core, sink| core.start_persistent_import(sink, backup.entries))
+    }
+
+    fn start_persistent(
+        &mut self,
+        start: impl FnOnce(&mut ConnectionCore, &mut Router) -> Result<(), JsValue>,
+    ) -> Result<js_sys::Promise, JsValue> {
+        let mut resolvers = None;
+        let promise = js_sys::Promise::new(&mut |resolve, reject| resolvers = Some((resolve, reject)));
+        with_core(&self.inner, |core, sink| {
+            sink.callbacks.persistent = resolvers;
+            let started = start(core, sink);
+            if started.is_err() {
+                sink.callbacks.persistent = None;
+            }
+            started
+        })?;
+        Ok(promise)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.borrow().core.is_closed()
+    }
+
+    #[doc = " set_topic_validator(string name, {min?, max?, allowed_values?, max_rate_of_change_per_s?, mode?} validator)\n"]
+    #[doc = " Checks every value we send to `name`, rejecting violations or, with `mode: \"clamp\"`, clamping numbers into range."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_topic_validator(&mut self, name: &str, validator: JsValue) -> Result<(), JsValue> {
+        let validator = serde_wasm_bindgen::from_value(validator)?;
+        self.inner.borrow_mut().core.set_topic_validator(name, validator);
+        Ok(())
+    }
+
+    pub fn remove_topic_validator(&mut self, name: &str) {
+        self.inner.borrow_mut().core.remove_topic_validator(name);
+    }
+
+    #[doc = " All validators as a JSON object keyed by topic name, for {@link import_validators}."]
+    pub fn export_validators(&self) -> Result<String, JsValue> {
+        self.inner.borrow().core.export_validators().map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " import_validators(string json)\n"]
+    #[doc = " Replaces every validator with those in `json`, as produced by {@link export_validators}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn import_validators(&mut self, json: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.import_validators(json).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " attach_announce_fn_with_replay(function f)\n"]
+    #[doc = " Calls `f` for every currently announced topic, sorted by name, then installs it as `announce_fn`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn attach_announce_fn_with_replay(&mut self, f: js_sys::Function) -> Result<(), JsValue> {
+        let inner = &mut *self.inner.borrow_mut();
+        inner.core.check_open().map_err(JsString::from)?;
+        for topic in inner.core.sorted_topics() {
+            let data = serde_wasm_bindgen::to_value(topic)?;
+            f.call1(&JsValue::NULL, &data)?;
+        }
+        inner.callbacks.announce_fn = Some(f);
+        Ok(())
+    }
+
+    #[doc = " momentary(string name) -> int\n"]
+    #[doc = " Publishes `name` as a non-retained boolean for a button that is `true` only while held."]
+    #[doc = " @returns {number} a handle for {@link press} and {@link release}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn momentary(&mut self, name: &str) -> Result<i32, JsValue> {
+        with_core(&self.inner, |core, sink| core.momentary(sink, name))
+    }
+
+    pub fn press(&mut self, id: i32) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.press(sink, id))
+    }
+
+    pub fn release(&mut self, id: i32) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.release(sink, id))
+    }
+
+    #[doc = " log_channel(string name, object options?) -> int\n"]
+    #[doc = " Publishes `name` as a non-retained string for rate limited console-style messages sent with {@link log}."]
+    #[doc = " @param {object} [options] - `{max_msgs_per_s: 20, max_bytes_per_s: 4096, queue_limit: 256}` by default."]
+    #[doc = " @returns {number} a handle for {@link log}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn log_channel(&mut self, name: &str, options: JsValue) -> Result<i32, JsValue> {
+        let options: Option<LogOptions> = serde_wasm_bindgen::from_value(options)?;
+        with_core(&self.inner, |core, sink| core.log_channel(sink, name, options.unwrap_or_default()))
+    }
+
+    #[doc = " log(int id, string message)\n"]
+    #[doc = " Queues `message` on a {@link log_channel}, prefixed with the server time as `[hh:mm:ss.mmm] `."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn log(&mut self, id: i32, message: &str) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.log(sink, id, message))
+    }
+
+    #[doc = " Sends the messages of every {@link log_channel} that the limits allow right now."]
+    pub fn flush_logs(&mut self) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.flush_logs(sink))
+    }
+
+    #[doc = " log_counts(int id) -> {queued, dropped}\n"]
+    #[doc = " Messages of a {@link log_channel} waiting to be sent, and dropped so far."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn log_counts(&self, id: i32) -> Result<JsValue, JsValue> {
+        let (queued, dropped) =
+            self.inner.borrow().core.log_counts(id).ok_or_else(|| JsString::from(format!("{} is not a log channel", id)))?;
+        let counts = serde_json::json!({"queued": queued, "dropped": dropped});
+        Ok(serde::Serialize::serialize(&counts, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    #[doc = " Sends `false` for every held momentary."]
+    pub fn release_all_momentaries(&mut self) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.release_all_momentaries(sink))
+    }
+
+    #[doc = " toggle(string name) -> boolean\n"]
+    #[doc = " Publishes the inverse of the most recent value of the boolean topic `name`, whether received or sent by us."]
+    #[doc = " @returns {boolean} the value sent."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn toggle(&mut self, name: &str) -> Result<bool, JsValue> {
+        with_core(&self.inner, |core, sink| core.toggle(sink, name))
+    }
+
+    #[doc = " get_local_time_us() -> number\n"]
+    #[doc = " @returns {number} microseconds on the local clock used for outgoing timestamps."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn get_local_time_us(&mut self) -> Result<f64, JsValue> {
+        Ok(self.inner.borrow_mut().core.now().map_err(JsString::from)? as f64)
+    }
+
+    #[doc = " local_to_server_us(number local_us) -> number\n"]
+    #[doc = " @param {number} local_us - a time received from {@link get_local_time_us}."]
+    #[doc = " @returns {number} the same instant on the server clock, using the last timesync offset."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn local_to_server_us(&mut self, local_us: f64) -> Result<f64, JsValue> {
+        Ok(local_us + self.inner.borrow_mut().core.offset() as f64)
+    }
+
+    #[doc = " set_diff_mode(string name, boolean enabled)\n"]
+    #[doc = " While enabled, array values of `name` reach `on_data_fn` as `{base_revision, revision, changes: [{start, values}], full}`"]
+    #[doc = " instead of the whole array. `changes` holds the runs of elements that differ from revision `base_revision`,"]
+    #[doc = " numbers compared bit for bit: a `NaN` that stays `NaN` is unchanged, `0` becoming `-0` is a change."]
+    #[doc = " A full snapshot (`base_revision` and `changes` empty, the array in `full`) is sent for the first value, after"]
+    #[doc = " a reconnect or re-announce, when the length or type changes, and after {@link request_full}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_diff_mode(&mut self, name: &str, enabled: bool) {
+        self.inner.borrow_mut().core.set_diff_mode(name, enabled);
+    }
+
+    #[doc = " request_full(string name)\n"]
+    #[doc = " Makes the next value of `name`, which must be in {@link set_diff_mode}, a full snapshot."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn request_full(&mut self, name: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.request_full(name).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " pause_delivery(boolean? announces)\n"]
+    #[doc = " Stops calling `on_data_fn` and group callbacks, and with `announces` the announce callbacks, while still processing frames."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn pause_delivery(&mut self, announces: Option<bool>) -> Result<(), JsValue> {
+        let paused = self.inner.borrow_mut().core.pause_delivery(announces.unwrap_or(false));
+        paused.map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " resume_delivery(boolean catch_up)\n"]
+    #[doc = " Ends {@link pause_delivery}; with `catch_up`, `on_data_fn` gets the latest value of every topic that got one meanwhile."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn resume_delivery(&mut self, catch_up: bool) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.resume_delivery(sink, catch_up))
+    }
+
+    pub fn is_delivery_paused(&self) -> bool {
+        self.inner.borrow().core.is_delivery_paused()
+    }
+
+    #[doc = " set_client_metadata_prefix(string prefix)\n"]
+    #[doc = " Where {@link publish_client_metadata} publishes, e.g. `/Dashboards/<client name>`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_client_metadata_prefix(&mut self, prefix: &str) {
+        self.inner.borrow_mut().core.set_client_metadata_prefix(prefix);
+    }
+
+    #[doc = " publish_client_metadata(boolean enabled)\n"]
+    #[doc = " While enabled, publishes our subscriptions and publications as `<prefix>/subscriptions` and `<prefix>/publications`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn publish_client_metadata(&mut self, enabled: bool) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.publish_client_metadata(sink, enabled))
+    }
+
+    #[doc = " set_timestamp_mode(\"live\" | \"passthrough\" | \"fixed_offset\" mode)\n"]
+    #[doc = " Where sent timestamps come from: the synced local clock for `live` (default), or the one passed with each call."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_timestamp_mode(&mut self, mode: JsValue) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.set_timestamp_mode(serde_wasm_bindgen::from_value(mode)?);
+        Ok(())
+    }
+
+    #[doc = " set_value_encoding(\"msgpack\" | \"json\" encoding)\n"]
+    #[doc = " How values and timesync requests are sent: `msgpack` (default) in binary frames, or `json` in text frames."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_value_encoding(&mut self, encoding: JsValue) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.set_value_encoding(serde_wasm_bindgen::from_value(encoding)?);
+        Ok(())
+    }
+
+    #[doc = " set_wire_options({raw?: \"bin\" | \"array\", booleans?: \"bool\" | \"int\", strings?: \"compact\" | \"no_str8\"} options)\n"]
+    #[doc = " How values are encoded in binary frames, for servers that only accept some encodings. Omitted fields take the first choice."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_wire_options(&mut self, options: JsValue) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.set_wire_options(serde_wasm_bindgen::from_value(options)?);
+        Ok(())
+    }
+
+    #[doc = " set_timestamp_offset_us(number | bigint offset_us)\n"]
+    #[doc = " @param {number | bigint} offset_us - added to given timestamps in the `fixed_offset` {@link set_timestamp_mode}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_timestamp_offset_us(&mut self, offset_us: JsValue) -> Result<(), JsValue> {
+        let offset_us = timestamp_us(&offset_us)?.unwrap_or_default();
+        self.inner.borrow_mut().core.set_timestamp_offset(offset_us);
+        Ok(())
+    }
+
+    #[doc = " set_fire_duplicate_announces(boolean fire)\n"]
+    #[doc = " When set, an announce repeating the name and id of an announced topic calls `announce_fn` again."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_fire_duplicate_announces(&mut self, fire: bool) {
+        self.inner.borrow_mut().core.set_fire_duplicate_announces(fire);
+    }
+
+    #[doc = " Pretty-print outgoing text frames so they are readable in browser DevTools. Off by default."]
+    pub fn set_pretty_text_frames(&mut self, pretty: bool) {
+        self.inner.borrow_mut().core.set_pretty_text_frames(pretty);
+    }
+
+    #[doc = " set_debug_validation(boolean enabled)\n"]
+    #[doc = " When enabled, a text frame that breaks what NT4 requires of it throws and is not sent. Off by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_debug_validation(&mut self, enabled: bool) {
+        self.inner.borrow_mut().core.set_debug_validation(enabled);
+    }
+
+    #[doc = " When set, `on_data_fn` is called as `(topic_id, timestamp, data, type)` where `type` is the NT4 type string."]
+    pub fn set_include_type_in_callback(&mut self, b: bool) {
+        self.inner.borrow_mut().callbacks.include_type_in_callback = b;
+    }
+
+    #[doc = " type_mismatch_count(string name) -> number\n"]
+    #[doc = " How many values of `name` arrived with a type that could not be converted to the announced one without loss."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn type_mismatch_count(&self, name: &str) -> f64 {
+        self.inner.borrow().core.type_mismatch_count(name) as f64
+    }
+
+    #[doc = " set_type_mismatch_warn_after(number count)\n"]
+    #[doc = " How many values in a row of {@link type_mismatch_count} make a warning, 10 by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_type_mismatch_warn_after(&mut self, count: u32) {
+        self.inner.borrow_mut().core.set_type_mismatch_warn_after(count);
+    }
+
+    #[doc = " set_max_outgoing_frame_bytes(number? n)\n"]
+    #[doc = " Keeps every message sent to at most `n` bytes, throwing on those that cannot be split; `undefined` removes the limit."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_max_outgoing_frame_bytes(&mut self, n: Option<u32>) {
+        self.inner.borrow_mut().callbacks.max_frame_bytes = n.map(|n| n as usize);
+    }
+
+    #[doc = " set_type_coercions({[name]: string} table)\n"]
+    #[doc = " Delivers values of each topic in `table`, or under a key ending in `/`, as the type given whatever it is announced as."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_type_coercions(&mut self, table: JsValue) -> Result<(), JsValue> {
+        let table = serde_wasm_bindgen::from_value(table)?;
+        self.inner.borrow_mut().core.set_type_coercions(table);
+        Ok(())
+    }
+
+    #[doc = " The table of {@link set_type_coercions} as a JSON object, for {@link import_type_coercions}."]
+    pub fn export_type_coercions(&self) -> Result<String, JsValue> {
+        self.inner.borrow().core.export_type_coercions().map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " import_type_coercions(string json)\n"]
+    #[doc = " Replaces the table of {@link set_type_coercions} with `json`, as produced by {@link export_type_coercions}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn import_type_coercions(&mut self, json: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.import_type_coercions(json).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " load_manifest(Array<{name, type, units?, description?}> manifest, \"warn\" | \"error\" strictness?)\n"]
+    #[doc = " Checks publishes and subscribes from now on against `manifest`, every topic the robot is expected to have."]
+    #[doc = " @param {\"warn\" | \"error\"} [strictness] - `\"warn\"` by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn load_manifest(&mut self, manifest: JsValue, strictness: JsValue) -> Result<(), JsValue> {
+        let topics = serde_wasm_bindgen::from_value(manifest)?;
+        let strictness: Option<ManifestStrictness> = serde_wasm_bindgen::from_value(strictness)?;
+        let mut inner = self.inner.borrow_mut();
+        inner.core.load_manifest(topics, strictness.unwrap_or_default()).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " manifest_topics() -> Array<{name, type, units, description}>\n"]
+    #[doc = " The topics of {@link load_manifest} sorted by name, for pickers; empty without a manifest."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn manifest_topics(&self) -> Result<JsValue, JsValue> {
+        let topics = self.inner.borrow().core.manifest_topics();
+        Ok(serde::Serialize::serialize(&topics, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    #[doc = " manifest_drift() -> Array<{kind, name, expected?, announced?}>\n"]
+    #[doc = " How the announced topics differ from {@link load_manifest}'s, `kind` being `\"missing\"`, `\"unlisted\"` or `\"type\"`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn manifest_drift(&self) -> Result<JsValue, JsValue> {
+        let drift = self.inner.borrow().core.manifest_drift();
+        Ok(serde::Serialize::serialize(&drift, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    #[doc = " set_value_ownership(\"copy\" | \"borrow\" mode)\n"]
+    #[doc = " Whether `on_data_fn` gets its own copy of array and raw values, or with `borrow` a view only valid until it returns."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_value_ownership(&mut self, mode: JsValue) -> Result<(), JsValue> {
+        self.inner.borrow_mut().callbacks.value_ownership = serde_wasm_bindgen::from_value(mode)?;
+        Ok(())
+    }
+
+    #[doc = " set_background_mode(boolean enabled)\n"]
+    #[doc = " While enabled, every subscription is re-issued with a `periodic` of at least {@link set_background_periodic}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_background_mode(&mut self, enabled: bool) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.set_background_mode(sink, enabled))
+    }
+
+    #[doc = " set_background_periodic(number seconds)\n"]
+    #[doc = " @param {number} seconds - the relaxed `periodic` used while in background mode."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_background_periodic(&mut self, seconds: f64) -> Result<(), JsValue> {
+        let periodic = std::time::Duration::try_from_secs_f64(seconds)
+            .map_err(|x| JsString::from(format!("{:?}", x)))?;
+        with_core(&self.inner, |core, sink| core.set_background_periodic(sink, periodic))
+    }
+
+    #[doc = " always_fast(string name)\n"]
+    #[doc = " Exempts subscriptions to `name` from {@link set_background_mode}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn always_fast(&mut self, name: &str) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.always_fast(sink, name))
+    }
+
+    #[doc = " set_pending_unannounced_limits(int per_topic, int total, int max_age_ms)\n"]
+    #[doc = " Values for topics that have not been announced yet are held until the announce arrives."]
+    #[doc = " @param {number} per_topic - values held per unknown topic id (default 16)."]
+    #[doc = " @param {number} total - values held across all unknown topic ids (default 256)."]
+    #[doc = " @param {number} max_age_ms - how long a value is held before it is dropped (default 1000)."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_pending_unannounced_limits(&mut self, per_topic: usize, total: usize, max_age_ms: u32) {
+        self.inner.borrow_mut().core.set_pending_unannounced_limits(per_topic, total, max_age_ms as i64 * 1000);
+    }
+
+    #[doc = " Number of values currently held for topics that have not been announced."]
+    pub fn pending_unannounced_count(&self) -> usize {
+        self.inner.borrow().core.pending_unannounced_count()
+    }
+
+    #[doc = " Number of values for unannounced topics dropped to overflow or expiry."]
+    pub fn dropped_unannounced_count(&self) -> f64 {
+        self.inner.borrow().core.dropped_unannounced_count() as f64
+    }
+
+    #[doc = " set_max_topics(number max_topics)\n"]
+    #[doc = " Drops announces of new topics once `max_topics` (default 100000) are announced, warning each time it starts."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_max_topics(&mut self, max_topics: usize) {
+        self.inner.borrow_mut().core.set_max_topics(max_topics);
+    }
+
+    #[doc = " Whether {@link set_max_topics} is reached, so announces of new topics are dropped."]
+    pub fn is_topic_limit_reached(&self) -> bool {
+        self.inner.borrow().core.is_topic_limit_reached()
+    }
+
+    #[doc = " Number of announces dropped by {@link set_max_topics}."]
+    pub fn over_limit_topic_count(&self) -> f64 {
+        self.inner.borrow().core.over_limit_topic_count() as f64
+    }
+
+    #[doc = " publishers_of(string topic) -> string[]\n"]
+    #[doc = " Names of the clients publishing `topic`, according to the server's `$pub$` and `$clientpub$` metadata topics."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn publishers_of(&self, topic: &str) -> Vec<String> {
+        self.inner.borrow().core.publishers_of(topic)
+    }
+
+    #[doc = " topics_of_client(string client) -> string[]\n"]
+    #[doc = " Topics published by `client`, from the same metadata as {@link publishers_of}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn topics_of_client(&self, client: &str) -> Vec<String> {
+        self.inner.borrow().core.topics_of_client(client)
+    }
+
+    #[doc = " search_topics(string query, number limit, string? only, boolean? include_hidden) -> Array<{name, type, score, match_ranges}>\n"]
+    #[doc = " The `limit` announced topics whose names contain the characters of `query` in order, ignoring case, best first."]
+    #[doc = " @param {string} [only] - a type name such as `\"double\"`, to only search topics of that type."]
+    #[doc = " @param {boolean} [include_hidden] - to also search topics with a part starting with `.`, such as `.type` entries."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn search_topics(&self, query: &str, limit: usize, only: JsValue, include_hidden: Option<bool>) -> Result<JsValue, JsValue> {
+        let only = serde_wasm_bindgen::from_value(only)?;
+        let found = self.inner.borrow().core.search_topics(query, limit, only, include_hidden.unwrap_or(false));
+        Ok(serde_wasm_bindgen::to_value(&found)?)
+    }
+
+    #[doc = " get_widget_descriptors(string prefix) -> Array<{path, widget_type, name, controllable, children: Array<{name, type}>}>\n"]
+    #[doc = " The widgets under `prefix`, each subtable with a `.type` entry as Shuffleboard and SmartDashboard mark them."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn get_widget_descriptors(&self, prefix: &str) -> Result<JsValue, JsValue> {
+        let widgets = self.inner.borrow().core.widget_descriptors(prefix);
+        Ok(serde::Serialize::serialize(&widgets, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    #[doc = " snapshot() -> object\n"]
+    #[doc = " The state of the connection and every topic right now, for bug reports and for diffing against another snapshot."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn snapshot(&mut self) -> Result<JsValue, JsValue> {
+        let snapshot = self.inner.borrow_mut().core.snapshot().map_err(JsString::from)?;
+        Ok(to_js(&snapshot)?)
+    }
+
+    #[doc = " Nt4Connection.from_snapshot(object snapshot, number? settle_ms) -> Nt4Connection\n"]
+    #[doc = " A new connection seeded with the topics, properties and values of a {@link snapshot}, delivered on {@link attach}."]
+    #[doc = " @param {number} [settle_ms] - after which seeded topics the server has not announced are unannounced, 2000 by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn from_snapshot(snapshot: JsValue, settle_ms: Option<f64>) -> Result<Nt4Connection, JsValue> {
+        let snapshot = serde_wasm_bindgen::from_value(snapshot)?;
+        let settle_us = settle_ms.map_or(hydration::DEFAULT_SETTLE_US, |x| (x * 1e3) as i64);
+        let connection = Self::new();
+        connection.inner.borrow_mut().core.hydrate(snapshot, settle_us).map_err(JsString::from)?;
+        Ok(connection)
+    }
+
+    #[doc = " Calls `announce_fn` for every topic seeded by {@link from_snapshot}, then `on_data_fn` with each stale value."]
+    pub fn attach(&mut self) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.replay_seeded(sink))
+    }
+
+    #[doc = " is_stale(string name) -> bool\n"]
+    #[doc = " Whether the value of `name` still is the one seeded by {@link from_snapshot}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn is_stale(&self, name: &str) -> bool {
+        self.inner.borrow().core.is_stale(name)
+    }
+
+    #[doc = " events_since(number seq) -> Array<{seq, kind, name, detail, server_time}>\n"]
+    #[doc = " Every topic event numbered after `seq`, oldest first, starting with a `\"gap\"` if some were dropped."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn events_since(&self, seq: f64) -> Result<JsValue, JsValue> {
+        let events = self.inner.borrow().core.events_since(seq.max(0.0) as u64);
+        Ok(to_js(&events)?)
+    }
+
+    #[doc = " The number of the last topic event of {@link events_since}, 0 before the first."]
+    pub fn latest_seq(&self) -> f64 {
+        self.inner.borrow().core.latest_seq() as f64
+    }
+
+    #[doc = " Drops every event of {@link events_since}. Numbering carries on, so readers behind see a gap."]
+    pub fn clear_journal(&mut self) {
+        self.inner.borrow_mut().core.clear_journal();
+    }
+
+    #[doc = " follower(number uid_base) -> Nt4Connection\n"]
+    #[doc = " A connection without a socket of its own that mirrors a leader connection through {@link apply_delta}."]
+    #[doc = " @param {number} uid_base - the first subuid and pubuid, leaving room for the leader's own."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn follower(uid_base: i32) -> Result<Nt4Connection, JsValue> {
+        let connection = Self::new();
+        {
+            let mut inner = connection.inner.borrow_mut();
+            inner.core.set_uid_base(uid_base).map_err(JsString::from)?;
+            inner.callbacks.outbound = Some(Vec::new());
+        }
+        Ok(connection)
+    }
+
+    #[doc = " export_delta(number since_seq) -> Uint8Array\n"]
+    #[doc = " The topics, properties and latest values that changed since `since_seq`, for {@link apply_delta} on followers."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn export_delta(&mut self, since_seq: f64) -> Result<Vec<u8>, JsValue> {
+        Ok(self.inner.borrow_mut().core.export_delta(since_seq.max(0.0) as u64).map_err(JsString::from)?)
+    }
+
+    #[doc = " apply_delta(Uint8Array delta) -> boolean\n"]
+    #[doc = " Applies a delta of {@link export_delta} as if the leader's server had sent it."]
+    #[doc = " @returns {boolean} false for a delta that is already applied."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn apply_delta(&mut self, delta: Vec<u8>) -> Result<bool, JsValue> {
+        with_core(&self.inner, |core, sink| core.apply_delta(sink, &delta))
+    }
+
+    #[doc = " The `since_seq` for the next {@link export_delta} of the leader, 0 before the first delta is applied."]
+    pub fn applied_delta_seq(&self) -> f64 {
+        self.inner.borrow().core.applied_delta_seq() as f64
+    }
+
+    #[doc = " drain_outbound() -> Array<string | Uint8Array>\n"]
+    #[doc = " The text and binary frames a {@link follower} sent since the last call, in order, for the leader to send on."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn drain_outbound(&mut self) -> Result<js_sys::Array, JsValue> {
+        match &mut self.inner.borrow_mut().callbacks.outbound {
+            Some(outbound) => Ok(outbound.drain(..).collect()),
+            None => Err(JsString::from("only a follower collects outbound frames").into()),
+        }
+    }
+
+    #[doc = " set_journal_capacity(number capacity)\n"]
+    #[doc = " How many of the last events {@link events_since} keeps, 1024 by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_journal_capacity(&mut self, capacity: usize) {
+        self.inner.borrow_mut().core.set_journal_capacity(capacity);
+    }
+
+    #[doc = " topics_diff(number seq, \"name\" | \"last_update\" order?, boolean? include_hidden) -> {resync_required, added, removed, changed, topics?, seq}\n"]
+    #[doc = " The topics announced, unannounced or changed since `seq`, or every topic with `resync_required` if those are lost."]
+    #[doc = " @param {\"name\" | \"last_update\"} [order] - `\"name\"` by default."]
+    #[doc = " @param {boolean} [include_hidden] - to also list topics with a part starting with `.`, such as `.type` entries."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn topics_diff(&self, seq: f64, order: JsValue, include_hidden: Option<bool>) -> Result<JsValue, JsValue> {
+        let order: Option<TopicOrder> = serde_wasm_bindgen::from_value(order)?;
+        let seq = seq.max(0.0) as u64;
+        let diff = self.inner.borrow().core.topics_diff(seq, order.unwrap_or_default(), include_hidden.unwrap_or(false));
+        Ok(to_js(&diff)?)
+    }
+
+    #[doc = " set_metrics_fn(function metrics_fn, number interval_hint_ms)\n"]
+    #[doc = " Has {@link emit_metrics} call `metrics_fn({[name]: number})` at most once per `interval_hint_ms`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_metrics_fn(&mut self, f: js_sys::Function, interval_hint_ms: f64) {
+        self.inner.borrow_mut().callbacks.metrics = Some(MetricsCallback { f, interval_ms: interval_hint_ms, last_ms: None });
+    }
+
+    #[doc = " emit_metrics(number now_ms) -> bool\n"]
+    #[doc = " Calls the `metrics_fn` of {@link set_metrics_fn} with every metric by name, unless called too recently."]
+    #[doc = " @returns {boolean} whether `metrics_fn` was called."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn emit_metrics(&mut self, now_ms: f64) -> Result<bool, JsValue> {
+        let (f, metrics) = {
+            let mut inner = self.inner.borrow_mut();
+            let Some(callback) = &mut inner.callbacks.metrics else {
+                return Ok(false);
+            };
+            if callback.last_ms.is_some_and(|last| now_ms - last < callback.interval_ms) {
+                return Ok(false);
+            }
+            callback.last_ms = Some(now_ms);
+            let f = callback.f.clone();
+            (f, inner.core.metrics())
+        };
+        let values = js_sys::Object::new();
+        for metric in metrics.iter() {
+            js_sys::Reflect::set(&values, &JsValue::from_str(metric.name), &JsValue::from(metric.value))?;
+        }
+        f.call1(&JsValue::NULL, &values)?;
+        Ok(true)
+    }
+
+    #[doc = " set_telemetry_endpoint(string url, number interval_ms)\n"]
+    #[doc = " POSTs every metric of {@link emit_metrics} as JSON to `url` every `interval_ms`, best-effort."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_telemetry_endpoint(&mut self, url: &str, interval_ms: u32) -> Result<(), JsValue> {
+        self.inner.borrow().core.check_open().map_err(JsString::from)?;
+        if url.is_empty() {
+            return Err(JsString::from("telemetry url must not be empty").into());
+        }
+        if interval_ms == 0 {
+            return Err(JsString::from("telemetry interval must be at least 1 ms").into());
+        }
+        let telemetry = telemetry::Telemetry::start(Rc::downgrade(&self.inner), url, interval_ms)?;
+        self.inner.borrow_mut().callbacks.telemetry = Some(telemetry);
+        Ok(())
+    }
+
+    pub fn stop_telemetry(&mut self) {
+        self.inner.borrow_mut().callbacks.telemetry = None;
+    }
+
+    #[doc = " The `url` of {@link set_telemetry_endpoint} while posting, `undefined` otherwise."]
+    pub fn get_telemetry_url(&self) -> Option<String> {
+        self.inner.borrow().callbacks.telemetry.as_ref().map(|x| x.url().to_string())
+    }
+
+    #[doc = " describe_metrics() -> Array<{name, kind, help, value}>\n"]
+    #[doc = " Every metric of {@link emit_metrics} with its current value, for the Prometheus text format."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn describe_metrics(&self) -> Result<JsValue, JsValue> {
+        let metrics: Vec<_> = self.inner.borrow().core.metrics().iter().cloned().collect();
+        Ok(serde::Serialize::serialize(&metrics, &serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    #[doc = " {@link snapshot} as pretty-printed JSON, with topics sorted by name."]
+    pub fn snapshot_json(&mut self) -> Result<String, JsValue> {
+        let snapshot = self.inner.borrow_mut().core.snapshot().map_err(JsString::from)?;
+        serde_json::to_string_pretty(&snapshot).map_err(|x| JsString::from(format!("{:?}", x)).into())
+    }
+
+    #[doc = " set_topic_filter(string[] prefixes, \"include\" | \"exclude\" mode)\n"]
+    #[doc = " Drops announced topics by name: with `include` those not under one of `prefixes`, with `exclude` those under any."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_topic_filter(&mut self, prefixes: Vec<String>, mode: JsValue) -> Result<(), JsValue> {
+        let mode = serde_wasm_bindgen::from_value(mode)?;
+        with_core(&self.inner, |core, sink| core.set_topic_filter(sink, Some(TopicFilter { prefixes, mode })))
+    }
+
+    #[doc = " Keeps every announced topic again, as before {@link set_topic_filter}."]
+    pub fn clear_topic_filter(&mut self) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| core.set_topic_filter(sink, None))
+    }
+
+    #[doc = " Number of announces dropped by {@link set_topic_filter}."]
+    pub fn filtered_topic_count(&self) -> f64 {
+        self.inner.borrow().core.filtered_topic_count() as f64
+    }
+
+    #[doc = " enable_interpolation(string name, \"linear\" | \"pose2d\" mode, {degrees?, max_extrapolation_ms?} options?)\n"]
+    #[doc = " Keeps the last two values of `name` for {@link sample_interpolated}, starting from the one already received."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn enable_interpolation(&mut self, name: &str, mode: JsValue, options: JsValue) -> Result<(), JsValue> {
+        let mode = serde_wasm_bindgen::from_value(mode)?;
+        let options: Option<InterpolationOptions> = serde_wasm_bindgen::from_value(options)?;
+        let mut inner = self.inner.borrow_mut();
+        inner.core.enable_interpolation(name, mode, options.unwrap_or_default()).map_err(|x| JsString::from(x).into())
+    }
+
+    pub fn disable_interpolation(&mut self, name: &str) {
+        self.inner.borrow_mut().core.disable_interpolation(name);
+    }
+
+    #[doc = " sample_interpolated(string name, number render_time_us) -> {value, extrapolated} | undefined\n"]
+    #[doc = " The value of `name` at server time `render_time_us`, blended between the two values received around it."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn sample_interpolated(&mut self, name: &str, render_time_us: f64) -> Result<JsValue, JsValue> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(sample) = inner.core.sample_interpolated(name, render_time_us as i64).map_err(JsString::from)? else {
+            return Ok(JsValue::UNDEFINED);
+        };
+        let value = if sample.scalar {
+            let value = sample.values.first().ok_or_else(|| JsString::from(format!("{:?} has no value", name)))?;
+            JsValue::from(*value)
+        } else {
+            js_sys::Float64Array::from(sample.values).into()
+        };
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"value".into(), &value)?;
+        js_sys::Reflect::set(&result, &"extrapolated".into(), &JsValue::from(sample.extrapolated))?;
+        Ok(result.into())
+    }
+
+    #[doc = " sample_interpolated_into(string name, number render_time_us, Float64Array out) -> boolean | undefined\n"]
+    #[doc = " {@link sample_interpolated} for render loops, writing the value into `out` without allocating."]
+    #[doc = " @returns {boolean | undefined} `extrapolated`, or `undefined` before the first value."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn sample_interpolated_into(
+        &mut self,
+        name: &str,
+        render_time_us: f64,
+        out: &js_sys::Float64Array,
+    ) -> Result<Option<bool>, JsValue> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(sample) = inner.core.sample_interpolated(name, render_time_us as i64).map_err(JsString::from)? else {
+            return Ok(None);
+        };
+        if out.length() as usize != sample.values.len() {
+            let message = format!("out has {} elements, the value of {:?} {}", out.length(), name, sample.values.len());
+            return Err(JsString::from(message).into());
+        }
+        out.copy_from(sample.values);
+        Ok(Some(sample.extrapolated))
+    }
+
+    #[doc = " track_trajectory(string name, {max_points?, min_distance_m?, min_rotation_rad?, max_age_s?, degrees?, clear_on_teleop?} options)\n"]
+    #[doc = " Keeps a trail of the `[x, y, theta]` poses received for `name`, for {@link get_trajectory}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn track_trajectory(&mut self, name: &str, options: JsValue) -> Result<(), JsValue> {
+        let options = if options.is_undefined() {
+            TrajectoryOptions::default()
+        } else {
+            serde_wasm_bindgen::from_value(options)?
+        };
+        self.inner.borrow_mut().core.track_trajectory(name, options);
+        Ok(())
+    }
+
+    pub fn untrack_trajectory(&mut self, name: &str) {
+        self.inner.borrow_mut().core.untrack_trajectory(name);
+    }
+
+    #[doc = " get_trajectory(string name) -> Float64Array\n"]
+    #[doc = " @returns {Float64Array} `[x0, y0, theta0, x1, y1, theta1, ...]`, oldest first, with theta in radians."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn get_trajectory(&self, name: &str) -> Result<Vec<f64>, JsValue> {
+        self.inner
+            .borrow()
+            .core
+            .trajectory(name)
+            .map(Trajectory::poses)
+            .ok_or_else(|| JsString::from(format!("{:?} is not tracked", name)).into())
+    }
+
+    #[doc = " get_trajectory_timestamps(string name) -> Float64Array\n"]
+    #[doc = " @returns {Float64Array} the server timestamp in µs of each pose of {@link get_trajectory}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn get_trajectory_timestamps(&self, name: &str) -> Result<Vec<f64>, JsValue> {
+        self.inner
+            .borrow()
+            .core
+            .trajectory(name)
+            .map(Trajectory::timestamps)
+            .ok_or_else(|| JsString::from(format!("{:?} is not tracked", name)).into())
+    }
+
+    pub fn clear_trajectory(&mut self, name: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.clear_trajectory(name).map_err(|x| JsString::from(x).into())
+    }
+
+    #[doc = " configure_retention({history_max_age_s?, cache_max_idle_s?, sweep_interval_s?} options)\n"]
+    #[doc = " Drops trajectory points older than `history_max_age_s` and cached values idle for `cache_max_idle_s`, both off by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn configure_retention(&mut self, options: JsValue) -> Result<(), JsValue> {
+        self.inner.borrow_mut().core.configure_retention(serde_wasm_bindgen::from_value(options)?);
+        Ok(())
+    }
+
+    #[doc = " sweep(number? now_us) -> number\n"]
+    #[doc = " Runs a sweep of {@link configure_retention} now, e.g. from a timer."]
+    #[doc = " @param {number} [now_us] - a time from {@link get_local_time_us}, the current one by default."]
+    #[doc = " @returns {number} how many values and points were reclaimed."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn sweep(&mut self, now_us: Option<f64>) -> Result<f64, JsValue> {
+        let mut inner = self.inner.borrow_mut();
+        let now = match now_us {
+            Some(now) => now as i64,
+            None => inner.core.now().map_err(JsString::from)?,
+        };
+        Ok(inner.core.sweep(now) as f64)
+    }
+
+    #[doc = " retention_stats() -> {sweeps: number, cache_evicted: number, history_trimmed: number}\n"]
+    #[doc = " What sweeps of {@link configure_retention} have reclaimed so far."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn retention_stats(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(self.inner.borrow().core.retention_stats())?)
+    }
+
+    #[doc = " set_idempotent(boolean idempotent)\n"]
+    #[doc = " When set, {@link unsubscribe} and {@link unpublish} of a uid that is not active do nothing instead of throwing."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_idempotent(&mut self, idempotent: bool) {
+        self.inner.borrow_mut().core.set_idempotent(idempotent);
+    }
+
+    pub fn is_active_subscription(&self, id: i32) -> bool {
+        self.inner.borrow().core.is_active_subscription(id)
+    }
+
+    pub fn is_active_publication(&self, id: i32) -> bool {
+        self.inner.borrow().core.is_active_publication(id)
+    }
+
+    #[doc = " set_local_echo(boolean enabled)\n"]
+    #[doc = " For servers that never send a client its own values, delivers values we send to our own subscriptions. Off by default."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn set_local_echo(&mut self, enabled: bool) {
+        self.inner.borrow_mut().core.set_local_echo(enabled);
+    }
+
+    #[doc = " topic_record({name} | {id} | {pubuid} key) -> {name, id?, pubuid?, type, local_echo} | undefined\n"]
+    #[doc = " The topic `key` refers to, by name, by the id the server announced it with, or by the pubuid we published it with."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn topic_record(&self, key: JsValue) -> Result<JsValue, JsValue> {
+        let key: TopicKey = serde_wasm_bindgen::from_value(key)?;
+        match self.inner.borrow().core.topic_record(key) {
+            Some(record) => Ok(serde::Serialize::serialize(&record, &serde_wasm_bindgen::Serializer::json_compatible())?),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    #[doc = " Whether a timesync response has been received since the last disconnect."]
+    pub fn is_ready(&self) -> bool {
+        self.inner.borrow().core.is_ready()
     }
 }
 
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+/// Limits of a log channel, see [`crate::ConnectionCore::log_channel`].
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LogOptions {
+    pub max_msgs_per_s: f64,
+    pub max_bytes_per_s: f64,
+    /// Messages waiting to be sent past which new ones are dropped.
+    pub queue_limit: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self { max_msgs_per_s: 20.0, max_bytes_per_s: 4096.0, queue_limit: 256 }
+    }
+}
+
+impl LogOptions {
+    pub fn check(&self) -> Result<(), String> {
+        for (name, rate) in [("max_msgs_per_s", self.max_msgs_per_s), ("max_bytes_per_s", self.max_bytes_per_s)] {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(format!("{} must be a positive number, not {}", name, rate));
+            }
+        }
+        if self.queue_limit == 0 {
+            return Err("queue_limit must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// `message` prefixed with `server_time_us` as `[hh:mm:ss.mmm] `, hours going past 24.
+pub fn format_line(server_time_us: i64, message: &str) -> String {
+    let ms = server_time_us.max(0) / 1000;
+    let (hours, minutes, seconds) = (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60);
+    format!("[{:02}:{:02}:{:02}.{:03}] {}", hours, minutes, seconds, ms % 1000, message)
+}
+
+#[derive(Debug)]
+enum Entry {
+    Message(String),
+    /// Messages dropped in a row while the queue was full, from the server time of the first.
+    Dropped { count: u64, since: i64 },
+}
+
+impl Entry {
+    fn line(&self) -> String {
+        match self {
+            Self::Message(line) => line.clone(),
+            Self::Dropped { count, since } => format_line(*since, &format!("… {} messages dropped", count)),
+        }
+    }
+}
+
+/// The queue of one log channel, sent through two token buckets that each hold a second's worth.
+#[derive(Debug)]
+pub struct LogChannel {
+    options: LogOptions,
+    queue: VecDeque<Entry>,
+    /// Messages in `queue`, drop markers aside.
+    queued: usize,
+    dropped: u64,
+    messages: f64,
+    bytes: f64,
+    refilled: i64,
+    /// The publish has to be sent again before anything else, after a reconnect.
+    pub republish: bool,
+}
+
+impl LogChannel {
+    pub fn new(options: LogOptions, now: i64) -> Self {
+        Self {
+            options,
+            queue: VecDeque::new(),
+            queued: 0,
+            dropped: 0,
+            messages: options.max_msgs_per_s,
+            bytes: options.max_bytes_per_s,
+            refilled: now,
+            republish: false,
+        }
+    }
+
+    /// Queues `message` stamped `server_time_us`, or counts it into the drop marker at the end of
+    /// the queue if it is full.
+    pub fn push(&mut self, server_time_us: i64, message: &str) {
+        if self.queued < self.options.queue_limit {
+            self.queue.push_back(Entry::Message(format_line(server_time_us, message)));
+            self.queued += 1;
+            return;
+        }
+        self.dropped += 1;
+        match self.queue.back_mut() {
+            Some(Entry::Dropped { count, .. }) => *count += 1,
+            _ => self.queue.push_back(Entry::Dropped { count: 1, since: server_time_us }),
+        }
+    }
+
+    /// The lines at the front of the queue the limits allow at `now`, left queued until
+    /// [`LogChannel::sent`]. A line longer than a second's worth of bytes goes out alone once the
+    /// bucket is full, rather than never.
+    pub fn sendable(&mut self, now: i64) -> Vec<String> {
+        let elapsed = (now - self.refilled).max(0) as f64 / 1e6;
+        self.refilled = now;
+        self.messages = (self.messages + elapsed * self.options.max_msgs_per_s).min(self.options.max_msgs_per_s);
+        self.bytes = (self.bytes + elapsed * self.options.max_bytes_per_s).min(self.options.max_bytes_per_s);
+        let (mut messages, mut bytes) = (self.messages, self.bytes);
+        let mut lines = Vec::new();
+        for entry in &self.queue {
+            let line = entry.line();
+            let full = bytes >= self.options.max_bytes_per_s;
+            if messages < 1.0 || (bytes < line.len() as f64 && !full) {
+                break;
+            }
+            messages -= 1.0;
+            bytes -= line.len() as f64;
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Takes the `lines` returned by [`LogChannel::sendable`] off the queue once they are sent.
+    pub fn sent(&mut self, lines: &[String]) {
+        for line in lines {
+            if let Some(Entry::Message(_)) = self.queue.pop_front() {
+                self.queued -= 1;
+            }
+            self.messages -= 1.0;
+            self.bytes -= line.len() as f64;
+        }
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
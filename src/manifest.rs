@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::types::{Nt4TypeId, Topic};
+
+/// A topic the robot is expected to have, as listed in a manifest.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestTopic {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    #[serde(default)]
+    pub units: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// What publishing or subscribing to a topic missing from the manifest, or with another type, does.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestStrictness {
+    /// A warning, the publish or subscribe goes ahead.
+    #[default]
+    Warn,
+    /// An error, nothing is sent.
+    Error,
+}
+
+/// How the announced topics differ from the manifest, see [`crate::ConnectionCore::manifest_drift`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestDrift {
+    /// In the manifest, not announced.
+    Missing { name: String },
+    /// Announced, not in the manifest.
+    Unlisted { name: String },
+    /// Announced with another type than the manifest's.
+    Type { name: String, expected: Nt4TypeId, announced: Nt4TypeId },
+}
+
+/// The topics of a manifest by name, see [`crate::ConnectionCore::load_manifest`].
+#[derive(Debug)]
+pub struct Manifest {
+    topics: BTreeMap<String, ManifestTopic>,
+    pub strictness: ManifestStrictness,
+}
+
+impl Manifest {
+    pub fn new(topics: Vec<ManifestTopic>, strictness: ManifestStrictness) -> Result<Self, String> {
+        let mut by_name = BTreeMap::new();
+        for topic in topics {
+            if !topic.name.starts_with('/') {
+                return Err(format!("manifest topic {:?} does not start with /", topic.name));
+            }
+            if let Some(topic) = by_name.insert(topic.name.clone(), topic) {
+                return Err(format!("{:?} is in the manifest twice", topic.name));
+            }
+        }
+        Ok(Self { topics: by_name, strictness })
+    }
+
+    /// Sorted by name.
+    pub fn topics(&self) -> impl Iterator<Item = &ManifestTopic> {
+        self.topics.values()
+    }
+
+    /// What is wrong with publishing `name` as `ty`, if anything.
+    pub fn check_publish(&self, name: &str, ty: Nt4TypeId) -> Option<String> {
+        match self.topics.get(name) {
+            None => Some(format!("{} is not in the manifest", name)),
+            Some(topic) if topic.ty != ty => {
+                Some(format!("{} is a {} topic in the manifest, not {}", name, topic.ty.get_name(), ty.get_name()))
+            },
+            Some(_) => None,
+        }
+    }
+
+    /// What is wrong with subscribing to `path`, if anything: a prefix subscription has to match at
+    /// least one topic of the manifest.
+    pub fn check_subscribe(&self, path: &str, prefix: bool) -> Option<String> {
+        let known = if prefix {
+            self.topics.range(path.to_string()..).next().is_some_and(|(name, _)| name.starts_with(path))
+        } else {
+            self.topics.contains_key(path)
+        };
+        (!known).then(|| format!("{} is not in the manifest", path))
+    }
+
+    /// Every difference between the manifest and the `announced` topics, by name. `listed` picks
+    /// the announced topics that should be in the manifest.
+    pub fn drift<'a>(&self, announced: impl IntoIterator<Item = &'a Topic>, listed: impl Fn(&str) -> bool) -> Vec<ManifestDrift> {
+        let mut announced: BTreeMap<&str, Nt4TypeId> =
+            announced.into_iter().filter(|x| listed(&x.name)).map(|x| (&*x.name, x.ty)).collect();
+        let mut drift = Vec::new();
+        for topic in self.topics.values() {
+            match announced.remove(topic.name.as_str()) {
+                None => drift.push(ManifestDrift::Missing { name: topic.name.clone() }),
+                Some(ty) if ty != topic.ty => {
+                    drift.push(ManifestDrift::Type { name: topic.name.clone(), expected: topic.ty, announced: ty })
+                },
+                Some(_) => {},
+            }
+        }
+        drift.extend(announced.into_keys().map(|name| ManifestDrift::Unlisted { name: name.to_string() }));
+        drift.sort_by(|a, b| drift_name(a).cmp(drift_name(b)));
+        drift
+    }
+}
+
+fn drift_name(drift: &ManifestDrift) -> &str {
+    match drift {
+        ManifestDrift::Missing { name } | ManifestDrift::Unlisted { name } | ManifestDrift::Type { name, .. } => name,
+    }
+}
+
+/// The TypeScript type values of `ty` are delivered as, with values copied.
+fn ts_type(ty: Nt4TypeId) -> &'static str {
+    match ty {
+        Nt4TypeId::Boolean => "boolean",
+        Nt4TypeId::Double | Nt4TypeId::Int | Nt4TypeId::Float => "number",
+        Nt4TypeId::String | Nt4TypeId::Json => "string",
+        Nt4TypeId::Raw | Nt4TypeId::Rpc | Nt4TypeId::MsgPack | Nt4TypeId::Protobuf => "Uint8Array",
+        Nt4TypeId::BooleanArray => "boolean[]",
+        Nt4TypeId::DoubleArray | Nt4TypeId::IntArray | Nt4TypeId::FloatArray => "number[]",
+        Nt4TypeId::StringArray => "string[]",
+    }
+}
+
+/// A TypeScript declaration of the topic names of `topics` and the type of each one's values.
+pub fn dts(topics: &[ManifestTopic]) -> String {
+    let mut topics: Vec<&ManifestTopic> = topics.iter().collect();
+    topics.sort_by(|a, b| a.name.cmp(&b.name));
+    topics.dedup_by(|a, b| a.name == b.name);
+    let quoted = |name: &str| serde_json::to_string(name).expect("strings serialize");
+    let mut out = String::from("// Generated from a topic manifest by nt4-wasm.\n\nexport type TopicName =");
+    if topics.is_empty() {
+        out.push_str(" never");
+    }
+    for topic in &topics {
+        let _ = write!(out, "\n    | {}", quoted(&topic.name));
+    }
+    out.push_str(";\n\nexport interface TopicValues {\n");
+    for topic in &topics {
+        let doc: Vec<&str> = [topic.description.as_deref(), topic.units.as_deref()].into_iter().flatten().collect();
+        if !doc.is_empty() {
+            let doc = if doc.len() == 2 { format!("{} ({})", doc[0], doc[1]) } else { doc[0].to_string() };
+            let _ = writeln!(out, "    /** {} */", doc.replace("*/", "*\\/").replace('\n', " "));
+        }
+        let _ = writeln!(out, "    {}: {};", quoted(&topic.name), ts_type(topic.ty));
+    }
+    out.push_str("}\n\nexport type TopicValue<T extends TopicName> = TopicValues[T];\n");
+    out
+}
+
+#[doc = " generate_dts(Array<{name, type, units?, description?}> manifest) -> string\n"]
+#[doc = " A TypeScript declaration of the names and value types of the topics of `manifest`, as given to {@link load_manifest}."]
+#[wasm_bindgen(skip_jsdoc)]
+pub fn generate_dts(manifest: JsValue) -> Result<String, JsValue> {
+    let topics: Vec<ManifestTopic> = serde_wasm_bindgen::from_value(manifest)?;
+    Ok(dts(&topics))
+}
@@ -0,0 +1,119 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    text::SubscribeParams,
+    types::{Nt4Data, Nt4TypeId, SubscriptionOptions, Topic},
+};
+
+const PUB_PREFIX: &str = "$pub$";
+const CLIENTPUB_PREFIX: &str = "$clientpub$";
+
+#[derive(serde::Deserialize)]
+struct TopicPublisher {
+    client: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ClientPublication {
+    topic: String,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionEntry<'a> {
+    uid: i32,
+    topics: &'a [String],
+    options: &'a SubscriptionOptions,
+}
+
+#[derive(serde::Serialize)]
+struct PublicationEntry<'a> {
+    uid: i32,
+    topic: &'a str,
+    #[serde(rename = "type")]
+    ty: Nt4TypeId,
+}
+
+/// Publisher bookkeeping decoded from the server's `$pub$<topic>` and
+/// `$clientpub$<client>` metadata topics.
+#[derive(Debug, Default)]
+pub struct Publishers {
+    by_topic: HashMap<String, Vec<String>>,
+    by_client: HashMap<String, Vec<String>>,
+}
+
+impl Publishers {
+    /// Updates the tables if `name` is a publisher metadata topic.
+    pub fn update(&mut self, name: &str, data: &Nt4Data) -> Result<(), String> {
+        if let Some(topic) = name.strip_prefix(PUB_PREFIX) {
+            let entries: Vec<TopicPublisher> = decode(name, data)?;
+            self.by_topic
+                .insert(topic.to_string(), entries.into_iter().map(|x| x.client).collect());
+        } else if let Some(client) = name.strip_prefix(CLIENTPUB_PREFIX) {
+            let entries: Vec<ClientPublication> = decode(name, data)?;
+            self.by_client
+                .insert(client.to_string(), entries.into_iter().map(|x| x.topic).collect());
+        }
+        Ok(())
+    }
+
+    /// Forgets whatever was learned from `name` once it is unannounced.
+    pub fn remove(&mut self, name: &str) {
+        if let Some(topic) = name.strip_prefix(PUB_PREFIX) {
+            self.by_topic.remove(topic);
+        } else if let Some(client) = name.strip_prefix(CLIENTPUB_PREFIX) {
+            self.by_client.remove(client);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.by_topic.clear();
+        self.by_client.clear();
+    }
+
+    pub fn publishers_of(&self, topic: &str) -> Vec<String> {
+        let mut clients: BTreeSet<&String> = self.by_topic.get(topic).into_iter().flatten().collect();
+        clients.extend(
+            self.by_client
+                .iter()
+                .filter(|(_, topics)| topics.iter().any(|x| x == topic))
+                .map(|(client, _)| client),
+        );
+        clients.into_iter().cloned().collect()
+    }
+
+    pub fn topics_of_client(&self, client: &str) -> Vec<String> {
+        let mut topics: BTreeSet<&String> = self.by_client.get(client).into_iter().flatten().collect();
+        topics.extend(
+            self.by_topic
+                .iter()
+                .filter(|(_, clients)| clients.iter().any(|x| x == client))
+                .map(|(topic, _)| topic),
+        );
+        topics.into_iter().cloned().collect()
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(name: &str, data: &Nt4Data) -> Result<Vec<T>, String> {
+    let bytes = data
+        .as_raw()
+        .ok_or_else(|| format!("Expected msgpack data for {:?}, got {:?}", name, data.get_name()))?;
+    rmp_serde::from_slice(bytes).map_err(|x| format!("Invalid metadata for {:?}: {}", name, x))
+}
+
+/// Our subscription table in the shape of the server's `$clientsub$` topics, ordered by uid.
+pub fn encode_subscriptions<'a>(subscriptions: impl Iterator<Item = &'a SubscribeParams>) -> Result<Vec<u8>, String> {
+    let mut entries: Vec<SubscriptionEntry> = subscriptions
+        .map(|x| SubscriptionEntry { uid: x.subuid, topics: &x.topics, options: &x.options })
+        .collect();
+    entries.sort_by_key(|x| x.uid);
+    rmp_serde::to_vec_named(&entries).map_err(|x| x.to_string())
+}
+
+/// Our publication table in the shape of the server's `$clientpub$` topics, ordered by uid.
+pub fn encode_publications<'a>(publications: impl Iterator<Item = (&'a i32, &'a Topic)>) -> Result<Vec<u8>, String> {
+    let mut entries: Vec<PublicationEntry> = publications
+        .map(|(uid, topic)| PublicationEntry { uid: *uid, topic: &topic.name, ty: topic.ty })
+        .collect();
+    entries.sort_by_key(|x| x.uid);
+    rmp_serde::to_vec_named(&entries).map_err(|x| x.to_string())
+}
@@ -0,0 +1,91 @@
+use std::{cell::Cell, collections::BTreeMap};
+
+/// How a metric behaves, as in the Prometheus text format.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    /// Only ever goes up, across reconnects too, until the connection is closed.
+    Counter,
+    /// The current state.
+    Gauge,
+}
+
+/// One metric of [`Metrics`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub help: &'static str,
+    pub value: f64,
+}
+
+/// Every metric of a connection by name, gathered by [`crate::ConnectionCore::metrics`]. Names
+/// end in `_total` for counters and in the unit where there is one, and do not change once added.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    metrics: BTreeMap<&'static str, Metric>,
+}
+
+impl Metrics {
+    pub fn counter(&mut self, name: &'static str, help: &'static str, value: u64) {
+        self.insert(Metric { name, kind: MetricKind::Counter, help, value: value as f64 });
+    }
+
+    pub fn gauge(&mut self, name: &'static str, help: &'static str, value: f64) {
+        self.insert(Metric { name, kind: MetricKind::Gauge, help, value });
+    }
+
+    fn insert(&mut self, metric: Metric) {
+        let name = metric.name;
+        let replaced = self.metrics.insert(name, metric);
+        debug_assert!(replaced.is_none(), "metric {} is reported twice", name);
+    }
+
+    /// Sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Metric> {
+        self.metrics.values()
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.metrics.get(name).map(|x| x.value)
+    }
+}
+
+/// A part of the connection with metrics of its own. Each is listed once in
+/// [`crate::ConnectionCore::metrics`], so a new one only has to implement this and be added there.
+pub trait ReportMetrics {
+    fn report(&self, metrics: &mut Metrics);
+}
+
+/// Counts of frames in and out, kept in cells as frames are sent through `&self`.
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub frames_rx: Cell<u64>,
+    pub bytes_rx: Cell<u64>,
+    pub frames_tx: Cell<u64>,
+    pub bytes_tx: Cell<u64>,
+    pub values_rx: Cell<u64>,
+    pub decode_errors: Cell<u64>,
+    pub internal_errors: Cell<u64>,
+    pub disconnects: Cell<u64>,
+}
+
+/// Adds `n` to `counter`.
+pub fn bump(counter: &Cell<u64>, n: usize) {
+    counter.set(counter.get() + n as u64);
+}
+
+impl ReportMetrics for Counters {
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.counter("frames_rx_total", "WebSocket messages received.", self.frames_rx.get());
+        metrics.counter("bytes_rx_total", "Bytes of WebSocket messages received.", self.bytes_rx.get());
+        metrics.counter("frames_tx_total", "WebSocket messages sent.", self.frames_tx.get());
+        metrics.counter("bytes_tx_total", "Bytes of WebSocket messages sent.", self.bytes_tx.get());
+        metrics.counter("values_rx_total", "Values received, timesync responses aside.", self.values_rx.get());
+        metrics.counter("decode_errors_total", "Frames or values that failed to decode.", self.decode_errors.get());
+        metrics.counter("internal_errors_total", "Frames whose processing panicked.", self.internal_errors.get());
+        metrics.counter("disconnects_total", "Disconnects.", self.disconnects.get());
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use crate::{
+    metrics::{Metrics, ReportMetrics},
+    types::{Nt4Data, Nt4TypeId},
+};
+
+/// See [`crate::ConnectionCore::set_type_mismatch_warn_after`].
+pub const DEFAULT_WARN_AFTER: u32 = 10;
+
+/// `data` as a value of `ty` if that loses nothing: types the wire does not tell apart, such as
+/// `string` and `json`, and exact numeric conversions, e.g. `int` to `double`. Otherwise `data` as is.
+pub fn coerce(data: Nt4Data, ty: Nt4TypeId) -> Result<Nt4Data, Nt4Data> {
+    let data = match data.try_convert(ty.get_name()) {
+        Ok(data) => return Ok(data),
+        Err(data) => data,
+    };
+    match data.convert_number(ty) {
+        Some((converted, false)) => Ok(converted),
+        _ => Err(data),
+    }
+}
+
+/// Values received with a type other than the announced one that could not be coerced, by topic name.
+#[derive(Debug)]
+pub struct TypeMismatches {
+    counts: HashMap<String, u64>,
+    /// Mismatches since the last value of the announced type.
+    streaks: HashMap<String, u32>,
+    pub warn_after: u32,
+}
+
+impl Default for TypeMismatches {
+    fn default() -> Self {
+        Self { counts: HashMap::new(), streaks: HashMap::new(), warn_after: DEFAULT_WARN_AFTER }
+    }
+}
+
+impl TypeMismatches {
+    /// Counts a mismatching value of `name`, returning whether it makes [`TypeMismatches::warn_after`] in a row.
+    pub fn mismatched(&mut self, name: &str) -> bool {
+        *self.counts.entry(name.to_string()).or_default() += 1;
+        let streak = self.streaks.entry(name.to_string()).or_default();
+        *streak += 1;
+        *streak == self.warn_after
+    }
+
+    /// A value of `name` of the announced type ends its streak.
+    pub fn matched(&mut self, name: &str) {
+        if !self.streaks.is_empty() {
+            self.streaks.remove(name);
+        }
+    }
+
+    pub fn count(&self, name: &str) -> u64 {
+        self.counts.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.streaks.clear();
+    }
+}
+
+impl ReportMetrics for TypeMismatches {
+    fn report(&self, metrics: &mut Metrics) {
+        let total = self.counts.values().sum();
+        metrics.counter("type_mismatches_total", "Values of another type than announced that could not be coerced.", total);
+    }
+}
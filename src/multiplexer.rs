@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap};
+
+use js_sys::JsString;
+use wasm_bindgen::prelude::*;
+
+use crate::{types::Topic, widgets, Nt4Connection};
+
+/// Separates the connection name from the topic path, e.g. `robot:/SmartDashboard/x`.
+/// Connection names may not contain it, so the first occurrence always marks the prefix.
+const SEPARATOR: char = ':';
+
+#[wasm_bindgen]
+pub struct Nt4Multiplexer {
+    connections: BTreeMap<String, Nt4Connection>,
+    routes: HashMap<i32, (String, i32)>,
+    uid_cnt: i32,
+    announce_fn: Option<js_sys::Function>,
+    unannounce_fn: Option<js_sys::Function>,
+    ready_fn: Option<js_sys::Function>,
+    unready_fn: Option<js_sys::Function>,
+    on_data_fn: Option<js_sys::Function>,
+    type_changed_fn: Option<js_sys::Function>,
+}
+
+#[derive(serde::Serialize)]
+struct ConnectionState<'a> {
+    name: &'a str,
+    ready: bool,
+    topics: usize,
+}
+
+fn bind_name(f: &js_sys::Function, name: &str) -> js_sys::Function {
+    f.bind1(&JsValue::NULL, &JsValue::from_str(name)).unchecked_into()
+}
+
+macro_rules! set_merged_fns {
+    ($($name:ident),* $(,)?) => {
+        paste::paste! {
+            #[wasm_bindgen]
+            impl Nt4Multiplexer {
+                $(
+                    pub fn [<set_ $name>](&mut self, f: js_sys::Function) {
+                        for (name, connection) in self.connections.iter_mut() {
+                            connection.[<set_ $name>](bind_name(&f, name));
+                        }
+                        self.$name = Some(f);
+                    }
+                )*
+            }
+
+            impl Nt4Multiplexer {
+                fn bind_callbacks(&self, name: &str, connection: &mut Nt4Connection) {
+                    $(
+                        if let Some(f) = &self.$name {
+                            connection.[<set_ $name>](bind_name(f, name));
+                        }
+                    )*
+                }
+            }
+        }
+    };
+}
+
+set_merged_fns! {
+    announce_fn,
+    unannounce_fn,
+    ready_fn,
+    unready_fn,
+    on_data_fn,
+    type_changed_fn,
+}
+
+impl Nt4Multiplexer {
+    fn connection_mut(&mut self, name: &str) -> Result<&mut Nt4Connection, JsValue> {
+        self.connections
+            .get_mut(name)
+            .ok_or_else(|| JsString::from(format!("Unknown connection: {:?}", name)).into())
+    }
+
+    fn split_path(path: &str) -> Result<(&str, &str), JsValue> {
+        path.split_once(SEPARATOR).ok_or_else(|| {
+            JsString::from(format!(
+                "Expected a path of the form \"<connection>{}<topic>\", got {:?}",
+                SEPARATOR, path
+            ))
+            .into()
+        })
+    }
+
+    fn route(&self, id: i32) -> Result<(String, i32), JsValue> {
+        self.routes
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| JsString::from(format!("Unknown id: {}", id)).into())
+    }
+
+    fn new_route(&mut self, name: &str, inner: i32) -> i32 {
+        let next = self.uid_cnt;
+        self.uid_cnt += 1;
+        self.routes.insert(next, (name.to_string(), inner));
+        next
+    }
+}
+
+impl Default for Nt4Multiplexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Nt4Multiplexer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Nt4Multiplexer {
+        Self {
+            connections: BTreeMap::new(),
+            routes: HashMap::new(),
+            uid_cnt: 0,
+            announce_fn: None,
+            unannounce_fn: None,
+            ready_fn: None,
+            unready_fn: None,
+            on_data_fn: None,
+            type_changed_fn: None,
+        }
+    }
+
+    #[doc = " add_connection(string name)\n"]
+    #[doc = " Registers a new connection. Callbacks set on the multiplexer are called with `name` as their first argument."]
+    #[doc = " @param {string} name - unique connection name, must not contain `:`."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn add_connection(&mut self, name: &str) -> Result<(), JsValue> {
+        if name.is_empty() || name.contains(SEPARATOR) {
+            return Err(JsString::from(format!(
+                "Invalid connection name {:?}: must be non-empty and not contain {:?}",
+                name, SEPARATOR
+            ))
+            .into());
+        }
+        if self.connections.contains_key(name) {
+            return Err(JsString::from(format!("Connection {:?} already exists", name)).into());
+        }
+        let mut connection = Nt4Connection::new();
+        self.bind_callbacks(name, &mut connection);
+        self.connections.insert(name.to_string(), connection);
+        Ok(())
+    }
+
+    pub fn remove_connection(&mut self, name: &str) -> Result<(), JsValue> {
+        self.connections
+            .remove(name)
+            .ok_or_else(|| JsValue::from(JsString::from(format!("Unknown connection: {:?}", name))))?
+            .close();
+        self.routes.retain(|_, (connection, _)| connection != name);
+        Ok(())
+    }
+
+    pub fn set_send_binary_fn(&mut self, name: &str, f: js_sys::Function) -> Result<(), JsValue> {
+        self.connection_mut(name)?.set_send_binary_fn(f);
+        Ok(())
+    }
+
+    pub fn set_send_text_fn(&mut self, name: &str, f: js_sys::Function) -> Result<(), JsValue> {
+        self.connection_mut(name)?.set_send_text_fn(f)
+    }
+
+    pub fn timesync(&mut self, name: &str) -> Result<(), JsValue> {
+        self.connection_mut(name)?.timesync()
+    }
+
+    pub fn on_binary(&mut self, name: &str, data_frame: Vec<u8>) -> Result<(), JsValue> {
+        self.connection_mut(name)?.on_binary(data_frame)
+    }
+
+    pub fn on_text(&mut self, name: &str, data_frame: String) -> Result<(), JsValue> {
+        self.connection_mut(name)?.on_text(data_frame)
+    }
+
+    pub fn on_disconnect(&mut self, name: &str) -> Result<(), JsValue> {
+        self.connection_mut(name)?.on_disconnect()
+    }
+
+    #[doc = " subscribe(string path, object options) -> int\n"]
+    #[doc = " @param {string} path - prefixed topic path, e.g. `robot:/SmartDashboard/x`."]
+    #[doc = " @returns {number} an id unique across all connections."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn subscribe(&mut self, path: &str, options: JsValue) -> Result<i32, JsValue> {
+        let (name, topic) = Self::split_path(path)?;
+        let inner = self.connection_mut(name)?.subscribe(topic, options)?;
+        Ok(self.new_route(name, inner))
+    }
+
+    pub fn unsubscribe(&mut self, id: i32) -> Result<(), JsValue> {
+        let (name, inner) = self.route(id)?;
+        self.connection_mut(&name)?.unsubscribe(inner)?;
+        self.routes.remove(&id);
+        Ok(())
+    }
+
+    pub fn publish(&mut self, path: &str, ty: JsValue, properties: JsValue) -> Result<i32, JsValue> {
+        let (name, topic) = Self::split_path(path)?;
+        let inner = self.connection_mut(name)?.publish(topic, ty, properties)?;
+        Ok(self.new_route(name, inner))
+    }
+
+    pub fn unpublish(&mut self, id: i32) -> Result<(), JsValue> {
+        let (name, inner) = self.route(id)?;
+        self.connection_mut(&name)?.unpublish(inner)?;
+        self.routes.remove(&id);
+        Ok(())
+    }
+
+    pub fn set_properties(&mut self, path: &str, update: JsValue) -> Result<(), JsValue> {
+        let (name, topic) = Self::split_path(path)?;
+        self.connection_mut(name)?.set_properties(topic, update)
+    }
+
+    pub fn send_data(&mut self, id: i32, data: JsValue, timestamp: JsValue) -> Result<(), JsValue> {
+        let (name, inner) = self.route(id)?;
+        self.connection_mut(&name)?.send_data(inner, data, timestamp)
+    }
+
+    #[doc = " Every announced topic across all connections, with names prefixed by their connection."]
+    pub fn topics(&self, include_hidden: Option<bool>) -> Result<JsValue, JsValue> {
+        let include_hidden = include_hidden.unwrap_or(false);
+        let mut topics: Vec<Topic> = self
+            .connections
+            .iter()
+            .flat_map(|(name, connection)| {
+                let topics = connection.topics().into_iter();
+                topics.filter(move |topic| include_hidden || !widgets::is_hidden(&topic.name)).map(move |topic| Topic {
+                    name: format!("{}{}{}", name, SEPARATOR, topic.name).into(),
+                    ty: topic.ty,
+                })
+            })
+            .collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(serde_wasm_bindgen::to_value(&topics)?)
+    }
+
+    #[doc = " `[{name, ready, topics}]` for every connection, ordered by name."]
+    pub fn connection_states(&self) -> Result<JsValue, JsValue> {
+        let states: Vec<ConnectionState> = self
+            .connections
+            .iter()
+            .map(|(name, connection)| ConnectionState {
+                name,
+                ready: connection.is_ready(),
+                topics: connection.topics().len(),
+            })
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&states)?)
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::types::Nt4TypeId;
+
+/// One of the three ways to refer to a topic, see [`crate::ConnectionCore::topic_record`].
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicKey {
+    Name(String),
+    /// The id the server announced the topic with.
+    Id(i32),
+    /// The id we published the topic with.
+    Pubuid(i32),
+}
+
+/// A topic as announced by the server and as published by us, whichever applies.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRecord {
+    pub name: String,
+    /// The server's id, `None` until the topic is announced.
+    pub id: Option<i32>,
+    /// Our id, `None` unless we publish the topic.
+    pub pubuid: Option<i32>,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    /// Whether the cached value is one we sent that the server has not sent back.
+    pub local_echo: bool,
+}
+
+/// The server ids of the topics we publish, from the announces that carry our pubuid, and which
+/// of their cached values are local echoes.
+#[derive(Debug, Default)]
+pub struct OwnTopics {
+    ids: HashMap<i32, i32>,
+    echoed: HashSet<i32>,
+    /// Whether values we send are cached and delivered as if the server had sent them back.
+    pub local_echo: bool,
+}
+
+impl OwnTopics {
+    pub fn correlate(&mut self, pubuid: i32, id: i32) {
+        self.ids.insert(pubuid, id);
+    }
+
+    pub fn id_of(&self, pubuid: i32) -> Option<i32> {
+        self.ids.get(&pubuid).copied()
+    }
+
+    pub fn pubuid_of(&self, id: i32) -> Option<i32> {
+        self.ids.iter().find(|(_, &x)| x == id).map(|(&pubuid, _)| pubuid)
+    }
+
+    pub fn unpublished(&mut self, pubuid: i32) {
+        if let Some(id) = self.ids.remove(&pubuid) {
+            self.echoed.remove(&id);
+        }
+    }
+
+    pub fn unannounced(&mut self, id: i32) {
+        self.ids.retain(|_, x| *x != id);
+        self.echoed.remove(&id);
+    }
+
+    /// The cached value of `id` is now one we sent.
+    pub fn echoed(&mut self, id: i32) {
+        self.echoed.insert(id);
+    }
+
+    pub fn is_echo(&self, id: i32) -> bool {
+        self.echoed.contains(&id)
+    }
+
+    /// The server sent a value of `id`, returning whether the cached value was a local echo.
+    pub fn received(&mut self, id: i32) -> bool {
+        !self.echoed.is_empty() && self.echoed.remove(&id)
+    }
+
+    /// Forgets every correlation, keeping the option.
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.echoed.clear();
+    }
+}
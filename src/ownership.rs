@@ -0,0 +1,58 @@
+use wasm_bindgen::JsValue;
+
+use crate::types::Nt4Data;
+
+/// Who owns the arrays passed to `on_data_fn`, see `Nt4Connection::set_value_ownership`.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueOwnership {
+    /// Every value is copied into a JS value of its own, which stays valid.
+    #[default]
+    Copy,
+    /// `double[]`, `float[]` and raw values are typed array views into wasm memory, only valid
+    /// during the callback. Everything else is copied.
+    Borrow,
+}
+
+/// Buffers the views of [`ValueOwnership::Borrow`] are made over, reused from one value to the next.
+#[derive(Debug, Default)]
+pub struct Lender {
+    doubles: Vec<f64>,
+    floats: Vec<f32>,
+    bytes: Vec<u8>,
+}
+
+impl Lender {
+    /// Calls `f` with a typed array view of a copy of the array in `data`, then overwrites the copy
+    /// so a view kept past `f` reads `NaN`s, or zeros for raw values, until the buffer is reused.
+    /// `None`, without calling `f`, for values without such an array.
+    pub fn lend<T>(&mut self, data: &Nt4Data, f: impl FnOnce(JsValue) -> T) -> Option<T> {
+        // SAFETY: the buffers are not touched while `f` runs, and a callback that makes wasm
+        // memory grow only detaches the view.
+        match data {
+            Nt4Data::DoubleArray(x) => {
+                self.doubles.clear();
+                self.doubles.extend_from_slice(x);
+                let result = f(unsafe { js_sys::Float64Array::view(&self.doubles) }.into());
+                self.doubles.fill(f64::NAN);
+                Some(result)
+            },
+            Nt4Data::FloatArray(x) => {
+                self.floats.clear();
+                self.floats.extend_from_slice(x);
+                let result = f(unsafe { js_sys::Float32Array::view(&self.floats) }.into());
+                self.floats.fill(f32::NAN);
+                Some(result)
+            },
+            Nt4Data::Raw(x) | Nt4Data::Rpc(x) | Nt4Data::MsgPack(x) | Nt4Data::Protobuf(x) => {
+                self.bytes.clear();
+                self.bytes.extend_from_slice(x);
+                let result = f(unsafe { js_sys::Uint8Array::view(&self.bytes) }.into());
+                self.bytes.fill(0);
+                Some(result)
+            },
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    connection::ConnectionEvent,
+    self_test,
+    types::{Properties, Topic},
+};
+
+/// What changed while delivery was paused, see [`crate::ConnectionCore::pause_delivery`]. Holds at
+/// most one entry per topic, however long the pause.
+#[derive(Debug)]
+pub struct Pause {
+    /// The topics as last delivered, if announces are paused too.
+    topics: Option<BTreeMap<i32, (Topic, Properties)>>,
+    /// Topics with values since the pause.
+    valued: BTreeSet<i32>,
+}
+
+impl Pause {
+    pub fn new(topics: Option<BTreeMap<i32, (Topic, Properties)>>) -> Self {
+        Self { topics, valued: BTreeSet::new() }
+    }
+
+    pub fn announces_paused(&self) -> bool {
+        self.topics.is_some()
+    }
+
+    pub fn valued(&mut self, topic_id: i32) {
+        self.valued.insert(topic_id);
+    }
+
+    pub fn into_valued(self) -> BTreeSet<i32> {
+        self.valued
+    }
+
+    /// The events that take the owner from the topics as of the pause to `topics`, unannounces first.
+    pub fn announce_events(
+        &self,
+        topics: &HashMap<i32, Topic>,
+        properties: &HashMap<i32, Properties>,
+    ) -> Vec<ConnectionEvent> {
+        let Some(before) = &self.topics else {
+            return Vec::new();
+        };
+        let mut unannounces = Vec::new();
+        let mut events = Vec::new();
+        for (&id, (old, old_properties)) in before {
+            match topics.get(&id) {
+                Some(topic) if topic.name == old.name => {
+                    if topic.ty != old.ty {
+                        events.push(ConnectionEvent::TypeChanged { name: topic.name.to_string(), old: old.ty, new: topic.ty });
+                    }
+                    let properties = properties.get(&id).cloned().unwrap_or_default();
+                    if properties != *old_properties {
+                        events.push(ConnectionEvent::PropertiesChanged { id, name: topic.name.to_string(), properties });
+                    }
+                },
+                _ => unannounces.push(ConnectionEvent::Unannounce { id, name: old.name.to_string() }),
+            }
+        }
+        let mut announced: Vec<(&i32, &Topic)> = topics
+            .iter()
+            .filter(|(id, topic)| {
+                !self_test::is_test_topic(&topic.name) && before.get(id).is_none_or(|(old, _)| old.name != topic.name)
+            })
+            .collect();
+        announced.sort_unstable_by_key(|(id, _)| **id);
+        events.extend(announced.into_iter().map(|(&id, topic)| ConnectionEvent::Announce { id, topic: topic.clone() }));
+        unannounces.extend(events);
+        unannounces
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    binary::BinaryDataFrame,
+    metrics::{Metrics, ReportMetrics},
+};
+
+/// Values received for topic ids that have not been announced yet, held until
+/// the announce arrives. Oldest values are dropped once a limit is reached.
+#[derive(Debug)]
+pub struct PendingValues {
+    values: HashMap<i32, VecDeque<(i64, BinaryDataFrame)>>,
+    len: usize,
+    dropped: u64,
+    pub per_topic_limit: usize,
+    pub total_limit: usize,
+    pub max_age_us: i64,
+}
+
+impl Default for PendingValues {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+            len: 0,
+            dropped: 0,
+            per_topic_limit: 16,
+            total_limit: 256,
+            max_age_us: 1_000_000,
+        }
+    }
+}
+
+impl PendingValues {
+    /// Buffers `frame`, received at local time `now`.
+    pub fn push(&mut self, now: i64, frame: BinaryDataFrame) {
+        self.expire(now);
+        if self.per_topic_limit == 0 || self.total_limit == 0 {
+            self.dropped += 1;
+            return;
+        }
+        let queue = self.values.entry(frame.topic_id).or_default();
+        if queue.len() >= self.per_topic_limit {
+            queue.pop_front();
+            self.len -= 1;
+            self.dropped += 1;
+        }
+        queue.push_back((now, frame));
+        self.len += 1;
+        while self.len > self.total_limit {
+            self.drop_oldest();
+        }
+    }
+
+    /// Removes and returns every value buffered for `topic_id`, oldest first.
+    pub fn take(&mut self, topic_id: i32) -> VecDeque<(i64, BinaryDataFrame)> {
+        let queue = self.values.remove(&topic_id).unwrap_or_default();
+        self.len -= queue.len();
+        queue
+    }
+
+    /// Drops every value received more than `max_age_us` before `now`.
+    pub fn expire(&mut self, now: i64) {
+        let max_age_us = self.max_age_us;
+        let mut expired = 0;
+        self.values.retain(|_, queue| {
+            while queue.front().is_some_and(|(t, _)| now - *t > max_age_us) {
+                queue.pop_front();
+                expired += 1;
+            }
+            !queue.is_empty()
+        });
+        self.len -= expired;
+        self.dropped += expired as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of values dropped to overflow or expiry.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .values
+            .iter()
+            .filter_map(|(id, queue)| queue.front().map(|(t, _)| (*t, *id)))
+            .min();
+        if let Some((_, id)) = oldest {
+            if let Some(queue) = self.values.get_mut(&id) {
+                queue.pop_front();
+                if queue.is_empty() {
+                    self.values.remove(&id);
+                }
+            }
+            self.len -= 1;
+            self.dropped += 1;
+        }
+    }
+}
+
+impl ReportMetrics for PendingValues {
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.gauge("pending_unannounced", "Values held until their topic is announced.", self.len as f64);
+        metrics.counter("dropped_unannounced_total", "Values dropped before their topic was announced.", self.dropped);
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeSeed;
+
+use crate::types::{Nt4Data, Nt4DataSeed, Nt4TypeId, Properties};
+
+/// Announces are taken to be complete once none arrived for this long.
+pub const SETTLE_US: i64 = 500_000;
+
+/// How long values of an export, or the announces of an import, may take to arrive.
+pub const TIMEOUT_US: i64 = 5_000_000;
+
+/// Values encoding to more msgpack than this are not imported.
+pub const MAX_VALUE_BYTES: usize = 64 * 1024;
+
+/// The first field of every backup. Only bumped when a field changes meaning.
+pub const VERSION: u32 = 1;
+
+/// A persistent topic and its value.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistentEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    pub properties: Properties,
+    /// The value as JSON, so a backup survives `JSON.stringify`. Raw values are arrays of bytes.
+    pub value: serde_json::Value,
+}
+
+impl PersistentEntry {
+    pub fn new(name: &str, properties: &Properties, data: &Nt4Data) -> Result<Self, String> {
+        Ok(Self {
+            name: name.to_string(),
+            ty: data.get_type_id(),
+            properties: properties.clone(),
+            value: serde_json::to_value(data).map_err(|x| x.to_string())?,
+        })
+    }
+
+    pub fn data(&self) -> Result<Nt4Data, String> {
+        Nt4DataSeed(self.ty)
+            .deserialize(&self.value)
+            .map_err(|x| format!("{:?} is not a valid {}: {}", self.value, self.ty.get_name(), x))
+    }
+}
+
+/// The outcome of [`crate::ConnectionCore::start_persistent_export`].
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistentBackup {
+    pub version: u32,
+    pub entries: Vec<PersistentEntry>,
+    /// Persistent topics whose value did not arrive in time.
+    #[serde(default)]
+    pub missing: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportResult {
+    pub name: String,
+    /// Why the entry was not restored, `None` if it was.
+    pub error: Option<String>,
+}
+
+/// The outcome of [`crate::ConnectionCore::start_persistent_import`], one result per entry in order.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportReport {
+    pub restored: usize,
+    pub failed: usize,
+    pub results: Vec<ImportResult>,
+}
+
+#[derive(Debug)]
+pub enum ExportStage {
+    /// Subscribed to every topic without values, until announces settle.
+    Announces { subuid: i32, last_announce: i64 },
+    /// Subscribed to the values of the persistent topics found, by name.
+    Values { subuid: i32, started: i64, properties: BTreeMap<String, Properties> },
+}
+
+/// An entry being imported, waiting for its announce.
+#[derive(Debug)]
+pub struct Importing {
+    pub index: usize,
+    pub name: String,
+    pub ty: Nt4TypeId,
+    pub pubuid: i32,
+}
+
+/// A running export or import; only one runs at a time.
+#[derive(Debug)]
+pub enum PersistentTask {
+    Export(ExportStage),
+    Import { started: i64, results: Vec<ImportResult>, waiting: Vec<Importing> },
+}
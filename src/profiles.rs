@@ -0,0 +1,78 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use crate::types::SubscriptionOptions;
+
+fn options(periodic_ms: u64, all: bool, topicsonly: bool, prefix: bool) -> SubscriptionOptions {
+    SubscriptionOptions { periodic: Duration::from_millis(periodic_ms), all, topicsonly, prefix }
+}
+
+/// Named [`SubscriptionOptions`], and which subscriptions were made from each.
+#[derive(Debug)]
+pub struct SubscriptionProfiles {
+    profiles: BTreeMap<String, SubscriptionOptions>,
+    /// The profile of every active subscription made with one, by subuid.
+    subscriptions: HashMap<i32, String>,
+}
+
+impl Default for SubscriptionProfiles {
+    fn default() -> Self {
+        let profiles = [
+            // Every value, often enough for a smooth graph.
+            ("plot", options(20, true, false, false)),
+            // The latest value, often enough for a human.
+            ("display", options(100, false, false, false)),
+            // Topics only, everything under the path.
+            ("browse", options(100, false, true, true)),
+        ];
+        Self {
+            profiles: profiles.into_iter().map(|(name, options)| (name.to_string(), options)).collect(),
+            subscriptions: HashMap::new(),
+        }
+    }
+}
+
+impl SubscriptionProfiles {
+    pub fn register(&mut self, name: &str, options: SubscriptionOptions) {
+        self.profiles.insert(name.to_string(), options);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&SubscriptionOptions, String> {
+        self.profiles.get(name).ok_or_else(|| format!("no subscription profile named {:?}", name))
+    }
+
+    pub fn track(&mut self, subuid: i32, name: &str) {
+        self.subscriptions.insert(subuid, name.to_string());
+    }
+
+    pub fn untrack(&mut self, subuid: i32) {
+        self.subscriptions.remove(&subuid);
+    }
+
+    pub fn clear_subscriptions(&mut self) {
+        self.subscriptions.clear();
+    }
+
+    /// The subuids made from profile `name`, sorted.
+    pub fn subscriptions_of(&self, name: &str) -> Vec<i32> {
+        let mut subuids: Vec<i32> =
+            self.subscriptions.iter().filter(|(_, x)| x.as_str() == name).map(|(id, _)| *id).collect();
+        subuids.sort();
+        subuids
+    }
+
+    /// Every profile as a JSON object keyed by name.
+    pub fn export(&self) -> Result<String, String> {
+        serde_json::to_string(&self.profiles).map_err(|x| x.to_string())
+    }
+
+    /// Registers every profile in `json`, as produced by [`SubscriptionProfiles::export`], replacing
+    /// those of the same name. The options of existing subscriptions are not changed.
+    pub fn import(&mut self, json: &str) -> Result<(), String> {
+        let profiles: BTreeMap<String, SubscriptionOptions> = serde_json::from_str(json).map_err(|x| x.to_string())?;
+        self.profiles.extend(profiles);
+        Ok(())
+    }
+}
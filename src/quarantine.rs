@@ -0,0 +1,59 @@
+use std::{any::Any, collections::VecDeque};
+
+use serde_bytes::ByteBuf;
+
+/// Frames kept by [`Quarantine`].
+pub const CAPACITY: usize = 16;
+
+/// A frame as received.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantinedInput {
+    Text(String),
+    Binary(ByteBuf),
+}
+
+/// A frame whose processing panicked, see [`crate::ConnectionCore::quarantined`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedFrame {
+    /// The panic message.
+    pub message: String,
+    #[serde(flatten)]
+    pub input: QuarantinedInput,
+}
+
+/// The last frames whose processing panicked, oldest first.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    frames: VecDeque<QuarantinedFrame>,
+}
+
+impl Quarantine {
+    pub fn push(&mut self, message: String, input: QuarantinedInput) {
+        self.frames.push_back(QuarantinedFrame { message, input });
+        while self.frames.len() > CAPACITY {
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &QuarantinedFrame> {
+        self.frames.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+/// The message of a panic payload, which is a `&str` or a `String` unless raised with `panic_any`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked with a non-string payload".to_string()
+    }
+}
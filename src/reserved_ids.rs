@@ -0,0 +1,20 @@
+/// Binary frames with this topic id are timesync requests and responses.
+pub const TIMESYNC_TOPIC_ID: i32 = -1;
+
+/// What a binary frame's topic id refers to. Negative ids are reserved by the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicIdClass {
+    /// An id the server assigned to an announced topic.
+    Normal,
+    Timesync,
+    /// A reserved id this library does not know, which is never delivered as a value.
+    ReservedUnknown(i32),
+}
+
+pub fn classify_topic_id(id: i32) -> TopicIdClass {
+    match id {
+        TIMESYNC_TOPIC_ID => TopicIdClass::Timesync,
+        id if id < 0 => TopicIdClass::ReservedUnknown(id),
+        _ => TopicIdClass::Normal,
+    }
+}
@@ -0,0 +1,65 @@
+use crate::metrics::{Metrics, ReportMetrics};
+
+/// Most cache entries and history points one [`crate::ConnectionCore::sweep`] looks at, so that
+/// a sweep never holds up a frame.
+pub const SWEEP_BUDGET: usize = 256;
+
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(default)]
+pub struct RetentionOptions {
+    /// Trajectory points older than this much server time are dropped, even if no new pose arrives.
+    pub history_max_age_s: Option<f64>,
+    /// Cached values not updated for this much server time are forgotten, unless their topic is
+    /// retained or persistent.
+    pub cache_max_idle_s: Option<f64>,
+    /// Sweeps run at most this often when piggybacked on incoming frames.
+    pub sweep_interval_s: f64,
+}
+
+impl Default for RetentionOptions {
+    fn default() -> Self {
+        Self { history_max_age_s: None, cache_max_idle_s: None, sweep_interval_s: 10.0 }
+    }
+}
+
+/// What sweeps have reclaimed since the connection was made.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionStats {
+    pub sweeps: u64,
+    pub cache_evicted: u64,
+    pub history_trimmed: u64,
+}
+
+/// Sweep state, so that each sweep continues where the last one ran out of budget.
+#[derive(Debug, Default)]
+pub struct Retention {
+    pub options: RetentionOptions,
+    pub stats: RetentionStats,
+    /// Ids of the cache entries left to look at in this round.
+    pub queue: Vec<i32>,
+    last_sweep: Option<i64>,
+}
+
+impl Retention {
+    /// Whether a sweep is due at local time `now`, never if no limit is set.
+    pub fn due(&self, now: i64) -> bool {
+        let enabled = self.options.history_max_age_s.is_some() || self.options.cache_max_idle_s.is_some();
+        let interval = (self.options.sweep_interval_s * 1e6) as i64;
+        enabled && self.last_sweep.is_none_or(|last| now - last >= interval)
+    }
+
+    pub fn swept(&mut self, now: i64) {
+        self.last_sweep = Some(now);
+        self.stats.sweeps += 1;
+    }
+}
+
+impl ReportMetrics for Retention {
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.counter("retention_sweeps_total", "Retention sweeps run.", self.stats.sweeps);
+        metrics.counter("cache_evicted_total", "Cached values evicted by retention.", self.stats.cache_evicted);
+        metrics.counter("history_trimmed_total", "Trajectory points trimmed by retention.", self.stats.history_trimmed);
+    }
+}
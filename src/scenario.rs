@@ -0,0 +1,290 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use js_sys::JsString;
+use serde::de::DeserializeSeed;
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    binary::BinaryDataFrame,
+    connection::{ConnectionCore, ConnectionSink},
+    reserved_ids::TIMESYNC_TOPIC_ID,
+    types::{Nt4Data, Nt4DataSeed, Nt4TypeId},
+    wire::{self, WireOptions},
+    with_core, Inner, Nt4Connection,
+};
+
+/// One step of a scenario file, at `t_us` on the server clock.
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Step {
+    /// Answers timesync, which makes the connection ready with its server clock at `t_us`. After
+    /// a `disconnect`, the topics announced before it are then announced again, as a server would.
+    Connect,
+    Disconnect,
+    Announce {
+        name: String,
+        #[serde(rename = "type")]
+        ty: Nt4TypeId,
+        #[serde(default)]
+        properties: serde_json::Map<String, serde_json::Value>,
+    },
+    Unannounce {
+        name: String,
+    },
+    /// The keys of `update` that changed, `null` for deleted ones.
+    Properties {
+        name: String,
+        update: serde_json::Map<String, serde_json::Value>,
+    },
+    Value {
+        name: String,
+        value: serde_json::Value,
+    },
+    /// `values` one after the other, `every_us` apart from `t_us` on.
+    Values {
+        name: String,
+        every_us: i64,
+        values: Vec<serde_json::Value>,
+    },
+}
+
+#[derive(serde::Deserialize)]
+struct TimedStep {
+    t_us: i64,
+    #[serde(flatten)]
+    step: Step,
+}
+
+#[derive(serde::Deserialize)]
+struct Spec {
+    steps: Vec<TimedStep>,
+}
+
+/// A step checked and resolved to topic ids.
+#[derive(Debug, Clone)]
+enum Event {
+    Connect,
+    Disconnect,
+    Announce { id: i32, topic: Announced },
+    Unannounce { id: i32 },
+    Properties { id: i32, update: serde_json::Map<String, serde_json::Value> },
+    Value { id: i32, data: Nt4Data },
+}
+
+#[derive(Debug, Clone)]
+struct Announced {
+    name: String,
+    ty: Nt4TypeId,
+    properties: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A timeline of server frames, disconnects and reconnects, fed to a [`ConnectionCore`] up to a
+/// given server time by [`Scenario::run_until`]. Values are stamped with the time of their step,
+/// so what the connection delivers is the same on every run, in the browser and natively.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    events: Vec<(i64, Event)>,
+    /// The topics announced so far by id, with their properties as last updated.
+    announced: BTreeMap<i32, Announced>,
+    connected: bool,
+    next: usize,
+}
+
+impl Scenario {
+    /// Reads a scenario file: `{"steps": [{"t_us", "kind", ...}]}`, see [`Step`]. Steps are run in
+    /// order of `t_us`, those at the same time in file order. Topics get ids from 1 in order of
+    /// their first announce. Fails on values of topics not announced at the time, or not of their
+    /// type, with the index of the step.
+    pub fn from_json(spec: &str) -> Result<Self, String> {
+        let spec: Spec = serde_json::from_str(spec).map_err(|x| format!("invalid scenario: {}", x))?;
+        let mut steps = Vec::new();
+        for (index, TimedStep { t_us, step }) in spec.steps.into_iter().enumerate() {
+            match step {
+                Step::Values { name, every_us, values } => {
+                    if every_us <= 0 {
+                        return Err(format!("step {}: every_us must be positive", index));
+                    }
+                    for (i, value) in values.into_iter().enumerate() {
+                        let t_us = t_us.saturating_add(every_us.saturating_mul(i as i64));
+                        steps.push((t_us, index, Step::Value { name: name.clone(), value }));
+                    }
+                },
+                step => steps.push((t_us, index, step)),
+            }
+        }
+        steps.sort_by_key(|(t_us, ..)| *t_us);
+
+        let mut ids: BTreeMap<String, i32> = BTreeMap::new();
+        let mut announced: BTreeMap<i32, Nt4TypeId> = BTreeMap::new();
+        let mut events = Vec::with_capacity(steps.len());
+        for (t_us, index, step) in steps {
+            if t_us < 0 {
+                return Err(format!("step {}: t_us must not be negative", index));
+            }
+            let announced_id = |name: &str| {
+                ids.get(name)
+                    .copied()
+                    .filter(|id| announced.contains_key(id))
+                    .ok_or_else(|| format!("step {}: {} is not announced at {} µs", index, name, t_us))
+            };
+            let event = match step {
+                Step::Connect => Event::Connect,
+                Step::Disconnect => Event::Disconnect,
+                Step::Announce { name, ty, properties } => {
+                    let next_id = ids.len() as i32 + 1;
+                    let id = *ids.entry(name.clone()).or_insert(next_id);
+                    if announced.insert(id, ty).is_some() {
+                        return Err(format!("step {}: {} is already announced", index, name));
+                    }
+                    Event::Announce { id, topic: Announced { name, ty, properties } }
+                },
+                Step::Unannounce { name } => {
+                    let id = announced_id(&name)?;
+                    announced.remove(&id);
+                    Event::Unannounce { id }
+                },
+                Step::Properties { name, update } => Event::Properties { id: announced_id(&name)?, update },
+                Step::Value { name, value } => {
+                    let id = announced_id(&name)?;
+                    let ty = announced[&id];
+                    let data = Nt4DataSeed(ty)
+                        .deserialize(value)
+                        .map_err(|x| format!("step {}: invalid {} value for {}: {}", index, ty.get_name(), name, x))?;
+                    Event::Value { id, data }
+                },
+                Step::Values { .. } => unreachable!("expanded above"),
+            };
+            events.push((t_us, event));
+        }
+        Ok(Self { events, announced: BTreeMap::new(), connected: false, next: 0 })
+    }
+
+    /// The time of the last step.
+    pub fn end_us(&self) -> i64 {
+        self.events.last().map_or(0, |(t_us, _)| *t_us)
+    }
+
+    /// Whether every step has been run.
+    pub fn finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    /// Runs every step up to and including `t_us` not run yet, returning how many. While
+    /// disconnected, announces and property changes are only remembered for the next connect,
+    /// and values are dropped.
+    pub fn run_until<S: ConnectionSink>(&mut self, core: &mut ConnectionCore, sink: &mut S, t_us: i64) -> Result<usize, S::Error> {
+        let start = self.next;
+        while let Some((at, event)) = self.events.get(self.next).filter(|(at, _)| *at <= t_us).cloned() {
+            self.next += 1;
+            self.run(core, sink, at, event)?;
+        }
+        Ok(self.next - start)
+    }
+
+    fn run<S: ConnectionSink>(&mut self, core: &mut ConnectionCore, sink: &mut S, t_us: i64, event: Event) -> Result<(), S::Error> {
+        match event {
+            Event::Connect => {
+                let local_time = core.now()?;
+                let response = BinaryDataFrame { topic_id: TIMESYNC_TOPIC_ID, timestamp: t_us, data: Nt4Data::Int(local_time) };
+                self.connected = true;
+                core.on_binary(sink, &wire::write_frames(&[response], &WireOptions::default())?)?;
+                for (&id, topic) in &self.announced {
+                    core.on_text(sink, &announce_frame(id, topic))?;
+                }
+            },
+            Event::Disconnect => {
+                if self.connected {
+                    self.connected = false;
+                    core.on_disconnect(sink)?;
+                }
+            },
+            Event::Announce { id, topic } => {
+                if self.connected {
+                    core.on_text(sink, &announce_frame(id, &topic))?;
+                }
+                self.announced.insert(id, topic);
+            },
+            Event::Unannounce { id } => {
+                if let Some(topic) = self.announced.remove(&id) {
+                    if self.connected {
+                        let frame = json!({"method": "unannounce", "params": {"name": topic.name, "id": id}});
+                        core.on_text(sink, &frame.to_string())?;
+                    }
+                }
+            },
+            Event::Properties { id, update } => {
+                let Some(topic) = self.announced.get_mut(&id) else {
+                    return Ok(());
+                };
+                for (key, value) in &update {
+                    match value {
+                        serde_json::Value::Null => topic.properties.remove(key),
+                        value => topic.properties.insert(key.clone(), value.clone()),
+                    };
+                }
+                if self.connected {
+                    let frame = json!({"method": "properties", "params": {"name": topic.name, "update": update}});
+                    core.on_text(sink, &frame.to_string())?;
+                }
+            },
+            Event::Value { id, data } => {
+                if self.connected {
+                    let frame = BinaryDataFrame { topic_id: id, timestamp: t_us, data };
+                    core.on_binary(sink, &wire::write_frames(&[frame], &WireOptions::default())?)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+fn announce_frame(id: i32, topic: &Announced) -> String {
+    let params = json!({"name": topic.name, "id": id, "type": topic.ty, "properties": topic.properties});
+    json!({"method": "announce", "params": params}).to_string()
+}
+
+#[doc = " A scripted timeline of server frames for examples and docs, see {@link Nt4Scenario.from_json}."]
+#[wasm_bindgen]
+pub struct Nt4Scenario {
+    scenario: Scenario,
+    connection: Option<Rc<RefCell<Inner>>>,
+}
+
+#[wasm_bindgen]
+impl Nt4Scenario {
+    #[doc = " from_json(string spec) -> Nt4Scenario\n"]
+    #[doc = " Reads a scenario: `{steps: [{t_us, kind, ...}]}`, run in order of `t_us` on the server clock."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn from_json(spec: &str) -> Result<Nt4Scenario, JsValue> {
+        let scenario = Scenario::from_json(spec).map_err(JsString::from)?;
+        Ok(Self { scenario, connection: None })
+    }
+
+    #[doc = " bind(Nt4Connection connection)\n"]
+    #[doc = " Has {@link run_until} feed `connection` as if its server sent the frames, in place of a WebSocket."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn bind(&mut self, connection: &Nt4Connection) {
+        self.connection = Some(connection.inner.clone());
+    }
+
+    #[doc = " run_until(number t_us) -> number\n"]
+    #[doc = " Runs the steps up to and including server time `t_us` not run yet, returning how many."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn run_until(&mut self, t_us: f64) -> Result<usize, JsValue> {
+        let Some(connection) = &self.connection else {
+            return Err(JsString::from("the scenario is not bound to a connection").into());
+        };
+        with_core(connection, |core, sink| self.scenario.run_until(core, sink, t_us as i64))
+    }
+
+    #[doc = " The server time of the last step."]
+    pub fn end_us(&self) -> f64 {
+        self.scenario.end_us() as f64
+    }
+
+    #[doc = " Whether every step has been run."]
+    pub fn finished(&self) -> bool {
+        self.scenario.finished()
+    }
+}
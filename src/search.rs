@@ -0,0 +1,176 @@
+use crate::types::{Nt4TypeId, Topic};
+
+/// Matched characters of a name, worth one point each...
+const MATCH_SCORE: i32 = 1;
+/// ...and this many more if right after the previous one...
+const CONTIGUOUS_BONUS: i32 = 4;
+/// ...or this many more if at the start of the name or right after a `/`.
+const BOUNDARY_BONUS: i32 = 6;
+
+/// A topic found by [`search`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicMatch {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    pub score: i32,
+    /// `[start, end)` of each run of matched characters, in UTF-16 code units as in JS strings.
+    pub match_ranges: Vec<[usize; 2]>,
+}
+
+/// Matches `query`, already lowercase, as a subsequence of `name` from `start` on, taking the first
+/// occurrence of each character. Returns the score, and the index in `name` of each matched
+/// character in `positions`.
+fn match_from<T: Copy + PartialEq>(
+    name: &[T],
+    query: &[T],
+    start: usize,
+    same: impl Fn(T, T) -> bool,
+    slash: T,
+    positions: &mut Vec<usize>,
+) -> Option<i32> {
+    positions.clear();
+    let mut score = 0;
+    let mut at = start;
+    for &q in query {
+        let i = at + name[at..].iter().position(|&c| same(c, q))?;
+        score += MATCH_SCORE;
+        if positions.last().is_some_and(|&last| last + 1 == i) {
+            score += CONTIGUOUS_BONUS;
+        } else if i == 0 || name[i - 1] == slash {
+            score += BOUNDARY_BONUS;
+        }
+        positions.push(i);
+        at = i + 1;
+    }
+    Some(score)
+}
+
+/// The score and start of the best match of `query` in `name`, trying the first occurrence of its
+/// first character and every occurrence right after a `/`.
+fn best_match<T: Copy + PartialEq>(
+    name: &[T],
+    query: &[T],
+    same: impl Fn(T, T) -> bool + Copy,
+    slash: T,
+    positions: &mut Vec<usize>,
+) -> Option<(i32, usize)> {
+    let first = name.iter().position(|&c| same(c, query[0]))?;
+    let starts = std::iter::once(first)
+        .chain((first + 1..name.len()).filter(|&i| name[i - 1] == slash && same(name[i], query[0])));
+    let mut best: Option<(i32, usize)> = None;
+    for start in starts {
+        let Some(score) = match_from(name, query, start, same, slash, positions) else {
+            // Later starts only leave less of the name to match.
+            break;
+        };
+        if best.is_none_or(|(best, _)| score > best) {
+            best = Some((score, start));
+        }
+    }
+    best
+}
+
+/// Runs of consecutive `positions` of characters in `name`, in UTF-16 code units.
+fn ranges(name: &str, positions: &[usize]) -> Vec<[usize; 2]> {
+    let mut offsets = Vec::with_capacity(name.len() + 1);
+    let mut offset = 0;
+    for c in name.chars() {
+        offsets.push(offset);
+        offset += c.len_utf16();
+    }
+    offsets.push(offset);
+    let mut ranges: Vec<[usize; 2]> = Vec::new();
+    for &i in positions {
+        match ranges.last_mut() {
+            Some(range) if range[1] == offsets[i] => range[1] = offsets[i + 1],
+            _ => ranges.push([offsets[i], offsets[i + 1]]),
+        }
+    }
+    ranges
+}
+
+/// Lowercase `query` for matching against `name`, either as bytes, when both are ASCII as almost all
+/// topic names are, or as characters.
+struct Query {
+    bytes: Vec<u8>,
+    chars: Vec<char>,
+}
+
+impl Query {
+    fn new(query: &str) -> Self {
+        let chars: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+        let bytes = if query.is_ascii() { query.to_ascii_lowercase().into_bytes() } else { Vec::new() };
+        Self { bytes, chars }
+    }
+}
+
+/// Scores `name` against `query`, returning the score and start of the best match. With `start`
+/// given, fills `positions` with the match from there instead.
+fn score(
+    name: &str,
+    query: &Query,
+    buffer: &mut Vec<char>,
+    positions: &mut Vec<usize>,
+    start: Option<usize>,
+) -> Option<(i32, usize)> {
+    if !query.bytes.is_empty() && name.is_ascii() {
+        let same = |c: u8, q: u8| c.to_ascii_lowercase() == q;
+        let name = name.as_bytes();
+        return match start {
+            Some(start) => match_from(name, &query.bytes, start, same, b'/', positions).map(|x| (x, start)),
+            None => best_match(name, &query.bytes, same, b'/', positions),
+        };
+    }
+    buffer.clear();
+    buffer.extend(name.chars());
+    let same = |c: char, q: char| c == q || c.to_lowercase().eq(std::iter::once(q));
+    match start {
+        Some(start) => match_from(buffer, &query.chars, start, same, '/', positions).map(|x| (x, start)),
+        None => best_match(buffer, &query.chars, same, '/', positions),
+    }
+}
+
+/// The `limit` topics whose names best match `query` case-insensitively as a subsequence, with
+/// contiguous matches and matches right after a `/` ranked higher. Ties go to the shorter name,
+/// then alphabetically. An empty query matches every topic with a score of 0.
+pub fn search<'a>(
+    topics: impl IntoIterator<Item = &'a Topic>,
+    query: &str,
+    limit: usize,
+    only: Option<Nt4TypeId>,
+) -> Vec<TopicMatch> {
+    let query = Query::new(query);
+    let mut buffer = Vec::new();
+    let mut positions = Vec::new();
+    let mut found: Vec<(i32, usize, &Topic)> = Vec::new();
+    for topic in topics {
+        if only.is_some_and(|ty| ty != topic.ty) {
+            continue;
+        }
+        if query.chars.is_empty() {
+            found.push((0, 0, topic));
+        } else if let Some((score, start)) = score(&topic.name, &query, &mut buffer, &mut positions, None) {
+            found.push((score, start, topic));
+        }
+    }
+    let order = |a: &(i32, usize, &Topic), b: &(i32, usize, &Topic)| {
+        b.0.cmp(&a.0).then(a.2.name.len().cmp(&b.2.name.len())).then(a.2.name.cmp(&b.2.name))
+    };
+    if found.len() > limit && limit > 0 {
+        found.select_nth_unstable_by(limit - 1, order);
+    }
+    found.truncate(limit);
+    found.sort_by(order);
+    found
+        .into_iter()
+        .map(|(score, start, topic)| {
+            positions.clear();
+            if !query.chars.is_empty() {
+                self::score(&topic.name, &query, &mut buffer, &mut positions, Some(start));
+            }
+            TopicMatch { name: topic.name.to_string(), ty: topic.ty, score, match_ranges: ranges(&topic.name, &positions) }
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+/// Topics under this prefix belong to self tests and are hidden from the connection's events.
+pub const PREFIX: &str = "/nt4-wasm/self_test/";
+
+/// How long each stage of a self test may take.
+pub const STAGE_TIMEOUT_US: i64 = 5_000_000;
+
+/// A timesync round trip slower than this is reported as a warning.
+pub const SLOW_RTT_US: i64 = 100_000;
+
+/// A timesync round trip slower than this fails the self test.
+pub const MAX_RTT_US: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTestStage {
+    /// Waiting for a timesync response, or checking its round-trip time.
+    Timesync,
+    /// Waiting for the server to announce the published test topic.
+    Announce,
+    /// Waiting for the value sent to the test topic to come back.
+    Echo,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SelfTestReport {
+    pub announce_ms: f64,
+    pub echo_ms: f64,
+    pub rtt_us: i64,
+    pub offset_us: i64,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SelfTestFailure {
+    pub stage: SelfTestStage,
+    pub message: String,
+}
+
+/// A running self test, see [`ConnectionCore::start_self_test`](crate::ConnectionCore::start_self_test).
+#[derive(Debug)]
+pub struct SelfTest {
+    pub name: String,
+    pub pubuid: i32,
+    pub subuids: Vec<i32>,
+    pub stage: SelfTestStage,
+    pub started: i64,
+    pub stage_started: i64,
+    pub topic_id: Option<i32>,
+    pub value: f64,
+    pub announce_us: i64,
+    pub warnings: Vec<String>,
+}
+
+impl SelfTest {
+    pub fn fail(&self, message: impl Into<String>) -> SelfTestFailure {
+        SelfTestFailure { stage: self.stage, message: message.into() }
+    }
+}
+
+pub fn is_test_topic(name: &str) -> bool {
+    name.starts_with(PREFIX)
+}
@@ -0,0 +1,84 @@
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+
+/// What is done with a NaN, or a `null` or `undefined`, in a number or an array of numbers being sent.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValuePolicy {
+    /// Sent as is, only for NaN.
+    Allow,
+    /// The send fails, naming the index of the element in an array.
+    Error,
+    /// Nothing is sent, without an error.
+    SkipMessage,
+    /// Sent as this number instead.
+    Replace(f64),
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SendPolicies {
+    pub nan_policy: ValuePolicy,
+    pub null_policy: ValuePolicy,
+}
+
+impl Default for SendPolicies {
+    fn default() -> Self {
+        Self { nan_policy: ValuePolicy::Allow, null_policy: ValuePolicy::Error }
+    }
+}
+
+impl SendPolicies {
+    pub fn check(&self) -> Result<(), String> {
+        if self.null_policy == ValuePolicy::Allow {
+            return Err("null_policy cannot be \"allow\", null is not a number".to_string());
+        }
+        Ok(())
+    }
+
+    /// The policy for `value` and what it is, if one applies.
+    fn policy(&self, value: &JsValue) -> Option<(ValuePolicy, &'static str)> {
+        if value.is_null() || value.is_undefined() {
+            let what = if value.is_null() { "null" } else { "undefined" };
+            Some((self.null_policy, what))
+        } else if value.as_f64().is_some_and(f64::is_nan) {
+            Some((self.nan_policy, "NaN"))
+        } else {
+            None
+        }
+    }
+
+    /// `data` with its NaNs and nulls handled, or `None` if the message is to be skipped. Arrays
+    /// are only looked into if every element is a number, `null` or `undefined`, holes included.
+    pub fn apply(&self, data: JsValue) -> Result<Option<JsValue>, String> {
+        if !Array::is_array(&data) {
+            return match self.policy(&data) {
+                None | Some((ValuePolicy::Allow, _)) => Ok(Some(data)),
+                Some((ValuePolicy::Error, what)) => Err(format!("the value is {}", what)),
+                Some((ValuePolicy::SkipMessage, _)) => Ok(None),
+                Some((ValuePolicy::Replace(x), _)) => Ok(Some(JsValue::from_f64(x))),
+            };
+        }
+        let array: &Array = data.unchecked_ref();
+        let elements: Vec<JsValue> = (0..array.length()).map(|i| array.get(i)).collect();
+        if !elements.iter().all(|x| x.as_f64().is_some() || x.is_null() || x.is_undefined()) {
+            return Ok(Some(data));
+        }
+        let mut replaced = None;
+        for (i, element) in elements.iter().enumerate() {
+            match self.policy(element) {
+                None | Some((ValuePolicy::Allow, _)) => {},
+                Some((ValuePolicy::Error, what)) => return Err(format!("element {} of the array is {}", i, what)),
+                Some((ValuePolicy::SkipMessage, _)) => return Ok(None),
+                Some((ValuePolicy::Replace(x), _)) => {
+                    // Copied on the first replacement, the caller's array is left alone.
+                    let copy = replaced.get_or_insert_with(|| Array::from(&data));
+                    copy.set(i as u32, JsValue::from_f64(x));
+                },
+            }
+        }
+        Ok(Some(replaced.map_or(data, JsValue::from)))
+    }
+}
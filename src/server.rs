@@ -0,0 +1,518 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{HeaderValue, StatusCode},
+    Message,
+};
+
+use crate::{
+    binary::BinaryDataFrame,
+    reserved_ids::{self, TopicIdClass},
+    text::*,
+    types::{Nt4Data, Nt4TypeId, Properties},
+    wire::{self, WireOptions},
+};
+
+const SUBPROTOCOL: &str = "networktables.first.wpi.edu";
+
+/// How long a client's thread waits for a message before sending what other clients caused.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Shortest time between two updates of the demo topics.
+const DEMO_INTERVAL_US: i64 = 20_000;
+
+/// A client of a [`ServerCore`], from [`ServerCore::connect`].
+pub type ClientId = u32;
+
+/// Something for the transport to send to one client.
+#[derive(Debug)]
+pub enum ServerFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct ServerTopic {
+    id: i32,
+    ty: Nt4TypeId,
+    properties: Properties,
+    value: Option<(i64, Nt4Data)>,
+    /// Clients publishing the topic, with their pubuids.
+    publishers: HashSet<(ClientId, i32)>,
+    /// Published by the server itself, see [`ServerCore::publish_local`].
+    local: bool,
+}
+
+#[derive(Debug, Default)]
+struct ServerClient {
+    name: String,
+    /// Names of the client's publications, by pubuid.
+    publications: HashMap<i32, String>,
+    subscriptions: HashMap<i32, SubscribeParams>,
+    /// Ids of the topics announced to the client.
+    announced: HashSet<i32>,
+    frames: Vec<ServerFrame>,
+}
+
+impl ServerClient {
+    fn subscription_matches(&self, name: &str, values: bool) -> bool {
+        self.subscriptions.values().any(|params| {
+            (!values || !params.options.topicsonly)
+                && params.topics.iter().any(|x| if params.options.prefix { name.starts_with(x.as_str()) } else { name == x })
+        })
+    }
+
+    fn send_text(&mut self, data: &ServerToClientTextDataFrame) -> Result<(), String> {
+        let data = serde_json::to_string(data).map_err(|x| format!("{:?}", x))?;
+        self.frames.push(ServerFrame::Text(data));
+        Ok(())
+    }
+
+    fn send_value(&mut self, frame: BinaryDataFrame) -> Result<(), String> {
+        // One value per message, `ConnectionCore::on_binary` takes one at a time.
+        let data = wire::write_frames(&[frame], &WireOptions::default())?;
+        self.frames.push(ServerFrame::Binary(data));
+        Ok(())
+    }
+}
+
+/// The protocol state of a small NT4 server, without any sockets, so it can be driven in tests
+/// and behind any transport. Topics live as long as they are published, or while `persistent`
+/// or `retained`. Every value is sent to every subscriber as soon as it arrives, one per binary
+/// message: `periodic` and `all` are ignored.
+#[derive(Debug)]
+pub struct ServerCore {
+    start_time: Instant,
+    topics: BTreeMap<String, ServerTopic>,
+    next_topic_id: i32,
+    clients: BTreeMap<ClientId, ServerClient>,
+    next_client: ClientId,
+    demo: bool,
+    last_demo_update: Option<i64>,
+}
+
+impl Default for ServerCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerCore {
+    pub fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            topics: BTreeMap::new(),
+            next_topic_id: 1,
+            clients: BTreeMap::new(),
+            next_client: 0,
+            demo: false,
+            last_demo_update: None,
+        }
+    }
+
+    /// A server with topics under `/demo/` that [`Self::tick`] keeps changing: `counter` (int),
+    /// `sine` (double), `pose` (double[]) and `message` (string).
+    pub fn with_demo_topics() -> Self {
+        let mut core = Self::new();
+        core.demo = true;
+        for (name, ty) in [
+            ("/demo/counter", Nt4TypeId::Int),
+            ("/demo/sine", Nt4TypeId::Double),
+            ("/demo/pose", Nt4TypeId::DoubleArray),
+            ("/demo/message", Nt4TypeId::String),
+        ] {
+            core.publish_local(name, ty, Properties::default());
+        }
+        let _ = core.set_local("/demo/message", Nt4Data::String("hello from nt4-wasm".to_string()));
+        core.tick();
+        core
+    }
+
+    /// Microseconds since the server started, the server clock.
+    pub fn now(&self) -> i64 {
+        self.start_time.elapsed().as_micros() as i64
+    }
+
+    /// Updates the demo topics if they are due, see [`Self::with_demo_topics`].
+    pub fn tick(&mut self) {
+        let now = self.now();
+        if !self.demo || self.last_demo_update.is_some_and(|x| now - x < DEMO_INTERVAL_US) {
+            return;
+        }
+        self.last_demo_update = Some(now);
+        let t = now as f64 / 1e6;
+        let _ = self.set_local("/demo/counter", Nt4Data::Int(now / 1_000_000));
+        let _ = self.set_local("/demo/sine", Nt4Data::Double(t.sin()));
+        let _ = self.set_local("/demo/pose", Nt4Data::DoubleArray(vec![4.0 + 2.0 * t.cos(), 2.0 + t.sin(), t]));
+    }
+
+    pub fn connect(&mut self, name: &str) -> ClientId {
+        let id = self.next_client;
+        self.next_client += 1;
+        self.clients.insert(id, ServerClient { name: name.to_string(), ..Default::default() });
+        id
+    }
+
+    /// Forgets `client`, unpublishing everything it published.
+    pub fn disconnect(&mut self, client: ClientId) {
+        let Some(state) = self.clients.remove(&client) else {
+            return;
+        };
+        for (pubuid, name) in state.publications {
+            let _ = self.unpublish(client, pubuid, &name);
+        }
+    }
+
+    pub fn client_name(&self, client: ClientId) -> Option<&str> {
+        self.clients.get(&client).map(|x| x.name.as_str())
+    }
+
+    /// Everything to send to `client` since the last call, in order.
+    pub fn take_frames(&mut self, client: ClientId) -> Vec<ServerFrame> {
+        self.clients.get_mut(&client).map(|x| std::mem::take(&mut x.frames)).unwrap_or_default()
+    }
+
+    /// Publishes `name` from the server itself, for [`Self::set_local`]. A topic that already
+    /// exists keeps its type.
+    pub fn publish_local(&mut self, name: &str, ty: Nt4TypeId, properties: Properties) {
+        if let Some(topic) = self.topics.get_mut(name) {
+            topic.local = true;
+            return;
+        }
+        self.create_topic(name, ty, properties, true);
+        let _ = self.announce_to_subscribers(name, None);
+    }
+
+    /// Sets the value of `name`, published with [`Self::publish_local`], at the current server time.
+    pub fn set_local(&mut self, name: &str, data: Nt4Data) -> Result<(), String> {
+        if !self.topics.get(name).is_some_and(|x| x.local) {
+            return Err(format!("{:?} is not published by the server", name));
+        }
+        let timestamp = self.now();
+        self.set_value(name, timestamp, data)
+    }
+
+    /// A text frame from `client`: a single message, or an array of them as NT4 clients send.
+    pub fn on_text(&mut self, client: ClientId, data: &str) -> Result<(), String> {
+        let value: serde_json::Value = serde_json::from_str(data).map_err(|x| format!("{:?}", x))?;
+        let messages = match value {
+            serde_json::Value::Array(messages) => messages,
+            message => vec![message],
+        };
+        for message in messages {
+            let message: ClientToServerTextDataFrame =
+                serde_json::from_value(message).map_err(|x| format!("{:?}", x))?;
+            self.on_message(client, message)?;
+        }
+        Ok(())
+    }
+
+    /// A binary frame from `client`, holding any number of values.
+    pub fn on_binary(&mut self, client: ClientId, mut data: &[u8]) -> Result<(), String> {
+        while !data.is_empty() {
+            let frame: BinaryDataFrame = serde::Deserialize::deserialize(&mut rmp_serde::Deserializer::new(&mut data))
+                .map_err(|x| format!("{:?}", x))?;
+            self.on_value(client, frame)?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, client: ClientId, message: ClientToServerTextDataFrame) -> Result<(), String> {
+        match message {
+            ClientToServerTextDataFrame::Publish(params) => self.publish(client, params),
+            ClientToServerTextDataFrame::Unpublish(params) => {
+                let name = self.client(client)?.publications.remove(&params.pubuid);
+                match name {
+                    Some(name) => self.unpublish(client, params.pubuid, &name),
+                    None => Ok(()),
+                }
+            },
+            ClientToServerTextDataFrame::SetProperties(params) => self.set_properties(client, params),
+            ClientToServerTextDataFrame::Subscribe(params) => self.subscribe(client, params),
+            ClientToServerTextDataFrame::Unsubscribe(params) => {
+                self.client(client)?.subscriptions.remove(&params.subuid);
+                Ok(())
+            },
+            ClientToServerTextDataFrame::Values(frames) => {
+                for frame in frames {
+                    self.on_value(client, frame)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn client(&mut self, client: ClientId) -> Result<&mut ServerClient, String> {
+        self.clients.get_mut(&client).ok_or_else(|| format!("unknown client {}", client))
+    }
+
+    fn create_topic(&mut self, name: &str, ty: Nt4TypeId, properties: Properties, local: bool) {
+        let id = self.next_topic_id;
+        self.next_topic_id += 1;
+        let topic = ServerTopic { id, ty, properties, value: None, publishers: HashSet::new(), local };
+        self.topics.insert(name.to_string(), topic);
+    }
+
+    /// Announces `name` to every subscribed client but `except`.
+    fn announce_to_subscribers(&mut self, name: &str, except: Option<ClientId>) -> Result<(), String> {
+        let ids: Vec<ClientId> = self.clients.keys().copied().filter(|&x| Some(x) != except).collect();
+        for client in ids {
+            self.announce_to(client, name, None)?;
+        }
+        Ok(())
+    }
+
+    /// Announces `name` to `client` if one of its subscriptions matches, or always with its
+    /// `pubuid` to a publisher, then sends it the value unless its subscriptions only want topics.
+    fn announce_to(&mut self, client: ClientId, name: &str, pubuid: Option<i32>) -> Result<(), String> {
+        let Some(topic) = self.topics.get(name) else {
+            return Ok(());
+        };
+        let state = self.clients.get_mut(&client).ok_or_else(|| format!("unknown client {}", client))?;
+        if pubuid.is_none() && !state.subscription_matches(name, false) {
+            return Ok(());
+        }
+        // Publishers are told their pubuid even if the topic was announced to them already.
+        if state.announced.insert(topic.id) || pubuid.is_some() {
+            state.send_text(&ServerToClientTextDataFrame::Announce(AnnounceParams {
+                name: name.to_string(),
+                id: topic.id,
+                ty: topic.ty,
+                pubuid,
+                properties: topic.properties.clone(),
+            }))?;
+            if let Some((timestamp, data)) = &topic.value {
+                if state.subscription_matches(name, true) {
+                    state.send_value(BinaryDataFrame { topic_id: topic.id, timestamp: *timestamp, data: data.clone() })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, client: ClientId, params: PublishParams) -> Result<(), String> {
+        self.client(client)?.publications.insert(params.pubuid, params.name.clone());
+        let created = !self.topics.contains_key(&params.name);
+        if created {
+            self.create_topic(&params.name, params.ty, params.properties, false);
+        }
+        if let Some(topic) = self.topics.get_mut(&params.name) {
+            topic.publishers.insert((client, params.pubuid));
+        }
+        self.announce_to(client, &params.name, Some(params.pubuid))?;
+        if created {
+            self.announce_to_subscribers(&params.name, Some(client))?;
+        }
+        Ok(())
+    }
+
+    /// Drops the publication `pubuid` of `client`, and the topic with its last publisher unless
+    /// it is local, `persistent` or `retained`.
+    fn unpublish(&mut self, client: ClientId, pubuid: i32, name: &str) -> Result<(), String> {
+        let Some(topic) = self.topics.get_mut(name) else {
+            return Ok(());
+        };
+        topic.publishers.remove(&(client, pubuid));
+        if !topic.publishers.is_empty() || topic.local || topic.properties.persistent || topic.properties.retained {
+            return Ok(());
+        }
+        let id = topic.id;
+        self.topics.remove(name);
+        for state in self.clients.values_mut() {
+            if state.announced.remove(&id) {
+                state.send_text(&ServerToClientTextDataFrame::Unannounce(UnannounceParams {
+                    name: name.to_string(),
+                    id,
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the update and passes it on to every client the topic is announced to,
+    /// acknowledged to the one that sent it.
+    fn set_properties(&mut self, client: ClientId, params: SetPropertiesParams) -> Result<(), String> {
+        let Some(topic) = self.topics.get_mut(&params.name) else {
+            return Ok(());
+        };
+        params.update.apply_to(&mut topic.properties);
+        let id = topic.id;
+        let update = match serde_json::to_value(&params.update).map_err(|x| format!("{:?}", x))? {
+            serde_json::Value::Object(update) => update,
+            _ => serde_json::Map::new(),
+        };
+        for (&other, state) in self.clients.iter_mut() {
+            if state.announced.contains(&id) || other == client {
+                state.send_text(&ServerToClientTextDataFrame::Properties(PropertiesParams {
+                    name: params.name.clone(),
+                    ack: (other == client).then_some(true),
+                    update: update.clone(),
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&mut self, client: ClientId, params: SubscribeParams) -> Result<(), String> {
+        let state = self.client(client)?;
+        state.subscriptions.insert(params.subuid, params);
+        let names: Vec<String> = self.topics.keys().cloned().collect();
+        for name in names {
+            let topic = &self.topics[&name];
+            let (id, value) = (topic.id, topic.value.clone());
+            let state = self.client(client)?;
+            if !state.announced.contains(&id) {
+                self.announce_to(client, &name, None)?;
+            } else if let Some((timestamp, data)) = value {
+                // Already announced, but a new subscription is sent the current value too.
+                if state.subscription_matches(&name, true) {
+                    state.send_value(BinaryDataFrame { topic_id: id, timestamp, data })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_value(&mut self, client: ClientId, frame: BinaryDataFrame) -> Result<(), String> {
+        match reserved_ids::classify_topic_id(frame.topic_id) {
+            TopicIdClass::Normal => {},
+            TopicIdClass::Timesync => {
+                let timestamp = self.now();
+                return self.client(client)?.send_value(BinaryDataFrame { timestamp, ..frame });
+            },
+            TopicIdClass::ReservedUnknown(_) => return Ok(()),
+        }
+        let Some(name) = self.client(client)?.publications.get(&frame.topic_id).cloned() else {
+            return Ok(());
+        };
+        let timestamp = if frame.timestamp == 0 { self.now() } else { frame.timestamp };
+        self.set_value(&name, timestamp, frame.data)
+    }
+
+    fn set_value(&mut self, name: &str, timestamp: i64, data: Nt4Data) -> Result<(), String> {
+        let Some(topic) = self.topics.get_mut(name) else {
+            return Ok(());
+        };
+        // Types that share an id, such as `string` and `json`, cannot be told apart on the wire.
+        if data.get_id() != topic.ty.get_id() {
+            return Err(format!("{:?} is {}, not {}", name, topic.ty.get_name(), data.get_name()));
+        }
+        topic.value = Some((timestamp, data.clone()));
+        let id = topic.id;
+        for state in self.clients.values_mut() {
+            if state.announced.contains(&id) && state.subscription_matches(name, true) {
+                state.send_value(BinaryDataFrame { topic_id: id, timestamp, data: data.clone() })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`ServerCore`] serving NT4 over WebSockets at `ws://<addr>/nt/<client name>`, one thread per
+/// client.
+pub struct Nt4Server {
+    listener: TcpListener,
+    core: Arc<Mutex<ServerCore>>,
+}
+
+fn err<E: std::fmt::Display>(x: E) -> String {
+    x.to_string()
+}
+
+impl Nt4Server {
+    /// Listens on `addr`, usually port 5810. Port 0 picks a free one, see [`Self::local_addr`].
+    pub fn bind(addr: &str, core: ServerCore) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr).map_err(err)?;
+        Ok(Self { listener, core: Arc::new(Mutex::new(core)) })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(err)
+    }
+
+    /// The state shared by every client, e.g. to call [`ServerCore::set_local`] from outside.
+    pub fn core(&self) -> Arc<Mutex<ServerCore>> {
+        self.core.clone()
+    }
+
+    /// Accepts clients until the listener fails. A client that breaks the protocol is disconnected.
+    pub fn serve(&self) -> Result<(), String> {
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(err)?;
+            let core = self.core.clone();
+            std::thread::spawn(move || serve_client(core, stream));
+        }
+        Ok(())
+    }
+}
+
+fn lock(core: &Mutex<ServerCore>) -> std::sync::MutexGuard<'_, ServerCore> {
+    // A client thread that panicked leaves the state as consistent as any other error would.
+    core.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+// The handshake callback has to return tungstenite's `ErrorResponse` as is.
+#[allow(clippy::result_large_err)]
+fn serve_client(core: Arc<Mutex<ServerCore>>, stream: TcpStream) -> Result<(), String> {
+    stream.set_nodelay(true).map_err(err)?;
+    let mut name = None;
+    let callback = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+        let Some(client) = request.uri().path().strip_prefix("/nt/") else {
+            let mut error = ErrorResponse::new(Some("NT4 is served at /nt/<client name>".to_string()));
+            *error.status_mut() = StatusCode::NOT_FOUND;
+            return Err(error);
+        };
+        name = Some(client.to_string());
+        let protocols = request.headers().get("Sec-WebSocket-Protocol").and_then(|x| x.to_str().ok());
+        if protocols.is_some_and(|x| x.split(',').any(|x| x.trim() == SUBPROTOCOL)) {
+            response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(SUBPROTOCOL));
+        }
+        Ok(response)
+    };
+    let mut socket = tungstenite::accept_hdr(stream, callback).map_err(err)?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).map_err(err)?;
+    let client = lock(&core).connect(name.as_deref().unwrap_or_default());
+    let result = run_client(&core, &mut socket, client);
+    lock(&core).disconnect(client);
+    result
+}
+
+fn run_client(
+    core: &Mutex<ServerCore>,
+    socket: &mut tungstenite::WebSocket<TcpStream>,
+    client: ClientId,
+) -> Result<(), String> {
+    loop {
+        let received = match socket.read() {
+            Ok(Message::Text(data)) => lock(core).on_text(client, &data),
+            Ok(Message::Binary(data)) => lock(core).on_binary(client, &data),
+            Ok(_) => Ok(()),
+            Err(tungstenite::Error::Io(x))
+                if matches!(x.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) =>
+            {
+                Ok(())
+            },
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return Ok(()),
+            Err(x) => return Err(err(x)),
+        };
+        let frames = {
+            let mut core = lock(core);
+            core.tick();
+            core.take_frames(client)
+        };
+        received?;
+        for frame in frames {
+            let message = match frame {
+                ServerFrame::Text(data) => Message::Text(data),
+                ServerFrame::Binary(data) => Message::Binary(data),
+            };
+            socket.send(message).map_err(err)?;
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+/// The first byte of every share state. Only bumped when a field changes meaning; new fields are
+/// just added, and decoders pass through the ones they do not know.
+pub const VERSION: u8 = 1;
+
+/// Longest encoded share state, to keep links well within what browsers and chat apps accept.
+pub const MAX_ENCODED_LEN: usize = 2048;
+
+/// Strings longer than this many characters are cut short, ending with `…`.
+pub const MAX_STRING_LEN: usize = 64;
+
+/// Arrays longer than this many items are cut short, ending with the marker `{"…": <items left out>}`.
+pub const MAX_ARRAY_LEN: usize = 16;
+
+/// Objects nested deeper than this are rejected.
+const MAX_DEPTH: usize = 8;
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Unpadded base64url.
+fn base64url_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(BASE64URL[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64url_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for (i, c) in text.bytes().enumerate() {
+        let Some(value) = BASE64URL.iter().position(|&x| x == c) else {
+            return Err(format!("share state has an invalid character {:?} at {}", c as char, i));
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    if count >= 6 {
+        return Err("share state is truncated".to_string());
+    }
+    Ok(out)
+}
+
+/// Cuts `value` down to what a share state holds.
+fn shorten(value: Value, depth: usize) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(x) if x.chars().count() > MAX_STRING_LEN => {
+            Value::String(x.chars().take(MAX_STRING_LEN - 1).chain(['…']).collect())
+        },
+        Value::Array(_) | Value::Object(_) if depth >= MAX_DEPTH => {
+            return Err(format!("share state is nested deeper than {} levels", MAX_DEPTH));
+        },
+        Value::Array(items) => {
+            let left_out = items.len().saturating_sub(MAX_ARRAY_LEN);
+            let mut items = items
+                .into_iter()
+                .take(MAX_ARRAY_LEN)
+                .map(|x| shorten(x, depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            if left_out > 0 {
+                items.push(Value::Object(Map::from_iter([("…".to_string(), Value::from(left_out))])));
+            }
+            Value::Array(items)
+        },
+        Value::Object(map) => Value::Object(
+            map.into_iter().map(|(k, v)| Ok((k, shorten(v, depth + 1)?))).collect::<Result<_, String>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// Encodes `state` as `VERSION` followed by msgpack, in base64url.
+pub fn encode(state: Value) -> Result<String, String> {
+    let mut data = vec![VERSION];
+    rmp_serde::encode::write(&mut data, &shorten(state, 0)?).map_err(|x| x.to_string())?;
+    let encoded = base64url_encode(&data);
+    if encoded.len() > MAX_ENCODED_LEN {
+        return Err(format!(
+            "share state is {} characters, over the limit of {}",
+            encoded.len(),
+            MAX_ENCODED_LEN
+        ));
+    }
+    Ok(encoded)
+}
+
+pub fn decode(encoded: &str) -> Result<Value, String> {
+    if encoded.len() > MAX_ENCODED_LEN {
+        return Err(format!("share state is over the limit of {} characters", MAX_ENCODED_LEN));
+    }
+    let data = base64url_decode(encoded)?;
+    match data.first() {
+        Some(&VERSION) => rmp_serde::from_slice(&data[1..]).map_err(|x| format!("invalid share state: {}", x)),
+        Some(version) => Err(format!("share state version {} is not supported, only {}", version, VERSION)),
+        None => Err("share state is empty".to_string()),
+    }
+}
+
+#[doc = " encode_share_state(any state) -> string\n"]
+#[doc = " Encodes `state` into a URL-safe string for {@link decode_share_state}, throwing if it is over 2048 characters."]
+#[wasm_bindgen(skip_jsdoc)]
+pub fn encode_share_state(state: JsValue) -> Result<String, JsValue> {
+    let state = serde_wasm_bindgen::from_value(state)?;
+    encode(state).map_err(|x| js_sys::JsString::from(x).into())
+}
+
+#[doc = " decode_share_state(string encoded) -> any\n"]
+#[doc = " The state given to {@link encode_share_state}, as shortened there."]
+#[wasm_bindgen(skip_jsdoc)]
+pub fn decode_share_state(encoded: &str) -> Result<JsValue, JsValue> {
+    let state = decode(encoded).map_err(js_sys::JsString::from)?;
+    Ok(serde::Serialize::serialize(&state, &serde_wasm_bindgen::Serializer::json_compatible())?)
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+use crate::types::{Nt4Data, Nt4DataSeed, Nt4TypeId, Properties};
+
+/// Bumped whenever a field of [`Snapshot`] is renamed, removed or changes meaning.
+pub const VERSION: u32 = 2;
+
+/// Raw values longer than this are summarized as [`SnapshotValue::Summary`].
+pub const RAW_SUMMARY_THRESHOLD: usize = 256;
+
+/// The state of a connection at one point in time, see [`ConnectionCore::snapshot`](crate::ConnectionCore::snapshot).
+/// Maps are ordered by key so two snapshots serialize in the same order and can be diffed.
+/// Snapshots read back, e.g. by [`ConnectionCore::hydrate`](crate::ConnectionCore::hydrate), must
+/// have the `type` of each topic before its `value`, as they are written.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub connection: ConnectionSnapshot,
+    /// Every announced topic by name.
+    pub topics: BTreeMap<String, TopicSnapshot>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// No timesync response since the last disconnect.
+    Connecting,
+    Ready,
+    Closed,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionSnapshot {
+    pub state: ConnectionState,
+    /// When the snapshot was taken, on the server clock.
+    pub server_time_us: i64,
+    pub offset_us: i64,
+    /// Round-trip time of the last timesync.
+    pub rtt_us: Option<i64>,
+    pub subscriptions: usize,
+    pub publications: usize,
+    pub pending_unannounced: usize,
+    pub dropped_unannounced: u64,
+    /// Announces dropped by [`ConnectionCore::set_max_topics`](crate::ConnectionCore::set_max_topics).
+    pub topics_over_limit: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TopicSnapshot {
+    pub id: i32,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    pub properties: Option<Properties>,
+    /// The last value received, if any.
+    pub value: Option<SnapshotValue>,
+    /// Server timestamp of `value`.
+    pub timestamp_us: Option<i64>,
+    /// How long before the snapshot `value` was sent, on the server clock.
+    pub staleness_us: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+pub enum SnapshotValue {
+    Value(Nt4Data),
+    /// A raw value too long to embed, identified by its 64-bit FNV-1a hash as 16 hex digits.
+    Summary { length: usize, fnv1a: String },
+}
+
+impl SnapshotValue {
+    pub fn new(data: &Nt4Data) -> Self {
+        match data {
+            Nt4Data::Raw(x) | Nt4Data::Rpc(x) | Nt4Data::MsgPack(x) | Nt4Data::Protobuf(x)
+                if x.len() > RAW_SUMMARY_THRESHOLD =>
+            {
+                Self::Summary { length: x.len(), fnv1a: format!("{:016x}", fnv1a(x)) }
+            },
+            data => Self::Value(data.clone()),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TopicSnapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TopicSnapshotVisitor)
+    }
+}
+
+/// Reads the value of a topic with its type, rather than guessing it like the untagged [`Nt4Data`].
+struct TopicSnapshotVisitor;
+
+impl<'de> Visitor<'de> for TopicSnapshotVisitor {
+    type Value = TopicSnapshot;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a topic snapshot")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TopicSnapshot, A::Error> {
+        let (mut id, mut ty, mut properties, mut value, mut timestamp_us, mut staleness_us) =
+            (None, None, None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "id" => id = Some(map.next_value()?),
+                "type" => ty = Some(map.next_value()?),
+                "properties" => properties = map.next_value()?,
+                "value" => {
+                    let ty = ty.ok_or_else(|| de::Error::custom("the type of a topic must come before its value"))?;
+                    value = map.next_value_seed(SnapshotValueSeed(ty))?;
+                },
+                "timestamp_us" => timestamp_us = map.next_value()?,
+                "staleness_us" => staleness_us = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+        Ok(TopicSnapshot {
+            id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+            ty: ty.ok_or_else(|| de::Error::missing_field("type"))?,
+            properties,
+            value,
+            timestamp_us,
+            staleness_us,
+        })
+    }
+}
+
+/// An optional [`SnapshotValue`] of a topic of the given type.
+struct SnapshotValueSeed(Nt4TypeId);
+
+impl<'de> DeserializeSeed<'de> for SnapshotValueSeed {
+    type Value = Option<SnapshotValue>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SnapshotValueSeed {
+    type Value = Option<SnapshotValue>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a {} value or null", self.0.get_name())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        if self.0.get_id() == 5 {
+            // Raw values may be summarized, which only the shape tells apart.
+            return deserializer.deserialize_any(RawValueVisitor(self.0)).map(Some);
+        }
+        Nt4DataSeed(self.0).deserialize(deserializer).map(|x| Some(SnapshotValue::Value(x)))
+    }
+}
+
+/// A raw value, either its bytes or a [`SnapshotValue::Summary`].
+struct RawValueVisitor(Nt4TypeId);
+
+impl RawValueVisitor {
+    fn value<E: de::Error>(&self, bytes: Vec<u8>) -> Result<SnapshotValue, E> {
+        Nt4Data::Raw(bytes.into()).convert(self.0.get_name()).map(SnapshotValue::Value).map_err(E::custom)
+    }
+}
+
+impl<'de> Visitor<'de> for RawValueVisitor {
+    type Value = SnapshotValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("bytes or {length, fnv1a}")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<SnapshotValue, E> {
+        self.value(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<SnapshotValue, E> {
+        self.value(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SnapshotValue, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.value(bytes)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SnapshotValue, A::Error> {
+        let (mut length, mut fnv1a) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "length" => length = Some(map.next_value()?),
+                "fnv1a" => fnv1a = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+        Ok(SnapshotValue::Summary {
+            length: length.ok_or_else(|| de::Error::missing_field("length"))?,
+            fnv1a: fnv1a.ok_or_else(|| de::Error::missing_field("fnv1a"))?,
+        })
+    }
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is the same on every platform and version.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    binary::BinaryDataFrame,
+    text::{AnnounceParams, UnannounceParams},
+    types::{Nt4Data, Properties, Topic},
+};
+
+/// The first byte of every delta. Only bumped when a field changes meaning; new fields are just
+/// added, and followers skip the ones they do not know.
+pub const VERSION: u8 = 1;
+
+/// Unannounces kept for deltas, past which followers further behind get a full delta.
+pub const MAX_REMOVED: usize = 1024;
+
+/// What changed on a leader connection between two sequence numbers, see
+/// [`crate::ConnectionCore::export_delta`].
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug)]
+pub struct Delta {
+    /// The sequence number the delta was asked for since.
+    pub from_seq: u64,
+    /// The leader's latest sequence number, to ask for the next delta since.
+    pub to_seq: u64,
+    /// Whether the delta holds every topic and value rather than the changes, so whatever else the
+    /// follower has is gone.
+    pub full: bool,
+    pub ready: bool,
+    /// The leader's estimate of the server clock when the delta was made.
+    pub server_time_us: i64,
+    /// Sorted by sequence number.
+    pub unannounced: Vec<UnannounceParams>,
+    /// Announced or with changed properties, sorted by id.
+    pub announced: Vec<AnnounceParams>,
+    /// The latest value of each topic that has one, sorted by id.
+    pub values: Vec<BinaryDataFrame>,
+}
+
+impl Delta {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut data = vec![VERSION];
+        rmp_serde::encode::write_named(&mut data, self).map_err(|x| x.to_string())?;
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        match data.first() {
+            Some(&VERSION) => rmp_serde::from_slice(&data[1..]).map_err(|x| format!("invalid delta: {}", x)),
+            Some(version) => Err(format!("delta version {} is not supported, only {}", version, VERSION)),
+            None => Err("delta is empty".to_string()),
+        }
+    }
+}
+
+/// When each announced topic and cached value last changed, for deltas. Nothing is tracked until
+/// the first delta is exported, which is full.
+#[derive(Debug, Default)]
+pub struct SyncLog {
+    enabled: bool,
+    seq: u64,
+    /// Deltas since a sequence number before this one are full: changes before it were not
+    /// tracked, or their unannounces are no longer kept.
+    full_before: u64,
+    announced: HashMap<i32, u64>,
+    valued: HashMap<i32, u64>,
+    removed: VecDeque<(u64, UnannounceParams)>,
+    /// The `to_seq` of the last delta applied, as a follower.
+    pub applied: u64,
+}
+
+impl SyncLog {
+    fn next(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    /// `id` was announced, or its properties changed.
+    pub fn announced(&mut self, id: i32) {
+        if self.enabled {
+            let seq = self.next();
+            self.announced.insert(id, seq);
+        }
+    }
+
+    pub fn valued(&mut self, id: i32) {
+        if self.enabled {
+            let seq = self.next();
+            self.valued.insert(id, seq);
+        }
+    }
+
+    pub fn removed(&mut self, id: i32, name: &str) {
+        if !self.enabled {
+            return;
+        }
+        self.announced.remove(&id);
+        self.valued.remove(&id);
+        let seq = self.next();
+        self.removed.push_back((seq, UnannounceParams { name: name.to_string(), id }));
+        while self.removed.len() > MAX_REMOVED {
+            if let Some((seq, _)) = self.removed.pop_front() {
+                self.full_before = self.full_before.max(seq);
+            }
+        }
+    }
+
+    /// Every topic is gone at once, as on a disconnect, so the next deltas are full.
+    pub fn reset(&mut self) {
+        if self.enabled {
+            self.full_before = self.next();
+            self.announced.clear();
+            self.valued.clear();
+            self.removed.clear();
+        }
+    }
+
+    /// The changes since `since`, from the announced `topics` with their `properties` and the
+    /// cached values, or all of them if the changes are not all known.
+    pub fn delta<'a>(
+        &mut self,
+        since: u64,
+        topics: impl IntoIterator<Item = (&'a i32, &'a Topic)>,
+        properties: &HashMap<i32, Properties>,
+        cache: &HashMap<i32, (i64, Nt4Data)>,
+        ready: bool,
+        server_time_us: i64,
+    ) -> Delta {
+        if !self.enabled {
+            self.enabled = true;
+            self.full_before = self.next();
+        }
+        let full = since < self.full_before || since > self.seq;
+        let changed = |changes: &HashMap<i32, u64>, id: i32| full || changes.get(&id).is_some_and(|&seq| seq > since);
+        let mut announced = Vec::new();
+        let mut values = Vec::new();
+        for (&id, topic) in topics {
+            if changed(&self.announced, id) {
+                announced.push(AnnounceParams {
+                    name: topic.name.to_string(),
+                    id,
+                    ty: topic.ty,
+                    pubuid: None,
+                    properties: properties.get(&id).cloned().unwrap_or_default(),
+                });
+            }
+            if let Some((timestamp, data)) = cache.get(&id).filter(|_| changed(&self.valued, id)) {
+                values.push(BinaryDataFrame { topic_id: id, timestamp: *timestamp, data: data.clone() });
+            }
+        }
+        announced.sort_by_key(|x| x.id);
+        values.sort_by_key(|x| x.topic_id);
+        let unannounced = if full {
+            Vec::new()
+        } else {
+            let removed = self.removed.iter().filter(|(seq, _)| *seq > since);
+            removed.map(|(_, x)| UnannounceParams { name: x.name.clone(), id: x.id }).collect()
+        };
+        Delta { from_seq: since, to_seq: self.seq, full, ready, server_time_us, unannounced, announced, values }
+    }
+}
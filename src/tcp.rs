@@ -0,0 +1,224 @@
+use std::{collections::HashMap, net::TcpStream, time::Instant};
+
+use tungstenite::{client::IntoClientRequest, http::HeaderValue, Message, WebSocket};
+
+use crate::{
+    binary::BinaryDataFrame,
+    reserved_ids::{self, TopicIdClass},
+    text::*,
+    types::{Nt4Data, Nt4TypeId, PartialProperties, Properties, SubscriptionOptions, Topic},
+    wire::{self, WireOptions},
+};
+
+const SUBPROTOCOL: &str = "networktables.first.wpi.edu";
+
+/// Something received from the server by [`Nt4TcpClient::read`].
+#[derive(Debug)]
+pub enum Nt4Event {
+    Announce { id: i32, topic: Topic },
+    Unannounce { id: i32, name: String },
+    Properties { name: String },
+    Value { topic_id: i32, timestamp: i64, data: Nt4Data },
+    /// A timesync response was received; [`Nt4TcpClient::server_time`] is now meaningful.
+    Ready,
+}
+
+/// Servers send text frames as JSON arrays of messages, but a bare message is accepted too.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ServerTextFrames {
+    Many(Vec<ServerToClientTextDataFrame>),
+    One(ServerToClientTextDataFrame),
+}
+
+/// A blocking NT4 client for native programs, speaking the same protocol as `Nt4Connection`
+/// over a WebSocket on a plain `TcpStream`.
+pub struct Nt4TcpClient {
+    socket: WebSocket<TcpStream>,
+    start_time: Instant,
+    offs: i64,
+    uid_cnt: i32,
+    topics: HashMap<i32, Topic>,
+}
+
+fn err<E: std::fmt::Display>(x: E) -> String {
+    x.to_string()
+}
+
+impl Nt4TcpClient {
+    /// Connects to `ws://<addr>/nt/<client_name>`, where `addr` is `host:port` (usually port 5810).
+    pub fn connect(addr: &str, client_name: &str) -> Result<Self, String> {
+        let mut request = format!("ws://{}/nt/{}", addr, client_name)
+            .into_client_request()
+            .map_err(err)?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(SUBPROTOCOL));
+        let stream = TcpStream::connect(addr).map_err(err)?;
+        stream.set_nodelay(true).map_err(err)?;
+        let (socket, _) = tungstenite::client(request, stream).map_err(err)?;
+        Ok(Self {
+            socket,
+            start_time: Instant::now(),
+            offs: 0,
+            uid_cnt: 0,
+            topics: HashMap::new(),
+        })
+    }
+
+    fn now(&self) -> i64 {
+        self.start_time.elapsed().as_micros() as i64
+    }
+
+    fn new_uid(&mut self) -> i32 {
+        let next = self.uid_cnt;
+        self.uid_cnt += 1;
+        next
+    }
+
+    /// Sends one bare message per text frame, like `Nt4Connection`.
+    fn send_text(&mut self, data: ClientToServerTextDataFrame) -> Result<(), String> {
+        let data = serde_json::to_string(&data).map_err(err)?;
+        self.socket.send(Message::Text(data)).map_err(err)
+    }
+
+    fn send_binary(&mut self, data: &BinaryDataFrame) -> Result<(), String> {
+        let data = wire::write_frames(std::slice::from_ref(data), &WireOptions::default())?;
+        self.socket.send(Message::Binary(data)).map_err(err)
+    }
+
+    /// The current time on the server clock, in microseconds.
+    pub fn server_time(&self) -> i64 {
+        self.now() + self.offs
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = (&i32, &Topic)> {
+        self.topics.iter()
+    }
+
+    pub fn subscribe(&mut self, path: &str, options: SubscriptionOptions) -> Result<i32, String> {
+        let id = self.new_uid();
+        self.send_text(ClientToServerTextDataFrame::Subscribe(SubscribeParams {
+            topics: vec![path.to_string()],
+            subuid: id,
+            options,
+        }))?;
+        Ok(id)
+    }
+
+    pub fn unsubscribe(&mut self, id: i32) -> Result<(), String> {
+        self.send_text(ClientToServerTextDataFrame::Unsubscribe(UnsubscribeParams { subuid: id }))
+    }
+
+    pub fn publish(&mut self, name: &str, ty: Nt4TypeId, properties: Properties) -> Result<i32, String> {
+        let id = self.new_uid();
+        self.send_text(ClientToServerTextDataFrame::Publish(PublishParams {
+            name: name.to_string(),
+            pubuid: id,
+            ty,
+            properties,
+        }))?;
+        Ok(id)
+    }
+
+    pub fn unpublish(&mut self, id: i32) -> Result<(), String> {
+        self.send_text(ClientToServerTextDataFrame::Unpublish(UnpublishParams { pubuid: id }))
+    }
+
+    pub fn set_properties(&mut self, name: &str, update: PartialProperties) -> Result<(), String> {
+        self.send_text(ClientToServerTextDataFrame::SetProperties(SetPropertiesParams {
+            name: name.to_string(),
+            update,
+        }))
+    }
+
+    pub fn send_data(&mut self, topic_id: i32, data: Nt4Data) -> Result<(), String> {
+        let timestamp = self.server_time().max(0);
+        self.send_binary(&BinaryDataFrame { topic_id, timestamp, data })
+    }
+
+    pub fn timesync(&mut self) -> Result<(), String> {
+        let now = self.now();
+        self.send_binary(&BinaryDataFrame::timesync(now))
+    }
+
+    /// Blocks until the next WebSocket message and returns the events it contains.
+    pub fn read(&mut self) -> Result<Vec<Nt4Event>, String> {
+        match self.socket.read().map_err(err)? {
+            Message::Text(data) => self.on_text(&data),
+            Message::Binary(data) => self.on_binary(&data),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn on_text(&mut self, data: &str) -> Result<Vec<Nt4Event>, String> {
+        let frames = match serde_json::from_str(data).map_err(err)? {
+            ServerTextFrames::Many(frames) => frames,
+            ServerTextFrames::One(frame) => vec![frame],
+        };
+        let mut events = Vec::new();
+        for frame in frames {
+            match frame {
+                ServerToClientTextDataFrame::Announce(ann) => {
+                    let topic = Topic { name: ann.name.into(), ty: ann.ty };
+                    self.topics.insert(ann.id, topic.clone());
+                    events.push(Nt4Event::Announce { id: ann.id, topic });
+                }
+                ServerToClientTextDataFrame::Unannounce(unann) => {
+                    self.topics.remove(&unann.id);
+                    events.push(Nt4Event::Unannounce { id: unann.id, name: unann.name });
+                }
+                ServerToClientTextDataFrame::Properties(props) => events.push(Nt4Event::Properties { name: props.name }),
+                ServerToClientTextDataFrame::Values(frames) => {
+                    for frame in frames {
+                        self.on_value(frame, &mut events)?;
+                    }
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn on_binary(&mut self, mut data: &[u8]) -> Result<Vec<Nt4Event>, String> {
+        let mut events = Vec::new();
+        while !data.is_empty() {
+            let frame: BinaryDataFrame =
+                serde::Deserialize::deserialize(&mut rmp_serde::Deserializer::new(&mut data)).map_err(err)?;
+            self.on_value(frame, &mut events)?;
+        }
+        Ok(events)
+    }
+
+    fn on_value(&mut self, frame: BinaryDataFrame, events: &mut Vec<Nt4Event>) -> Result<(), String> {
+        match reserved_ids::classify_topic_id(frame.topic_id) {
+            TopicIdClass::Normal => events.push(Nt4Event::Value {
+                topic_id: frame.topic_id,
+                timestamp: frame.timestamp,
+                data: frame.data,
+            }),
+            TopicIdClass::Timesync => {
+                let local_time = *frame
+                    .data
+                    .as_int()
+                    .ok_or_else(|| format!("Invalid timesync dataframe: {:?}", frame))?;
+                self.offs = crate::connection::timesync_offset(local_time, frame.timestamp, self.now())?;
+                events.push(Nt4Event::Ready);
+            },
+            // Never a value of a real topic.
+            TopicIdClass::ReservedUnknown(_) => {},
+        }
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<(), String> {
+        self.socket.close(None).map_err(err)?;
+        // Drive the closing handshake until the server acknowledges it.
+        loop {
+            match self.socket.read() {
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(x) => return Err(err(x)),
+            }
+        }
+    }
+}
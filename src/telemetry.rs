@@ -0,0 +1,61 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Weak};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{connection::ConnectionCore, Inner};
+
+/// Metrics posted to a URL from a window timer, see `Nt4Connection::set_telemetry_endpoint`. The
+/// timer is cleared when this is dropped.
+pub struct Telemetry {
+    url: String,
+    handle: i32,
+    _tick: Closure<dyn FnMut()>,
+}
+
+impl Telemetry {
+    /// Posts the metrics of the connection in `inner` to `url` every `interval_ms`, for as long as
+    /// both are alive.
+    pub fn start(inner: Weak<RefCell<Inner>>, url: &str, interval_ms: u32) -> Result<Self, JsValue> {
+        let window = web_sys::window().ok_or("telemetry needs a browser window")?;
+        let target = url.to_string();
+        let tick = Closure::<dyn FnMut()>::new(move || {
+            // Best-effort: a post that fails is dropped and the next tick tries again.
+            if let Some(inner) = inner.upgrade() {
+                if let Ok(inner) = inner.try_borrow() {
+                    let _ = post(&target, &body(&inner.core));
+                }
+            }
+        });
+        let timeout = interval_ms.min(i32::MAX as u32) as i32;
+        let handle =
+            window.set_interval_with_callback_and_timeout_and_arguments_0(tick.as_ref().unchecked_ref(), timeout)?;
+        Ok(Self { url: url.to_string(), handle, _tick: tick })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(window) = web_sys::window() {
+            window.clear_interval_with_handle(self.handle);
+        }
+    }
+}
+
+/// Every metric by name, as a JSON object.
+fn body(core: &ConnectionCore) -> String {
+    let metrics = core.metrics();
+    let values: BTreeMap<&str, f64> = metrics.iter().map(|x| (x.name, x.value)).collect();
+    // Non-finite values are written as null.
+    serde_json::to_string(&values).unwrap_or_default()
+}
+
+fn post(url: &str, body: &str) -> Result<(), JsValue> {
+    let request = web_sys::XmlHttpRequest::new()?;
+    request.open_with_async("POST", url, true)?;
+    request.set_request_header("Content-Type", "application/json")?;
+    request.send_with_opt_str(Some(body))
+}
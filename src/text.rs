@@ -24,7 +24,7 @@ pub struct SetPropertiesParams {
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscribeParams {
     pub topics: Vec<String>,
     pub subuid: i32,
@@ -62,6 +62,9 @@ pub struct PropertiesParams {
     pub name: String,
     #[serde(default)]
     pub ack: Option<bool>,
+    /// The keys that changed, `null` for deleted ones.
+    #[serde(default)]
+    pub update: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -75,6 +78,8 @@ pub enum ClientToServerTextDataFrame {
     SetProperties(SetPropertiesParams),
     Subscribe(SubscribeParams),
     Unsubscribe(UnsubscribeParams),
+    /// Values as text, for bridges that cannot carry binary frames. Not part of NT4.
+    Values(Vec<crate::binary::BinaryDataFrame>),
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -86,4 +91,6 @@ pub enum ServerToClientTextDataFrame {
     Announce(AnnounceParams),
     Unannounce(UnannounceParams),
     Properties(PropertiesParams),
+    /// Values as text, for bridges that cannot carry binary frames. Not part of NT4.
+    Values(Vec<crate::binary::BinaryDataFrame>),
 }
\ No newline at end of file
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(default)]
+pub struct TimeResetOptions {
+    pub enabled: bool,
+    /// A value at least this much older than the last one of its topic counts as going backwards.
+    pub threshold_s: f64,
+    /// Topics must go backwards within this much local time of each other...
+    pub window_s: f64,
+    /// ...and at least this many of them, or every topic with a value if there are fewer.
+    pub min_topics: usize,
+}
+
+impl Default for TimeResetOptions {
+    fn default() -> Self {
+        Self { enabled: true, threshold_s: 5.0, window_s: 1.0, min_topics: 3 }
+    }
+}
+
+/// Notices the server's clock restarting, such as when the robot reboots while the connection
+/// survives, from received timestamps jumping backwards across several topics at once.
+#[derive(Debug, Default)]
+pub struct TimeResetDetector {
+    pub options: TimeResetOptions,
+    /// Topics that went backwards recently, with the local time and the new timestamp.
+    backwards: HashMap<i32, (i64, i64)>,
+}
+
+impl TimeResetDetector {
+    /// Records a value of `topic_id` stamped `timestamp`, whose previous value was stamped
+    /// `previous`, received at local time `now` while `valued` topics have a value. Once enough
+    /// topics went backwards, returns the newest of their new timestamps and the topics, whose
+    /// values since are already on the new clock.
+    pub fn observe(
+        &mut self,
+        topic_id: i32,
+        previous: i64,
+        timestamp: i64,
+        now: i64,
+        valued: usize,
+    ) -> Option<(i64, Vec<i32>)> {
+        if !self.options.enabled {
+            return None;
+        }
+        let window = (self.options.window_s * 1e6) as i64;
+        self.backwards.retain(|_, (seen, _)| now - *seen <= window);
+        if previous.saturating_sub(timestamp) < (self.options.threshold_s * 1e6) as i64 {
+            return None;
+        }
+        self.backwards.insert(topic_id, (now, timestamp));
+        if self.backwards.len() < self.options.min_topics.min(valued).max(1) {
+            return None;
+        }
+        let newest = self.backwards.values().map(|(_, timestamp)| *timestamp).max()?;
+        Some((newest, self.backwards.drain().map(|(id, _)| id).collect()))
+    }
+
+    pub fn clear(&mut self) {
+        self.backwards.clear();
+    }
+}
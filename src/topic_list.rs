@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    journal::{JournalEvent, JournalKind},
+    types::{Nt4TypeId, Properties},
+};
+
+/// How [`crate::ConnectionCore::topics_diff`] sorts topics.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicOrder {
+    #[default]
+    Name,
+    /// Most recently updated first, then topics without a value, each by name.
+    LastUpdate,
+}
+
+/// A topic as listed by [`TopicsDiff`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicEntry {
+    pub id: i32,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+    pub properties: Option<Properties>,
+    /// Server timestamp of the last value received, if any.
+    pub last_update_us: Option<i64>,
+}
+
+/// The topics announced, unannounced or changed since a journal sequence number, see
+/// [`crate::ConnectionCore::topics_diff`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicsDiff {
+    /// The journal no longer reaches back to the number asked for: `topics` holds every topic
+    /// instead, and the rest is empty.
+    pub resync_required: bool,
+    pub added: Vec<TopicEntry>,
+    /// Names, sorted.
+    pub removed: Vec<String>,
+    /// Topics whose type or properties changed.
+    pub changed: Vec<TopicEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topics: Option<Vec<TopicEntry>>,
+    /// The number to ask from next time.
+    pub seq: u64,
+}
+
+/// How a topic changed over a run of journal events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Not there before the first event, whether or not it is still there.
+    Added,
+    /// There before the first event, whether or not it is still there.
+    Existing,
+}
+
+/// Each topic named in `events`, by whether it was there before them. `None` if they start with
+/// a gap.
+pub fn fold(events: &[JournalEvent]) -> Option<BTreeMap<&str, Change>> {
+    let mut changes = BTreeMap::new();
+    for event in events {
+        let change = match event.kind {
+            JournalKind::Gap => return None,
+            JournalKind::Announce => Change::Added,
+            JournalKind::Unannounce | JournalKind::Properties | JournalKind::TypeChange => Change::Existing,
+        };
+        changes.entry(event.name.as_str()).or_insert(change);
+    }
+    Some(changes)
+}
+
+pub fn sort(topics: &mut [TopicEntry], order: TopicOrder) {
+    match order {
+        TopicOrder::Name => topics.sort_by(|a, b| a.name.cmp(&b.name)),
+        TopicOrder::LastUpdate => {
+            topics.sort_by(|a, b| b.last_update_us.cmp(&a.last_update_us).then_with(|| a.name.cmp(&b.name)))
+        },
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::Nt4Data;
+
+/// The driver station's control word; bit 0 is enabled, bit 1 autonomous and bit 2 test.
+pub const FMS_CONTROL_DATA: &str = "/FMSInfo/FMSControlData";
+
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(default)]
+pub struct TrajectoryOptions {
+    /// Oldest points are dropped beyond this many.
+    pub max_points: usize,
+    /// A pose is only appended once the robot has moved at least this far from the last point...
+    pub min_distance_m: f64,
+    /// ...or turned at least this much.
+    pub min_rotation_rad: f64,
+    /// Points this much older than the newest one are dropped.
+    pub max_age_s: Option<f64>,
+    /// Whether the heading is in degrees, as published by `Field2d`, rather than radians.
+    pub degrees: bool,
+    /// Whether the trail is cleared when teleop starts, according to [`FMS_CONTROL_DATA`].
+    pub clear_on_teleop: bool,
+}
+
+impl Default for TrajectoryOptions {
+    fn default() -> Self {
+        Self {
+            max_points: 1000,
+            min_distance_m: 0.05,
+            min_rotation_rad: 0.1,
+            max_age_s: None,
+            degrees: false,
+            clear_on_teleop: true,
+        }
+    }
+}
+
+/// A decimated trail of `[x, y, theta]` poses, with theta in radians.
+#[derive(Debug)]
+pub struct Trajectory {
+    options: TrajectoryOptions,
+    points: VecDeque<(i64, [f64; 3])>,
+}
+
+impl Trajectory {
+    fn push(&mut self, timestamp: i64, mut pose: [f64; 3]) {
+        if self.options.degrees {
+            pose[2] = pose[2].to_radians();
+        }
+        let moved = match self.points.back() {
+            Some((_, [x, y, theta])) => {
+                let turned = (pose[2] - theta + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU)
+                    - std::f64::consts::PI;
+                (pose[0] - x).hypot(pose[1] - y) >= self.options.min_distance_m
+                    || turned.abs() >= self.options.min_rotation_rad
+            },
+            None => true,
+        };
+        if moved {
+            self.points.push_back((timestamp, pose));
+        }
+        while self.points.len() > self.options.max_points {
+            self.points.pop_front();
+        }
+        if let Some(max_age_s) = self.options.max_age_s {
+            let oldest = timestamp.saturating_sub((max_age_s * 1e6) as i64);
+            while self.points.front().is_some_and(|(t, _)| *t < oldest) {
+                self.points.pop_front();
+            }
+        }
+    }
+
+    /// `[x0, y0, theta0, x1, ...]`.
+    pub fn poses(&self) -> Vec<f64> {
+        self.points.iter().flat_map(|(_, pose)| *pose).collect()
+    }
+
+    /// Server timestamps of [`Trajectory::poses`], in µs.
+    pub fn timestamps(&self) -> Vec<f64> {
+        self.points.iter().map(|(t, _)| *t as f64).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Trajectories {
+    trajectories: HashMap<String, Trajectory>,
+    teleop: bool,
+}
+
+impl Trajectories {
+    /// Starts (or restarts, empty) tracking the poses of `name`.
+    pub fn track(&mut self, name: &str, options: TrajectoryOptions) {
+        self.trajectories.insert(name.to_string(), Trajectory { options, points: VecDeque::new() });
+    }
+
+    pub fn untrack(&mut self, name: &str) {
+        self.trajectories.remove(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Trajectory> {
+        self.trajectories.get(name)
+    }
+
+    pub fn clear(&mut self, name: &str) -> Result<(), String> {
+        match self.trajectories.get_mut(name) {
+            Some(trajectory) => {
+                trajectory.points.clear();
+                Ok(())
+            },
+            None => Err(format!("{:?} is not tracked", name)),
+        }
+    }
+
+    /// Empties every trail, keeping the tracking.
+    pub fn clear_points(&mut self) {
+        for trajectory in self.trajectories.values_mut() {
+            trajectory.points.clear();
+        }
+    }
+
+    /// Drops points stamped before `oldest`, no more than `budget` of them, and returns how many.
+    pub fn trim_older_than(&mut self, oldest: i64, budget: usize) -> usize {
+        let mut trimmed = 0;
+        for trajectory in self.trajectories.values_mut() {
+            while trimmed < budget && trajectory.points.front().is_some_and(|(t, _)| *t < oldest) {
+                trajectory.points.pop_front();
+                trimmed += 1;
+            }
+        }
+        trimmed
+    }
+
+    pub fn clear_all(&mut self) {
+        self.trajectories.clear();
+        self.teleop = false;
+    }
+
+    /// Records a value received for `name`, which is ignored unless it is a tracked `double[3]`
+    /// pose or the control word marking the start of teleop.
+    pub fn observe(&mut self, name: &str, timestamp: i64, data: &Nt4Data) {
+        if name == FMS_CONTROL_DATA {
+            let Some(word) = data.as_int() else {
+                return;
+            };
+            let teleop = word & 0b111 == 0b001;
+            if teleop && !self.teleop {
+                for trajectory in self.trajectories.values_mut().filter(|x| x.options.clear_on_teleop) {
+                    trajectory.points.clear();
+                }
+            }
+            self.teleop = teleop;
+            return;
+        }
+        let Some(trajectory) = self.trajectories.get_mut(name) else {
+            return;
+        };
+        if let Some([x, y, theta]) = data.as_double_array().map(Vec::as_slice) {
+            trajectory.push(timestamp, [*x, *y, *theta]);
+        }
+    }
+}
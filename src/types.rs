@@ -1,12 +1,12 @@
 #![allow(dead_code)]
 
-use std::time::Duration;
+use std::{cell::Cell, time::Duration};
 use serde_bytes::ByteBuf;
 
 macro_rules! nt4_type {
     ($($name:ident($str:literal, $id:literal, $ty:ty, [$(($other:ident, $other_name:literal)),* $(,)?])),* $(,)?) => {
         #[derive(Debug)]
-        #[derive(Clone, Copy)]
+        #[derive(Clone, Copy, PartialEq, Eq, Hash)]
         pub enum Nt4TypeId {
             $($name),*
         }
@@ -22,6 +22,16 @@ macro_rules! nt4_type {
                 }
             }
 
+            /// Every type, in declaration order.
+            pub fn all_variants() -> &'static [Nt4TypeId] {
+                &[$(Self::$name),*]
+            }
+
+            /// The name of every type, in the same order as [`Nt4TypeId::all_variants`].
+            pub fn all_type_names() -> &'static [&'static str] {
+                &[$($str),*]
+            }
+
             pub fn get_id(&self) -> u8 {
                 match self {
                     $(
@@ -62,12 +72,27 @@ macro_rules! nt4_type {
         }
         
         #[derive(serde::Deserialize, serde::Serialize)]
-        #[derive(Debug)]
+        #[derive(Debug, Clone)]
         #[serde(untagged)]
         pub enum Nt4Data {
             $($name($ty)),*
         }
 
+        #[doc = "Deserializes the [`Nt4Data`] variant for a known type, rather than guessing like the untagged implementation."]
+        pub struct Nt4DataSeed(pub Nt4TypeId);
+
+        impl<'de> serde::de::DeserializeSeed<'de> for Nt4DataSeed {
+            type Value = Nt4Data;
+
+            fn deserialize<D>(self, deserializer: D) -> Result<Nt4Data, D::Error> where D: serde::Deserializer<'de> {
+                match self.0 {
+                    $(
+                        Nt4TypeId::$name => <$ty as serde::Deserialize>::deserialize(deserializer).map(Nt4Data::$name)
+                    ),*
+                }
+            }
+        }
+
         impl Nt4Data {
             pub fn get_id(&self) -> u8 {
                 match self {
@@ -107,8 +132,13 @@ macro_rules! nt4_type {
                 }
             )*
 
-            #[allow(unused_variables)]
             pub fn convert(self, name: &str) -> Result<Self, String> {
+                self.try_convert(name).map_err(|x| format!("Cannot convert from {:?} to {:?}", x.get_name(), name))
+            }
+
+            /// Like [`Nt4Data::convert`], but gives the value back if it cannot be converted.
+            #[allow(unused_variables)]
+            pub fn try_convert(self, name: &str) -> Result<Self, Self> {
                 match self {
                     $(
                         Self::$name(y) => {
@@ -117,7 +147,7 @@ macro_rules! nt4_type {
                                 $(
                                     $other_name => Ok(Self::$other(y)),
                                 )*
-                                x => Err(format!("Cannot convert from {:?} to {:?}", $str, x))
+                                _ => Err(Self::$name(y))
                             }
                         },
                     )*
@@ -145,10 +175,99 @@ nt4_type! {
     StringArray("string[]", 20, Vec<String>, []),
 }
 
+/// Largest magnitude below which every integer is exact as an `f64`, 2^53.
+const MAX_EXACT_F64: u64 = 1 << 53;
+
+/// Largest magnitude below which every integer is exact as an `f32`, 2^24.
+const MAX_EXACT_F32: u64 = 1 << 24;
+
+/// `x` as an `i64`, and whether that loses a fraction or is out of range.
+fn f64_to_int(x: f64) -> (i64, bool) {
+    let int = x as i64;
+    (int, int as f64 != x)
+}
+
+impl Nt4Data {
+    /// Like [`Nt4Data::try_convert`], but also between `int`, `float` and `double` and between
+    /// arrays of them, with whether the conversion lost anything: a fraction, precision, or range.
+    pub fn convert_to(self, ty: Nt4TypeId) -> Result<(Self, bool), Self> {
+        let data = match self.try_convert(ty.get_name()) {
+            Ok(data) => return Ok((data, false)),
+            Err(data) => data,
+        };
+        data.convert_number(ty).ok_or(data)
+    }
+
+    /// `self` as a number or array of numbers of type `ty`, and whether that lost anything.
+    pub fn convert_number(&self, ty: Nt4TypeId) -> Option<(Self, bool)> {
+        let int_to_f64 = |x: i64| (x as f64, x.unsigned_abs() > MAX_EXACT_F64);
+        let int_to_f32 = |x: i64| (x as f32, x.unsigned_abs() > MAX_EXACT_F32);
+        let f64_to_f32 = |x: f64| (x as f32, x as f32 as f64 != x && !x.is_nan());
+        fn array<T: Copy, U>(x: &[T], f: impl Fn(T) -> (U, bool)) -> (Vec<U>, bool) {
+            let mut lossy = false;
+            let converted = x
+                .iter()
+                .map(|&x| {
+                    let (x, lost) = f(x);
+                    lossy |= lost;
+                    x
+                })
+                .collect();
+            (converted, lossy)
+        }
+        let (data, lossy) = match (self, ty) {
+            (Self::Int(x), Nt4TypeId::Double) => {
+                let (x, lossy) = int_to_f64(*x);
+                (Self::Double(x), lossy)
+            },
+            (Self::Int(x), Nt4TypeId::Float) => {
+                let (x, lossy) = int_to_f32(*x);
+                (Self::Float(x), lossy)
+            },
+            (Self::Float(x), Nt4TypeId::Double) => (Self::Double(*x as f64), false),
+            (Self::Float(x), Nt4TypeId::Int) => {
+                let (x, lossy) = f64_to_int(*x as f64);
+                (Self::Int(x), lossy)
+            },
+            (Self::Double(x), Nt4TypeId::Float) => {
+                let (x, lossy) = f64_to_f32(*x);
+                (Self::Float(x), lossy)
+            },
+            (Self::Double(x), Nt4TypeId::Int) => {
+                let (x, lossy) = f64_to_int(*x);
+                (Self::Int(x), lossy)
+            },
+            (Self::IntArray(x), Nt4TypeId::DoubleArray) => {
+                let (x, lossy) = array(x, int_to_f64);
+                (Self::DoubleArray(x), lossy)
+            },
+            (Self::IntArray(x), Nt4TypeId::FloatArray) => {
+                let (x, lossy) = array(x, int_to_f32);
+                (Self::FloatArray(x), lossy)
+            },
+            (Self::FloatArray(x), Nt4TypeId::DoubleArray) => (Self::DoubleArray(x.iter().map(|&x| x as f64).collect()), false),
+            (Self::FloatArray(x), Nt4TypeId::IntArray) => {
+                let (x, lossy) = array(x, |x: f32| f64_to_int(x as f64));
+                (Self::IntArray(x), lossy)
+            },
+            (Self::DoubleArray(x), Nt4TypeId::FloatArray) => {
+                let (x, lossy) = array(x, f64_to_f32);
+                (Self::FloatArray(x), lossy)
+            },
+            (Self::DoubleArray(x), Nt4TypeId::IntArray) => {
+                let (x, lossy) = array(x, f64_to_int);
+                (Self::IntArray(x), lossy)
+            },
+            _ => return None,
+        };
+        Some((data, lossy))
+    }
+}
+
 
 
 #[derive(serde::Deserialize, serde::Serialize)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriptionOptions {
     #[
         serde(
@@ -213,61 +332,168 @@ mod defaults {
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Properties {
     #[serde(default)]
     pub persistent: bool,
     #[serde(default)]
     pub retained: bool,
+    /// Every other property, kept as is so it survives a read-modify-write. Integers are exact
+    /// across the 64-bit range, as `BigInt`s in JS past `Number.MAX_SAFE_INTEGER`, see [`for_js`].
+    #[serde(flatten, serialize_with = "serialize_extra")]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+thread_local! {
+    static FOR_JS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f`, which serializes for JS with `serde_wasm_bindgen`, where integer properties past
+/// `Number.MAX_SAFE_INTEGER` would fail. They are serialized as 128-bit integers instead, which it
+/// makes `BigInt`s. Other serializers get them as they are: `rmp_serde` writes 128-bit integers
+/// as bytes.
+pub fn for_js<T>(f: impl FnOnce() -> T) -> T {
+    let outer = FOR_JS.replace(true);
+    let result = f();
+    FOR_JS.set(outer);
+    result
+}
+
+fn serialize_extra<S>(extra: &serde_json::Map<String, serde_json::Value>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer {
+    if FOR_JS.get() {
+        serializer.collect_map(extra.iter().map(|(key, value)| (key, JsNumbers(value))))
+    } else {
+        serde::Serialize::serialize(extra, serializer)
+    }
+}
+
+/// A JSON value with integers past `Number.MAX_SAFE_INTEGER` serialized as 128-bit integers.
+struct JsNumbers<'a>(&'a serde_json::Value);
+
+impl serde::Serialize for JsNumbers<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer {
+        const SAFE: std::ops::RangeInclusive<i64> = -9_007_199_254_740_991..=9_007_199_254_740_991;
+        match self.0 {
+            serde_json::Value::Number(x) => match (x.as_i64(), x.as_u64()) {
+                (Some(x), _) if !SAFE.contains(&x) => serializer.serialize_i128(x.into()),
+                (None, Some(x)) => serializer.serialize_u128(x.into()),
+                _ => x.serialize(serializer),
+            },
+            serde_json::Value::Array(items) => serializer.collect_seq(items.iter().map(JsNumbers)),
+            serde_json::Value::Object(map) => {
+                serializer.collect_map(map.iter().map(|(key, value)| (key, JsNumbers(value))))
+            },
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+impl Properties {
+    /// Applies one key of a properties update, where `null` deletes the key.
+    /// `persistent` and `retained` default to `false`, so deleting one of them clears it.
+    pub fn apply(&mut self, key: &str, value: serde_json::Value) -> Result<(), String> {
+        let flag = match key {
+            "persistent" => &mut self.persistent,
+            "retained" => &mut self.retained,
+            _ => {
+                if value.is_null() {
+                    self.extra.remove(key);
+                } else {
+                    self.extra.insert(key.to_string(), value);
+                }
+                return Ok(());
+            },
+        };
+        *flag = match value {
+            serde_json::Value::Bool(x) => x,
+            serde_json::Value::Null => false,
+            value => return Err(format!("{:?} must be a boolean or null, not {}", key, value)),
+        };
+        Ok(())
+    }
 }
 
 #[doc = "Properties, but all members are optional. Used for updating properties of a topic."]
+#[doc = "Other keys are sent as they are, with `null` deleting the key."]
 #[derive(serde::Deserialize)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PartialProperties {
     #[serde(default)]
     pub persistent: Option<bool>,
     #[serde(default)]
     pub retained: Option<bool>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl PartialProperties {
+    /// An update of the single property `key`, see [`Properties::apply`].
+    pub fn single(key: &str, value: serde_json::Value) -> Result<Self, String> {
+        let flag = |value: serde_json::Value| match value {
+            serde_json::Value::Bool(x) => Ok(Some(x)),
+            serde_json::Value::Null => Ok(Some(false)),
+            value => Err(format!("{:?} must be a boolean or null, not {}", key, value)),
+        };
+        let mut update = Self::default();
+        match key {
+            "persistent" => update.persistent = flag(value)?,
+            "retained" => update.retained = flag(value)?,
+            _ => {
+                update.extra.insert(key.to_string(), value);
+            },
+        }
+        Ok(update)
+    }
+
+    /// Applies this update to `properties`, as the server will.
+    pub fn apply_to(&self, properties: &mut Properties) {
+        if let Some(persistent) = self.persistent {
+            properties.persistent = persistent;
+        }
+        if let Some(retained) = self.retained {
+            properties.retained = retained;
+        }
+        for (key, value) in &self.extra {
+            // Only a non-boolean flag in `extra` fails, which the server rejects as well.
+            let _ = properties.apply(key, value.clone());
+        }
+    }
 }
 
 impl serde::Serialize for PartialProperties {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer {
-        #[derive(serde::Serialize)]
-        struct PnR {
-            persistent: bool,
-        }
-        #[derive(serde::Serialize)]
-        struct RnP {
-            retained: bool
-        }
-        #[derive(serde::Serialize)]
-        struct RP {
-            persistent: bool,
-            retained: bool,
-        }
-        #[derive(serde::Serialize)]
-        struct N {}
+        use serde::ser::SerializeMap;
 
+        // Only the keys being changed, the server leaves the rest alone.
+        let mut map = serializer.serialize_map(None)?;
         if let Some(persistent) = self.persistent {
-            if let Some(retained) = self.retained {
-                (RP { persistent, retained }).serialize(serializer)
-            } else {
-                (PnR { persistent }).serialize(serializer)
-            }
-        } else if let Some(retained) = self.retained {
-            (RnP { retained }).serialize(serializer)
-        } else {
-            (N {}).serialize(serializer)
+            map.serialize_entry("persistent", &persistent)?;
+        }
+        if let Some(retained) = self.retained {
+            map.serialize_entry("retained", &retained)?;
         }
+        for (key, value) in &self.extra {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Topic {
-    pub name: String,
+    pub name: std::sync::Arc<str>,
     #[serde(rename = "type")]
     pub ty: Nt4TypeId,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AtomicEntry {
+    pub topic_id: i32,
+    pub data: Nt4Data,
+}
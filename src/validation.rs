@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::types::Nt4Data;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AllowedValue {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+/// Constraints on the values we send to a topic. Numeric bounds apply element-wise to arrays.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Default)]
+pub struct Validator {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_values: Option<Vec<AllowedValue>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rate_of_change_per_s: Option<f64>,
+    #[serde(default)]
+    pub mode: ValidationMode,
+}
+
+#[derive(Debug, Default)]
+pub struct Validators {
+    validators: BTreeMap<String, Validator>,
+    last_sent: HashMap<String, (i64, Vec<f64>)>,
+}
+
+impl Validators {
+    pub fn set(&mut self, name: &str, validator: Validator) {
+        self.validators.insert(name.to_string(), validator);
+        self.last_sent.remove(name);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.validators.remove(name);
+        self.last_sent.remove(name);
+    }
+
+    pub fn export(&self) -> Result<String, String> {
+        serde_json::to_string(&self.validators).map_err(|x| x.to_string())
+    }
+
+    /// Replaces every validator with the set in `json`, as produced by [`Validators::export`].
+    pub fn import(&mut self, json: &str) -> Result<(), String> {
+        self.validators = serde_json::from_str(json).map_err(|x| x.to_string())?;
+        self.last_sent.clear();
+        Ok(())
+    }
+
+    /// Checks `data` about to be sent to `name` at local time `now` (µs), returning the value to
+    /// send, which differs from `data` only when a clamping validator adjusted it.
+    pub fn check(&self, name: &str, data: Nt4Data, now: i64) -> Result<Nt4Data, String> {
+        self.check_after(name, data, self.last_sent.get(name), now)
+    }
+
+    /// Checks `values` about to be sent in one message, like [`Validators::check`], where a value
+    /// to a topic that appears earlier in `values` is checked against that earlier one.
+    pub fn check_all<'a>(
+        &self,
+        values: impl IntoIterator<Item = (&'a str, Nt4Data)>,
+        now: i64,
+    ) -> Result<Vec<Nt4Data>, String> {
+        let mut checked: HashMap<&str, (i64, Vec<f64>)> = HashMap::new();
+        let mut result = Vec::new();
+        for (name, data) in values {
+            let previous = checked.get(name).or_else(|| self.last_sent.get(name));
+            let data = self.check_after(name, data, previous, now)?;
+            if let Some(values) = self.validators.contains_key(name).then(|| numbers(&data)).flatten() {
+                checked.insert(name, (now, values));
+            }
+            result.push(data);
+        }
+        Ok(result)
+    }
+
+    fn check_after(
+        &self,
+        name: &str,
+        data: Nt4Data,
+        previous: Option<&(i64, Vec<f64>)>,
+        now: i64,
+    ) -> Result<Nt4Data, String> {
+        let Some(validator) = self.validators.get(name) else {
+            return Ok(data);
+        };
+        let check = |i: usize, value: f64| -> Result<f64, String> {
+            validator.check_number(name, i, value, previous, now)
+        };
+        Ok(match data {
+            Nt4Data::Double(x) => Nt4Data::Double(check(0, x)?),
+            Nt4Data::Float(x) => Nt4Data::Float(check(0, x as f64)? as f32),
+            Nt4Data::Int(x) => Nt4Data::Int(check(0, x as f64)?.round() as i64),
+            Nt4Data::DoubleArray(x) => Nt4Data::DoubleArray(
+                x.into_iter().enumerate().map(|(i, x)| check(i, x)).collect::<Result<_, _>>()?,
+            ),
+            Nt4Data::FloatArray(x) => Nt4Data::FloatArray(
+                x.into_iter()
+                    .enumerate()
+                    .map(|(i, x)| check(i, x as f64).map(|x| x as f32))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Nt4Data::IntArray(x) => Nt4Data::IntArray(
+                x.into_iter()
+                    .enumerate()
+                    .map(|(i, x)| check(i, x as f64).map(|x| x.round() as i64))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Nt4Data::Boolean(x) => {
+                validator.check_allowed(name, 0, &AllowedValue::Boolean(x))?;
+                Nt4Data::Boolean(x)
+            }
+            Nt4Data::BooleanArray(x) => {
+                for (i, x) in x.iter().enumerate() {
+                    validator.check_allowed(name, i, &AllowedValue::Boolean(*x))?;
+                }
+                Nt4Data::BooleanArray(x)
+            }
+            Nt4Data::String(x) => {
+                validator.check_allowed(name, 0, &AllowedValue::String(x.clone()))?;
+                Nt4Data::String(x)
+            }
+            Nt4Data::StringArray(x) => {
+                for (i, x) in x.iter().enumerate() {
+                    validator.check_allowed(name, i, &AllowedValue::String(x.clone()))?;
+                }
+                Nt4Data::StringArray(x)
+            }
+            x => x,
+        })
+    }
+
+    /// Remembers a value that was sent, for rate of change checks.
+    pub fn record(&mut self, name: &str, data: &Nt4Data, now: i64) {
+        if !self.validators.contains_key(name) {
+            return;
+        }
+        if let Some(values) = numbers(data) {
+            self.last_sent.insert(name.to_string(), (now, values));
+        }
+    }
+}
+
+/// The elements of a numeric value, for rate of change checks.
+fn numbers(data: &Nt4Data) -> Option<Vec<f64>> {
+    Some(match data {
+        Nt4Data::Double(x) => vec![*x],
+        Nt4Data::Float(x) => vec![*x as f64],
+        Nt4Data::Int(x) => vec![*x as f64],
+        Nt4Data::DoubleArray(x) => x.clone(),
+        Nt4Data::FloatArray(x) => x.iter().map(|x| *x as f64).collect(),
+        Nt4Data::IntArray(x) => x.iter().map(|x| *x as f64).collect(),
+        _ => return None,
+    })
+}
+
+impl Validator {
+    fn check_allowed(&self, name: &str, index: usize, value: &AllowedValue) -> Result<(), String> {
+        match &self.allowed_values {
+            Some(allowed) if !allowed.contains(value) => Err(format!(
+                "{:?} at index {} is not an allowed value for {:?}",
+                value, index, name
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_number(
+        &self,
+        name: &str,
+        index: usize,
+        value: f64,
+        previous: Option<&(i64, Vec<f64>)>,
+        now: i64,
+    ) -> Result<f64, String> {
+        self.check_allowed(name, index, &AllowedValue::Number(value))?;
+        // NaN compares false against every bound, and infinities cannot be clamped or rate limited,
+        // so neither mode lets them through.
+        let bounded = self.min.is_some() || self.max.is_some() || self.max_rate_of_change_per_s.is_some();
+        if bounded && !value.is_finite() {
+            return Err(format!("{} at index {} is not a finite number, as {:?} requires", value, index, name));
+        }
+        let clamp = self.mode == ValidationMode::Clamp;
+        let mut value = value;
+        if let Some(min) = self.min.filter(|min| value < *min) {
+            if !clamp {
+                return Err(format!("{} at index {} is below the minimum {} for {:?}", value, index, min, name));
+            }
+            value = min;
+        }
+        if let Some(max) = self.max.filter(|max| value > *max) {
+            if !clamp {
+                return Err(format!("{} at index {} is above the maximum {} for {:?}", value, index, max, name));
+            }
+            value = max;
+        }
+        if let (Some(rate), Some((then, values))) = (self.max_rate_of_change_per_s, previous) {
+            if let Some(last) = values.get(index) {
+                let max_delta = rate * (now - then) as f64 / 1e6;
+                if (value - last).abs() > max_delta {
+                    if !clamp {
+                        return Err(format!(
+                            "{} at index {} changes from {} faster than {}/s for {:?}",
+                            value, index, last, rate, name
+                        ));
+                    }
+                    value = value.clamp(last - max_delta, last + max_delta);
+                }
+            }
+        }
+        Ok(value)
+    }
+}
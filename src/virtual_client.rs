@@ -0,0 +1,368 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use js_sys::JsString;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    connection::{ConnectionEvent, ConnectionSink, UidKind, UnknownUid},
+    js_properties,
+    types::{Nt4Data, Nt4TypeId, SubscriptionOptions},
+    with_core, Callbacks, Inner,
+};
+
+/// A virtual client's subscription, kept to route what the server sends for it.
+struct Route {
+    real: i32,
+    topic: String,
+    prefix: bool,
+    topicsonly: bool,
+}
+
+impl Route {
+    fn matches(&self, name: &str) -> bool {
+        if self.prefix {
+            name.starts_with(&self.topic)
+        } else {
+            name == self.topic
+        }
+    }
+}
+
+struct VirtualClient {
+    name: String,
+    callbacks: Callbacks,
+    uid_cnt: i32,
+    /// Our subuids to their subscriptions on the connection.
+    subscriptions: HashMap<i32, Route>,
+    /// Our pubuids to the connection's.
+    publications: HashMap<i32, i32>,
+    /// Topics matched by any of our subscriptions.
+    announced: HashSet<i32>,
+    /// Topics matched by a subscription that is not `topicsonly`.
+    valued: HashSet<i32>,
+}
+
+impl VirtualClient {
+    fn new_uid(&mut self) -> i32 {
+        let next = self.uid_cnt;
+        self.uid_cnt += 1;
+        next
+    }
+
+    /// Updates which of our subscriptions match topic `id`, returning whether any does.
+    fn track(&mut self, id: i32, name: &str) -> bool {
+        let mut announced = false;
+        let mut valued = false;
+        for route in self.subscriptions.values().filter(|route| route.matches(name)) {
+            announced = true;
+            valued |= !route.topicsonly;
+        }
+        if announced {
+            self.announced.insert(id);
+        } else {
+            self.announced.remove(&id);
+        }
+        if valued {
+            self.valued.insert(id);
+        } else {
+            self.valued.remove(&id);
+        }
+        announced
+    }
+
+    fn wants(&mut self, event: &ConnectionEvent) -> bool {
+        match event {
+            ConnectionEvent::Announce { id, topic } => self.track(*id, &topic.name),
+            ConnectionEvent::Unannounce { id, .. } => {
+                self.valued.remove(id);
+                self.announced.remove(id)
+            },
+            ConnectionEvent::TypeChanged { name, .. } => {
+                self.subscriptions.values().any(|route| route.matches(name))
+            },
+            ConnectionEvent::PropertiesChanged { id, .. } => self.announced.contains(id),
+            ConnectionEvent::Ready => true,
+            ConnectionEvent::Unready => {
+                self.announced.clear();
+                self.valued.clear();
+                true
+            },
+            ConnectionEvent::Value { topic_id, .. } | ConnectionEvent::Diff { topic_id, .. } => {
+                self.valued.contains(topic_id)
+            },
+            ConnectionEvent::Warning(_)
+            | ConnectionEvent::SelfTest(_)
+            | ConnectionEvent::Fetched { .. }
+            | ConnectionEvent::PersistentExport(_)
+            | ConnectionEvent::PersistentImport(_)
+            | ConnectionEvent::ServerTimeReset { .. }
+            | ConnectionEvent::Group { .. }
+            | ConnectionEvent::Watch(_)
+            | ConnectionEvent::Alert(_) => false,
+        }
+    }
+}
+
+/// The virtual clients of one connection.
+#[derive(Default)]
+pub(crate) struct VirtualClients {
+    clients: HashMap<u32, VirtualClient>,
+    next_id: u32,
+}
+
+impl VirtualClients {
+    pub(crate) fn add(&mut self, name: &str) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.insert(
+            id,
+            VirtualClient {
+                name: name.to_string(),
+                callbacks: Callbacks::default(),
+                uid_cnt: 0,
+                subscriptions: HashMap::new(),
+                publications: HashMap::new(),
+                announced: HashSet::new(),
+                valued: HashSet::new(),
+            },
+        );
+        id
+    }
+
+    fn get_mut(&mut self, id: u32) -> Result<&mut VirtualClient, JsValue> {
+        self.clients
+            .get_mut(&id)
+            .ok_or_else(|| JsString::from("virtual client closed").into())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.clients.clear();
+    }
+
+    /// Delivers `event` to every virtual client whose subscriptions match it. A failing callback
+    /// does not keep the event from the others; the first error is returned once all have seen it.
+    fn route(&mut self, event: &ConnectionEvent) -> Result<(), JsValue> {
+        let mut result = Ok(());
+        for client in self.clients.values_mut() {
+            if client.wants(event) {
+                result = result.and(client.callbacks.event(event.clone()));
+            }
+        }
+        result
+    }
+}
+
+/// The sink of a [`Nt4Connection`](crate::Nt4Connection): frames go to its socket callbacks, and events to
+/// its own callbacks as well as to its virtual clients.
+pub(crate) struct Router<'a> {
+    pub(crate) callbacks: &'a mut Callbacks,
+    pub(crate) virtual_clients: &'a mut VirtualClients,
+}
+
+impl ConnectionSink for Router<'_> {
+    type Error = JsValue;
+
+    fn send_text(&mut self, data: String) -> Result<(), JsValue> {
+        self.callbacks.send_text(data)
+    }
+
+    fn send_binary(&mut self, data: Vec<u8>) -> Result<(), JsValue> {
+        self.callbacks.send_binary(data)
+    }
+
+    fn max_frame_bytes(&self) -> Option<usize> {
+        self.callbacks.max_frame_bytes()
+    }
+
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), JsValue> {
+        let routed = self.virtual_clients.route(&event);
+        self.callbacks.event(event).and(routed)
+    }
+}
+
+#[doc = " A client sharing the WebSocket and timesync of a {@link Nt4Connection}, from {@link Nt4Connection.virtual_client}."]
+#[wasm_bindgen]
+pub struct Nt4VirtualClient {
+    pub(crate) id: u32,
+    pub(crate) inner: Rc<RefCell<Inner>>,
+}
+
+macro_rules! set_virtual_fns {
+    ($($name:ident),* $(,)?) => {
+        paste::paste! {
+            #[wasm_bindgen]
+            impl Nt4VirtualClient {
+                $(
+                    pub fn [<set_ $name>](&mut self, f: js_sys::Function) -> Result<(), JsValue> {
+                        self.with_client(|client| client.callbacks.$name = Some(f))
+                    }
+                )*
+            }
+        }
+    };
+}
+
+set_virtual_fns! {
+    announce_fn,
+    unannounce_fn,
+    ready_fn,
+    unready_fn,
+    on_data_fn,
+    type_changed_fn,
+    properties_changed_fn,
+}
+
+impl Nt4VirtualClient {
+    fn with_client<T>(&self, f: impl FnOnce(&mut VirtualClient) -> T) -> Result<T, JsValue> {
+        with_core(&self.inner, |_, sink| Ok(f(sink.virtual_clients.get_mut(self.id)?)))
+    }
+}
+
+#[wasm_bindgen]
+impl Nt4VirtualClient {
+    pub fn name(&self) -> Result<String, JsValue> {
+        self.with_client(|client| client.name.clone())
+    }
+
+    #[doc = " When set, `on_data_fn` is called as `(topic_id, timestamp, data, type)` where `type` is the NT4 type string."]
+    pub fn set_include_type_in_callback(&mut self, b: bool) -> Result<(), JsValue> {
+        self.with_client(|client| client.callbacks.include_type_in_callback = b)
+    }
+
+    #[doc = " subscribe(string path, object options) -> int\n"]
+    #[doc = " Topics the connection already knows about and `path` matches are announced right away."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn subscribe(&mut self, path: &str, options: JsValue) -> Result<i32, JsValue> {
+        let options: SubscriptionOptions = serde_wasm_bindgen::from_value(options)?;
+        let (prefix, topicsonly) = (options.prefix, options.topicsonly);
+        with_core(&self.inner, |core, sink| {
+            sink.virtual_clients.get_mut(self.id)?;
+            let real = core.subscribe(sink, path, options)?;
+            let client = sink.virtual_clients.get_mut(self.id)?;
+            let uid = client.new_uid();
+            client.subscriptions.insert(uid, Route { real, topic: path.to_string(), prefix, topicsonly });
+            let mut topics: Vec<_> = core.topics().collect();
+            topics.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+            for (id, topic) in topics {
+                let known = client.announced.contains(id);
+                if client.track(*id, &topic.name) && !known {
+                    client.callbacks.event(ConnectionEvent::Announce { id: *id, topic: topic.clone() })?;
+                }
+            }
+            Ok(uid)
+        })
+    }
+
+    #[doc = " unsubscribe(int id)\n"]
+    #[doc = " @param {number} id - an id returned by this client's {@link subscribe}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn unsubscribe(&mut self, id: i32) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| {
+            let real = match sink.virtual_clients.get_mut(self.id)?.subscriptions.get(&id) {
+                Some(route) => route.real,
+                None if core.is_idempotent() => return Ok(()),
+                None => return Err(UnknownUid { kind: UidKind::Subscription, uid: id }.into()),
+            };
+            core.unsubscribe(sink, real)?;
+            let client = sink.virtual_clients.get_mut(self.id)?;
+            client.subscriptions.remove(&id);
+            for (id, topic) in core.topics() {
+                client.track(*id, &topic.name);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn publish(&mut self, name: &str, ty: JsValue, properties: JsValue) -> Result<i32, JsValue> {
+        let ty: Nt4TypeId = serde_wasm_bindgen::from_value(ty)?;
+        with_core(&self.inner, |core, sink| {
+            sink.virtual_clients.get_mut(self.id)?;
+            let properties = js_properties::convert(sink, |x| x.properties(&properties))?;
+            let real = core.publish(sink, name, ty, properties)?;
+            let client = sink.virtual_clients.get_mut(self.id)?;
+            let uid = client.new_uid();
+            client.publications.insert(uid, real);
+            Ok(uid)
+        })
+    }
+
+    pub fn unpublish(&mut self, id: i32) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| {
+            if core.is_idempotent() && !sink.virtual_clients.get_mut(self.id)?.publications.contains_key(&id) {
+                return Ok(());
+            }
+            let real = self.publication(sink, id)?;
+            core.unpublish(sink, real)?;
+            sink.virtual_clients.get_mut(self.id)?.publications.remove(&id);
+            Ok(())
+        })
+    }
+
+    pub fn set_properties(&mut self, name: &str, update: JsValue) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| {
+            sink.virtual_clients.get_mut(self.id)?;
+            let update = js_properties::convert(sink, |x| x.partial_properties(&update))?;
+            core.set_properties(sink, name, update)
+        })
+    }
+
+    #[doc = " send_data(int topic_id, any data, (number | bigint)? timestamp)\n"]
+    #[doc = " As {@link Nt4Connection.send_data}, with an id returned by this client's {@link publish}."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn send_data(&mut self, topic_id: i32, data: JsValue, timestamp: JsValue) -> Result<(), JsValue> {
+        let data: Nt4Data = serde_wasm_bindgen::from_value(data)?;
+        let timestamp = crate::timestamp_us(&timestamp)?;
+        with_core(&self.inner, |core, sink| {
+            let real = self.publication(sink, topic_id)?;
+            core.send_data(sink, real, data, timestamp)
+        })
+    }
+
+    pub fn is_active_subscription(&self, id: i32) -> Result<bool, JsValue> {
+        self.with_client(|client| client.subscriptions.contains_key(&id))
+    }
+
+    pub fn is_active_publication(&self, id: i32) -> Result<bool, JsValue> {
+        self.with_client(|client| client.publications.contains_key(&id))
+    }
+
+    #[doc = " Whether the shared connection has received a timesync response since the last disconnect."]
+    pub fn is_ready(&self) -> Result<bool, JsValue> {
+        with_core(&self.inner, |core, _| Ok(core.is_ready()))
+    }
+
+    #[doc = " close()\n"]
+    #[doc = " Unsubscribes and unpublishes everything of this client (best-effort) and drops its callbacks."]
+    #[wasm_bindgen(skip_jsdoc)]
+    pub fn close(&mut self) -> Result<(), JsValue> {
+        with_core(&self.inner, |core, sink| {
+            let Some(client) = sink.virtual_clients.clients.remove(&self.id) else {
+                return Ok(());
+            };
+            if core.check_open().is_ok() {
+                for route in client.subscriptions.values() {
+                    let _ = core.unsubscribe(sink, route.real);
+                }
+                for real in client.publications.values() {
+                    let _ = core.unpublish(sink, *real);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Nt4VirtualClient {
+    fn publication(&self, sink: &mut Router, id: i32) -> Result<i32, JsValue> {
+        sink.virtual_clients
+            .get_mut(self.id)?
+            .publications
+            .get(&id)
+            .copied()
+            .ok_or_else(|| UnknownUid { kind: UidKind::Publication, uid: id }.into())
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Value};
+
+use crate::types::Nt4Data;
+
+/// How a value is compared with another in a [`WatchSpec`]. Numbers compare as numbers, anything
+/// else only equals or differs.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, left: &Value, right: &Value) -> bool {
+        if let (Some(left), Some(right)) = (left.as_f64(), right.as_f64()) {
+            return match self {
+                Self::Eq => left == right,
+                Self::Ne => left != right,
+                Self::Lt => left < right,
+                Self::Le => left <= right,
+                Self::Gt => left > right,
+                Self::Ge => left >= right,
+            };
+        }
+        match self {
+            Self::Eq => left == right,
+            Self::Ne => left != right,
+            _ => false,
+        }
+    }
+}
+
+/// A condition over one or two topics, see [`crate::ConnectionCore::add_watch`]. Times are in
+/// milliseconds of server time, measured between the timestamps of received values.
+#[derive(serde::Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WatchSpec {
+    /// `topic op value`, checked on every value of `topic`.
+    Compare { topic: String, op: Comparison, value: Value },
+    /// `left op right` on the latest values of both, checked on every value of either.
+    CompareTopics { left: String, op: Comparison, right: String },
+    /// Every time `trigger` changes, a value of `topic` stamped within `within_ms` of the change
+    /// must satisfy `op value`. Each change has an outcome. The first value of `trigger` is not a change.
+    WithinAfterChange { trigger: String, topic: String, op: Comparison, value: Value, within_ms: f64 },
+    /// `topic` must stay within `[min, max]` for `for_ms`, and fails as soon as it leaves.
+    StaysInRange { topic: String, min: f64, max: f64, for_ms: f64 },
+}
+
+impl WatchSpec {
+    fn mentions(&self, name: &str) -> bool {
+        match self {
+            Self::Compare { topic, .. } | Self::StaysInRange { topic, .. } => topic == name,
+            Self::CompareTopics { left, right, .. } => left == name || right == name,
+            Self::WithinAfterChange { trigger, topic, .. } => trigger == name || topic == name,
+        }
+    }
+}
+
+/// A watch passing or failing, or a change of [`WatchSpec::WithinAfterChange`] being decided.
+#[derive(Debug, Clone)]
+pub struct WatchResult {
+    pub id: String,
+    pub passed: bool,
+    /// The values and server times the outcome was decided on.
+    pub details: Value,
+}
+
+#[derive(Debug)]
+struct Watch {
+    spec: WatchSpec,
+    passed: Option<bool>,
+    /// Server time of the change of the trigger being waited on.
+    changed_at: Option<i64>,
+    /// Server time the topic last went into range.
+    entered: Option<i64>,
+}
+
+impl Watch {
+    /// Records the outcome if it differs from the last one.
+    fn transition(&mut self, passed: bool, details: Value) -> Option<(bool, Value)> {
+        (self.passed != Some(passed)).then(|| {
+            self.passed = Some(passed);
+            (passed, details)
+        })
+    }
+
+    /// The outcome decided by the server clock reaching `server_time`, if any.
+    fn advance(&mut self, server_time: i64) -> Option<(bool, Value)> {
+        match self.spec {
+            WatchSpec::WithinAfterChange { within_ms, .. } => {
+                let changed_at = self.changed_at?;
+                let deadline = changed_at.saturating_add(ms_to_us(within_ms));
+                if server_time <= deadline {
+                    return None;
+                }
+                self.changed_at = None;
+                self.passed = Some(false);
+                Some((false, json!({"changed_at": changed_at, "deadline": deadline})))
+            },
+            WatchSpec::StaysInRange { for_ms, .. } => {
+                let entered = self.entered?;
+                if server_time.saturating_sub(entered) < ms_to_us(for_ms) {
+                    return None;
+                }
+                self.transition(true, json!({"since": entered, "time": server_time}))
+            },
+            _ => None,
+        }
+    }
+
+    /// The outcome decided by `value`, the value of `name` stamped `timestamp`.
+    fn observe(
+        &mut self,
+        name: &str,
+        timestamp: i64,
+        value: &Value,
+        previous: Option<&Value>,
+        latest: &HashMap<String, Value>,
+    ) -> Option<(bool, Value)> {
+        match &self.spec {
+            WatchSpec::Compare { topic, op, value: expected } if topic == name => {
+                let passed = op.holds(value, expected);
+                self.transition(passed, json!({"value": value, "time": timestamp}))
+            },
+            WatchSpec::CompareTopics { left, op, right } if left == name || right == name => {
+                let (left, right) = (latest.get(left)?, latest.get(right)?);
+                let passed = op.holds(left, right);
+                self.transition(passed, json!({"left": left, "right": right, "time": timestamp}))
+            },
+            WatchSpec::WithinAfterChange { trigger, topic, op, value: expected, within_ms } => {
+                if trigger == name && previous.is_some_and(|previous| previous != value) {
+                    // A change while waiting on the last one starts over.
+                    self.changed_at = Some(timestamp);
+                    return None;
+                }
+                let changed_at = self.changed_at?;
+                if topic != name || timestamp < changed_at || timestamp > changed_at.saturating_add(ms_to_us(*within_ms)) {
+                    return None;
+                }
+                if !op.holds(value, expected) {
+                    return None;
+                }
+                self.changed_at = None;
+                self.passed = Some(true);
+                Some((true, json!({"changed_at": changed_at, "time": timestamp, "value": value})))
+            },
+            WatchSpec::StaysInRange { topic, min, max, .. } if topic == name => {
+                if value.as_f64().is_some_and(|x| *min <= x && x <= *max) {
+                    self.entered.get_or_insert(timestamp);
+                    return self.advance(timestamp);
+                }
+                self.entered = None;
+                self.transition(false, json!({"value": value, "time": timestamp}))
+            },
+            _ => None,
+        }
+    }
+}
+
+fn ms_to_us(ms: f64) -> i64 {
+    (ms * 1e3) as i64
+}
+
+/// Every watch by id, with the latest value of each topic they mention.
+#[derive(Debug, Default)]
+pub struct Watches {
+    watches: BTreeMap<String, Watch>,
+    latest: HashMap<String, Value>,
+}
+
+impl Watches {
+    /// Adds a watch, replacing any with the same id.
+    pub fn add(&mut self, id: &str, spec: WatchSpec) -> Result<(), String> {
+        match &spec {
+            WatchSpec::WithinAfterChange { within_ms: ms, .. } | WatchSpec::StaysInRange { for_ms: ms, .. }
+                if !(ms.is_finite() && *ms >= 0.0) =>
+            {
+                return Err(format!("watch {:?} has a duration of {} ms", id, ms));
+            },
+            WatchSpec::StaysInRange { min, max, .. } if min.is_nan() || max.is_nan() || min > max => {
+                return Err(format!("watch {:?} has an empty range [{}, {}]", id, min, max));
+            },
+            _ => {},
+        }
+        let watch = Watch { spec, passed: None, changed_at: None, entered: None };
+        self.watches.insert(id.to_string(), watch);
+        self.latest.retain(|name, _| self.watches.values().any(|x| x.spec.mentions(name)));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let removed = self.watches.remove(id).is_some();
+        self.latest.retain(|name, _| self.watches.values().any(|x| x.spec.mentions(name)));
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// The outcomes decided by the server clock reaching `server_time`.
+    pub fn advance(&mut self, server_time: i64) -> Vec<WatchResult> {
+        let mut results = Vec::new();
+        for (id, watch) in &mut self.watches {
+            if let Some((passed, details)) = watch.advance(server_time) {
+                results.push(WatchResult { id: id.clone(), passed, details });
+            }
+        }
+        results
+    }
+
+    /// The outcomes decided by a value of `name` stamped `timestamp`, including those decided by
+    /// the server clock reaching `timestamp` first.
+    pub fn observe(&mut self, name: &str, timestamp: i64, data: &Nt4Data) -> Vec<WatchResult> {
+        let mut results = self.advance(timestamp);
+        if !self.watches.values().any(|x| x.spec.mentions(name)) {
+            return results;
+        }
+        let Ok(value) = serde_json::to_value(data) else {
+            return results;
+        };
+        let previous = self.latest.insert(name.to_string(), value.clone());
+        for (id, watch) in &mut self.watches {
+            if let Some((passed, details)) = watch.observe(name, timestamp, &value, previous.as_ref(), &self.latest) {
+                results.push(WatchResult { id: id.clone(), passed, details });
+            }
+        }
+        results
+    }
+
+    /// Forgets every value and outcome, keeping the watches, e.g. when the server clock restarts.
+    pub fn reset(&mut self) {
+        self.latest.clear();
+        for watch in self.watches.values_mut() {
+            watch.passed = None;
+            watch.changed_at = None;
+            watch.entered = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+        self.latest.clear();
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use crate::types::{Nt4Data, Nt4TypeId, Topic};
+
+/// The entry that makes its subtable a widget, holding the widget type as a string.
+pub const TYPE_ENTRY: &str = ".type";
+/// The display name of a widget, a string.
+pub const NAME_ENTRY: &str = ".name";
+/// Whether a widget accepts changes from the dashboard, a boolean.
+pub const CONTROLLABLE_ENTRY: &str = ".controllable";
+
+/// Whether any part of `name` starts with a `.`, as the entries describing widgets do. Such topics
+/// are left out of topic listings unless asked for.
+pub fn is_hidden(name: &str) -> bool {
+    name.split('/').any(|part| part.starts_with('.'))
+}
+
+/// A topic of a widget, named relative to the widget.
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WidgetChild {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Nt4TypeId,
+}
+
+/// A subtable with a `.type` entry, as Shuffleboard and SmartDashboard publish sendables, see
+/// [`crate::ConnectionCore::widget_descriptors`].
+#[derive(serde::Serialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WidgetDescriptor {
+    pub path: String,
+    /// The value of `.type`, `None` until it is received.
+    pub widget_type: Option<String>,
+    /// The value of `.name`, if any.
+    pub name: Option<String>,
+    /// The value of `.controllable`, false until it is received.
+    pub controllable: bool,
+    /// The topics under `path` that no nested widget claims, hidden ones aside, sorted by name.
+    pub children: Vec<WidgetChild>,
+}
+
+/// The widgets among the announced topics, kept up to date as topics come and go. Each topic
+/// belongs to the widget of its nearest enclosing subtable with a `.type`, if any.
+#[derive(Debug, Default)]
+pub struct Widgets {
+    /// Children by widget path.
+    widgets: BTreeMap<String, BTreeMap<String, Nt4TypeId>>,
+}
+
+impl Widgets {
+    /// The path of the widget `name` belongs to, and its name relative to that widget.
+    fn owner<'a>(&self, name: &'a str) -> Option<(&'a str, &'a str)> {
+        name.rmatch_indices('/')
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .find(|(path, _)| self.widgets.contains_key(*path))
+    }
+
+    pub fn announce<'a>(
+        &mut self,
+        name: &str,
+        ty: Nt4TypeId,
+        topics: impl IntoIterator<Item = &'a Topic>,
+    ) -> Result<(), String> {
+        let Some((path, entry)) = name.rsplit_once('/') else {
+            return Ok(());
+        };
+        if entry != TYPE_ENTRY {
+            if let Some((path, child)) = self.owner(name).filter(|(_, child)| !is_hidden(child)) {
+                self.widgets.entry(path.to_string()).or_default().insert(child.to_string(), ty);
+            }
+            return Ok(());
+        }
+        if self.widgets.contains_key(path) {
+            return Ok(());
+        }
+        self.widgets.insert(path.to_string(), BTreeMap::new());
+        // Take over the topics already announced under `path` from the widget around it, or claim
+        // them if there is none.
+        let mut children = BTreeMap::new();
+        for topic in topics {
+            let Some((owner, child)) = self.owner(&topic.name) else {
+                continue;
+            };
+            if owner == path && !is_hidden(child) {
+                children.insert(child.to_string(), topic.ty);
+            }
+        }
+        if let Some((outer, relative)) = self.owner(path) {
+            let outer = self.widget_mut(outer)?;
+            for child in children.keys() {
+                outer.remove(&format!("{}/{}", relative, child));
+            }
+        }
+        self.widgets.insert(path.to_string(), children);
+        Ok(())
+    }
+
+    pub fn unannounce(&mut self, name: &str) -> Result<(), String> {
+        let Some((path, entry)) = name.rsplit_once('/') else {
+            return Ok(());
+        };
+        if entry != TYPE_ENTRY {
+            if let Some((path, child)) = self.owner(name) {
+                self.widget_mut(path)?.remove(child);
+            }
+            return Ok(());
+        }
+        // The topics of the widget go back to the one around it, if any.
+        let Some(children) = self.widgets.remove(path) else {
+            return Ok(());
+        };
+        if let Some((outer, relative)) = self.owner(path) {
+            let outer = self.widget_mut(outer)?;
+            outer.extend(children.into_iter().map(|(child, ty)| (format!("{}/{}", relative, child), ty)));
+        }
+        Ok(())
+    }
+
+    /// The children of the widget at `path`, which [`Self::owner`] found.
+    fn widget_mut(&mut self, path: &str) -> Result<&mut BTreeMap<String, Nt4TypeId>, String> {
+        self.widgets.get_mut(path).ok_or_else(|| format!("widget {:?} is missing from the widget table", path))
+    }
+
+    /// The widgets whose path starts with `prefix`, sorted by path, with the values of their
+    /// entries looked up through `value`.
+    pub fn descriptors<'a>(&self, prefix: &str, value: impl Fn(&str) -> Option<&'a Nt4Data>) -> Vec<WidgetDescriptor> {
+        let string = |path: &str, entry: &str| match value(&format!("{}/{}", path, entry)) {
+            Some(Nt4Data::String(x)) => Some(x.clone()),
+            _ => None,
+        };
+        self.widgets
+            .range(prefix.to_string()..)
+            .take_while(|(path, _)| path.starts_with(prefix))
+            .map(|(path, children)| WidgetDescriptor {
+                path: path.clone(),
+                widget_type: string(path, TYPE_ENTRY),
+                name: string(path, NAME_ENTRY),
+                controllable: matches!(value(&format!("{}/{}", path, CONTROLLABLE_ENTRY)), Some(Nt4Data::Boolean(true))),
+                children: children.iter().map(|(name, &ty)| WidgetChild { name: name.clone(), ty }).collect(),
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.widgets.clear();
+    }
+}
@@ -0,0 +1,366 @@
+//! How values are laid out as msgpack in binary frames.
+//!
+//! Sending follows [`WireOptions`], which defaults to the most compact encoding of each value as
+//! the msgpack spec recommends, as wpilib's ntcore does. Receiving accepts every encoding below,
+//! by the type id of the frame:
+//!
+//! | type                         | accepted                                                        |
+//! |------------------------------|-----------------------------------------------------------------|
+//! | `boolean`                    | bool, the int 0 or 1                                            |
+//! | `int`                        | any int in range, a float without a fractional part             |
+//! | `float`, `double`            | float 32 or 64, any int                                         |
+//! | `string`, `json`             | str, bin holding UTF-8                                          |
+//! | `raw`, `rpc`, `msgpack`, `protobuf` | bin, str (its UTF-8 bytes), an array of ints 0 to 255    |
+//! | arrays                       | fixarray, array 16 or 32 of anything accepted for one element   |
+//!
+//! Every int width, signed or not, and every str, bin and array header width is accepted too.
+
+use std::fmt;
+
+use serde::de::{DeserializeSeed, Deserializer, Error, SeqAccess, Unexpected, Visitor};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    binary::BinaryDataFrame,
+    types::{Nt4Data, Nt4DataSeed, Nt4TypeId},
+};
+
+/// Longest array allocated up front from the length its header claims, so a corrupt header cannot
+/// allocate more than the frame holds.
+const MAX_PREALLOCATED: usize = 4096;
+
+/// How raw values, of the `raw`, `rpc`, `msgpack` and `protobuf` types, are sent.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawEncoding {
+    /// msgpack bin, as in NT4.
+    #[default]
+    Bin,
+    /// An array of ints, for decoders that predate bin.
+    Array,
+}
+
+/// How `boolean` values and the elements of `boolean[]` values are sent.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BooleanEncoding {
+    /// msgpack true and false, as in NT4.
+    #[default]
+    Bool,
+    /// The ints 1 and 0.
+    Int,
+}
+
+/// Which header strings are sent with.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StringEncoding {
+    /// The smallest that fits: fixstr below 32 bytes, then str 8, 16 and 32.
+    #[default]
+    Compact,
+    /// Str 16 where str 8 would fit, for decoders that predate str 8.
+    NoStr8,
+}
+
+/// How [`write_frame`] encodes values. Topic ids, timestamps, numbers and array headers always
+/// take the smallest encoding that fits.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct WireOptions {
+    pub raw: RawEncoding,
+    pub booleans: BooleanEncoding,
+    pub strings: StringEncoding,
+}
+
+/// Writing into a `Vec` only fails on lengths msgpack cannot express.
+fn err(error: impl fmt::Display) -> String {
+    error.to_string()
+}
+
+fn write_bool(out: &mut Vec<u8>, value: bool, options: &WireOptions) -> Result<(), String> {
+    match options.booleans {
+        BooleanEncoding::Bool => rmp::encode::write_bool(out, value).map_err(err),
+        BooleanEncoding::Int => rmp::encode::write_uint(out, value as u64).map(|_| ()).map_err(err),
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, value: i64) -> Result<(), String> {
+    rmp::encode::write_sint(out, value).map(|_| ()).map_err(err)
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str, options: &WireOptions) -> Result<(), String> {
+    match options.strings {
+        StringEncoding::NoStr8 if (32..=u8::MAX as usize).contains(&value.len()) => {
+            out.push(rmp::Marker::Str16.to_u8());
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value.as_bytes());
+            Ok(())
+        },
+        _ => rmp::encode::write_str(out, value).map_err(err),
+    }
+}
+
+fn write_array<T>(
+    out: &mut Vec<u8>,
+    values: &[T],
+    mut write: impl FnMut(&mut Vec<u8>, &T) -> Result<(), String>,
+) -> Result<(), String> {
+    let len = u32::try_from(values.len()).map_err(|_| format!("array of {} items is too long", values.len()))?;
+    rmp::encode::write_array_len(out, len).map_err(err)?;
+    values.iter().try_for_each(|x| write(out, x))
+}
+
+fn write_raw(out: &mut Vec<u8>, value: &[u8], options: &WireOptions) -> Result<(), String> {
+    match options.raw {
+        RawEncoding::Bin => rmp::encode::write_bin(out, value).map_err(err),
+        RawEncoding::Array => write_array(out, value, |out, &x| write_int(out, x as i64)),
+    }
+}
+
+fn write_data(out: &mut Vec<u8>, data: &Nt4Data, options: &WireOptions) -> Result<(), String> {
+    match data {
+        Nt4Data::Boolean(x) => write_bool(out, *x, options),
+        Nt4Data::Double(x) => rmp::encode::write_f64(out, *x).map_err(err),
+        Nt4Data::Int(x) => write_int(out, *x),
+        Nt4Data::Float(x) => rmp::encode::write_f32(out, *x).map_err(err),
+        Nt4Data::String(x) | Nt4Data::Json(x) => write_str(out, x, options),
+        Nt4Data::Raw(x) | Nt4Data::Rpc(x) | Nt4Data::MsgPack(x) | Nt4Data::Protobuf(x) => write_raw(out, x, options),
+        Nt4Data::BooleanArray(x) => write_array(out, x, |out, x| write_bool(out, *x, options)),
+        Nt4Data::DoubleArray(x) => write_array(out, x, |out, x| rmp::encode::write_f64(out, *x).map_err(err)),
+        Nt4Data::IntArray(x) => write_array(out, x, |out, x| write_int(out, *x)),
+        Nt4Data::FloatArray(x) => write_array(out, x, |out, x| rmp::encode::write_f32(out, *x).map_err(err)),
+        Nt4Data::StringArray(x) => write_array(out, x, |out, x| write_str(out, x, options)),
+    }
+}
+
+/// Appends `frame` to `out` as `[topic id, timestamp, type id, value]`, or leaves `out` as it was
+/// on error. With the default options the bytes are the same as `rmp_serde` writes for
+/// [`BinaryDataFrame`].
+pub fn write_frame(out: &mut Vec<u8>, frame: &BinaryDataFrame, options: &WireOptions) -> Result<(), String> {
+    let timestamp = u64::try_from(frame.timestamp).map_err(|_| format!("timestamp {} is negative", frame.timestamp))?;
+    let start = out.len();
+    let mut write = || {
+        rmp::encode::write_array_len(out, 4).map_err(err)?;
+        write_int(out, frame.topic_id as i64)?;
+        rmp::encode::write_uint(out, timestamp).map_err(err)?;
+        rmp::encode::write_uint(out, frame.data.get_id() as u64).map_err(err)?;
+        write_data(out, &frame.data, options)
+    };
+    let written = write();
+    if written.is_err() {
+        out.truncate(start);
+    }
+    written
+}
+
+/// Writes `frames` one after the other, for a single binary message.
+pub fn write_frames(frames: &[BinaryDataFrame], options: &WireOptions) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    for frame in frames {
+        write_frame(&mut out, frame, options)?;
+    }
+    Ok(out)
+}
+
+/// `frames` encoded into as few messages of at most `limit` bytes as it takes, in order. Fails
+/// before encoding anything if one frame alone is over `limit`.
+pub fn write_frames_split(frames: &[BinaryDataFrame], options: &WireOptions, limit: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut messages = Vec::new();
+    let mut out = Vec::new();
+    let mut encoded = Vec::new();
+    for frame in frames {
+        encoded.clear();
+        write_frame(&mut encoded, frame, options)?;
+        if encoded.len() > limit {
+            return Err(format!(
+                "a value of topic {} is {} bytes encoded, over the {} byte frame limit",
+                frame.topic_id,
+                encoded.len(),
+                limit
+            ));
+        }
+        if out.len() + encoded.len() > limit {
+            messages.push(std::mem::take(&mut out));
+        }
+        out.extend_from_slice(&encoded);
+    }
+    if !out.is_empty() {
+        messages.push(out);
+    }
+    Ok(messages)
+}
+
+#[derive(Clone, Copy)]
+struct BooleanVisitor;
+
+impl<'de> Visitor<'de> for BooleanVisitor {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bool or the int 0 or 1")
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<bool, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<bool, E> {
+        match value {
+            0 | 1 => Ok(value == 1),
+            _ => Err(E::invalid_value(Unexpected::Signed(value), &self)),
+        }
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<bool, E> {
+        match value {
+            0 | 1 => Ok(value == 1),
+            _ => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IntVisitor;
+
+impl<'de> Visitor<'de> for IntVisitor {
+    type Value = i64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an int or a float without a fractional part")
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<i64, E> {
+        Ok(value)
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<i64, E> {
+        i64::try_from(value).map_err(|_| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_f64<E: Error>(self, value: f64) -> Result<i64, E> {
+        // -2^63 and every integral float below 2^63 fit an i64 exactly.
+        if value.fract() == 0.0 && (-9.223_372_036_854_776e18..9.223_372_036_854_776e18).contains(&value) {
+            Ok(value as i64)
+        } else {
+            Err(E::invalid_value(Unexpected::Float(value), &self))
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct StringVisitor;
+
+impl<'de> Visitor<'de> for StringVisitor {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a str or a bin holding UTF-8")
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_string<E: Error>(self, value: String) -> Result<String, E> {
+        Ok(value)
+    }
+
+    fn visit_bytes<E: Error>(self, value: &[u8]) -> Result<String, E> {
+        std::str::from_utf8(value)
+            .map(str::to_string)
+            .map_err(|_| E::invalid_value(Unexpected::Bytes(value), &self))
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bin, a str or an array of ints 0 to 255")
+    }
+
+    fn visit_bytes<E: Error>(self, value: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf::from(value))
+    }
+
+    fn visit_byte_buf<E: Error>(self, value: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf::from(value))
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<ByteBuf, E> {
+        Ok(ByteBuf::from(value.as_bytes()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default().min(MAX_PREALLOCATED));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(ByteBuf::from(bytes))
+    }
+}
+
+/// A value taken by `V` from whatever encoding it is in.
+#[derive(Clone, Copy)]
+struct Any<V>(V);
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for Any<V> {
+    type Value = V::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        deserializer.deserialize_any(self.0)
+    }
+}
+
+/// An array of values, each taken by `V`.
+struct ArrayVisitor<V>(V);
+
+impl<'de, V: Visitor<'de> + Copy> Visitor<'de> for ArrayVisitor<V> {
+    type Value = Vec<V::Value>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of ")?;
+        self.0.expecting(formatter)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<V::Value>, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default().min(MAX_PREALLOCATED));
+        while let Some(value) = seq.next_element_seed(Any(self.0))? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+/// Deserializes the [`Nt4Data`] variant for a known type from any of the encodings in the
+/// [module docs](self), where [`Nt4DataSeed`] only takes the one `serde` expects.
+pub struct WireDataSeed(pub Nt4TypeId);
+
+impl<'de> DeserializeSeed<'de> for WireDataSeed {
+    type Value = Nt4Data;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Nt4Data, D::Error> {
+        Ok(match self.0 {
+            Nt4TypeId::Boolean => Nt4Data::Boolean(deserializer.deserialize_any(BooleanVisitor)?),
+            Nt4TypeId::Int => Nt4Data::Int(deserializer.deserialize_any(IntVisitor)?),
+            Nt4TypeId::String => Nt4Data::String(deserializer.deserialize_any(StringVisitor)?),
+            Nt4TypeId::Json => Nt4Data::Json(deserializer.deserialize_any(StringVisitor)?),
+            Nt4TypeId::Raw => Nt4Data::Raw(deserializer.deserialize_any(BytesVisitor)?),
+            Nt4TypeId::Rpc => Nt4Data::Rpc(deserializer.deserialize_any(BytesVisitor)?),
+            Nt4TypeId::MsgPack => Nt4Data::MsgPack(deserializer.deserialize_any(BytesVisitor)?),
+            Nt4TypeId::Protobuf => Nt4Data::Protobuf(deserializer.deserialize_any(BytesVisitor)?),
+            Nt4TypeId::BooleanArray => Nt4Data::BooleanArray(deserializer.deserialize_seq(ArrayVisitor(BooleanVisitor))?),
+            Nt4TypeId::IntArray => Nt4Data::IntArray(deserializer.deserialize_seq(ArrayVisitor(IntVisitor))?),
+            Nt4TypeId::StringArray => Nt4Data::StringArray(deserializer.deserialize_seq(ArrayVisitor(StringVisitor))?),
+            Nt4TypeId::Double | Nt4TypeId::Float | Nt4TypeId::DoubleArray | Nt4TypeId::FloatArray => {
+                Nt4DataSeed(self.0).deserialize(deserializer)?
+            },
+        })
+    }
+}
@@ -0,0 +1,124 @@
+//! The recording sink and frame helpers shared by the native tests.
+// Each test crate uses some of these.
+#![allow(dead_code)]
+
+use nt4_wasm::{ConnectionCore, ConnectionEvent, ConnectionSink, Outgoing, TIMESYNC_TOPIC_ID};
+use serde_json::{json, Value};
+
+/// Records the frames a connection sends, in order, and the events it emits. Sends fail while
+/// `down`, and `hook` sees every event before it is recorded, e.g. to panic on one.
+#[derive(Default)]
+pub struct Wire {
+    pub sent: Vec<Outgoing>,
+    pub events: Vec<ConnectionEvent>,
+    pub down: bool,
+    pub hook: Option<fn(&ConnectionEvent)>,
+}
+
+impl ConnectionSink for Wire {
+    type Error = String;
+
+    fn send_text(&mut self, data: String) -> Result<(), String> {
+        if self.down {
+            return Err("socket closed".to_string());
+        }
+        self.sent.push(Outgoing::Text(data));
+        Ok(())
+    }
+
+    fn send_binary(&mut self, data: Vec<u8>) -> Result<(), String> {
+        if self.down {
+            return Err("socket closed".to_string());
+        }
+        self.sent.push(Outgoing::Binary(data));
+        Ok(())
+    }
+
+    fn event(&mut self, event: ConnectionEvent) -> Result<(), String> {
+        if let Some(hook) = self.hook {
+            hook(&event);
+        }
+        self.events.push(event);
+        Ok(())
+    }
+}
+
+impl Wire {
+    /// The text frames sent since the last call, as sent.
+    pub fn take_text(&mut self) -> Vec<String> {
+        let mut text = Vec::new();
+        self.sent.retain(|x| match x {
+            Outgoing::Text(data) => {
+                text.push(data.clone());
+                false
+            },
+            Outgoing::Binary(_) => true,
+        });
+        text
+    }
+
+    /// The text frames sent since the last call, parsed.
+    pub fn take_json(&mut self) -> Vec<Value> {
+        self.take_text().iter().map(|x| serde_json::from_str(x).unwrap()).collect()
+    }
+
+    /// The binary messages sent since the last call.
+    pub fn take_binary(&mut self) -> Vec<Vec<u8>> {
+        let mut binary = Vec::new();
+        self.sent.retain(|x| match x {
+            Outgoing::Binary(data) => {
+                binary.push(data.clone());
+                false
+            },
+            Outgoing::Text(_) => true,
+        });
+        binary
+    }
+
+    /// The one binary message sent since the last call.
+    pub fn take_one_binary(&mut self) -> Vec<u8> {
+        let mut binary = self.take_binary();
+        assert_eq!(binary.len(), 1, "expected exactly one binary message");
+        binary.remove(0)
+    }
+
+    pub fn take_events(&mut self) -> Vec<ConnectionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The warnings emitted since the last call, leaving the other events.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        self.events.retain(|x| match x {
+            ConnectionEvent::Warning(warning) => {
+                warnings.push(warning.clone());
+                false
+            },
+            _ => true,
+        });
+        warnings
+    }
+}
+
+pub fn announce(core: &mut ConnectionCore, wire: &mut Wire, name: &str, id: i32, ty: &str, properties: Value) {
+    let params = json!({"name": name, "id": id, "type": ty, "properties": properties});
+    core.on_text(wire, &json!({"method": "announce", "params": params}).to_string()).unwrap();
+}
+
+/// A binary message of one value, `type_id` as in NT4.
+pub fn value(topic_id: i32, timestamp: i64, type_id: u8, data: impl serde::Serialize) -> Vec<u8> {
+    rmp_serde::to_vec(&(topic_id, timestamp, type_id, data)).unwrap()
+}
+
+/// Sends a timesync request and returns the local time in it.
+pub fn request(core: &mut ConnectionCore, wire: &mut Wire) -> i64 {
+    core.timesync(wire).unwrap();
+    let (id, _, _, local_time): (i32, i64, u8, i64) = rmp_serde::from_slice(&wire.take_one_binary()).unwrap();
+    assert_eq!(id, TIMESYNC_TOPIC_ID);
+    local_time
+}
+
+/// The server's answer at `server_time` to a timesync request sent at `local_time`.
+pub fn response(server_time: i64, local_time: i64) -> Vec<u8> {
+    value(TIMESYNC_TOPIC_ID, server_time, 2, local_time)
+}
@@ -0,0 +1,134 @@
+//! `ConnectionCore` driven natively through a sink that records everything it sends and emits:
+//! uid allocation, the announce table, the timesync offset and values held until their topic
+//! is announced.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{announce, request, response, Wire};
+use nt4_wasm::{ConnectionCore, ConnectionEvent, Nt4Data, Nt4TypeId, Properties, SubscriptionOptions};
+use serde_json::json;
+
+/// The (topic id, timestamp, value) of every value event since the last call, values being ints here.
+fn take_values(wire: &mut Wire) -> Vec<(i32, i64, i64)> {
+    let values = wire.take_events().into_iter().filter_map(|x| match x {
+        ConnectionEvent::Value { topic_id, timestamp, data: Nt4Data::Int(x), .. } => Some((topic_id, timestamp, x)),
+        _ => None,
+    });
+    values.collect()
+}
+
+fn options() -> SubscriptionOptions {
+    SubscriptionOptions { periodic: Duration::from_millis(100), all: false, topicsonly: false, prefix: true }
+}
+
+fn value(topic_id: i32, timestamp: i64, data: i64) -> Vec<u8> {
+    common::value(topic_id, timestamp, 2, data)
+}
+
+#[test]
+fn uid_allocation() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    // Subscriptions and publications share one counter, so a uid names exactly one of them.
+    let subuid = core.subscribe(&mut wire, "/drive", options()).unwrap();
+    let pubuid = core.publish(&mut wire, "/dashboard/speed", Nt4TypeId::Double, Properties::default()).unwrap();
+    let other = core.subscribe(&mut wire, "/arm", options()).unwrap();
+    assert_eq!((subuid, pubuid, other), (0, 1, 2));
+    let text = wire.take_json();
+    assert_eq!(text[0]["params"]["subuid"], json!(subuid));
+    assert_eq!(text[1]["params"]["pubuid"], json!(pubuid));
+    assert_eq!(text[2]["params"]["subuid"], json!(other));
+
+    assert_eq!(core.unsubscribe(&mut wire, pubuid).unwrap_err(), "1 is not an active subscription");
+    assert_eq!(core.unpublish(&mut wire, subuid).unwrap_err(), "0 is not an active publication");
+    core.unsubscribe(&mut wire, subuid).unwrap();
+    assert_eq!(core.unsubscribe(&mut wire, subuid).unwrap_err(), "0 is not an active subscription");
+
+    // Never reused, even after a disconnect.
+    core.on_disconnect(&mut wire).unwrap();
+    assert_eq!(core.subscribe(&mut wire, "/drive", options()).unwrap(), 3);
+}
+
+#[test]
+fn announce_table() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    announce(&mut core, &mut wire, "/drive/speed", 7, "double", json!({}));
+    announce(&mut core, &mut wire, "/drive/heading", 8, "int", json!({"retained": true}));
+    let mut topics: Vec<(i32, String)> = core.topics().map(|(id, topic)| (*id, topic.name.to_string())).collect();
+    topics.sort();
+    assert_eq!(topics, [(7, "/drive/speed".to_string()), (8, "/drive/heading".to_string())]);
+    let announced: Vec<i32> = wire.events.drain(..).filter_map(|x| match x {
+        ConnectionEvent::Announce { id, .. } => Some(id),
+        _ => None,
+    }).collect();
+    assert_eq!(announced, [7, 8]);
+
+    // The same announce again is not news, new properties are.
+    announce(&mut core, &mut wire, "/drive/speed", 7, "double", json!({}));
+    assert!(wire.events.is_empty());
+    announce(&mut core, &mut wire, "/drive/speed", 7, "double", json!({"persistent": true}));
+    assert!(matches!(wire.events.as_slice(), [ConnectionEvent::PropertiesChanged { id: 7, .. }]), "{:?}", wire.events);
+    wire.events.clear();
+
+    let unannounce = json!({"method": "unannounce", "params": {"name": "/drive/speed", "id": 7}});
+    core.on_text(&mut wire, &unannounce.to_string()).unwrap();
+    assert!(matches!(wire.events.as_slice(), [ConnectionEvent::Unannounce { id: 7, name }] if name == "/drive/speed"));
+    assert_eq!(core.topics().map(|(id, _)| *id).collect::<Vec<_>>(), [8]);
+
+    // A disconnect forgets every topic.
+    core.on_disconnect(&mut wire).unwrap();
+    assert_eq!(core.topics().count(), 0);
+}
+
+#[test]
+fn timesync_offset() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    assert_eq!(core.offset(), 0);
+    let local_time = request(&mut core, &mut wire);
+    core.on_binary(&mut wire, &response(local_time + 3_000_000, local_time)).unwrap();
+    // Off by half the round trip, which is well under a second here.
+    assert!((core.offset() - 3_000_000).abs() < 1_000_000, "{}", core.offset());
+    assert!(matches!(wire.events.as_slice(), [ConnectionEvent::Ready]), "{:?}", wire.events);
+    wire.events.clear();
+
+    // The offset outlives a disconnect, so values can be stamped until the next timesync.
+    let offset = core.offset();
+    core.on_disconnect(&mut wire).unwrap();
+    assert!(matches!(wire.events.as_slice(), [ConnectionEvent::Unready]), "{:?}", wire.events);
+    assert_eq!(core.offset(), offset);
+}
+
+#[test]
+fn pending_values() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    core.set_pending_unannounced_limits(2, 3, 60_000_000);
+    // Values may arrive before the announce of their topic.
+    for (id, timestamp) in [(4, 100), (4, 200), (4, 300), (5, 100), (6, 100)] {
+        core.on_binary(&mut wire, &value(id, timestamp, timestamp)).unwrap();
+    }
+    assert!(take_values(&mut wire).is_empty());
+    // Two per topic and three in all, the oldest to arrive going first: 4 at 100, then 4 at 200.
+    assert_eq!((core.pending_unannounced_count(), core.dropped_unannounced_count()), (3, 2));
+
+    announce(&mut core, &mut wire, "/d", 6, "int", json!({}));
+    assert_eq!(take_values(&mut wire), [(6, 100, 100)]);
+    announce(&mut core, &mut wire, "/b", 4, "int", json!({}));
+    assert_eq!(take_values(&mut wire), [(4, 300, 300)]);
+    announce(&mut core, &mut wire, "/c", 5, "int", json!({}));
+    assert_eq!(take_values(&mut wire), [(5, 100, 100)]);
+    assert_eq!(core.pending_unannounced_count(), 0);
+
+    // Values of announced topics are delivered as they come, and a disconnect drops what is held.
+    core.on_binary(&mut wire, &value(4, 400, 400)).unwrap();
+    core.on_binary(&mut wire, &value(9, 400, 400)).unwrap();
+    assert_eq!(take_values(&mut wire), [(4, 400, 400)]);
+    assert_eq!(core.pending_unannounced_count(), 1);
+    core.on_disconnect(&mut wire).unwrap();
+    assert_eq!(core.pending_unannounced_count(), 0);
+}
@@ -0,0 +1,51 @@
+//! Diff mode compares array elements bit for bit, so NaNs that stay NaN are not reported as
+//! changed on every value.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{announce, value, Wire};
+use nt4_wasm::{ArrayDiff, ConnectionCore, ConnectionEvent, Nt4Data};
+use serde_json::json;
+
+/// The diffs delivered since the last call.
+fn take_diffs(wire: &mut Wire) -> Vec<ArrayDiff> {
+    let diffs = wire.take_events().into_iter().filter_map(|x| match x {
+        ConnectionEvent::Diff { diff, .. } => Some(diff),
+        _ => None,
+    });
+    diffs.collect()
+}
+
+/// The (start, values) of each change in the one diff delivered for `values`.
+fn changes(core: &mut ConnectionCore, wire: &mut Wire, timestamp: i64, values: &[f64]) -> Vec<(usize, Vec<u64>)> {
+    core.on_binary(wire, &value(1, timestamp, 17, values)).unwrap();
+    let [diff] = &take_diffs(wire)[..] else {
+        panic!("expected one diff");
+    };
+    assert!(diff.full.is_none());
+    let bits = |data: &Nt4Data| match data {
+        Nt4Data::DoubleArray(x) => x.iter().map(|x| x.to_bits()).collect(),
+        data => panic!("{:?}", data),
+    };
+    diff.changes.iter().map(|x| (x.start, bits(&x.values))).collect()
+}
+
+#[test]
+fn nan_elements() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    core.set_diff_mode("/LEDs", true);
+    announce(&mut core, &mut wire, "/LEDs", 1, "double[]", json!({}));
+    core.on_binary(&mut wire, &value(1, 100, 17, [f64::NAN, 0.0, 1.0])).unwrap();
+    assert!(take_diffs(&mut wire)[0].full.is_some());
+
+    assert_eq!(changes(&mut core, &mut wire, 200, &[f64::NAN, 0.0, 2.0]), [(2, vec![2.0_f64.to_bits()])]);
+    assert!(changes(&mut core, &mut wire, 300, &[f64::NAN, 0.0, 2.0]).is_empty());
+    // Only the sign differs, which is still a different value.
+    assert_eq!(changes(&mut core, &mut wire, 400, &[f64::NAN, -0.0, 2.0]), [(1, vec![(-0.0_f64).to_bits()])]);
+    assert_eq!(changes(&mut core, &mut wire, 500, &[1.0, -0.0, f64::NAN]), [
+        (0, vec![1.0_f64.to_bits()]),
+        (2, vec![f64::NAN.to_bits()])
+    ]);
+}
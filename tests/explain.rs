@@ -0,0 +1,70 @@
+//! `explain_binary_frame` on malformed input: what was read before the input goes wrong is
+//! explained, followed by what went wrong, and hostile lengths and nesting are survived.
+#![cfg(not(target_arch = "wasm32"))]
+
+use nt4_wasm::explain_binary_frame;
+
+fn frame() -> Vec<u8> {
+    rmp_serde::to_vec(&(14_i32, 1234567_i64, 1_u8, 3.25_f64)).unwrap()
+}
+
+/// The first line of each frame, without the elements.
+fn summaries(bytes: &[u8]) -> Vec<String> {
+    let explained = explain_binary_frame(bytes);
+    explained.lines().filter(|x| !x.starts_with(' ')).map(str::to_string).collect()
+}
+
+#[test]
+fn truncated_frame() {
+    let mut bytes = frame();
+    bytes.extend_from_slice(&frame()[..14]);
+    assert_eq!(explain_binary_frame(&bytes), [
+        "frame 0, bytes 0..17: topic 14 @ 1234567 µs double 3.25",
+        "  0..1        fixarray  4 items",
+        "  1..2          positive fixint  14",
+        "  2..7          uint32  1234567",
+        "  7..8          positive fixint  1",
+        "  8..17         float64  3.25",
+        "frame 1, bytes 17..26: truncated: float64 needs 8 bytes at byte 26, only 5 left",
+        "  17..18      fixarray  4 items",
+        "  18..19        positive fixint  14",
+        "  19..24        uint32  1234567",
+        "  24..25        positive fixint  1",
+        "",
+    ].join("\n"));
+    assert_eq!(explain_binary_frame(&[]), "empty\n");
+    assert_eq!(summaries(&[0x94]), ["frame 0, bytes 0..1: truncated: a type marker needs 1 bytes at byte 1, only 0 left"]);
+}
+
+#[test]
+fn malformed_frames() {
+    assert_eq!(explain_binary_frame(&[0x94, 0x01, 0xc1, 0x02]), [
+        "frame 0, bytes 0..3: invalid type marker c1 at byte 2",
+        "  0..1        fixarray  4 items",
+        "  1..2          positive fixint  1",
+        "",
+    ].join("\n"));
+    // Well formed msgpack that is not a frame, then garbage after it.
+    assert_eq!(summaries(&[0x93, 0x01, 0x02, 0x03, 0xc1]), [
+        "frame 0, bytes 0..4: not a data frame",
+        "frame 1, bytes 4..5: invalid type marker c1 at byte 4",
+    ]);
+    let mut bytes = frame();
+    bytes[7] = 0x7f;
+    assert_eq!(summaries(&bytes), ["frame 0, bytes 0..17: topic 14 @ 1234567 µs unknown type 127 3.25"]);
+}
+
+#[test]
+fn hostile_frames() {
+    // Lengths far beyond the input are reported rather than allocated.
+    assert_eq!(summaries(&[0xdb, 0xff, 0xff, 0xff, 0xff, b'a']), [
+        "frame 0, bytes 0..5: truncated: str32 needs 4294967295 bytes at byte 5, only 1 left",
+    ]);
+    assert_eq!(summaries(&[0xdd, 0xff, 0xff, 0xff, 0xff, 0x01]), [
+        "frame 0, bytes 0..6: truncated: a type marker needs 1 bytes at byte 6, only 0 left",
+    ]);
+    // Deep nesting stops at the limit instead of overflowing the stack.
+    let explained = explain_binary_frame(&[0x91; 100_000]);
+    assert!(explained.starts_with("frame 0, bytes 0..33: nested deeper than 32 at byte 32\n"), "{}", explained);
+    assert_eq!(explained.lines().count(), 34);
+}
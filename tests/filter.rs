@@ -0,0 +1,110 @@
+//! The topic filter: topics it drops are unannounced, topics a new filter re-admits are announced
+//! again through `topicsonly` subscriptions, and subscriptions that can only match dropped topics
+//! are warned about.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use common::Wire;
+use nt4_wasm::{ConnectionCore, ConnectionEvent, SubscriptionOptions, TopicFilter, TopicFilterMode};
+use serde_json::json;
+
+/// The method, subuid and `topicsonly` option of every frame sent since the last call.
+fn take_text(wire: &mut Wire) -> Vec<(String, i64, Option<bool>)> {
+    let frames = wire.take_json().into_iter().map(|x| {
+        let params = &x["params"];
+        (x["method"].as_str().unwrap().to_string(), params["subuid"].as_i64().unwrap(), params["options"]["topicsonly"].as_bool())
+    });
+    frames.collect()
+}
+
+/// Short names of the events emitted since the last call.
+fn take_events(wire: &mut Wire) -> Vec<String> {
+    let events = wire.take_events().into_iter().map(|x| match x {
+        ConnectionEvent::Announce { topic, .. } => format!("announce {}", topic.name),
+        ConnectionEvent::Unannounce { name, .. } => format!("unannounce {}", name),
+        ConnectionEvent::Warning(message) => message,
+        event => format!("{:?}", event),
+    });
+    events.collect()
+}
+
+fn announce(core: &mut ConnectionCore, wire: &mut Wire, name: &str, id: i32) {
+    common::announce(core, wire, name, id, "double", json!({}));
+}
+
+fn filter(mode: TopicFilterMode, prefixes: &[&str]) -> Option<TopicFilter> {
+    Some(TopicFilter { prefixes: prefixes.iter().map(|x| x.to_string()).collect(), mode })
+}
+
+#[test]
+fn readmitted_topics() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let options = SubscriptionOptions { periodic: Duration::from_millis(100), all: false, topicsonly: false, prefix: true };
+    core.subscribe(&mut wire, "/", options).unwrap();
+    take_text(&mut wire);
+    announce(&mut core, &mut wire, "/drive/speed", 1);
+    announce(&mut core, &mut wire, "/arm/angle", 2);
+    take_events(&mut wire);
+
+    // Already announced topics that the filter drops are unannounced, and nothing is resubscribed.
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Include, &["/drive"])).unwrap();
+    assert_eq!(take_events(&mut wire), ["unannounce /arm/angle"]);
+    assert!(take_text(&mut wire).is_empty());
+    announce(&mut core, &mut wire, "/arm/current", 3);
+    assert!(take_events(&mut wire).is_empty());
+    assert_eq!(core.filtered_topic_count(), 2);
+
+    // Dropping more re-admits nothing, so nothing is resubscribed either.
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Include, &["/drive/speed"])).unwrap();
+    assert!(take_text(&mut wire).is_empty());
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Include, &["/drive"])).unwrap();
+    assert!(take_text(&mut wire).is_empty());
+
+    // Re-admitting /arm repeats the subscription as topicsonly, once.
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Exclude, &["/arm/current"])).unwrap();
+    assert_eq!(take_text(&mut wire), [
+        ("subscribe".to_string(), 1, Some(true)),
+        ("unsubscribe".to_string(), 1, None),
+    ]);
+    announce(&mut core, &mut wire, "/arm/angle", 2);
+    announce(&mut core, &mut wire, "/arm/current", 3);
+    assert_eq!(take_events(&mut wire), ["announce /arm/angle"]);
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Exclude, &["/arm/current"])).unwrap();
+    assert!(take_text(&mut wire).is_empty());
+
+    core.set_topic_filter(&mut wire, None).unwrap();
+    assert_eq!(take_text(&mut wire), [
+        ("subscribe".to_string(), 2, Some(true)),
+        ("unsubscribe".to_string(), 2, None),
+    ]);
+    core.set_topic_filter(&mut wire, None).unwrap();
+    assert!(take_text(&mut wire).is_empty());
+}
+
+#[test]
+fn subscriptions_to_dropped_topics() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let options = |prefix| SubscriptionOptions { periodic: Duration::from_millis(100), all: false, topicsonly: false, prefix };
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Exclude, &["/Shuffleboard"])).unwrap();
+    core.subscribe(&mut wire, "/Shuffleboard/Auto", options(true)).unwrap();
+    core.subscribe(&mut wire, "/", options(true)).unwrap();
+    core.subscribe(&mut wire, "/Shuffleboard/Auto/selected", options(false)).unwrap();
+    assert_eq!(take_events(&mut wire), [
+        "subscription 0 to \"/Shuffleboard/Auto\" only matches topics dropped by the topic filter",
+        "subscription 2 to \"/Shuffleboard/Auto/selected\" only matches topics dropped by the topic filter",
+    ]);
+
+    // A new filter warns again about every subscription it leaves without topics.
+    core.set_topic_filter(&mut wire, filter(TopicFilterMode::Include, &["/SmartDashboard"])).unwrap();
+    assert_eq!(take_events(&mut wire), [
+        "subscription 0 to \"/Shuffleboard/Auto\" only matches topics dropped by the topic filter",
+        "subscription 2 to \"/Shuffleboard/Auto/selected\" only matches topics dropped by the topic filter",
+    ]);
+    core.set_topic_filter(&mut wire, None).unwrap();
+    assert!(take_events(&mut wire).is_empty());
+}
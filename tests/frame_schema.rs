@@ -0,0 +1,150 @@
+//! The checks of `check_client_frame` against frames broken the ways frames go wrong, and the
+//! connection's use of them.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::Wire;
+use nt4_wasm::{
+    check_client_frame, ConnectionCore, Nt4TypeId, PartialProperties, Properties, SubscriptionOptions,
+};
+use serde_json::{json, Value};
+
+fn check(frame: Value) -> Result<(), String> {
+    check_client_frame(&frame.to_string())
+}
+
+#[test]
+fn frames_sent_are_valid() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    core.set_debug_validation(true);
+    let properties = Properties { persistent: true, ..Default::default() };
+    let pubuid = core.publish(&mut wire, "/Arm/Setpoint", Nt4TypeId::DoubleArray, properties).unwrap();
+    let update = PartialProperties { retained: Some(false), ..Default::default() };
+    core.set_properties(&mut wire, "/Arm/Setpoint", update).unwrap();
+    let subuid = core.subscribe(&mut wire, "", SubscriptionOptions { prefix: true, ..Default::default() }).unwrap();
+    core.unsubscribe(&mut wire, subuid).unwrap();
+    core.unpublish(&mut wire, pubuid).unwrap();
+    core.set_pretty_text_frames(true);
+    core.subscribe(&mut wire, "/Arm", SubscriptionOptions::default()).unwrap();
+
+    let sent = wire.take_text();
+    assert_eq!(sent.len(), 6);
+    for frame in &sent {
+        assert_eq!(check_client_frame(frame), Ok(()), "{}", frame);
+    }
+    assert!(wire.take_warnings().is_empty());
+}
+
+#[test]
+fn broken_frames() {
+    let publish = |params: Value| json!({"method": "publish", "params": params});
+    let subscribe = |options: Value| {
+        json!({"method": "subscribe", "params": {"topics": ["/x"], "subuid": 1, "options": options}})
+    };
+    let cases = [
+        (json!({"method": "publish"}), "frame has no \"params\""),
+        (json!({"method": "publish", "params": {}, "id": 1}), "frame has an unknown key \"id\""),
+        (json!({"method": "Publish", "params": {}}), "method \"Publish\" is not an NT4 client method"),
+        (json!({"method": 1, "params": {}}), "method must be a string"),
+        (json!({"method": "announce", "params": {}}), "method \"announce\" is not an NT4 client method"),
+        (
+            publish(json!({"name": "/x", "pubUid": 1, "type": "double", "properties": {}})),
+            "publish frame: params has no \"pubuid\"",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1, "ty": "double", "type": "double", "properties": {}})),
+            "publish frame: params has an unknown key \"ty\"",
+        ),
+        (
+            publish(json!({"name": "", "pubuid": 1, "type": "double", "properties": {}})),
+            "publish frame: name must be a non-empty string",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1, "type": "Double", "properties": {}})),
+            "publish frame: type must be an NT4 type name, not \"Double\"",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1.5, "type": "double", "properties": {}})),
+            "publish frame: pubuid must be a 32-bit integer, not 1.5",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1_u64 << 40, "type": "double", "properties": {}})),
+            "publish frame: pubuid must be a 32-bit integer, not 1099511627776",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1, "type": "double", "properties": {"retained": "yes"}})),
+            "publish frame: properties.retained must be a boolean, not \"yes\"",
+        ),
+        (
+            publish(json!({"name": "/x", "pubuid": 1, "type": "double", "properties": {"persistent": null}})),
+            "publish frame: properties.persistent must be a boolean, not null",
+        ),
+        (
+            json!({"method": "setproperties", "params": {"name": "/x", "update": {"cached": 0}}}),
+            "setproperties frame: update.cached must be a boolean, not 0",
+        ),
+        (
+            json!({"method": "setproperties", "params": {"name": "/x", "update": []}}),
+            "setproperties frame: update must be an object",
+        ),
+        (json!({"method": "unpublish", "params": {"subuid": 1}}), "unpublish frame: params has no \"pubuid\""),
+        (json!({"method": "unsubscribe", "params": []}), "unsubscribe frame: params must be an object"),
+        (
+            json!({"method": "subscribe", "params": {"topics": "/x", "subuid": 1}}),
+            "subscribe frame: topics must be an array",
+        ),
+        (
+            json!({"method": "subscribe", "params": {"topics": ["/x", 2], "subuid": 1}}),
+            "subscribe frame: topics must be strings, not 2",
+        ),
+        (
+            subscribe(json!({"topicsOnly": true})),
+            "subscribe frame: options has an unknown key \"topicsOnly\", custom ones start with \"x-\"",
+        ),
+        (subscribe(json!({"periodic": -0.1})), "subscribe frame: options.periodic cannot be -0.1"),
+        (subscribe(json!({"all": "true"})), "subscribe frame: options.all cannot be \"true\""),
+        (
+            json!({"method": "values", "params": [[1, -5, 1, 0.5]]}),
+            "values frame: params[0] must be [id, timestamp, type, value], not [1,-5,1,0.5]",
+        ),
+    ];
+    for (frame, expected) in cases {
+        assert_eq!(check(frame.clone()), Err(expected.to_string()), "{}", frame);
+    }
+    let eof = "frame is not JSON: EOF while parsing a value at line 1 column 11";
+    assert_eq!(check_client_frame("{\"method\": "), Err(eof.to_string()));
+
+    // What NT4 leaves open is fine.
+    assert_eq!(check(subscribe(json!({"periodic": 0.02, "x-priority": [1, 2]}))), Ok(()));
+    assert_eq!(check(json!({"method": "subscribe", "params": {"topics": [""], "subuid": 1}})), Ok(()));
+    let properties = json!({"persistent": true, "units": "m", "nested": {"retained": 5}});
+    assert_eq!(check(publish(json!({"name": "/x", "pubuid": 1, "type": "json", "properties": properties}))), Ok(()));
+    let update = json!({"persistent": null, "units": null});
+    assert_eq!(check(json!({"method": "setproperties", "params": {"name": "/x", "update": update}})), Ok(()));
+    assert_eq!(check(json!({"method": "values", "params": [[1, 5, 1, 0.5], [2, 0, 4, "on"]]})), Ok(()));
+}
+
+#[test]
+fn debug_validation() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+
+    // Without it, debug builds warn and send the frame anyway.
+    core.publish(&mut wire, "", Nt4TypeId::Double, Properties::default()).unwrap();
+    assert_eq!(wire.take_text().len(), 1);
+    let warnings = wire.take_warnings();
+    if cfg!(debug_assertions) {
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("invalid text frame, publish frame: name must be a non-empty string: {"));
+    } else {
+        assert!(warnings.is_empty());
+    }
+
+    // With it, the frame is not sent.
+    core.set_debug_validation(true);
+    let error = core.publish(&mut wire, "", Nt4TypeId::Double, Properties::default()).unwrap_err();
+    assert!(error.starts_with("invalid text frame, publish frame: name must be a non-empty string"), "{}", error);
+    assert!(wire.take_text().is_empty());
+}
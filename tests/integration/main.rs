@@ -0,0 +1,2460 @@
+//! The full client flow against mock JS callbacks. Run with `wasm-pack test --headless --firefox`.
+#![cfg(target_arch = "wasm32")]
+
+use std::{cell::RefCell, rc::Rc};
+
+use js_sys::Function;
+use nt4_wasm::{
+    decode_share_state, encode_share_state, Nt4Connection, Nt4Multiplexer, Nt4Scenario, Nt4TypeId, Topic, TIMESYNC_TOPIC_ID,
+};
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+type Callback = dyn FnMut(JsValue, JsValue, JsValue, JsValue);
+
+/// A JS callback that records the arguments of every call.
+struct Mock {
+    calls: Rc<RefCell<Vec<[JsValue; 4]>>>,
+    closure: Closure<Callback>,
+}
+
+impl Mock {
+    fn new() -> Self {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        let closure = Closure::<Callback>::new(move |a, b, c, d| recorded.borrow_mut().push([a, b, c, d]));
+        Self { calls, closure }
+    }
+
+    fn function(&self) -> Function {
+        self.closure.as_ref().unchecked_ref::<Function>().clone()
+    }
+
+    fn take(&self) -> Vec<[JsValue; 4]> {
+        self.calls.borrow_mut().drain(..).collect()
+    }
+
+    /// The first argument of the only call since the last `take`.
+    fn take_one(&self) -> JsValue {
+        let calls = self.take();
+        assert_eq!(calls.len(), 1, "expected exactly one call");
+        calls[0][0].clone()
+    }
+
+    /// The data argument of the only `on_data_fn` call since the last `take`.
+    fn take_one_data(&self) -> JsValue {
+        let calls = self.take();
+        assert_eq!(calls.len(), 1, "expected exactly one call");
+        calls[0][2].clone()
+    }
+}
+
+fn sent_text(mock: &Mock) -> serde_json::Value {
+    serde_json::from_str(&mock.take_one().as_string().unwrap()).unwrap()
+}
+
+fn sent_binary(mock: &Mock) -> Vec<u8> {
+    serde_wasm_bindgen::from_value(mock.take_one()).unwrap()
+}
+
+fn js(json: &str) -> JsValue {
+    js_sys::JSON::parse(json).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn round_trip() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let announce = Mock::new();
+    let unannounce = Mock::new();
+    let ready = Mock::new();
+    let unready = Mock::new();
+    let on_data = Mock::new();
+    let type_changed = Mock::new();
+
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_announce_fn(announce.function());
+    conn.set_unannounce_fn(unannounce.function());
+    conn.set_ready_fn(ready.function());
+    conn.set_unready_fn(unready.function());
+    conn.set_on_data_fn(on_data.function());
+    conn.set_type_changed_fn(type_changed.function());
+
+    // Timesync request: [TIMESYNC_TOPIC_ID, 0, int, local time].
+    conn.timesync().unwrap();
+    let (id, _, ty, local_time): (i32, i64, u8, i64) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    assert_eq!((id, ty), (TIMESYNC_TOPIC_ID, 2));
+
+    // The server answers with its own time and echoes ours.
+    let server_time = 5_000_000_i64;
+    conn.on_binary(rmp_serde::to_vec(&(-1_i32, server_time, 2_u8, local_time)).unwrap()).unwrap();
+    assert_eq!(ready.take().len(), 1);
+    assert!(conn.is_ready());
+
+    let pubuid = conn.publish("/test/value", JsValue::from_str("double"), js("{}")).unwrap();
+    assert_eq!(
+        sent_text(&send_text),
+        json!({
+            "method": "publish",
+            "params": {
+                "name": "/test/value",
+                "pubuid": pubuid,
+                "type": "double",
+                "properties": {"persistent": false, "retained": false},
+            },
+        })
+    );
+
+    let subuid = conn
+        .subscribe("/test", js(r#"{"periodic": 0.5, "all": false, "topicsonly": false, "prefix": true}"#))
+        .unwrap();
+    assert_ne!(subuid, pubuid);
+    assert_eq!(
+        sent_text(&send_text),
+        json!({
+            "method": "subscribe",
+            "params": {
+                "topics": ["/test"],
+                "subuid": subuid,
+                "options": {"periodic": 0.5, "all": false, "topicsonly": false, "prefix": true},
+            },
+        })
+    );
+
+    conn.on_text(
+        json!({
+            "method": "announce",
+            "params": {"name": "/test/value", "id": 7, "type": "double", "pubuid": pubuid, "properties": {}},
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let topic: Topic = serde_wasm_bindgen::from_value(announce.take_one()).unwrap();
+    assert_eq!(&*topic.name, "/test/value");
+    assert_eq!(topic.ty, Nt4TypeId::Double);
+
+    // Outgoing values are stamped on the server clock.
+    conn.send_data(pubuid, JsValue::from_f64(2.5), JsValue::UNDEFINED).unwrap();
+    let (id, timestamp, ty, value): (i32, i64, u8, f64) =
+        rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    assert_eq!((id, ty, value), (pubuid, 1, 2.5));
+    assert!(timestamp >= server_time);
+
+    assert!(unannounce.take().is_empty());
+    assert!(unready.take().is_empty());
+    assert!(on_data.take().is_empty());
+    assert!(type_changed.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn timestamp_modes() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    let pubuid = conn.publish("/replay", JsValue::from_str("double"), js("{}")).unwrap();
+    send_text.take();
+    let value = || JsValue::from_f64(2.5);
+    // [pubuid, <timestamp>, double, 2.5]
+    let frame = |timestamp: &[u8]| {
+        let mut frame = vec![0x94, pubuid as u8];
+        frame.extend_from_slice(timestamp);
+        frame.extend_from_slice(&[0x01, 0xcb, 0x40, 0x04, 0, 0, 0, 0, 0, 0]);
+        frame
+    };
+
+    // Live stamps on its own and rejects given timestamps.
+    assert!(conn.send_data(pubuid, value(), JsValue::from_f64(1_234_567.0)).is_err());
+    conn.send_data(pubuid, value(), JsValue::UNDEFINED).unwrap();
+    send_binary.take();
+
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    assert!(conn.send_data(pubuid, value(), JsValue::UNDEFINED).is_err());
+    conn.send_data(pubuid, value(), JsValue::from_f64(1_234_567.0)).unwrap();
+    assert_eq!(sent_binary(&send_binary), frame(&[0xce, 0x00, 0x12, 0xd6, 0x87]));
+
+    conn.set_timestamp_mode(JsValue::from_str("fixed_offset")).unwrap();
+    conn.set_timestamp_offset_us(JsValue::from_f64(1_000.0)).unwrap();
+    conn.send_data(pubuid, value(), JsValue::from_f64(1_234_567.0)).unwrap();
+    assert_eq!(sent_binary(&send_binary), frame(&[0xce, 0x00, 0x12, 0xda, 0x6f]));
+    assert!(conn.send_data(pubuid, value(), JsValue::UNDEFINED).is_err());
+
+    assert!(conn.set_timestamp_mode(JsValue::from_str("replay")).is_err());
+}
+
+fn announce(conn: &mut Nt4Connection, name: &str, id: i32, ty: &str, properties: serde_json::Value) {
+    conn.on_text(
+        json!({
+            "method": "announce",
+            "params": {"name": name, "id": id, "type": ty, "properties": properties},
+        })
+        .to_string(),
+    )
+    .unwrap();
+}
+
+/// Lets a test announce topics without checking the announce callback, which the connection requires.
+fn ignore_announces(conn: &mut Nt4Connection) {
+    conn.set_announce_fn(Function::new_no_args(""));
+}
+
+fn topic_count(conn: &mut Nt4Connection) -> usize {
+    let snapshot: serde_json::Value = serde_json::from_str(&conn.snapshot_json().unwrap()).unwrap();
+    snapshot["topics"].as_object().unwrap().len()
+}
+
+#[wasm_bindgen_test]
+fn duplicate_announces() {
+    let announce_fn = Mock::new();
+    let unannounce_fn = Mock::new();
+    let type_changed = Mock::new();
+    let properties_changed = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_announce_fn(announce_fn.function());
+    conn.set_unannounce_fn(unannounce_fn.function());
+    conn.set_type_changed_fn(type_changed.function());
+    conn.set_properties_changed_fn(properties_changed.function());
+
+    announce(&mut conn, "/a", 1, "double", json!({}));
+    assert_eq!(announce_fn.take().len(), 1);
+
+    // The same name and id again is an update, not a new topic.
+    announce(&mut conn, "/a", 1, "double", json!({}));
+    assert!(announce_fn.take().is_empty());
+    assert!(properties_changed.take().is_empty());
+    announce(&mut conn, "/a", 1, "double", json!({"retained": true}));
+    let calls = properties_changed.take();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0][0].as_string().unwrap(), "/a");
+    assert_eq!(
+        serde_wasm_bindgen::from_value::<serde_json::Value>(calls[0][1].clone()).unwrap(),
+        json!({"persistent": false, "retained": true})
+    );
+    announce(&mut conn, "/a", 1, "int", json!({"retained": true}));
+    assert_eq!(type_changed.take().len(), 1);
+    assert!(announce_fn.take().is_empty());
+    assert!(unannounce_fn.take().is_empty());
+    assert_eq!(topic_count(&mut conn), 1);
+
+    // Unless asked to fire again.
+    conn.set_fire_duplicate_announces(true);
+    announce(&mut conn, "/a", 1, "int", json!({"retained": true}));
+    assert_eq!(announce_fn.take().len(), 1);
+    conn.set_fire_duplicate_announces(false);
+
+    // The same name under a new id replaces the old id.
+    announce(&mut conn, "/a", 2, "int", json!({"retained": true}));
+    assert_eq!(unannounce_fn.take_one().as_string().unwrap(), "/a");
+    let topic: Topic = serde_wasm_bindgen::from_value(announce_fn.take_one()).unwrap();
+    assert_eq!(&*topic.name, "/a");
+    assert_eq!(topic_count(&mut conn), 1);
+    assert!(properties_changed.take().is_empty());
+    assert!(type_changed.take().is_empty());
+}
+
+fn error_kind(error: JsValue) -> String {
+    js_sys::Reflect::get(&error, &JsValue::from_str("kind")).unwrap().as_string().unwrap()
+}
+
+#[wasm_bindgen_test]
+fn unknown_uids() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+
+    // Uids that were never allocated.
+    assert_eq!(error_kind(conn.unsubscribe(999).unwrap_err()), "UnknownUid");
+    assert_eq!(error_kind(conn.unpublish(42).unwrap_err()), "UnknownUid");
+    assert_eq!(error_kind(conn.send_data(42, JsValue::from_f64(1.0), JsValue::UNDEFINED).unwrap_err()), "UnknownUid");
+    assert!(send_text.take().is_empty());
+    assert!(send_binary.take().is_empty());
+
+    let subuid = conn.subscribe("/a", js("{}")).unwrap();
+    let pubuid = conn.publish("/b", JsValue::from_str("double"), js("{}")).unwrap();
+    assert!(conn.is_active_subscription(subuid));
+    assert!(conn.is_active_publication(pubuid));
+    assert!(!conn.is_active_subscription(pubuid));
+    send_text.take();
+
+    // Releasing twice.
+    conn.unsubscribe(subuid).unwrap();
+    conn.unpublish(pubuid).unwrap();
+    assert_eq!(send_text.take().len(), 2);
+    assert!(!conn.is_active_subscription(subuid));
+    assert!(!conn.is_active_publication(pubuid));
+    assert_eq!(error_kind(conn.unsubscribe(subuid).unwrap_err()), "UnknownUid");
+    assert_eq!(error_kind(conn.unpublish(pubuid).unwrap_err()), "UnknownUid");
+    assert_eq!(error_kind(conn.send_data(pubuid, JsValue::from_f64(1.0), JsValue::UNDEFINED).unwrap_err()), "UnknownUid");
+
+    conn.set_idempotent(true);
+    conn.unsubscribe(subuid).unwrap();
+    conn.unpublish(pubuid).unwrap();
+    assert!(send_text.take().is_empty());
+    assert!(send_binary.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn exact_timestamps() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    let pubuid = conn.publish("/replay", JsValue::from_str("double"), js("{}")).unwrap();
+    let value = || JsValue::from_f64(2.5);
+    // [pubuid, <uint64 timestamp>, double, 2.5]
+    let frame = |timestamp: u64| {
+        let mut frame = vec![0x94, pubuid as u8, 0xcf];
+        frame.extend_from_slice(&timestamp.to_be_bytes());
+        frame.extend_from_slice(&[0x01, 0xcb, 0x40, 0x04, 0, 0, 0, 0, 0, 0]);
+        frame
+    };
+
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    conn.send_data(pubuid, value(), JsValue::from(i64::MAX)).unwrap();
+    assert_eq!(sent_binary(&send_binary), frame(i64::MAX as u64));
+    // 2^53 + 1 cannot be a number, so only a BigInt gets it across exactly.
+    conn.send_data(pubuid, value(), JsValue::from(9_007_199_254_740_993_i64)).unwrap();
+    assert_eq!(sent_binary(&send_binary), frame(9_007_199_254_740_993));
+    assert!(conn.send_data(pubuid, value(), JsValue::from_f64(9_007_199_254_740_992.0)).is_err());
+    assert!(send_binary.take().is_empty());
+
+    conn.set_timestamp_mode(JsValue::from_str("fixed_offset")).unwrap();
+    conn.set_timestamp_offset_us(JsValue::from(-1_i64)).unwrap();
+    conn.send_data(pubuid, value(), JsValue::from(i64::MAX)).unwrap();
+    assert_eq!(sent_binary(&send_binary), frame(i64::MAX as u64 - 1));
+    // Overflowing the offset is an error rather than a wrapped timestamp.
+    conn.set_timestamp_offset_us(JsValue::from(1_i64)).unwrap();
+    assert!(conn.send_data(pubuid, value(), JsValue::from(i64::MAX)).is_err());
+    assert!(send_binary.take().is_empty());
+}
+
+fn properties_update(conn: &mut Nt4Connection, name: &str, update: serde_json::Value, ack: bool) {
+    conn.on_text(
+        json!({
+            "method": "properties",
+            "params": {"name": name, "update": update, "ack": ack},
+        })
+        .to_string(),
+    )
+    .unwrap();
+}
+
+fn topic_properties(conn: &Nt4Connection, name: &str) -> serde_json::Value {
+    serde_wasm_bindgen::from_value(conn.get_topic_properties(name).unwrap()).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn update_topic_property() {
+    let send_text = Mock::new();
+    let properties_changed = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_properties_changed_fn(properties_changed.function());
+
+    // Keys this library does not know about survive.
+    announce(&mut conn, "/a", 1, "double", json!({"retained": true, "cached": false, "unit": "m"}));
+    let read = topic_properties(&conn, "/a");
+    assert_eq!(read, json!({"persistent": false, "retained": true, "cached": false, "unit": "m"}));
+
+    // Someone else changes a key between our read and our write...
+    properties_update(&mut conn, "/a", json!({"description": "height"}), false);
+    assert_eq!(properties_changed.take().len(), 1);
+
+    // ...which our update does not send back.
+    conn.update_topic_property("/a", "unit", JsValue::from_str("cm")).unwrap();
+    assert_eq!(
+        sent_text(&send_text),
+        json!({"method": "setproperties", "params": {"name": "/a", "update": {"unit": "cm"}}})
+    );
+    let expected = json!({"persistent": false, "retained": true, "cached": false, "unit": "cm", "description": "height"});
+    assert_eq!(topic_properties(&conn, "/a"), expected);
+
+    // Our own update coming back changes nothing.
+    properties_update(&mut conn, "/a", json!({"unit": "cm"}), true);
+    assert!(properties_changed.take().is_empty());
+    assert_eq!(topic_properties(&conn, "/a"), expected);
+
+    // Someone else changing the same key after us wins, as it does on the server.
+    conn.update_topic_property("/a", "cached", JsValue::TRUE).unwrap();
+    properties_update(&mut conn, "/a", json!({"cached": true}), true);
+    properties_update(&mut conn, "/a", json!({"cached": false}), false);
+    assert_eq!(topic_properties(&conn, "/a")["cached"], json!(false));
+    assert_eq!(properties_changed.take().len(), 1);
+    send_text.take();
+
+    // null deletes.
+    conn.update_topic_property("/a", "description", JsValue::NULL).unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["update"], json!({"description": null}));
+    assert!(topic_properties(&conn, "/a").get("description").is_none());
+    conn.update_topic_property("/a", "retained", JsValue::NULL).unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["update"], json!({"retained": false}));
+    assert!(conn.update_topic_property("/a", "retained", JsValue::from_str("yes")).is_err());
+    assert!(send_text.take().is_empty());
+    assert!(conn.get_topic_properties("/b").unwrap().is_undefined());
+}
+
+#[wasm_bindgen_test]
+fn unsigned_timestamps() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    let pubuid = conn.publish("/replay", JsValue::from_str("boolean"), js("{}")).unwrap();
+
+    // Always the smallest unsigned format, never a signed one.
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    for (timestamp, encoded) in [
+        (0_i64, vec![0x00]),
+        (127, vec![0x7f]),
+        (128, vec![0xcc, 0x80]),
+        (256, vec![0xcd, 0x01, 0x00]),
+        (65_536, vec![0xce, 0x00, 0x01, 0x00, 0x00]),
+        (4_294_967_296, vec![0xcf, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]),
+    ] {
+        conn.send_data(pubuid, JsValue::TRUE, JsValue::from_f64(timestamp as f64)).unwrap();
+        let frame = sent_binary(&send_binary);
+        assert_eq!(frame[..2], [0x94, pubuid as u8]);
+        assert_eq!(frame[2..frame.len() - 2], encoded[..], "timestamp {}", timestamp);
+    }
+    assert!(conn.send_data(pubuid, JsValue::TRUE, JsValue::from_f64(-1.0)).is_err());
+    conn.set_timestamp_mode(JsValue::from_str("fixed_offset")).unwrap();
+    conn.set_timestamp_offset_us(JsValue::from_f64(-10.0)).unwrap();
+    assert!(conn.send_data(pubuid, JsValue::TRUE, JsValue::from_f64(5.0)).is_err());
+    assert!(send_binary.take().is_empty());
+
+    // Incoming timestamps may be either.
+    conn.subscribe("/x", js("{}")).unwrap();
+    announce(&mut conn, "/x", 5, "int", json!({}));
+    for (frame, timestamp) in [
+        (vec![0x94, 5, 0xcd, 0x01, 0x00, 2, 1], 256_i64),
+        (vec![0x94, 5, 0xd3, 0, 0, 0, 0, 0, 0, 0, 200, 2, 1], 200),
+        (vec![0x94, 5, 0xd1, 0x01, 0x00, 2, 1], 256),
+    ] {
+        conn.on_binary(frame).unwrap();
+        let calls = on_data.take();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][1], JsValue::from(timestamp));
+    }
+}
+
+#[wasm_bindgen_test]
+fn reserved_topic_ids() {
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let warning = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    conn.set_warning_fn(warning.function());
+    conn.subscribe("", js(r#"{"prefix": true}"#)).unwrap();
+
+    // Even announced, a reserved id other than timesync's never reaches on_data_fn.
+    announce(&mut conn, "/bad", -2, "int", json!({}));
+    for _ in 0..3 {
+        conn.on_binary(vec![0x94, 0xfe, 0x00, 2, 1]).unwrap();
+    }
+    assert!(on_data.take().is_empty());
+    assert!(warning.take_one().as_string().unwrap().contains("-2"));
+
+    announce(&mut conn, "/good", 2, "int", json!({}));
+    conn.on_binary(vec![0x94, 0x02, 0x00, 2, 1]).unwrap();
+    assert_eq!(on_data.take().len(), 1);
+}
+
+fn error_message(error: JsValue) -> String {
+    error.as_string().unwrap()
+}
+
+#[wasm_bindgen_test]
+fn publish_properties() {
+    let send_text = Mock::new();
+    let warning = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_warning_fn(warning.function());
+    let double = || JsValue::from_str("double");
+
+    // Errors name the key.
+    let error = error_message(conn.publish("/a", double(), js(r#"{"persistent": "yes"}"#)).unwrap_err());
+    assert!(error.contains("\"persistent\""), "{}", error);
+    let cyclic = js(r#"{"unit": "m", "source": {"nested": {}}}"#);
+    let nested = js_sys::Reflect::get(&js_sys::Reflect::get(&cyclic, &"source".into()).unwrap(), &"nested".into()).unwrap();
+    js_sys::Reflect::set(&nested, &"parent".into(), &cyclic).unwrap();
+    let error = error_message(conn.publish("/a", double(), cyclic).unwrap_err());
+    assert!(error.contains("\"source.nested.parent\""), "{}", error);
+    assert!(send_text.take().is_empty());
+
+    // Functions and undefined are skipped, everything JSON passes through.
+    let properties = js(r#"{"retained": true, "unit": "m", "limits": [0, 1.5, {"hard": true}], "note": null}"#);
+    js_sys::Reflect::set(&properties, &"callback".into(), &js_sys::Function::new_no_args("")).unwrap();
+    js_sys::Reflect::set(&properties, &"missing".into(), &JsValue::UNDEFINED).unwrap();
+    conn.publish("/a", double(), properties).unwrap();
+    assert_eq!(
+        sent_text(&send_text)["params"]["properties"],
+        json!({"persistent": false, "retained": true, "unit": "m", "limits": [0, 1.5, {"hard": true}], "note": null})
+    );
+    let warnings = warning.take();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0][0].as_string().unwrap().contains("\"callback\""));
+
+    // Oversized properties never reach the wire.
+    conn.set_max_properties_bytes(64);
+    let error = error_message(conn.publish("/b", double(), js(&json!({"note": "x".repeat(64)}).to_string())).unwrap_err());
+    assert!(error.contains("over the limit of 64"), "{}", error);
+    let error = error_message(conn.set_properties("/a", js(&json!({"note": "x".repeat(64)}).to_string())).unwrap_err());
+    assert!(error.contains("over the limit of 64"), "{}", error);
+    assert!(send_text.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn subscription_profiles() {
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+
+    let plot = conn.subscribe_with_profile("/a", "plot").unwrap();
+    assert_eq!(
+        sent_text(&send_text)["params"],
+        json!({"topics": ["/a"], "subuid": plot, "options": {"periodic": 0.02, "all": true, "topicsonly": false, "prefix": false}})
+    );
+    let display = conn.subscribe_with_profile("/b", "display").unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["options"]["periodic"], json!(0.1));
+    let browse = conn.subscribe_with_profile("/c", "browse").unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["options"]["topicsonly"], json!(true));
+    assert!(conn.subscribe_with_profile("/d", "missing").is_err());
+    assert!(send_text.take().is_empty());
+
+    // Only subscriptions made from the profile are updated, in place.
+    let second = conn.subscribe_with_profile("/e", "plot").unwrap();
+    send_text.take();
+    let options = js(r#"{"periodic": 0.05, "all": true, "topicsonly": false, "prefix": false}"#);
+    assert_eq!(conn.update_all_with_profile("plot", options).unwrap(), 2);
+    let sent: Vec<serde_json::Value> = send_text
+        .take()
+        .into_iter()
+        .map(|x| serde_json::from_str(&x[0].as_string().unwrap()).unwrap())
+        .collect();
+    assert_eq!(sent.len(), 2);
+    assert_eq!((sent[0]["method"].clone(), sent[0]["params"]["subuid"].clone()), (json!("subscribe"), json!(plot)));
+    assert_eq!(sent[1]["params"]["subuid"], json!(second));
+    assert!(sent.iter().all(|x| x["params"]["options"]["periodic"] == json!(0.05)));
+
+    // Unsubscribed ones are forgotten.
+    conn.unsubscribe(second).unwrap();
+    send_text.take();
+    assert_eq!(conn.update_all_with_profile("plot", js(r#"{"periodic": 0.02}"#)).unwrap(), 1);
+    assert_eq!(conn.update_all_with_profile("display", js(r#"{"periodic": 1}"#)).unwrap(), 1);
+    assert_ne!(display, browse);
+
+    // Profiles round-trip through export and import.
+    conn.register_subscription_profile("slow", js(r#"{"periodic": 1, "all": false, "topicsonly": false, "prefix": true}"#))
+        .unwrap();
+    let exported = conn.export_subscription_profiles().unwrap();
+    let mut restored = Nt4Connection::new();
+    restored.set_send_text_fn(send_text.function()).unwrap();
+    restored.import_subscription_profiles(&exported).unwrap();
+    send_text.take();
+    restored.subscribe_with_profile("/f", "slow").unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["options"]["prefix"], json!(true));
+}
+
+#[wasm_bindgen_test]
+fn json_value_encoding() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    conn.set_value_encoding(JsValue::from_str("json")).unwrap();
+    let pubuid = conn.publish("/speed", JsValue::from_str("double"), js("{}")).unwrap();
+    send_text.take();
+
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    conn.send_data(pubuid, JsValue::from_f64(1.5), JsValue::from_f64(100.0)).unwrap();
+    assert_eq!(sent_text(&send_text), json!({"method": "values", "params": [[pubuid, 100, 1, 1.5]]}));
+    assert!(conn.send_data(pubuid, JsValue::from_f64(f64::NAN), JsValue::from_f64(200.0)).is_err());
+    conn.timesync().unwrap();
+    assert_eq!(sent_text(&send_text)["params"][0][0], json!(TIMESYNC_TOPIC_ID));
+    assert!(send_binary.take().is_empty());
+
+    // Values are accepted in either encoding.
+    conn.subscribe("/x", js("{}")).unwrap();
+    announce(&mut conn, "/x", 5, "int", json!({}));
+    conn.on_text(json!({"method": "values", "params": [[5, 300, 2, 7], [5, 400, 2, 8]]}).to_string()).unwrap();
+    conn.on_binary(vec![0x94, 5, 0xcd, 0x01, 0xf4, 2, 9]).unwrap();
+    let timestamps: Vec<JsValue> = on_data.take().into_iter().map(|x| x[1].clone()).collect();
+    assert_eq!(timestamps, [JsValue::from(300_i64), JsValue::from(400_i64), JsValue::from(500_i64)]);
+}
+
+#[wasm_bindgen_test]
+fn subscribe_group() {
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let group = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    let id = conn.subscribe_group(vec!["/pose".into(), "/vision".into()], 5_000.0, group.function(), None).unwrap();
+    let sent = sent_text(&send_text);
+    assert_eq!(sent["params"]["topics"], json!(["/pose", "/vision"]));
+    assert_eq!(sent["params"]["options"]["all"], json!(true));
+    announce(&mut conn, "/pose", 1, "int", json!({}));
+    announce(&mut conn, "/vision", 2, "int", json!({}));
+
+    // /pose every 10 ms, /vision every 50 ms, 2 ms after a /pose value.
+    for timestamp in (0..=100_000_u32).step_by(2_000) {
+        let topic_id = match timestamp % 50_000 {
+            2_000 => 2,
+            x if x % 10_000 == 0 => 1,
+            _ => continue,
+        };
+        let mut frame = vec![0x94, topic_id, 0xce];
+        frame.extend(timestamp.to_be_bytes());
+        frame.extend([2, 0]);
+        conn.on_binary(frame).unwrap();
+    }
+    let sets: Vec<(Vec<i64>, bool)> = group
+        .take()
+        .into_iter()
+        .map(|call| {
+            let members = js_sys::Array::from(&call[0]);
+            let timestamps = members
+                .iter()
+                .map(|x| js_sys::Reflect::get(&x, &"timestamp".into()).unwrap().try_into().unwrap())
+                .collect();
+            (timestamps, call[1].as_bool().unwrap())
+        })
+        .collect();
+    // Each /vision value with the /pose value nearest to it, even though /pose is five times as fast.
+    assert_eq!(sets, [(vec![0, 2_000], true), (vec![50_000, 52_000], true)]);
+    assert!(!on_data.take().is_empty());
+
+    conn.unsubscribe(id).unwrap();
+    assert_eq!(sent_text(&send_text)["method"], json!("unsubscribe"));
+}
+
+#[wasm_bindgen_test]
+fn retention_sweep() {
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    conn.subscribe("/", js(r#"{"prefix": true}"#)).unwrap();
+    for id in 1..=300_u16 {
+        let properties = if id == 1 { json!({"retained": true}) } else { json!({}) };
+        announce(&mut conn, &format!("/t{}", id), id as i32, "int", properties);
+        let mut frame = vec![0x94, 0xcd];
+        frame.extend(id.to_be_bytes());
+        frame.extend([1, 2, 0]);
+        conn.on_binary(frame).unwrap();
+    }
+    let stats = |conn: &Nt4Connection| -> serde_json::Value {
+        serde_wasm_bindgen::from_value(conn.retention_stats().unwrap()).unwrap()
+    };
+
+    // Nothing is evicted by default.
+    assert_eq!(conn.sweep(Some(1e12)).unwrap(), 0.0);
+    conn.configure_retention(js(r#"{"cache_max_idle_s": 60}"#)).unwrap();
+
+    // Idle values go, a bounded number per sweep, except those of retained topics.
+    assert_eq!(conn.sweep(Some(1e12)).unwrap() + conn.sweep(Some(1e12)).unwrap(), 299.0);
+    assert_eq!(stats(&conn), json!({"sweeps": 3, "cache_evicted": 299, "history_trimmed": 0}));
+}
+
+#[wasm_bindgen_test]
+fn search_topics() {
+    let unannounce = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(unannounce.function());
+    announce(&mut conn, "/Drive/Pose", 1, "double[]", json!({}));
+    announce(&mut conn, "/Vision/Pose", 2, "double[]", json!({}));
+    announce(&mut conn, "/Arm/PositionSetpoint", 3, "double", json!({}));
+    announce(&mut conn, "/Émile/Pose", 4, "boolean", json!({}));
+    let search = |conn: &Nt4Connection, query: &str, only: JsValue| -> serde_json::Value {
+        serde_wasm_bindgen::from_value(conn.search_topics(query, 3, only, None).unwrap()).unwrap()
+    };
+
+    // Ties go to the shorter name, then alphabetically.
+    let found = search(&conn, "pos", JsValue::UNDEFINED);
+    let names: Vec<&str> = found.as_array().unwrap().iter().map(|x| x["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["/Drive/Pose", "/Vision/Pose", "/Émile/Pose"]);
+    assert_eq!(found[0]["type"], json!("double[]"));
+
+    // Ranges count UTF-16 code units, as JS strings do.
+    let found = search(&conn, "pos", JsValue::from_str("boolean"));
+    assert_eq!(found.as_array().unwrap().len(), 1);
+    assert_eq!(found[0]["match_ranges"], json!([[7, 10]]));
+
+    let found = search(&conn, "APS", JsValue::from_str("double"));
+    assert_eq!(found[0]["name"], json!("/Arm/PositionSetpoint"));
+    assert_eq!(found[0]["match_ranges"], json!([[1, 2], [5, 6], [7, 8]]));
+
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/Vision/Pose", "id": 2}}).to_string()).unwrap();
+    assert_eq!(search(&conn, "vis", JsValue::UNDEFINED), json!([]));
+}
+
+#[wasm_bindgen_test]
+fn persistent_backup() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    assert!(conn.import_persistent(js(r#"{"version": 2, "entries": []}"#)).is_err());
+
+    let backup = js(r#"{"version": 1, "entries": [
+        {"name": "/a", "type": "double", "properties": {"persistent": true}, "value": 2.5},
+        {"name": "/b", "type": "boolean", "properties": {"persistent": true}, "value": 1}
+    ]}"#);
+    let _ = conn.import_persistent(backup).unwrap();
+    let published: Vec<serde_json::Value> = send_text
+        .take()
+        .into_iter()
+        .map(|x| serde_json::from_str(&x[0].as_string().unwrap()).unwrap())
+        .filter(|x: &serde_json::Value| x["method"] == "publish")
+        .collect();
+    // The invalid boolean is never published.
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0]["params"]["name"], json!("/a"));
+    assert_eq!(send_binary.take().len(), 1);
+    assert!(conn.export_persistent().is_err());
+
+    announce(&mut conn, "/a", 1, "double", json!({"persistent": true}));
+    assert_eq!(sent_text(&send_text), json!({"method": "unpublish", "params": {"pubuid": published[0]["params"]["pubuid"]}}));
+    // The import is over, so an export may start.
+    let _ = conn.export_persistent().unwrap();
+    assert_eq!(sent_text(&send_text)["params"]["options"]["topicsonly"], json!(true));
+}
+
+#[wasm_bindgen_test]
+fn pause_delivery() {
+    let announce_fn = Mock::new();
+    let unannounce_fn = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_announce_fn(announce_fn.function());
+    conn.set_unannounce_fn(unannounce_fn.function());
+    conn.set_on_data_fn(on_data.function());
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    conn.subscribe("/x", js("{}")).unwrap();
+    announce(&mut conn, "/x", 5, "int", json!({}));
+    announce(&mut conn, "/y", 6, "int", json!({}));
+    announce_fn.take();
+
+    conn.pause_delivery(Some(true)).unwrap();
+    assert!(conn.pause_delivery(None).is_err());
+    conn.on_text(json!({"method": "values", "params": [[5, 300, 2, 7], [5, 400, 2, 8]]}).to_string()).unwrap();
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/y", "id": 6}}).to_string()).unwrap();
+    announce(&mut conn, "/z", 7, "int", json!({}));
+    assert!(on_data.take().is_empty());
+    assert!(announce_fn.take().is_empty());
+    assert!(unannounce_fn.take().is_empty());
+    // Values keep reaching the cache.
+    let snapshot: serde_json::Value = serde_json::from_str(&conn.snapshot_json().unwrap()).unwrap();
+    assert_eq!(snapshot["topics"]["/x"]["timestamp_us"], json!(400));
+
+    conn.resume_delivery(true).unwrap();
+    assert_eq!(unannounce_fn.take().len(), 1);
+    assert_eq!(announce_fn.take().len(), 1);
+    let delivered = on_data.take();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0][1], JsValue::from(400_i64));
+    assert_eq!(delivered[0][2], JsValue::from(8));
+
+    // Without catch-up, the next value delivered is the next one received.
+    conn.pause_delivery(None).unwrap();
+    conn.on_binary(vec![0x94, 5, 0xcd, 0x01, 0xf4, 2, 9]).unwrap();
+    conn.resume_delivery(false).unwrap();
+    conn.resume_delivery(false).unwrap();
+    assert!(on_data.take().is_empty());
+    conn.on_binary(vec![0x94, 5, 0xcd, 0x02, 0x58, 2, 10]).unwrap();
+    assert_eq!(on_data.take()[0][2], JsValue::from(10));
+}
+
+#[wasm_bindgen_test]
+fn wire_encodings() {
+    let send_binary = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    conn.subscribe("", js(r#"{"prefix": true}"#)).unwrap();
+    for (name, id, ty) in [("/flags", 1, "boolean[]"), ("/blob", 2, "raw"), ("/name", 3, "string"), ("/speed", 4, "double"), ("/count", 5, "int")] {
+        announce(&mut conn, name, id, ty, json!({}));
+    }
+    let received = |conn: &mut Nt4Connection, frame: &[u8]| {
+        conn.on_binary(frame.to_vec()).unwrap();
+        let calls = on_data.take();
+        assert_eq!(calls.len(), 1);
+        let value = calls[0][2].clone();
+        match value.dyn_ref::<js_sys::Uint8Array>() {
+            Some(bytes) => json!(bytes.to_vec()),
+            None => serde_wasm_bindgen::from_value::<serde_json::Value>(value).unwrap(),
+        }
+    };
+    // As wpilib's ntcore encodes them: the smallest header of each kind, bools and bin.
+    assert_eq!(received(&mut conn, &[0x94, 1, 0x0a, 16, 0x92, 0xc3, 0xc2]), json!([true, false]));
+    assert_eq!(received(&mut conn, &[0x94, 2, 0x0a, 5, 0xc4, 2, 0xde, 0xad]), json!([0xde, 0xad]));
+    // As msgpack-python without use_bin_type encodes them: raw as str, and str 16 where str 8 would fit.
+    assert_eq!(received(&mut conn, &[0x94, 2, 0x0a, 5, 0xa2, b'h', b'i']), json!([b'h', b'i']));
+    let mut frame = vec![0x94, 3, 0x0a, 4, 0xda, 0, 40];
+    frame.extend_from_slice(&[b'n'; 40]);
+    assert_eq!(received(&mut conn, &frame), json!("n".repeat(40)));
+    // As JS msgpack encoders do for numbers without a fractional part: ints, even for doubles.
+    assert_eq!(received(&mut conn, &[0x94, 4, 0x0a, 1, 3]), json!(3));
+    assert_eq!(received(&mut conn, &[0x94, 5, 0x0a, 2, 0xcb, 0x40, 0x14, 0, 0, 0, 0, 0, 0]), json!(5));
+    // Other reasonable encodings: 0 and 1 as booleans, array 16 for short arrays, strings as bin.
+    assert_eq!(received(&mut conn, &[0x94, 1, 0x0a, 16, 0xdc, 0, 3, 1, 0, 0xc3]), json!([true, false, true]));
+    assert_eq!(received(&mut conn, &[0x94, 3, 0x0a, 4, 0xc4, 2, b'o', b'k']), json!("ok"));
+    assert!(conn.on_binary(vec![0x94, 1, 0x0a, 16, 0x91, 2]).is_err());
+    assert!(conn.on_binary(vec![0x94, 5, 0x0a, 2, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]).is_err());
+
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    let flags = conn.publish("/out/flags", JsValue::from_str("boolean[]"), js("{}")).unwrap() as u8;
+    let blob = conn.publish("/out/blob", JsValue::from_str("raw"), js("{}")).unwrap() as u8;
+    let name = conn.publish("/out/name", JsValue::from_str("string"), js("{}")).unwrap() as u8;
+    let time = || JsValue::from_f64(10.0);
+    let long = JsValue::from_str(&"n".repeat(40));
+    conn.send_data(flags as i32, js("[true, false]"), time()).unwrap();
+    assert_eq!(sent_binary(&send_binary), [0x94, flags, 0x0a, 16, 0x92, 0xc3, 0xc2]);
+    conn.set_wire_options(js(r#"{"raw": "array", "booleans": "int", "strings": "no_str8"}"#)).unwrap();
+    conn.send_data(flags as i32, js("[true, false]"), time()).unwrap();
+    assert_eq!(sent_binary(&send_binary), [0x94, flags, 0x0a, 16, 0x92, 1, 0]);
+    conn.send_data(blob as i32, js_sys::Uint8Array::from(&[1, 200][..]).into(), time()).unwrap();
+    assert_eq!(sent_binary(&send_binary), [0x94, blob, 0x0a, 5, 0x92, 1, 0xcc, 200]);
+    conn.send_data(name as i32, long, time()).unwrap();
+    assert_eq!(sent_binary(&send_binary)[..7], [0x94, name, 0x0a, 4, 0xda, 0, 40]);
+}
+
+#[wasm_bindgen_test]
+fn send_policies() {
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    let pubuid = conn.publish("/pose", JsValue::from_str("double[]"), js("{}")).unwrap();
+    let speed = conn.publish("/speed", JsValue::from_str("double"), js("{}")).unwrap();
+    let sent_array = |mock: &Mock| rmp_serde::from_slice::<(i32, u64, u8, Vec<f64>)>(&sent_binary(mock)).unwrap().3;
+    let sent_double = |mock: &Mock| rmp_serde::from_slice::<(i32, u64, u8, f64)>(&sent_binary(mock)).unwrap().3;
+    let message = |error: JsValue| error.as_string().unwrap();
+
+    // Nulls fail naming the element by default, NaNs are sent as before.
+    let error = conn.send_data(pubuid, js("[1, null, 3]"), JsValue::UNDEFINED).unwrap_err();
+    assert_eq!(message(error), "element 1 of the array is null");
+    let holes = js_sys::Array::new_with_length(2);
+    holes.set(0, JsValue::from_f64(1.0));
+    assert_eq!(message(conn.send_data(pubuid, holes.clone().into(), JsValue::UNDEFINED).unwrap_err()), "element 1 of the array is undefined");
+    conn.send_data(speed, JsValue::from_f64(f64::NAN), JsValue::UNDEFINED).unwrap();
+    assert!(sent_double(&send_binary).is_nan());
+
+    conn.set_send_policies(js(r#"{"nan_policy": "error", "null_policy": {"replace": 0}}"#)).unwrap();
+    conn.send_data(pubuid, holes.clone().into(), JsValue::UNDEFINED).unwrap();
+    assert_eq!(sent_array(&send_binary), [1.0, 0.0]);
+    // The caller's array is left alone.
+    assert!(holes.get(1).is_undefined());
+    let nan = js_sys::Array::of2(&JsValue::from_f64(f64::NAN), &JsValue::from_f64(2.0));
+    assert_eq!(message(conn.send_data(pubuid, nan.clone().into(), JsValue::UNDEFINED).unwrap_err()), "element 0 of the array is NaN");
+    let entries = js_sys::Array::of1(&js_sys::Object::from_entries(&js_sys::Array::of2(
+        &js_sys::Array::of2(&"topic_id".into(), &JsValue::from(pubuid)),
+        &js_sys::Array::of2(&"data".into(), &nan),
+    )).unwrap());
+    assert_eq!(message(conn.send_atomic(entries.into(), JsValue::UNDEFINED).unwrap_err()), "entry 0: element 0 of the array is NaN");
+
+    conn.set_send_policies(js(r#"{"nan_policy": "skip_message"}"#)).unwrap();
+    conn.send_data(speed, JsValue::from_f64(f64::NAN), JsValue::UNDEFINED).unwrap();
+    assert!(send_binary.take().is_empty());
+    assert_eq!(message(conn.send_data(speed, JsValue::NULL, JsValue::UNDEFINED).unwrap_err()), "the value is null");
+    assert!(conn.set_send_policies(js(r#"{"null_policy": "allow"}"#)).is_err());
+}
+
+#[wasm_bindgen_test]
+fn fetch_value() {
+    let send_text = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_on_data_fn(on_data.function());
+
+    let _ = conn.fetch_value("/x", 1000.0, Some(1000.0)).unwrap();
+    let subscribe = sent_text(&send_text);
+    assert_eq!(subscribe["method"], json!("subscribe"));
+    assert_eq!(subscribe["params"]["options"]["all"], json!(false));
+    let subuid = subscribe["params"]["subuid"].clone();
+
+    announce(&mut conn, "/x", 5, "int", json!({}));
+    conn.on_text(json!({"method": "values", "params": [[5, 300, 2, 7]]}).to_string()).unwrap();
+    assert_eq!(on_data.take().len(), 1);
+    assert_eq!(sent_text(&send_text), json!({"method": "unsubscribe", "params": {"subuid": subuid}}));
+
+    // A cached value fresh enough answers without a subscription.
+    let _ = conn.fetch_value("/x", 1000.0, Some(f64::MAX)).unwrap();
+    assert!(send_text.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn max_topics() {
+    let warning_fn = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_warning_fn(warning_fn.function());
+    conn.set_on_data_fn(on_data.function());
+    conn.set_max_topics(3);
+
+    for id in 0..100 {
+        announce(&mut conn, &format!("/t{}", id), id, "int", json!({}));
+    }
+    assert_eq!(topic_count(&mut conn), 3);
+    assert!(conn.is_topic_limit_reached());
+    assert_eq!(conn.over_limit_topic_count(), 97.0);
+    assert_eq!(warning_fn.take().len(), 1);
+    let snapshot: serde_json::Value = serde_json::from_str(&conn.snapshot_json().unwrap()).unwrap();
+    assert_eq!(snapshot["connection"]["topics_over_limit"], json!(97));
+
+    // Known topics keep working, values of dropped ones are not buffered.
+    announce(&mut conn, "/t0", 0, "int", json!({}));
+    assert_eq!(topic_count(&mut conn), 3);
+    conn.on_text(json!({"method": "values", "params": [[0, 100, 2, 1], [4, 100, 2, 1]]}).to_string()).unwrap();
+    assert_eq!(on_data.take().len(), 1);
+    assert_eq!(conn.pending_unannounced_count(), 0);
+
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/t0", "id": 0}}).to_string()).unwrap();
+    assert!(!conn.is_topic_limit_reached());
+    announce(&mut conn, "/t4", 4, "int", json!({}));
+    assert_eq!(topic_count(&mut conn), 3);
+    assert!(warning_fn.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn borrowed_values() {
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    conn.set_on_data_fn(on_data.function());
+    conn.subscribe("/x", js("{}")).unwrap();
+    announce(&mut conn, "/x", 5, "double[]", json!({}));
+    assert!(conn.set_value_ownership(JsValue::from_str("lend")).is_err());
+
+    conn.on_text(json!({"method": "values", "params": [[5, 100, 17, [1.5, 2.5]]]}).to_string()).unwrap();
+    let copied = on_data.take_one_data();
+    assert!(js_sys::Array::is_array(&copied));
+
+    conn.set_value_ownership(JsValue::from_str("borrow")).unwrap();
+    conn.on_text(json!({"method": "values", "params": [[5, 200, 17, [1.5, 2.5]]]}).to_string()).unwrap();
+    // The view was only valid during the callback.
+    let kept = js_sys::Float64Array::from(on_data.take_one_data());
+    assert_eq!(kept.length(), 2);
+    assert!(kept.to_vec().iter().all(|x| x.is_nan()));
+    assert_eq!(js_sys::Array::from(&copied).get(1), JsValue::from(2.5));
+}
+
+/// Not a check, but the time `on_data_fn` takes in each ownership mode for large `double[]`s.
+#[wasm_bindgen_test]
+fn value_ownership_speed() {
+    const VALUES: usize = 2_000;
+    const FRAMES: i64 = 200;
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    conn.set_on_data_fn(Function::new_with_args("id, timestamp, data", "data[0]"));
+    conn.subscribe("/x", js("{}")).unwrap();
+    announce(&mut conn, "/x", 5, "double[]", json!({}));
+    let values: Vec<f64> = (0..VALUES).map(|x| x as f64).collect();
+    let frames: Vec<String> = (0..FRAMES)
+        .map(|t| json!({"method": "values", "params": [[5, t, 17, values]]}).to_string())
+        .collect();
+    for mode in ["copy", "borrow"] {
+        conn.set_value_ownership(JsValue::from_str(mode)).unwrap();
+        let start = js_sys::Date::now();
+        for frame in &frames {
+            conn.on_text(frame.clone()).unwrap();
+        }
+        console_log!("{}: {:.3} ms per frame", mode, (js_sys::Date::now() - start) / FRAMES as f64);
+    }
+}
+
+#[wasm_bindgen_test]
+fn event_journal() {
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    let events_since = |conn: &Nt4Connection, seq: f64| -> Vec<serde_json::Value> {
+        serde_wasm_bindgen::from_value(conn.events_since(seq).unwrap()).unwrap()
+    };
+    assert_eq!(conn.latest_seq(), 0.0);
+
+    announce(&mut conn, "/x", 5, "int", json!({}));
+    announce(&mut conn, "/x", 5, "int", json!({"persistent": true}));
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/x", "id": 5}}).to_string()).unwrap();
+    announce(&mut conn, "/x", 6, "double", json!({}));
+    let events = events_since(&conn, 0.0);
+    let kinds: Vec<&serde_json::Value> = events.iter().map(|x| &x["kind"]).collect();
+    assert_eq!(kinds, [&json!("announce"), &json!("properties"), &json!("unannounce"), &json!("type_change"), &json!("announce")]);
+    assert_eq!(events[0]["detail"], json!({"id": 5, "type": "int"}));
+    assert_eq!(events[1]["detail"]["persistent"], json!(true));
+    assert_eq!(events[3]["detail"], json!({"old": "int", "new": "double"}));
+    assert_eq!(conn.latest_seq(), 5.0);
+    assert_eq!(events_since(&conn, 4.0).len(), 1);
+
+    // Reading from before what is kept starts with a gap.
+    conn.set_journal_capacity(2);
+    let events = events_since(&conn, 1.0);
+    assert_eq!((events[0]["kind"].clone(), events[0]["detail"].clone()), (json!("gap"), json!({"missed": 2})));
+    assert_eq!(events[1]["seq"], json!(4));
+    assert_eq!(events_since(&conn, 3.0).len(), 2);
+
+    conn.clear_journal();
+    assert_eq!(conn.latest_seq(), 5.0);
+    assert!(events_since(&conn, 5.0).is_empty());
+    assert_eq!(events_since(&conn, 4.0)[0]["kind"], json!("gap"));
+}
+
+#[wasm_bindgen_test]
+fn snapshot_hydration() {
+    let mut source = Nt4Connection::new();
+    ignore_announces(&mut source);
+    source.set_on_data_fn(Function::new_no_args(""));
+    announce(&mut source, "/a", 1, "int", json!({"retained": true}));
+    announce(&mut source, "/b", 2, "raw", json!({}));
+    announce(&mut source, "/gone", 3, "double", json!({}));
+    announce(&mut source, "/moved", 4, "string", json!({}));
+    source.on_text(json!({"method": "values", "params": [[1, 100, 2, 7], [3, 100, 1, 1.5]]}).to_string()).unwrap();
+    source.on_binary(rmp_serde::to_vec(&(2_i32, 100_i64, 5_u8, serde_bytes::Bytes::new(&[1, 2, 3]))).unwrap()).unwrap();
+
+    let announce_fn = Mock::new();
+    let unannounce_fn = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::from_snapshot(source.snapshot().unwrap(), Some(0.0)).unwrap();
+    conn.set_announce_fn(announce_fn.function());
+    conn.set_unannounce_fn(unannounce_fn.function());
+    conn.set_on_data_fn(on_data.function());
+    conn.set_send_binary_fn(Function::new_no_args(""));
+    conn.set_ready_fn(Function::new_no_args(""));
+    assert!(announce_fn.take().is_empty());
+    conn.attach().unwrap();
+    assert_eq!(announce_fn.take().len(), 4);
+    let values = on_data.take();
+    assert_eq!(values.len(), 3);
+    assert_eq!(js_sys::Uint8Array::new(&values[1][2]).to_vec(), [1, 2, 3]);
+    assert!(conn.is_stale("/a"));
+    assert!(!conn.is_stale("/moved"));
+
+    // The same name under the same id is kept silently, a new id replaces the seeded topic.
+    announce(&mut conn, "/a", 1, "int", json!({"retained": true}));
+    announce(&mut conn, "/b", 2, "raw", json!({}));
+    announce(&mut conn, "/moved", 9, "string", json!({}));
+    assert_eq!(announce_fn.take().len(), 1);
+    assert_eq!(unannounce_fn.take_one(), "/moved");
+    conn.on_text(json!({"method": "values", "params": [[1, 200, 2, 8]]}).to_string()).unwrap();
+    assert_eq!(on_data.take_one_data(), 8);
+    assert!(!conn.is_stale("/a"));
+    assert!(conn.is_stale("/b"));
+
+    // Seeded topics the server has not announced are evicted once the settle window after ready is over.
+    conn.on_binary(rmp_serde::to_vec(&(-1_i32, 5_000_000_i64, 2_u8, 0_i64)).unwrap()).unwrap();
+    for _ in 0..1_000_000 {
+        conn.timesync().unwrap();
+        if !unannounce_fn.calls.borrow().is_empty() {
+            break;
+        }
+    }
+    assert_eq!(unannounce_fn.take_one(), "/gone");
+    assert_eq!(topic_count(&mut conn), 3);
+
+    // Snapshots read back from JSON work too, as long as they are of this version.
+    let mut snapshot: serde_json::Value = serde_json::from_str(&conn.snapshot_json().unwrap()).unwrap();
+    let restored = Nt4Connection::from_snapshot(js(&snapshot.to_string()), None).unwrap();
+    assert!(restored.is_stale("/b"));
+    snapshot["version"] = json!(1);
+    assert!(Nt4Connection::from_snapshot(js(&snapshot.to_string()), None).is_err());
+}
+
+/// `count` values alternating between topics 1 and 2, from `first` on, as one binary message.
+fn value_message(first: i64, count: i64) -> Vec<u8> {
+    let mut message = Vec::new();
+    for x in first..first + count {
+        message.extend(rmp_serde::to_vec(&(1 + (x % 2) as i32, x, 2_u8, x)).unwrap());
+    }
+    message
+}
+
+#[wasm_bindgen_test]
+fn chunked_binary() {
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(on_data.function());
+    announce(&mut conn, "/a", 1, "int", json!({}));
+    announce(&mut conn, "/b", 2, "int", json!({}));
+    let delivered = |on_data: &Mock| -> Vec<(i32, i64)> {
+        on_data.take().iter().map(|[id, _, value, _]| (id.as_f64().unwrap() as i32, value.as_f64().unwrap() as i64)).collect()
+    };
+    let expected = |first: i64, count: i64| -> Vec<(i32, i64)> { (first..first + count).map(|x| (1 + (x % 2) as i32, x)).collect() };
+
+    // Every value of a message is delivered, not just the first.
+    conn.on_binary(value_message(0, 3)).unwrap();
+    assert_eq!(delivered(&on_data), expected(0, 3));
+
+    let handle = conn.on_binary_chunked(value_message(0, 1000), 7).unwrap();
+    assert_eq!(delivered(&on_data), expected(0, 7));
+    let mut calls = 0;
+    loop {
+        calls += 1;
+        let result = conn.continue_processing(handle, 1 + calls % 13).unwrap();
+        if js_sys::Reflect::get(&result, &"done".into()).unwrap().is_truthy() {
+            break;
+        }
+    }
+    assert!(calls > 100);
+    assert_eq!(delivered(&on_data), expected(0, 1000)[7..]);
+
+    // Continuing a later message, or receiving one whole, finishes the older ones first.
+    let first = conn.on_binary_chunked(value_message(0, 10), 0).unwrap();
+    let second = conn.on_binary_chunked(value_message(10, 10), 0).unwrap();
+    let done = conn.continue_processing(second, 15).unwrap();
+    assert!(!js_sys::Reflect::get(&done, &"done".into()).unwrap().is_truthy());
+    assert_eq!(delivered(&on_data), expected(0, 15));
+    conn.on_binary(value_message(20, 5)).unwrap();
+    assert_eq!(delivered(&on_data), expected(15, 10));
+    let done = conn.continue_processing(first, 1).unwrap();
+    assert!(js_sys::Reflect::get(&done, &"done".into()).unwrap().is_truthy());
+    assert!(on_data.take().is_empty());
+
+    // A message that fails to decode is dropped from there on.
+    let mut broken = value_message(0, 2);
+    broken.push(0xc1);
+    broken.extend(value_message(2, 2));
+    let handle = conn.on_binary_chunked(broken, 1).unwrap();
+    assert_eq!(delivered(&on_data), expected(0, 1));
+    assert!(conn.continue_processing(handle, 10).is_err());
+    assert_eq!(delivered(&on_data), expected(1, 1));
+    assert!(js_sys::Reflect::get(&conn.continue_processing(handle, 10).unwrap(), &"done".into()).unwrap().is_truthy());
+}
+
+#[wasm_bindgen_test]
+fn type_mismatch() {
+    let delivered = Rc::new(RefCell::new(Vec::new()));
+    let recorded = delivered.clone();
+    let on_data = Closure::<dyn FnMut(JsValue, JsValue, JsValue, JsValue, JsValue)>::new(
+        move |_, _, _, ty: JsValue, flag: JsValue| recorded.borrow_mut().push((ty.as_string().unwrap(), flag.is_truthy())),
+    );
+    let take = || -> Vec<(String, bool)> { delivered.borrow_mut().drain(..).collect() };
+    let warning_fn = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(on_data.as_ref().unchecked_ref::<Function>().clone());
+    conn.set_warning_fn(warning_fn.function());
+    conn.set_include_type_in_callback(true);
+    conn.set_type_mismatch_warn_after(3);
+    announce(&mut conn, "/arr", 1, "double[]", json!({}));
+    announce(&mut conn, "/d", 2, "double", json!({}));
+    announce(&mut conn, "/j", 3, "json", json!({}));
+
+    // Values that convert without loss are delivered as the announced type, the rest as they came and
+    // flagged, without holding up the rest of the message.
+    let mut message = rmp_serde::to_vec(&(2_i32, 100_i64, 2_u8, 3_i64)).unwrap();
+    message.extend(rmp_serde::to_vec(&(3_i32, 100_i64, 4_u8, "{}")).unwrap());
+    message.extend(rmp_serde::to_vec(&(1_i32, 100_i64, 1_u8, 1.5_f64)).unwrap());
+    message.extend(rmp_serde::to_vec(&(1_i32, 200_i64, 17_u8, [1.5_f64])).unwrap());
+    conn.on_binary(message).unwrap();
+    let expected = [("double", false), ("json", false), ("double", true), ("double[]", false)];
+    assert_eq!(take(), expected.map(|(ty, flag)| (ty.to_string(), flag)));
+    assert_eq!(conn.type_mismatch_count("/arr"), 1.0);
+    assert_eq!(conn.type_mismatch_count("/d"), 0.0);
+
+    // A run of mismatches is warned about once, and a value of the announced type ends it.
+    for timestamp in 300..305 {
+        conn.on_binary(rmp_serde::to_vec(&(1_i32, timestamp as i64, 1_u8, 2.5_f64)).unwrap()).unwrap();
+    }
+    assert_eq!(warning_fn.take().len(), 1);
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 400_i64, 17_u8, [1.5_f64])).unwrap()).unwrap();
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 500_i64, 1_u8, 2.5_f64)).unwrap()).unwrap();
+    assert!(warning_fn.take().is_empty());
+    assert_eq!(conn.type_mismatch_count("/arr"), 7.0);
+    assert_eq!(take().len(), 7);
+}
+
+#[wasm_bindgen_test]
+fn watches() {
+    let watch_fn = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(on_data.function());
+    conn.set_watch_fn(watch_fn.function());
+    announce(&mut conn, "/mode", 1, "string", json!({}));
+    announce(&mut conn, "/speed", 2, "double", json!({}));
+    announce(&mut conn, "/limit", 3, "double", json!({}));
+    let send = |conn: &mut Nt4Connection, id: i32, timestamp: i64, value: serde_json::Value| {
+        let message = match value {
+            serde_json::Value::String(x) => rmp_serde::to_vec(&(id, timestamp, 4_u8, x)),
+            x => rmp_serde::to_vec(&(id, timestamp, 1_u8, x.as_f64().unwrap())),
+        };
+        conn.on_binary(message.unwrap()).unwrap();
+    };
+    let outcomes = |watch_fn: &Mock| -> Vec<(String, bool)> {
+        watch_fn.take().iter().map(|[id, passed, _, _]| (id.as_string().unwrap(), passed.is_truthy())).collect()
+    };
+
+    assert!(conn.add_watch("bad", js(r#"{"kind": "stays_in_range", "topic": "/speed", "min": 2, "max": 1, "for_ms": 5}"#)).is_err());
+    conn.add_watch("fast", js(r#"{"kind": "compare", "topic": "/speed", "op": ">", "value": 3}"#)).unwrap();
+    conn.add_watch("under", js(r#"{"kind": "compare_topics", "left": "/speed", "op": "<=", "right": "/limit"}"#)).unwrap();
+    conn.add_watch(
+        "responds",
+        js(r#"{"kind": "within_after_change", "trigger": "/mode", "topic": "/speed", "op": ">=", "value": 1, "within_ms": 10}"#),
+    )
+    .unwrap();
+    conn.add_watch("steady", js(r#"{"kind": "stays_in_range", "topic": "/speed", "min": 0, "max": 2, "for_ms": 5}"#)).unwrap();
+
+    // Outcomes are reported when they change, comparisons of two topics once both have a value.
+    send(&mut conn, 1, 0, json!("idle"));
+    send(&mut conn, 2, 1_000, json!(0.5));
+    assert_eq!(outcomes(&watch_fn), [("fast".to_string(), false)]);
+    send(&mut conn, 3, 2_000, json!(4.0));
+    assert_eq!(outcomes(&watch_fn), [("under".to_string(), true)]);
+
+    // Deadlines and durations are judged on server timestamps.
+    send(&mut conn, 1, 3_000, json!("auto"));
+    send(&mut conn, 2, 6_000, json!(1.5));
+    assert_eq!(outcomes(&watch_fn), [("steady".to_string(), true), ("responds".to_string(), true)]);
+    send(&mut conn, 1, 20_000, json!("teleop"));
+    send(&mut conn, 2, 31_000, json!(5.0));
+    assert_eq!(
+        outcomes(&watch_fn),
+        [("responds".to_string(), false), ("fast".to_string(), true), ("steady".to_string(), false), ("under".to_string(), false)]
+    );
+
+    assert!(conn.remove_watch("fast"));
+    assert!(!conn.remove_watch("fast"));
+    send(&mut conn, 2, 32_000, json!(0.0));
+    assert_eq!(outcomes(&watch_fn), [("under".to_string(), true)]);
+}
+
+#[wasm_bindgen_test]
+fn topics_diff() {
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_on_data_fn(on_data.function());
+    let diff = |conn: &Nt4Connection, seq: f64, order: &str| -> serde_json::Value {
+        serde_wasm_bindgen::from_value(conn.topics_diff(seq, JsValue::from_str(order), None).unwrap()).unwrap()
+    };
+    let names = |entries: &serde_json::Value| -> Vec<String> {
+        entries.as_array().unwrap().iter().map(|x| x["name"].as_str().unwrap().to_string()).collect()
+    };
+
+    announce(&mut conn, "/b", 1, "int", json!({}));
+    announce(&mut conn, "/a", 2, "int", json!({}));
+    announce(&mut conn, "/gone", 3, "int", json!({}));
+    let first = diff(&conn, 0.0, "name");
+    assert_eq!(first["resync_required"], json!(false));
+    assert_eq!(names(&first["added"]), ["/a", "/b", "/gone"]);
+    assert_eq!(first["added"][0], json!({"id": 2, "name": "/a", "type": "int", "properties": {"persistent": false, "retained": false}, "last_update_us": null}));
+    let seq = first["seq"].as_f64().unwrap();
+    assert_eq!(seq, 3.0);
+
+    // Only what happened since, with topics that came and went left out.
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 500_i64, 2_u8, 7_i64)).unwrap()).unwrap();
+    announce(&mut conn, "/a", 2, "int", json!({"persistent": true}));
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/gone", "id": 3}}).to_string()).unwrap();
+    announce(&mut conn, "/brief", 4, "int", json!({}));
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/brief", "id": 4}}).to_string()).unwrap();
+    announce(&mut conn, "/c", 5, "int", json!({}));
+    let next = diff(&conn, seq, "name");
+    assert_eq!(names(&next["added"]), ["/c"]);
+    assert_eq!(next["removed"], json!(["/gone"]));
+    assert_eq!(names(&next["changed"]), ["/a"]);
+    assert_eq!(next["changed"][0]["properties"]["persistent"], json!(true));
+    assert!(next.get("topics").is_none());
+    assert!(diff(&conn, next["seq"].as_f64().unwrap(), "name")["added"].as_array().unwrap().is_empty());
+
+    // Falling behind the journal gives every topic instead.
+    conn.set_journal_capacity(2);
+    let resync = diff(&conn, seq, "last_update");
+    assert_eq!(resync["resync_required"], json!(true));
+    assert_eq!(names(&resync["topics"]), ["/b", "/a", "/c"]);
+    assert_eq!(resync["topics"][0]["last_update_us"], json!(500));
+    assert_eq!(diff(&conn, 100.0, "name")["resync_required"], json!(true));
+}
+
+#[wasm_bindgen_test]
+fn log_channel() {
+    let send_binary = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_ready_fn(Function::new_no_args(""));
+    conn.set_unready_fn(Function::new_no_args(""));
+    let sync = |conn: &mut Nt4Connection, server_time: i64| {
+        conn.timesync().unwrap();
+        let (_, _, _, local_time): (i32, i64, u8, i64) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+        conn.on_binary(rmp_serde::to_vec(&(-1_i32, server_time, 2_u8, local_time)).unwrap()).unwrap();
+    };
+    let lines = |send_binary: &Mock| -> Vec<String> {
+        let mut lines = Vec::new();
+        for [message, ..] in send_binary.take() {
+            let message: Vec<u8> = serde_wasm_bindgen::from_value(message).unwrap();
+            let mut rest = &message[..];
+            while !rest.is_empty() {
+                let (_, _, ty, line): (i32, i64, u8, String) =
+                    serde::Deserialize::deserialize(&mut rmp_serde::Deserializer::new(&mut rest)).unwrap();
+                assert_eq!(ty, 4);
+                lines.push(line);
+            }
+        }
+        lines
+    };
+    let wait_ms = |ms: f64| {
+        let until = js_sys::Date::now() + ms;
+        while js_sys::Date::now() < until {}
+    };
+    let counts = |conn: &Nt4Connection, id: i32| -> serde_json::Value { serde_wasm_bindgen::from_value(conn.log_counts(id).unwrap()).unwrap() };
+
+    sync(&mut conn, 3_723_456_000);
+    assert!(conn.log_channel("/Dashboard/Log", js(r#"{"max_msgs_per_s": 0}"#)).is_err());
+    let id = conn.log_channel("/Dashboard/Log", js(r#"{"max_msgs_per_s": 20, "queue_limit": 3}"#)).unwrap();
+    let publish = sent_text(&send_text);
+    assert_eq!((publish["params"]["type"].clone(), publish["params"]["properties"]["retained"].clone()), (json!("string"), json!(false)));
+
+    // A second's worth goes out right away, then up to the queue limit waits and the rest is dropped.
+    for i in 0..25 {
+        conn.log(id, &format!("note {}", i)).unwrap();
+    }
+    let sent = lines(&send_binary);
+    assert_eq!(sent.len(), 20);
+    assert!(sent[0].starts_with("[01:02:03.4") && sent[0].ends_with("] note 0"), "{}", sent[0]);
+    assert_eq!(counts(&conn, id), json!({"queued": 3, "dropped": 2}));
+
+    // Kept while disconnected, then sent once after publishing again.
+    conn.on_disconnect().unwrap();
+    wait_ms(250.0);
+    conn.flush_logs().unwrap();
+    assert!(send_binary.take().is_empty());
+    sync(&mut conn, 4_000_000_000);
+    assert_eq!(sent_text(&send_text)["params"]["pubuid"], json!(id));
+    let sent = lines(&send_binary);
+    assert_eq!(sent.len(), 4);
+    assert!(sent[2].ends_with("] note 22"));
+    assert!(sent[3].starts_with("[01:02:03.4") && sent[3].ends_with("] … 2 messages dropped"), "{}", sent[3]);
+    conn.flush_logs().unwrap();
+    assert!(send_binary.take().is_empty());
+    assert_eq!(counts(&conn, id), json!({"queued": 0, "dropped": 2}));
+}
+
+#[wasm_bindgen_test]
+fn metrics() {
+    let send_binary = Mock::new();
+    let metrics_fn = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_unready_fn(Function::new_no_args(""));
+    let on_data = Mock::new();
+    conn.set_on_data_fn(on_data.function());
+    let emit = |conn: &mut Nt4Connection, now_ms: f64| -> Option<serde_json::Value> {
+        conn.emit_metrics(now_ms).unwrap().then(|| serde_wasm_bindgen::from_value(metrics_fn.take_one()).unwrap())
+    };
+    assert!(!conn.emit_metrics(0.0).unwrap());
+    conn.set_metrics_fn(metrics_fn.function(), 1000.0);
+
+    announce(&mut conn, "/a", 1, "int", json!({}));
+    let value = rmp_serde::to_vec(&(1_i32, 100_i64, 2_u8, 5_i64)).unwrap();
+    conn.on_binary(value.clone()).unwrap();
+    assert!(conn.on_text("not json".to_string()).is_err());
+    conn.timesync().unwrap();
+    let first = emit(&mut conn, 0.0).unwrap();
+    assert_eq!(first["frames_rx_total"], json!(3));
+    let announced = json!({"method": "announce", "params": {"name": "/a", "id": 1, "type": "int", "properties": {}}});
+    assert_eq!(first["bytes_rx_total"], json!(announced.to_string().len() + value.len() + "not json".len()));
+    assert_eq!(first["frames_tx_total"], json!(1));
+    assert_eq!(first["values_rx_total"], json!(1));
+    assert_eq!(first["decode_errors_total"], json!(1));
+    assert_eq!((first["topics_announced"].clone(), first["ready"].clone()), (json!(1), json!(0)));
+    assert!(first.get("rtt_us").is_none());
+
+    // At most once per interval, and counters keep counting across a reconnect.
+    assert!(emit(&mut conn, 999.0).is_none());
+    conn.on_disconnect().unwrap();
+    let second = emit(&mut conn, 1000.0).unwrap();
+    assert_eq!((second["frames_rx_total"].clone(), second["disconnects_total"].clone()), (json!(3), json!(1)));
+    assert_eq!(second["topics_announced"], json!(0));
+
+    let described: Vec<serde_json::Value> = serde_wasm_bindgen::from_value(conn.describe_metrics().unwrap()).unwrap();
+    let kind = |name: &str| described.iter().find(|x| x["name"] == name).map(|x| x["kind"].clone());
+    assert_eq!((kind("bytes_tx_total"), kind("offset_us")), (Some(json!("counter")), Some(json!("gauge"))));
+    send_binary.take();
+}
+
+#[wasm_bindgen_test]
+fn telemetry_endpoint() {
+    let mut conn = Nt4Connection::new();
+    assert_eq!(conn.get_telemetry_url(), None);
+    assert_eq!(error_message(conn.set_telemetry_endpoint("", 1000).unwrap_err()), "telemetry url must not be empty");
+    assert_eq!(
+        error_message(conn.set_telemetry_endpoint("/telemetry", 0).unwrap_err()),
+        "telemetry interval must be at least 1 ms"
+    );
+    if web_sys::window().is_none() {
+        // Outside a browser there is no timer to post from.
+        assert_eq!(
+            error_message(conn.set_telemetry_endpoint("/telemetry", 1000).unwrap_err()),
+            "telemetry needs a browser window"
+        );
+        assert_eq!(conn.get_telemetry_url(), None);
+        return;
+    }
+    conn.set_telemetry_endpoint("/telemetry", 1000).unwrap();
+    assert_eq!(conn.get_telemetry_url().as_deref(), Some("/telemetry"));
+    conn.set_telemetry_endpoint("/other", 500).unwrap();
+    assert_eq!(conn.get_telemetry_url().as_deref(), Some("/other"));
+    conn.stop_telemetry();
+    assert_eq!(conn.get_telemetry_url(), None);
+    conn.set_telemetry_endpoint("/telemetry", 1000).unwrap();
+    conn.close();
+    assert_eq!(conn.get_telemetry_url(), None);
+    assert!(conn.set_telemetry_endpoint("/telemetry", 1000).is_err());
+}
+
+#[wasm_bindgen_test]
+fn no_callbacks_after_close() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let callbacks = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_announce_fn(callbacks.function());
+    conn.set_unannounce_fn(callbacks.function());
+    conn.set_ready_fn(callbacks.function());
+    conn.set_unready_fn(callbacks.function());
+    conn.set_on_data_fn(callbacks.function());
+    conn.set_type_changed_fn(callbacks.function());
+    conn.set_properties_changed_fn(callbacks.function());
+    conn.set_warning_fn(callbacks.function());
+    conn.set_server_time_reset_fn(callbacks.function());
+    let subuid = conn.subscribe("/a", js("{}")).unwrap();
+    sent_text(&send_text);
+    announce(&mut conn, "/a", 1, "int", json!({}));
+    assert_eq!(callbacks.take().len(), 1);
+
+    conn.close();
+    assert_eq!(sent_text(&send_text), json!({"method": "unsubscribe", "params": {"subuid": subuid}}));
+    let value = rmp_serde::to_vec(&(1_i32, 100_i64, 2_u8, 5_i64)).unwrap();
+    let reannounce = json!({"method": "announce", "params": {"name": "/a", "id": 1, "type": "double", "properties": {}}});
+    let unannounce = json!({"method": "unannounce", "params": {"name": "/a", "id": 1}});
+    let results = [
+        conn.on_binary(value),
+        conn.on_binary(vec![0xc1]),
+        conn.on_text(reannounce.to_string()),
+        conn.on_text(unannounce.to_string()),
+        conn.on_text("not json".to_string()),
+        conn.on_disconnect(),
+        conn.timesync(),
+    ];
+    for result in results {
+        assert_eq!(error_message(result.unwrap_err()), "connection closed");
+    }
+    assert!(callbacks.take().is_empty());
+    assert!(send_text.take().is_empty());
+    assert!(send_binary.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn virtual_clients() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_on_data_fn(Function::new_no_args(""));
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    let names = |mock: &Mock| -> Vec<String> {
+        let name = |x: &JsValue| js_sys::Reflect::get(x, &"name".into()).unwrap().as_string().unwrap();
+        mock.take().iter().map(|x| name(&x[0])).collect()
+    };
+    let topic_ids = |mock: &Mock| -> Vec<f64> { mock.take().iter().map(|x| x[0].as_f64().unwrap()).collect() };
+    let values = |conn: &mut Nt4Connection, ids: &[i32]| {
+        let frames: Vec<u8> = ids.iter().flat_map(|id| rmp_serde::to_vec(&(*id, 100_i64, 2_u8, 1_i64)).unwrap()).collect();
+        conn.on_binary(frames).unwrap();
+    };
+
+    let mut arm = conn.virtual_client("arm").unwrap();
+    let mut drive = conn.virtual_client("drive").unwrap();
+    let (arm_announce, arm_unannounce, arm_data) = (Mock::new(), Mock::new(), Mock::new());
+    let (drive_announce, drive_unannounce, drive_data) = (Mock::new(), Mock::new(), Mock::new());
+    arm.set_announce_fn(arm_announce.function()).unwrap();
+    arm.set_unannounce_fn(arm_unannounce.function()).unwrap();
+    arm.set_on_data_fn(arm_data.function()).unwrap();
+    drive.set_announce_fn(drive_announce.function()).unwrap();
+    drive.set_unannounce_fn(drive_unannounce.function()).unwrap();
+    drive.set_on_data_fn(drive_data.function()).unwrap();
+    // Each has its own uids, over separate subscriptions of the connection.
+    let options = |prefix: bool| js(&json!({"prefix": prefix, "topicsonly": false, "all": false}).to_string());
+    assert_eq!(arm.subscribe("/arm", options(true)).unwrap(), 0);
+    assert_eq!(drive.subscribe("/drive", options(true)).unwrap(), 0);
+    assert_eq!(drive.subscribe("/arm/angle", options(false)).unwrap(), 1);
+    let subuid = |x: &[JsValue; 4]| serde_json::from_str::<serde_json::Value>(&x[0].as_string().unwrap()).unwrap()["params"]["subuid"].clone();
+    assert_eq!(send_text.take().iter().map(subuid).collect::<Vec<_>>(), [json!(0), json!(1), json!(2)]);
+
+    // Announces go to the clients with a matching subscription, some to both.
+    announce(&mut conn, "/arm/angle", 1, "int", json!({}));
+    announce(&mut conn, "/arm/speed", 2, "int", json!({}));
+    announce(&mut conn, "/drive/x", 3, "int", json!({}));
+    announce(&mut conn, "/other", 4, "int", json!({}));
+    assert_eq!(names(&arm_announce), ["/arm/angle", "/arm/speed"]);
+    assert_eq!(names(&drive_announce), ["/arm/angle", "/drive/x"]);
+
+    // Values go to every client subscribed to their topic.
+    values(&mut conn, &[1, 2, 3, 4]);
+    assert_eq!(topic_ids(&arm_data), [1.0, 2.0]);
+    assert_eq!(topic_ids(&drive_data), [1.0, 3.0]);
+
+    // Closing one client unsubscribes only its own subscription.
+    arm.close().unwrap();
+    assert_eq!(sent_text(&send_text), json!({"method": "unsubscribe", "params": {"subuid": 0}}));
+    assert_eq!(error_message(arm.subscribe("/arm", options(true)).unwrap_err()), "virtual client closed");
+    arm.close().unwrap();
+    assert!(send_text.take().is_empty());
+
+    values(&mut conn, &[1, 2, 3]);
+    announce(&mut conn, "/arm/wrist", 5, "int", json!({}));
+    announce(&mut conn, "/drive/y", 6, "int", json!({}));
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/arm/speed", "id": 2}}).to_string()).unwrap();
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/drive/x", "id": 3}}).to_string()).unwrap();
+    assert!(arm_announce.take().is_empty() && arm_unannounce.take().is_empty() && arm_data.take().is_empty());
+    assert_eq!(topic_ids(&drive_data), [1.0, 3.0]);
+    assert_eq!(names(&drive_announce), ["/drive/y"]);
+    assert_eq!(drive_unannounce.take_one(), JsValue::from("/drive/x"));
+    assert!(drive.is_active_subscription(0).unwrap() && drive.is_active_subscription(1).unwrap());
+    send_binary.take();
+}
+
+#[wasm_bindgen_test]
+fn interpolation() {
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(on_data.function());
+    announce(&mut conn, "/speed", 1, "double", json!({}));
+    announce(&mut conn, "/pose", 2, "double[]", json!({}));
+    announce(&mut conn, "/name", 3, "string", json!({}));
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 1_000_i64, 1_u8, 2.0_f64)).unwrap()).unwrap();
+
+    // Enabled after a value arrived, starting from it.
+    conn.enable_interpolation("/speed", "linear".into(), JsValue::UNDEFINED).unwrap();
+    let sample = |conn: &mut Nt4Connection, name: &str, time: f64| -> (f64, bool) {
+        let sample: serde_json::Value = serde_wasm_bindgen::from_value(conn.sample_interpolated(name, time).unwrap()).unwrap();
+        (sample["value"].as_f64().unwrap(), sample["extrapolated"].as_bool().unwrap())
+    };
+    assert_eq!(sample(&mut conn, "/speed", 5_000.0), (2.0, true));
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 11_000_i64, 1_u8, 4.0_f64)).unwrap()).unwrap();
+    assert_eq!(sample(&mut conn, "/speed", 0.0), (2.0, false));
+    assert_eq!(sample(&mut conn, "/speed", 6_000.0), (3.0, false));
+    // Carried on along the last change for 100 ms at most.
+    assert_eq!(sample(&mut conn, "/speed", 21_000.0), (6.0, true));
+    assert_eq!(sample(&mut conn, "/speed", 1e6), (24.0, true));
+
+    // Theta turns the shorter way, across 180 degrees.
+    conn.enable_interpolation("/pose", "pose2d".into(), js(r#"{"degrees": true}"#)).unwrap();
+    let out = js_sys::Float64Array::new_with_length(3);
+    assert_eq!(conn.sample_interpolated_into("/pose", 0.0, &out).unwrap(), None);
+    conn.on_binary(rmp_serde::to_vec(&(2_i32, 0_i64, 17_u8, [0.0_f64, 0.0, 170.0])).unwrap()).unwrap();
+    conn.on_binary(rmp_serde::to_vec(&(2_i32, 1_000_i64, 17_u8, [2.0_f64, 4.0, -170.0])).unwrap()).unwrap();
+    assert_eq!(conn.sample_interpolated_into("/pose", 500.0, &out).unwrap(), Some(false));
+    assert_eq!(out.to_vec(), [1.0, 2.0, 180.0]);
+    assert!(conn.sample_interpolated_into("/pose", 500.0, &js_sys::Float64Array::new_with_length(2)).is_err());
+    on_data.take();
+
+    assert!(conn.enable_interpolation("/name", "linear".into(), JsValue::UNDEFINED).is_err());
+    assert!(conn.sample_interpolated("/name", 0.0).is_err());
+    conn.disable_interpolation("/speed");
+    assert!(conn.sample_interpolated("/speed", 0.0).is_err());
+}
+
+#[wasm_bindgen_test]
+fn widget_descriptors() {
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_on_data_fn(on_data.function());
+    let widgets = |conn: &Nt4Connection, prefix: &str| -> serde_json::Value {
+        serde_wasm_bindgen::from_value(conn.get_widget_descriptors(prefix).unwrap()).unwrap()
+    };
+
+    // Topics announced before the `.type` entry are claimed by the widget once it is.
+    announce(&mut conn, "/SmartDashboard/Field/Robot", 1, "double[]", json!({}));
+    announce(&mut conn, "/SmartDashboard/Field/.type", 2, "string", json!({}));
+    announce(&mut conn, "/SmartDashboard/Field/.controllable", 3, "boolean", json!({}));
+    announce(&mut conn, "/SmartDashboard/Arm/.type", 4, "string", json!({}));
+    announce(&mut conn, "/SmartDashboard/Arm/root/angle", 5, "double", json!({}));
+    announce(&mut conn, "/SmartDashboard/Speed", 6, "double", json!({}));
+    let mut message = rmp_serde::to_vec(&(2_i32, 100_i64, 4_u8, "Field2d")).unwrap();
+    message.extend(rmp_serde::to_vec(&(3_i32, 100_i64, 0_u8, true)).unwrap());
+    conn.on_binary(message).unwrap();
+    let expected = json!([
+        {"path": "/SmartDashboard/Arm", "widget_type": null, "name": null, "controllable": false,
+         "children": [{"name": "root/angle", "type": "double"}]},
+        {"path": "/SmartDashboard/Field", "widget_type": "Field2d", "name": null, "controllable": true,
+         "children": [{"name": "Robot", "type": "double[]"}]},
+    ]);
+    assert_eq!(widgets(&conn, "/SmartDashboard"), expected);
+    assert_eq!(widgets(&conn, "/SmartDashboard/F").as_array().unwrap().len(), 1);
+
+    // A nested widget takes its topics from the one around it, and gives them back when it goes.
+    announce(&mut conn, "/SmartDashboard/Arm/root/.type", 7, "string", json!({}));
+    let found = widgets(&conn, "/SmartDashboard/Arm");
+    assert_eq!(found[0]["children"], json!([]));
+    assert_eq!(found[1]["children"], json!([{"name": "angle", "type": "double"}]));
+    let unannounce = json!({"method": "unannounce", "params": {"name": "/SmartDashboard/Arm/root/.type", "id": 7}});
+    conn.on_text(unannounce.to_string()).unwrap();
+    assert_eq!(widgets(&conn, "/SmartDashboard/Arm")[0]["children"], json!([{"name": "root/angle", "type": "double"}]));
+
+    // Hidden entries stay out of listings unless asked for.
+    let names = |found: JsValue| -> Vec<String> {
+        let found: serde_json::Value = serde_wasm_bindgen::from_value(found).unwrap();
+        found.as_array().unwrap().iter().map(|x| x["name"].as_str().unwrap().to_string()).collect()
+    };
+    assert!(names(conn.search_topics("type", 10, JsValue::UNDEFINED, None).unwrap()).is_empty());
+    assert_eq!(names(conn.search_topics("type", 10, JsValue::UNDEFINED, Some(true)).unwrap()).len(), 2);
+    let diff: serde_json::Value = serde_wasm_bindgen::from_value(conn.topics_diff(0.0, JsValue::UNDEFINED, None).unwrap()).unwrap();
+    let listed: Vec<&str> = diff["added"].as_array().unwrap().iter().map(|x| x["name"].as_str().unwrap()).collect();
+    assert_eq!(listed, ["/SmartDashboard/Arm/root/angle", "/SmartDashboard/Field/Robot", "/SmartDashboard/Speed"]);
+    on_data.take();
+}
+
+#[wasm_bindgen_test]
+fn type_coercions() {
+    let delivered = Rc::new(RefCell::new(Vec::new()));
+    let recorded = delivered.clone();
+    let on_data = Closure::<dyn FnMut(JsValue, JsValue, JsValue, JsValue, JsValue)>::new(
+        move |_, _, data: JsValue, ty: JsValue, flag: JsValue| {
+            recorded.borrow_mut().push((format!("{:?}", data), ty.as_string().unwrap(), flag.is_truthy()))
+        },
+    );
+    let take = || -> Vec<(String, String, bool)> { delivered.borrow_mut().drain(..).collect() };
+    let warning_fn = Mock::new();
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(on_data.as_ref().unchecked_ref::<Function>().clone());
+    conn.set_warning_fn(warning_fn.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_include_type_in_callback(true);
+    conn.set_type_coercions(js(r#"{"/speed": "double", "/arm/": "int"}"#)).unwrap();
+    announce(&mut conn, "/speed", 1, "float", json!({}));
+    announce(&mut conn, "/arm/position", 2, "double", json!({}));
+
+    // Converted to the type asked for, and flagged when that loses something.
+    let mut message = rmp_serde::to_vec(&(1_i32, 100_i64, 3_u8, 1.5_f32)).unwrap();
+    message.extend(rmp_serde::to_vec(&(2_i32, 100_i64, 1_u8, 2.5_f64)).unwrap());
+    message.extend(rmp_serde::to_vec(&(2_i32, 200_i64, 1_u8, 3.0_f64)).unwrap());
+    conn.on_binary(message).unwrap();
+    let expected = [("JsValue(1.5)", "double", false), ("JsValue(2)", "int", true), ("JsValue(3)", "int", false)];
+    assert_eq!(take(), expected.map(|(data, ty, flag)| (data.to_string(), ty.to_string(), flag)));
+
+    // Values that cannot be converted are delivered as they came, and warned about once per run.
+    for timestamp in [300_i64, 400] {
+        conn.on_binary(rmp_serde::to_vec(&(1_i32, timestamp, 4_u8, "fast")).unwrap()).unwrap();
+    }
+    assert_eq!(take().into_iter().map(|(_, ty, flag)| (ty, flag)).collect::<Vec<_>>(), vec![("string".to_string(), true); 2]);
+    let warning = warning_fn.take_one().as_string().unwrap();
+    assert!(warning.contains("/speed") && warning.contains("string") && warning.contains("double"), "{}", warning);
+
+    // Values sent go out as the type the server announced, not the one published.
+    let pubuid = conn.publish("/speed", JsValue::from_str("double"), js("{}")).unwrap();
+    send_text.take();
+    conn.send_data(pubuid, JsValue::from_f64(2.5), JsValue::UNDEFINED).unwrap();
+    let (_, _, ty, value): (i32, i64, u8, f32) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    assert_eq!((ty, value), (3, 2.5));
+    let error = error_message(conn.send_data(pubuid, JsValue::from_str("fast"), JsValue::UNDEFINED).unwrap_err());
+    assert!(error.contains("/speed") && error.contains("float") && error.contains("string"), "{}", error);
+
+    let exported = conn.export_type_coercions().unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&exported).unwrap(), json!({"/arm/": "int", "/speed": "double"}));
+    conn.import_type_coercions("{}").unwrap();
+    conn.on_binary(rmp_serde::to_vec(&(1_i32, 500_i64, 3_u8, 1.5_f32)).unwrap()).unwrap();
+    assert_eq!(take(), [("JsValue(1.5)".to_string(), "float".to_string(), false)]);
+}
+
+#[wasm_bindgen_test]
+fn manifest() {
+    let warning_fn = Mock::new();
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_warning_fn(warning_fn.function());
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    let manifest = js(r#"[
+        {"name": "/Drive/Speed", "type": "double", "units": "m/s", "description": "Chassis speed"},
+        {"name": "/Drive/Pose", "type": "double[]"},
+        {"name": "/Arm/Angle", "type": "double", "units": "deg"}
+    ]"#);
+    conn.load_manifest(manifest.clone(), JsValue::UNDEFINED).unwrap();
+
+    // Warned about by default, thrown with "error", and only the topics the manifest knows go quietly.
+    conn.publish("/Drive/Speed", JsValue::from_str("double"), js("{}")).unwrap();
+    conn.subscribe("/Drive/", js(r#"{"prefix": true}"#)).unwrap();
+    assert!(warning_fn.take().is_empty());
+    conn.publish("/Drive/Speed", JsValue::from_str("float"), js("{}")).unwrap();
+    conn.subscribe("/Elevator/Height", js("{}")).unwrap();
+    let warnings: Vec<String> = warning_fn.take().iter().map(|x| x[0].as_string().unwrap()).collect();
+    assert_eq!(warnings, ["/Drive/Speed is a double topic in the manifest, not float", "/Elevator/Height is not in the manifest"]);
+    conn.load_manifest(manifest, JsValue::from_str("error")).unwrap();
+    send_text.take();
+    let error = error_message(conn.subscribe("/Elevator/", js(r#"{"prefix": true}"#)).unwrap_err());
+    assert_eq!(error, "/Elevator/ is not in the manifest");
+    assert!(send_text.take().is_empty());
+
+    let topics: serde_json::Value = serde_wasm_bindgen::from_value(conn.manifest_topics().unwrap()).unwrap();
+    assert_eq!(topics[0], json!({"name": "/Arm/Angle", "type": "double", "units": "deg", "description": null}));
+
+    announce(&mut conn, "/Drive/Speed", 1, "double", json!({}));
+    announce(&mut conn, "/Drive/Pose", 2, "float[]", json!({}));
+    announce(&mut conn, "/Drive/Gyro", 3, "double", json!({}));
+    announce(&mut conn, "/Drive/.type", 4, "string", json!({}));
+    let drift: serde_json::Value = serde_wasm_bindgen::from_value(conn.manifest_drift().unwrap()).unwrap();
+    let expected = json!([
+        {"kind": "missing", "name": "/Arm/Angle"},
+        {"kind": "unlisted", "name": "/Drive/Gyro"},
+        {"kind": "type", "name": "/Drive/Pose", "expected": "double[]", "announced": "float[]"},
+    ]);
+    assert_eq!(drift, expected);
+
+    let dts = nt4_wasm::generate_dts(js(r#"[{"name": "/b", "type": "int[]", "units": "ticks"}, {"name": "/a", "type": "raw"}]"#)).unwrap();
+    let expected = "// Generated from a topic manifest by nt4-wasm.\n\nexport type TopicName =\n    | \"/a\"\n    | \"/b\";\n\n\
+        export interface TopicValues {\n    \"/a\": Uint8Array;\n    /** ticks */\n    \"/b\": number[];\n}\n\n\
+        export type TopicValue<T extends TopicName> = TopicValues[T];\n";
+    assert_eq!(dts, expected);
+}
+
+#[wasm_bindgen_test]
+fn max_outgoing_frame_bytes() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+    let pubuids: Vec<i32> =
+        ["/a", "/b", "/c"].iter().map(|name| conn.publish(name, JsValue::from_str("double"), js("{}")).unwrap()).collect();
+    send_text.take();
+    let entries = js_sys::Array::new();
+    for &pubuid in &pubuids {
+        entries.push(&js(&json!({"topic_id": pubuid, "data": 2.5}).to_string()));
+    }
+    let timestamp = JsValue::from_f64(1_234_567.0);
+    let one = rmp_serde::to_vec(&(pubuids[0], 1_234_567_i64, 1_u8, 2.5_f64)).unwrap().len();
+    let sent_lengths = || -> Vec<usize> {
+        send_binary.take().iter().map(|x| serde_wasm_bindgen::from_value::<Vec<u8>>(x[0].clone()).unwrap().len()).collect()
+    };
+
+    // Atomic sends are one message or nothing: exactly three values fit, one byte less none.
+    conn.set_max_outgoing_frame_bytes(Some(3 * one as u32));
+    conn.send_atomic(entries.clone().into(), timestamp.clone()).unwrap();
+    assert_eq!(sent_lengths(), [3 * one]);
+    conn.set_max_outgoing_frame_bytes(Some(3 * one as u32 - 1));
+    let error = error_message(conn.send_atomic(entries.clone().into(), timestamp.clone()).unwrap_err());
+    assert_eq!(error, format!("3 values sent together are {} bytes encoded, over the {} byte frame limit", 3 * one, 3 * one - 1));
+    let values = js_sys::Object::new();
+    for name in ["a", "b", "c"] {
+        js_sys::Reflect::set(&values, &JsValue::from_str(name), &JsValue::from_f64(2.5)).unwrap();
+    }
+    let error = error_message(conn.send_struct_of_values("", values, timestamp.clone()).unwrap_err());
+    assert!(error.starts_with("3 values sent together"), "{}", error);
+    assert!(send_binary.take().is_empty());
+    conn.set_max_outgoing_frame_bytes(None);
+    conn.send_atomic(entries.into(), timestamp.clone()).unwrap();
+    assert_eq!(sent_lengths(), [3 * one]);
+
+    // A value over the limit on its own is an error, and nothing is sent.
+    let raw = conn.publish("/raw", JsValue::from_str("raw"), js("{}")).unwrap();
+    send_text.take();
+    conn.set_max_outgoing_frame_bytes(Some(64));
+    let big = js_sys::Uint8Array::from(&[7_u8; 64][..]);
+    let error = error_message(conn.send_data(raw, big.into(), timestamp).unwrap_err());
+    assert!(error.contains("over the 64 byte frame limit"), "{}", error);
+    assert!(send_binary.take().is_empty());
+    let error = error_message(conn.publish(&"/long".repeat(20), JsValue::from_str("raw"), js("{}")).unwrap_err());
+    assert!(error.contains("byte text message is over the 64 byte frame limit"), "{}", error);
+    assert!(send_text.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn scenario() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let announce = Mock::new();
+    let unannounce = Mock::new();
+    let ready = Mock::new();
+    let unready = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_announce_fn(announce.function());
+    conn.set_unannounce_fn(unannounce.function());
+    conn.set_ready_fn(ready.function());
+    conn.set_unready_fn(unready.function());
+    conn.set_on_data_fn(on_data.function());
+
+    let strings = |data: &[[JsValue; 4]]| -> Vec<String> { data.iter().filter_map(|x| x[2].as_string()).collect() };
+
+    let mut scenario = Nt4Scenario::from_json(include_str!("../scenarios/match.json")).unwrap();
+    let error = error_message(scenario.run_until(0.0).unwrap_err());
+    assert_eq!(error, "the scenario is not bound to a connection");
+    scenario.bind(&conn);
+    assert_eq!(scenario.end_us(), 6_000_000.0);
+
+    // Auto, up to the brownout.
+    scenario.run_until(1_999_999.0).unwrap();
+    assert_eq!(ready.take().len(), 1);
+    assert_eq!(announce.take().len(), 5);
+    let data = on_data.take();
+    assert_eq!(data.len(), 30);
+    assert_eq!(data.last().unwrap()[1], JsValue::from(1_960_000_i64));
+    assert_eq!(strings(&data), ["", "disabled", "auto"]);
+
+    // The brownout and teleop: the topics come back along with the climber.
+    scenario.run_until(3_000_000.0).unwrap();
+    assert_eq!(unready.take().len(), 1);
+    assert_eq!(ready.take().len(), 1);
+    assert_eq!(announce.take().len(), 6);
+    let data = on_data.take();
+    assert_eq!(data.len(), 4);
+    assert_eq!(strings(&data), ["teleop", "R"]);
+
+    assert!(!scenario.finished());
+    scenario.run_until(scenario.end_us()).unwrap();
+    assert!(scenario.finished());
+    assert_eq!(unannounce.take().len(), 1);
+    let data = on_data.take();
+    assert_eq!(data.len(), 6);
+    assert_eq!(strings(&data), ["disabled"]);
+}
+
+#[wasm_bindgen_test]
+fn local_echo() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let announce = Mock::new();
+    let on_data = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_announce_fn(announce.function());
+    conn.set_on_data_fn(on_data.function());
+    conn.set_timestamp_mode(JsValue::from_str("passthrough")).unwrap();
+
+    // We alone publish and subscribe to /mine, and the server gives it an id of its own.
+    let pubuid = conn.publish("/mine", JsValue::from_str("double"), js("{}")).unwrap();
+    let other = conn.publish("/other", JsValue::from_str("double"), js("{}")).unwrap();
+    conn.subscribe("/mine", js(r#"{"topicsonly": false, "prefix": false}"#)).unwrap();
+    let id = pubuid + 40;
+    for (name, id, pubuid) in [("/mine", id, pubuid), ("/other", id + 1, other)] {
+        let frame = json!({"method": "announce", "params": {"name": name, "id": id, "type": "double", "pubuid": pubuid, "properties": {}}});
+        conn.on_text(frame.to_string()).unwrap();
+    }
+    announce.take();
+    let record = |conn: &Nt4Connection, key: &str| serde_wasm_bindgen::from_value::<serde_json::Value>(conn.topic_record(js(key)).unwrap()).unwrap();
+    let expected = json!({"name": "/mine", "id": id, "pubuid": pubuid, "type": "double", "local_echo": false});
+    assert_eq!(record(&conn, r#"{"name": "/mine"}"#), expected);
+    assert_eq!(record(&conn, &format!(r#"{{"id": {}}}"#, id)), expected);
+    assert_eq!(record(&conn, &format!(r#"{{"pubuid": {}}}"#, pubuid)), expected);
+    assert!(conn.topic_record(js(r#"{"name": "/nothing"}"#)).unwrap().is_undefined());
+
+    // Without the echo, nothing comes of our own values.
+    conn.send_data(pubuid, JsValue::from_f64(1.0), JsValue::from_f64(1_000.0)).unwrap();
+    assert!(on_data.take().is_empty());
+
+    conn.set_local_echo(true);
+    conn.send_data(pubuid, JsValue::from_f64(1.5), JsValue::from_f64(2_000.0)).unwrap();
+    let calls = on_data.take();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0][0], JsValue::from(id));
+    assert_eq!(calls[0][1], JsValue::from(2_000_i64));
+    assert_eq!(calls[0][2].as_f64(), Some(1.5));
+    assert_eq!(record(&conn, r#"{"name": "/mine"}"#)["local_echo"], json!(true));
+
+    // Unsubscribed topics are cached, not delivered.
+    conn.send_data(other, JsValue::from_f64(3.0), JsValue::from_f64(2_000.0)).unwrap();
+    assert!(on_data.take().is_empty());
+    assert_eq!(record(&conn, &format!(r#"{{"pubuid": {}}}"#, other))["local_echo"], json!(true));
+
+    // A server that does send the value back is not heard twice, later values are.
+    conn.on_binary(rmp_serde::to_vec(&(id, 2_000_i64, 1_u8, 1.5_f64)).unwrap()).unwrap();
+    assert!(on_data.take().is_empty());
+    assert_eq!(record(&conn, r#"{"name": "/mine"}"#)["local_echo"], json!(false));
+    conn.on_binary(rmp_serde::to_vec(&(id, 3_000_i64, 1_u8, 2.5_f64)).unwrap()).unwrap();
+    assert_eq!(on_data.take_one_data().as_f64(), Some(2.5));
+
+    // The correlation goes with the publication.
+    conn.unpublish(pubuid).unwrap();
+    assert_eq!(record(&conn, &format!(r#"{{"id": {}}}"#, id))["pubuid"], json!(null));
+}
+
+#[wasm_bindgen_test]
+fn tab_sync() {
+    let send_text = Mock::new();
+    let send_binary = Mock::new();
+    let mut leader = Nt4Connection::new();
+    leader.set_send_text_fn(send_text.function()).unwrap();
+    leader.set_send_binary_fn(send_binary.function());
+    let leader_mocks: Vec<Mock> = (0..5).map(|_| Mock::new()).collect();
+    leader.set_announce_fn(leader_mocks[0].function());
+    leader.set_unannounce_fn(leader_mocks[1].function());
+    leader.set_ready_fn(leader_mocks[2].function());
+    leader.set_unready_fn(leader_mocks[3].function());
+    leader.set_on_data_fn(leader_mocks[4].function());
+    leader.timesync().unwrap();
+    let (_, _, _, local_time): (i32, i64, u8, i64) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    leader.on_binary(rmp_serde::to_vec(&(-1_i32, 5_000_000_i64, 2_u8, local_time)).unwrap()).unwrap();
+    for (name, id) in [("/a", 1), ("/b", 2)] {
+        let frame = json!({"method": "announce", "params": {"name": name, "id": id, "type": "double", "properties": {}}});
+        leader.on_text(frame.to_string()).unwrap();
+    }
+    let value = |leader: &mut Nt4Connection, id: i32, timestamp: i64, x: f64| {
+        leader.on_binary(rmp_serde::to_vec(&(id, timestamp, 1_u8, x)).unwrap()).unwrap();
+    };
+    value(&mut leader, 1, 5_000_100, 1.0);
+
+    struct Follower {
+        conn: Nt4Connection,
+        announce: Mock,
+        unannounce: Mock,
+        ready: Mock,
+        unready: Mock,
+        on_data: Mock,
+    }
+    let follower = || {
+        let (announce, unannounce, ready, unready, on_data) = (Mock::new(), Mock::new(), Mock::new(), Mock::new(), Mock::new());
+        let mut conn = Nt4Connection::follower(1000).unwrap();
+        conn.set_announce_fn(announce.function());
+        conn.set_unannounce_fn(unannounce.function());
+        conn.set_ready_fn(ready.function());
+        conn.set_unready_fn(unready.function());
+        conn.set_on_data_fn(on_data.function());
+        Follower { conn, announce, unannounce, ready, unready, on_data }
+    };
+    let values = |f: &Follower| -> Vec<f64> { f.on_data.take().iter().map(|x| x[2].as_f64().unwrap()).collect() };
+
+    // The first delta has everything, and makes the follower ready on the leader's clock.
+    let mut first = follower();
+    let d1 = leader.export_delta(0.0).unwrap();
+    assert!(first.conn.apply_delta(d1.clone()).unwrap());
+    assert_eq!(first.ready.take().len(), 1);
+    assert_eq!(first.announce.take().len(), 2);
+    assert_eq!(values(&first), [1.0]);
+    let s1 = first.conn.applied_delta_seq();
+    assert!(s1 > 0.0);
+
+    value(&mut leader, 1, 5_000_200, 2.0);
+    leader.on_text(json!({"method": "properties", "params": {"name": "/b", "update": {"retained": true}}}).to_string()).unwrap();
+    let d2 = leader.export_delta(s1).unwrap();
+    assert!(first.conn.apply_delta(d2.clone()).unwrap());
+    assert_eq!(values(&first), [2.0]);
+    assert_eq!(topic_properties(&first.conn, "/b")["retained"], json!(true));
+    let s2 = first.conn.applied_delta_seq();
+
+    // Duplicate and late deltas are ignored.
+    assert!(!first.conn.apply_delta(d2.clone()).unwrap());
+    assert!(!first.conn.apply_delta(d1.clone()).unwrap());
+    assert!(first.announce.take().is_empty() && first.on_data.take().is_empty());
+
+    leader.on_text(json!({"method": "unannounce", "params": {"name": "/b", "id": 2}}).to_string()).unwrap();
+    value(&mut leader, 1, 5_000_300, 3.0);
+    let d3 = leader.export_delta(s2).unwrap();
+
+    // A follower that missed d2 cannot take d3 until it has it.
+    let mut second = follower();
+    assert!(second.conn.apply_delta(d1).unwrap());
+    values(&second);
+    let error = error_message(second.conn.apply_delta(d3.clone()).unwrap_err());
+    assert!(error.contains("cannot be applied after seq"), "{}", error);
+    assert!(second.unannounce.take().is_empty() && second.on_data.take().is_empty());
+    assert!(second.conn.apply_delta(d2).unwrap());
+    assert!(second.conn.apply_delta(d3.clone()).unwrap());
+    assert!(first.conn.apply_delta(d3).unwrap());
+    for f in [&first, &second] {
+        assert_eq!(f.unannounce.take().len(), 1);
+        assert_eq!(values(f).last(), Some(&3.0));
+    }
+
+    // What the follower sends is kept for the leader to forward, with ids of its own.
+    let pubuid = second.conn.publish("/from_tab", JsValue::from_str("double"), js("{}")).unwrap();
+    assert_eq!(pubuid, 1000);
+    let outbound = second.conn.drain_outbound().unwrap();
+    assert_eq!(outbound.length(), 1);
+    let publish: serde_json::Value = serde_json::from_str(&outbound.get(0).as_string().unwrap()).unwrap();
+    assert_eq!(publish["params"]["pubuid"], json!(1000));
+    assert!(error_message(leader.drain_outbound().unwrap_err()).contains("only a follower"));
+
+    // A disconnect of the leader reaches the follower, and the reconnect starts over in full.
+    let s3 = first.conn.applied_delta_seq();
+    leader.on_disconnect().unwrap();
+    assert!(first.conn.apply_delta(leader.export_delta(s3).unwrap()).unwrap());
+    assert_eq!(first.unready.take().len(), 1);
+    leader.on_binary(rmp_serde::to_vec(&(-1_i32, 6_000_000_i64, 2_u8, local_time)).unwrap()).unwrap();
+    leader.on_text(json!({"method": "announce", "params": {"name": "/c", "id": 3, "type": "double", "properties": {}}}).to_string()).unwrap();
+    assert!(first.conn.apply_delta(leader.export_delta(first.conn.applied_delta_seq()).unwrap()).unwrap());
+    assert_eq!(first.ready.take().len(), 1);
+    assert_eq!(first.announce.take().len(), 1);
+}
+
+#[wasm_bindgen_test]
+fn text_before_send_text_fn() {
+    let send_text = Mock::new();
+    let mut conn = Nt4Connection::new();
+
+    // A widget registers what it needs before the socket is wired up, and gets its uids as usual.
+    let pubuid = conn.publish("/Widget/Setpoint", JsValue::from_str("double"), js("{}")).unwrap();
+    let subuid = conn.subscribe("/Robot/Pose", js("{}")).unwrap();
+    conn.set_properties("/Widget/Setpoint", js(r#"{"persistent": true}"#)).unwrap();
+    let other = conn.publish("/Widget/Other", JsValue::from_str("int"), js("{}")).unwrap();
+    conn.unpublish(other).unwrap();
+    conn.unsubscribe(subuid).unwrap();
+    assert!(conn.send_data(pubuid, JsValue::from(1.5), JsValue::UNDEFINED).is_err(), "values need send_binary_fn");
+
+    // A send_text_fn that throws keeps every frame for the next one.
+    let closed = Function::new_with_args("data", "throw new Error('closed')");
+    assert!(conn.set_send_text_fn(closed).is_err());
+
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    let methods: Vec<serde_json::Value> = send_text
+        .take()
+        .iter()
+        .map(|x| serde_json::from_str::<serde_json::Value>(&x[0].as_string().unwrap()).unwrap())
+        .map(|x| json!([x["method"], x["params"]["pubuid"].clone(), x["params"]["subuid"].clone()]))
+        .collect();
+    assert_eq!(
+        methods,
+        [
+            json!(["publish", pubuid, null]),
+            json!(["subscribe", null, subuid]),
+            json!(["setproperties", null, null]),
+            json!(["publish", other, null]),
+            json!(["unpublish", other, null]),
+            json!(["unsubscribe", null, subuid]),
+        ]
+    );
+
+    // Once set, frames go straight out.
+    conn.unpublish(pubuid).unwrap();
+    assert_eq!(sent_text(&send_text)["method"], json!("unpublish"));
+}
+
+#[wasm_bindgen_test]
+fn large_integer_properties() {
+    let send_text = Mock::new();
+    let announce_fn = Mock::new();
+    let properties_changed = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(send_text.function()).unwrap();
+    conn.set_announce_fn(announce_fn.function());
+    conn.set_properties_changed_fn(properties_changed.function());
+    let big = 1_u64 << 60;
+
+    // Integers past Number.MAX_SAFE_INTEGER come to JS as exact BigInts, at any depth.
+    let properties = json!({"timestamp": big, "min": i64::MIN, "max": u64::MAX, "nested": {"at": [big]}, "small": 5, "ratio": 0.5});
+    announce(&mut conn, "/big", 1, "int", properties);
+    let get = |object: &JsValue, key: &str| js_sys::Reflect::get(object, &JsValue::from_str(key)).unwrap();
+    let properties = conn.get_topic_properties("/big").unwrap();
+    assert_eq!(get(&properties, "timestamp"), JsValue::from(big));
+    assert_eq!(get(&properties, "min"), JsValue::from(i64::MIN));
+    assert_eq!(get(&properties, "max"), JsValue::from(u64::MAX));
+    assert_eq!(js_sys::Array::from(&get(&get(&properties, "nested"), "at")).get(0), JsValue::from(big));
+    assert_eq!(get(&properties, "small"), JsValue::from(5.0));
+    assert_eq!(get(&properties, "ratio"), JsValue::from(0.5));
+
+    properties_update(&mut conn, "/big", json!({"timestamp": big + 1}), false);
+    let calls = properties_changed.take();
+    assert_eq!(get(&calls[0][1], "timestamp"), JsValue::from(big + 1));
+    let snapshot = conn.snapshot().unwrap();
+    let topic = get(&get(&snapshot, "topics"), "/big");
+    assert_eq!(get(&get(&topic, "properties"), "timestamp"), JsValue::from(big + 1));
+
+    // And go back to the server as the same integers, whether given as BigInts or as whole numbers.
+    let update = js_sys::Object::new();
+    js_sys::Reflect::set(&update, &JsValue::from_str("timestamp"), &JsValue::from(big + 2)).unwrap();
+    js_sys::Reflect::set(&update, &JsValue::from_str("rounded"), &JsValue::from(big as f64)).unwrap();
+    js_sys::Reflect::set(&update, &JsValue::from_str("max"), &JsValue::from(u64::MAX)).unwrap();
+    conn.set_properties("/big", update.into()).unwrap();
+    let sent = send_text.take_one().as_string().unwrap();
+    let sent: serde_json::Value = serde_json::from_str(&sent).unwrap();
+    assert_eq!(sent["params"]["update"], json!({"timestamp": big + 2, "rounded": big, "max": u64::MAX}));
+
+    let update = js_sys::Object::new();
+    js_sys::Reflect::set(&update, &JsValue::from_str("huge"), &JsValue::from(u128::MAX)).unwrap();
+    let error = error_message(conn.set_properties("/big", update.into()).unwrap_err());
+    assert!(error.contains("out of the 64-bit range"), "{}", error);
+}
+
+#[wasm_bindgen_test]
+fn alerts() {
+    let alert_fn = Mock::new();
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(Function::new_no_args(""));
+    conn.set_ready_fn(Function::new_no_args(""));
+    conn.set_unready_fn(Function::new_no_args(""));
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_alert_fn(alert_fn.function());
+    announce(&mut conn, "/battery", 1, "double", json!({}));
+    announce(&mut conn, "/current", 2, "double[]", json!({}));
+    announce(&mut conn, "/heartbeat", 3, "int", json!({}));
+    let send = |conn: &mut Nt4Connection, id: i32, timestamp: i64, value: serde_json::Value| {
+        let message = match value {
+            serde_json::Value::Array(x) => {
+                rmp_serde::to_vec(&(id, timestamp, 17_u8, x.iter().map(|x| x.as_f64().unwrap()).collect::<Vec<_>>()))
+            },
+            x if x.is_i64() => rmp_serde::to_vec(&(id, timestamp, 2_u8, x.as_i64().unwrap())),
+            x => rmp_serde::to_vec(&(id, timestamp, 1_u8, x.as_f64().unwrap())),
+        };
+        conn.on_binary(message.unwrap()).unwrap();
+    };
+    let alerts = |alert_fn: &Mock| -> Vec<(String, bool, Option<f64>, JsValue)> {
+        let calls = alert_fn.take().into_iter();
+        calls.map(|[name, active, value, since]| (name.as_string().unwrap(), active.is_truthy(), value.as_f64(), since)).collect()
+    };
+    let alert = |name: &str, active: bool, value: Option<f64>, since: i64| (name.to_string(), active, value, JsValue::from(since));
+
+    assert!(conn.add_alert("/battery", js("{}")).is_err());
+    assert!(conn.add_alert("/battery", js(r#"{"below": 12, "above": 10}"#)).is_err());
+    assert!(conn.add_alert("/current", js(r#"{"above": 40, "reduce": "median"}"#)).is_err());
+    conn.add_alert("/battery", js(r#"{"below": 11, "for_ms": 1000, "hysteresis": 0.5}"#)).unwrap();
+    conn.add_alert("/current", js(r#"{"above": 40, "reduce": "max"}"#)).unwrap();
+    conn.add_alert("/heartbeat", js(r#"{"missing_ms": 500}"#)).unwrap();
+
+    // The battery has to stay low for a second, by server time, and data that stops counts too.
+    send(&mut conn, 1, 0, json!(12.0));
+    send(&mut conn, 1, 100_000, json!(10.8));
+    send(&mut conn, 1, 600_000, json!(10.9));
+    assert_eq!(alerts(&alert_fn), [alert("/heartbeat", true, None, 0)]);
+    send(&mut conn, 1, 1_100_000, json!(10.7));
+    assert_eq!(alerts(&alert_fn), [alert("/battery", true, Some(10.7), 100_000)]);
+    send(&mut conn, 3, 1_200_000, json!(7));
+    assert_eq!(alerts(&alert_fn), [alert("/heartbeat", false, Some(7.0), 1_200_000)]);
+
+    // It only clears once back in range by the hysteresis, and a dip shorter than for_ms never alerts.
+    send(&mut conn, 1, 1_300_000, json!(11.2));
+    assert!(alerts(&alert_fn).is_empty());
+    send(&mut conn, 1, 1_400_000, json!(11.6));
+    assert_eq!(alerts(&alert_fn), [alert("/battery", false, Some(11.6), 1_400_000)]);
+    send(&mut conn, 1, 1_500_000, json!(10.9));
+    send(&mut conn, 3, 1_600_000, json!(8));
+    send(&mut conn, 1, 1_700_000, json!(11.1));
+    send(&mut conn, 3, 2_800_000, json!(9));
+    assert!(alerts(&alert_fn).is_empty());
+
+    // Arrays are reduced as asked.
+    send(&mut conn, 2, 2_900_000, json!([10.0, 45.0, 3.0]));
+    assert_eq!(alerts(&alert_fn), [alert("/current", true, Some(45.0), 2_900_000)]);
+    send(&mut conn, 2, 3_000_000, json!([10.0, 20.0]));
+    assert_eq!(alerts(&alert_fn), [alert("/current", false, Some(20.0), 3_000_000)]);
+
+    // A timesync response is stamped with the server clock too.
+    send(&mut conn, 1, 3_000_000, json!(10.0));
+    send(&mut conn, 3, 3_000_000, json!(10));
+    conn.timesync().unwrap();
+    let (_, _, _, local_time): (i32, i64, u8, i64) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    conn.on_binary(rmp_serde::to_vec(&(TIMESYNC_TOPIC_ID, 4_000_000_i64, 2_u8, local_time)).unwrap()).unwrap();
+    assert_eq!(alerts(&alert_fn), [alert("/battery", true, Some(10.0), 3_000_000), alert("/heartbeat", true, None, 3_000_000)]);
+
+    // Alerts are listed, and move between connections as JSON.
+    let listed: serde_json::Value = serde_wasm_bindgen::from_value(conn.alerts().unwrap()).unwrap();
+    assert_eq!(
+        listed,
+        json!([
+            {"name": "/battery", "below": 11, "for_ms": 1000, "hysteresis": 0.5, "active": true},
+            {"name": "/current", "above": 40, "for_ms": 0, "hysteresis": 0, "reduce": "max", "active": false},
+            {"name": "/heartbeat", "for_ms": 0, "hysteresis": 0, "missing_ms": 500, "active": true},
+        ])
+    );
+    let mut other = Nt4Connection::new();
+    other.import_alerts(&conn.export_alerts().unwrap()).unwrap();
+    assert_eq!(other.export_alerts().unwrap(), conn.export_alerts().unwrap());
+    assert!(other.import_alerts(r#"{"/x": {"hysteresis": 1}}"#).is_err());
+    assert_eq!(other.alerts().unwrap().unchecked_into::<js_sys::Array>().length(), 3);
+    assert!(other.remove_alert("/current"));
+    assert!(!other.remove_alert("/current"));
+
+    // A disconnect clears what is on.
+    conn.on_disconnect().unwrap();
+    let cleared: Vec<_> = alerts(&alert_fn).into_iter().map(|(name, active, value, _)| (name, active, value)).collect();
+    assert_eq!(cleared, [("/battery".to_string(), false, None), ("/heartbeat".to_string(), false, None)]);
+}
+
+#[wasm_bindgen_test]
+fn multiplexer_routing() {
+    let robot_text = Mock::new();
+    let sim_text = Mock::new();
+    let sim_binary = Mock::new();
+    let announce_fn = Mock::new();
+    let mut mux = Nt4Multiplexer::new();
+    mux.set_announce_fn(announce_fn.function());
+    mux.add_connection("robot").unwrap();
+    mux.add_connection("sim").unwrap();
+    assert!(mux.add_connection("sim").is_err());
+    assert!(mux.add_connection("a:b").is_err());
+    mux.set_send_text_fn("robot", robot_text.function()).unwrap();
+    mux.set_send_text_fn("sim", sim_text.function()).unwrap();
+    mux.set_send_binary_fn("sim", sim_binary.function()).unwrap();
+
+    // The prefix picks the connection, and only the rest of the path is sent.
+    let robot_sub = mux.subscribe("robot:/Drive", js("{}")).unwrap();
+    assert_eq!(sent_text(&robot_text)["params"]["topics"], json!(["/Drive"]));
+    let sim_sub = mux.subscribe("sim:/Drive", js("{}")).unwrap();
+    assert_eq!(sent_text(&sim_text)["params"]["topics"], json!(["/Drive"]));
+    assert_ne!(robot_sub, sim_sub);
+    assert!(mux.subscribe("/Drive", js("{}")).is_err());
+    assert!(mux.subscribe("coprocessor:/Drive", js("{}")).is_err());
+
+    // Ids are the multiplexer's own and map back to the connection and its uid.
+    let pubuid = mux.publish("sim:/Arm/Setpoint", JsValue::from_str("double"), js("{}")).unwrap();
+    let inner = sent_text(&sim_text)["params"]["pubuid"].as_i64().unwrap() as i32;
+    mux.send_data(pubuid, JsValue::from_f64(1.5), JsValue::UNDEFINED).unwrap();
+    let (id, _, _, value): (i32, i64, u8, f64) = rmp_serde::from_slice(&sent_binary(&sim_binary)).unwrap();
+    assert_eq!((id, value), (inner, 1.5));
+    mux.unsubscribe(robot_sub).unwrap();
+    assert_eq!(sent_text(&robot_text)["method"], json!("unsubscribe"));
+    assert!(mux.unsubscribe(robot_sub).is_err());
+    assert!(sim_text.take().is_empty());
+
+    // Announces are named by connection, and callbacks get the connection name first.
+    let params = json!({"name": "/Drive/x", "id": 3, "type": "double", "properties": {}});
+    let announce = json!({"method": "announce", "params": params});
+    mux.on_text("robot", announce.to_string()).unwrap();
+    mux.on_text("sim", announce.to_string()).unwrap();
+    let names: Vec<_> = announce_fn.take().into_iter().map(|[name, ..]| name.as_string().unwrap()).collect();
+    assert_eq!(names, ["robot", "sim"]);
+    let topics: Vec<Topic> = serde_wasm_bindgen::from_value(mux.topics(None).unwrap()).unwrap();
+    let topics: Vec<_> = topics.iter().map(|topic| &*topic.name).collect();
+    assert_eq!(topics, ["robot:/Drive/x", "sim:/Drive/x"]);
+
+    // Removing a connection forgets its ids and leaves the others working.
+    mux.remove_connection("sim").unwrap();
+    assert!(mux.send_data(pubuid, JsValue::from_f64(2.0), JsValue::UNDEFINED).is_err());
+    assert!(mux.unsubscribe(sim_sub).is_err());
+    mux.subscribe("robot:/Arm", js("{}")).unwrap();
+    assert_eq!(sent_text(&robot_text)["params"]["topics"], json!(["/Arm"]));
+    let states: serde_json::Value = serde_wasm_bindgen::from_value(mux.connection_states().unwrap()).unwrap();
+    assert_eq!(states, json!([{"name": "robot", "ready": false, "topics": 1}]));
+}
+
+#[wasm_bindgen_test]
+fn type_changes() {
+    let type_changed = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_unannounce_fn(Function::new_no_args(""));
+    conn.set_on_data_fn(Function::new_no_args(""));
+    conn.set_type_changed_fn(type_changed.function());
+    let changes = |mock: &Mock| -> Vec<[String; 3]> {
+        mock.take().into_iter().map(|call| [0, 1, 2].map(|i| call[i].as_string().unwrap())).collect()
+    };
+    let change = |old: &str, new: &str| ["/arm".to_string(), old.to_string(), new.to_string()];
+    let topic = |conn: &mut Nt4Connection| {
+        let snapshot: serde_json::Value = serde_json::from_str(&conn.snapshot_json().unwrap()).unwrap();
+        let topic = &snapshot["topics"]["/arm"];
+        (topic["id"].clone(), topic["type"].clone(), topic["value"].is_null())
+    };
+
+    // Unannounced, then announced again with another type.
+    announce(&mut conn, "/arm", 1, "double", json!({}));
+    conn.on_binary(rmp_serde::to_vec(&(1, 1_000_i64, 1_u8, 2.5)).unwrap()).unwrap();
+    assert_eq!(topic(&mut conn), (json!(1), json!("double"), false));
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/arm", "id": 1}}).to_string()).unwrap();
+    announce(&mut conn, "/arm", 2, "double[]", json!({}));
+    assert_eq!(changes(&type_changed), [change("double", "double[]")]);
+    assert_eq!(topic(&mut conn), (json!(2), json!("double[]"), true));
+
+    // Announced again without an unannounce, under the same id and under a new one. The old value goes either way.
+    conn.on_binary(rmp_serde::to_vec(&(2, 2_000_i64, 17_u8, vec![1.0, 2.0])).unwrap()).unwrap();
+    assert_eq!(topic(&mut conn), (json!(2), json!("double[]"), false));
+    announce(&mut conn, "/arm", 2, "int", json!({}));
+    assert_eq!(changes(&type_changed), [change("double[]", "int")]);
+    assert_eq!(topic(&mut conn), (json!(2), json!("int"), true));
+    conn.on_binary(rmp_serde::to_vec(&(2, 3_000_i64, 2_u8, 7)).unwrap()).unwrap();
+    announce(&mut conn, "/arm", 3, "boolean", json!({}));
+    assert_eq!(changes(&type_changed), [change("int", "boolean")]);
+    assert_eq!(topic(&mut conn), (json!(3), json!("boolean"), true));
+    assert_eq!(topic_count(&mut conn), 1);
+
+    // The same type again is no change.
+    conn.on_text(json!({"method": "unannounce", "params": {"name": "/arm", "id": 3}}).to_string()).unwrap();
+    announce(&mut conn, "/arm", 4, "boolean", json!({}));
+    assert!(type_changed.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn atomic_sends() {
+    let send_binary = Mock::new();
+    let mut conn = Nt4Connection::new();
+    conn.set_send_text_fn(Function::new_no_args("")).unwrap();
+    conn.set_send_binary_fn(send_binary.function());
+    let [kp, ki, count] = [("/pid/kP", "double"), ("/pid/kI", "double"), ("/pid/count", "int")]
+        .map(|(name, ty)| conn.publish(name, JsValue::from_str(ty), js("{}")).unwrap());
+    let entries = |values: serde_json::Value| -> JsValue { js(&values.to_string()) };
+    let object = |json: &str| -> js_sys::Object { js(json).unchecked_into() };
+    // Every [id, timestamp, type, value] in the one message sent.
+    let sent = |mock: &Mock| -> Vec<(i32, i64, u8, serde_json::Value)> {
+        let message = sent_binary(mock);
+        let mut reader = &message[..];
+        let mut frames = Vec::new();
+        while !reader.is_empty() {
+            frames.push(rmp_serde::from_read(&mut reader).unwrap());
+        }
+        frames
+    };
+
+    // In the order given, with one timestamp, and numbers sent as the type of their topic.
+    let values = json!([{"topic_id": ki, "data": 0.5}, {"topic_id": count, "data": 3}, {"topic_id": kp, "data": 2}]);
+    conn.send_atomic(entries(values), JsValue::UNDEFINED).unwrap();
+    let frames = sent(&send_binary);
+    let timestamp = frames[0].1;
+    assert_eq!(
+        frames,
+        [(ki, timestamp, 1, json!(0.5)), (count, timestamp, 2, json!(3)), (kp, timestamp, 1, json!(2.0))]
+    );
+
+    // A value of another type fails the whole send before anything is encoded.
+    let values = json!([{"topic_id": kp, "data": 1.0}, {"topic_id": ki, "data": "fast"}]);
+    let error = error_message(conn.send_atomic(entries(values), JsValue::UNDEFINED).unwrap_err());
+    assert_eq!(error, "entry 1: /pid/kI is published as double, a string value does not fit it");
+    let values = json!([{"topic_id": kp, "data": 1.0}, {"topic_id": count, "data": 2.5}]);
+    let error = error_message(conn.send_atomic(entries(values), JsValue::UNDEFINED).unwrap_err());
+    assert_eq!(error, "entry 1: /pid/count is published as int, a double value does not fit it");
+    assert!(send_binary.take().is_empty());
+
+    // Struct sends keep the order of the object's keys and check types the same way.
+    conn.send_struct_of_values("/pid", object(r#"{"kP": 1.5, "count": 4, "kI": 0}"#), JsValue::UNDEFINED).unwrap();
+    let ids: Vec<_> = sent(&send_binary).into_iter().map(|(id, _, ty, _)| (id, ty)).collect();
+    assert_eq!(ids, [(kp, 1), (count, 2), (ki, 1)]);
+    let values = object(r#"{"kP": 1.5, "count": [4.5]}"#);
+    let error = error_message(conn.send_struct_of_values("/pid", values, JsValue::UNDEFINED).unwrap_err());
+    assert_eq!(error, "/pid/count is published as int, a double[] value does not fit it");
+    assert!(send_binary.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn server_time_reset() {
+    let send_binary = Mock::new();
+    let reset = Mock::new();
+    let mut conn = Nt4Connection::new();
+    ignore_announces(&mut conn);
+    conn.set_on_data_fn(Function::new_no_args(""));
+    conn.set_send_binary_fn(send_binary.function());
+    conn.set_server_time_reset_fn(reset.function());
+    conn.set_server_time_reset_options(js(r#"{"threshold_s": 5, "window_s": 60, "min_topics": 2}"#)).unwrap();
+    for (name, id) in [("/a", 1), ("/b", 2), ("/c", 3)] {
+        announce(&mut conn, name, id, "int", json!({}));
+    }
+    let value = |id: i32, seconds: f64| rmp_serde::to_vec(&(id, (seconds * 1e6) as i64, 2_u8, 0_i64)).unwrap();
+    let last_updates = |conn: &Nt4Connection| -> Vec<i64> {
+        let diff: serde_json::Value = serde_wasm_bindgen::from_value(conn.topics_diff(0.0, JsValue::UNDEFINED, None).unwrap()).unwrap();
+        diff["added"].as_array().unwrap().iter().map(|x| x["last_update_us"].as_i64().unwrap()).collect()
+    };
+    for id in [1, 2, 3] {
+        conn.on_binary(value(id, 100.0)).unwrap();
+    }
+
+    // Going back less than the threshold, or on fewer topics than required, is not a reset.
+    conn.on_binary(value(1, 97.0)).unwrap();
+    conn.on_binary(value(1, 1.0)).unwrap();
+    assert!(reset.take().is_empty());
+
+    conn.on_binary(value(2, 2.0)).unwrap();
+    let calls = reset.take();
+    assert_eq!(calls.len(), 1);
+    // Server times in µs, as BigInts like every timestamp.
+    let old_estimate = i64::try_from(calls[0][0].clone()).unwrap();
+    assert_eq!(calls[0][1], JsValue::from(2_000_000_i64));
+    // /c has not gone backwards yet, so its value is moved onto the new clock.
+    assert_eq!(last_updates(&conn), [1_000_000, 2_000_000, 100_000_000 + 2_000_000 - old_estimate]);
+    let (id, _, _, _): (i32, i64, u8, i64) = rmp_serde::from_slice(&sent_binary(&send_binary)).unwrap();
+    assert_eq!(id, TIMESYNC_TOPIC_ID);
+
+    // Nothing is detected while disabled.
+    conn.set_server_time_reset_options(js(r#"{"enabled": false}"#)).unwrap();
+    for id in [1, 2, 3] {
+        conn.on_binary(value(id, -100.0)).unwrap();
+    }
+    assert!(reset.take().is_empty());
+    assert!(send_binary.take().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn share_state() {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    let encode = |state: serde_json::Value| encode_share_state(serde::Serialize::serialize(&state, &serializer).unwrap());
+    let decode = |encoded: &str| -> serde_json::Value { serde_wasm_bindgen::from_value(decode_share_state(encoded).unwrap()).unwrap() };
+    let state = json!({"topics": ["/drive/speed", "/arm/angle"], "cursor_us": 123456789, "values": {"/arm/angle": 1.5, "/auto": "left"}});
+    let encoded = encode(state.clone()).unwrap();
+    assert!(encoded.bytes().all(|x| x.is_ascii_alphanumeric() || x == b'-' || x == b'_'), "{}", encoded);
+    assert_eq!(decode(&encoded), state);
+
+    // Long strings and arrays are cut short with a marker, at any depth.
+    let long = "x".repeat(100);
+    let items: Vec<i32> = (0..20).collect();
+    let decoded = decode(&encode(json!({"note": long, "plot": {"topics": items}})).unwrap());
+    assert_eq!(decoded["note"], json!(format!("{}…", "x".repeat(63))));
+    let mut shortened: Vec<serde_json::Value> = (0..16).map(|x| json!(x)).collect();
+    shortened.push(json!({"…": 4}));
+    assert_eq!(decoded["plot"]["topics"], json!(shortened));
+
+    // At most 2048 characters either way.
+    let values: serde_json::Map<String, serde_json::Value> =
+        (0..40).map(|x| (format!("/SmartDashboard/{:02}", x), json!("y".repeat(40)))).collect();
+    let error = error_message(encode(json!({"values": values})).unwrap_err());
+    assert!(error.starts_with("share state is ") && error.ends_with(" characters, over the limit of 2048"), "{}", error);
+    let error = error_message(decode_share_state(&"A".repeat(2049)).unwrap_err());
+    assert_eq!(error, "share state is over the limit of 2048 characters");
+
+    let mut nested = json!(1);
+    for _ in 0..9 {
+        nested = json!([nested]);
+    }
+    assert_eq!(error_message(encode(nested).unwrap_err()), "share state is nested deeper than 8 levels");
+    let errors = ["", "Ag", "AQ=", "A"].map(|x| error_message(decode_share_state(x).unwrap_err()));
+    assert_eq!(errors, [
+        "share state is empty",
+        "share state version 2 is not supported, only 1",
+        "share state has an invalid character '=' at 2",
+        "share state is truncated",
+    ]);
+}
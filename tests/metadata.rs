@@ -0,0 +1,84 @@
+//! Publisher bookkeeping from `$pub$` and `$clientpub$` metadata values, encoded as the WPILib
+//! server encodes them: arrays of maps, keys in the order it writes them.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::Wire;
+use nt4_wasm::{ConnectionCore, ConnectionEvent};
+use serde_json::json;
+
+/// The ids of the values delivered since the last call.
+fn take_values(wire: &mut Wire) -> Vec<i32> {
+    let values = wire.take_events().into_iter().filter_map(|x| match x {
+        ConnectionEvent::Value { topic_id, .. } => Some(topic_id),
+        _ => None,
+    });
+    values.collect()
+}
+
+/// `[{"client": "robot@0", "pubuid": 3}, {"client": "dashboard@2", "pubuid": 1}]`
+const POSE_PUBLISHERS: &[u8] =
+    b"\x92\x82\xa6client\xa7robot@0\xa6pubuid\x03\x82\xa6client\xabdashboard@2\xa6pubuid\x01";
+/// `[{"uid": 1, "topic": "/Drive/Pose"}, {"uid": 2, "topic": "/Arm/Goal"}]`
+const DASHBOARD_PUBLICATIONS: &[u8] =
+    b"\x92\x82\xa3uid\x01\xa5topic\xab/Drive/Pose\x82\xa3uid\x02\xa5topic\xa9/Arm/Goal";
+/// `[{"uid": 1, "topic": "/Drive/Pose"}]`
+const ROBOT_PUBLICATIONS: &[u8] = b"\x91\x82\xa3uid\x01\xa5topic\xab/Drive/Pose";
+
+fn announce(core: &mut ConnectionCore, wire: &mut Wire, name: &str, id: i32) {
+    common::announce(core, wire, name, id, "msgpack", json!({}));
+}
+
+fn value(core: &mut ConnectionCore, wire: &mut Wire, id: i32, payload: &[u8]) {
+    core.on_binary(wire, &common::value(id, 1_000, 5, serde_bytes::Bytes::new(payload))).unwrap();
+}
+
+#[test]
+fn publishers_from_metadata() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    announce(&mut core, &mut wire, "$pub$/Drive/Pose", 10);
+    announce(&mut core, &mut wire, "$clientpub$dashboard@2", 11);
+    announce(&mut core, &mut wire, "$clientpub$robot@0", 12);
+    value(&mut core, &mut wire, 10, POSE_PUBLISHERS);
+    value(&mut core, &mut wire, 11, DASHBOARD_PUBLICATIONS);
+    value(&mut core, &mut wire, 12, ROBOT_PUBLICATIONS);
+    let warnings = wire.take_warnings();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+
+    assert_eq!(core.publishers_of("/Drive/Pose"), ["dashboard@2", "robot@0"]);
+    assert_eq!(core.publishers_of("/Arm/Goal"), ["dashboard@2"]);
+    assert!(core.publishers_of("/Arm/Angle").is_empty());
+    assert_eq!(core.topics_of_client("dashboard@2"), ["/Arm/Goal", "/Drive/Pose"]);
+    assert_eq!(core.topics_of_client("robot@0"), ["/Drive/Pose"]);
+
+    // Updates replace what the topic said before.
+    value(&mut core, &mut wire, 11, b"\x91\x82\xa3uid\x02\xa5topic\xa9/Arm/Goal");
+    value(&mut core, &mut wire, 10, b"\x91\x82\xa6client\xa7robot@0\xa6pubuid\x03");
+    assert_eq!(core.publishers_of("/Drive/Pose"), ["robot@0"]);
+    assert_eq!(core.topics_of_client("dashboard@2"), ["/Arm/Goal"]);
+
+    // And an unannounce forgets it.
+    let unannounce = json!({"method": "unannounce", "params": {"name": "$clientpub$dashboard@2", "id": 11}});
+    core.on_text(&mut wire, &unannounce.to_string()).unwrap();
+    assert!(core.topics_of_client("dashboard@2").is_empty());
+    assert_eq!(core.topics_of_client("robot@0"), ["/Drive/Pose"]);
+}
+
+#[test]
+fn undecodable_metadata_is_delivered() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    announce(&mut core, &mut wire, "$pub$/Drive/Pose", 10);
+    value(&mut core, &mut wire, 10, POSE_PUBLISHERS);
+
+    // A map where the array of publishers belongs.
+    value(&mut core, &mut wire, 10, b"\x81\xa6client\xa7robot@0");
+    let warnings = wire.take_warnings();
+    assert_eq!(take_values(&mut wire), [10, 10]);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("Invalid metadata for \"$pub$/Drive/Pose\": "), "{}", warnings[0]);
+    // What the topic said before stands.
+    assert_eq!(core.publishers_of("/Drive/Pose"), ["dashboard@2", "robot@0"]);
+}
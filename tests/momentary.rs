@@ -0,0 +1,75 @@
+//! Held momentaries are forced back to `false` on disconnect, on close and by
+//! `release_all_momentaries`, and only those that are held.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::Wire;
+use nt4_wasm::ConnectionCore;
+
+/// The (pubuid, value) of every boolean sent since the last call.
+fn take_booleans(wire: &mut Wire) -> Vec<(i32, bool)> {
+    let decode = |message: Vec<u8>| {
+        let (id, _, ty, value): (i32, i64, u8, bool) = rmp_serde::from_slice(&message).unwrap();
+        assert_eq!(ty, 0);
+        (id, value)
+    };
+    wire.take_binary().into_iter().map(decode).collect()
+}
+
+/// A connection with two momentaries of which only the first is held.
+fn held() -> (ConnectionCore, Wire, i32, i32) {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let fire = core.momentary(&mut wire, "/Dashboard/Fire").unwrap();
+    let intake = core.momentary(&mut wire, "/Dashboard/Intake").unwrap();
+    core.press(&mut wire, fire).unwrap();
+    core.press(&mut wire, intake).unwrap();
+    core.release(&mut wire, intake).unwrap();
+    assert_eq!(take_booleans(&mut wire), [(fire, true), (intake, true), (intake, false)]);
+    (core, wire, fire, intake)
+}
+
+#[test]
+fn release_all_momentaries() {
+    let (mut core, mut wire, fire, _) = held();
+    core.release_all_momentaries(&mut wire).unwrap();
+    assert_eq!(take_booleans(&mut wire), [(fire, false)]);
+    core.release_all_momentaries(&mut wire).unwrap();
+    assert!(wire.take_binary().is_empty());
+
+    // A failed send is reported, and the momentary counts as released all the same.
+    core.press(&mut wire, fire).unwrap();
+    take_booleans(&mut wire);
+    wire.down = true;
+    assert_eq!(core.release_all_momentaries(&mut wire).unwrap_err(), "socket closed");
+    wire.down = false;
+    core.release_all_momentaries(&mut wire).unwrap();
+    assert!(wire.take_binary().is_empty());
+}
+
+#[test]
+fn disconnect_releases_momentaries() {
+    let (mut core, mut wire, fire, intake) = held();
+    core.on_disconnect(&mut wire).unwrap();
+    assert_eq!(take_booleans(&mut wire), [(fire, false)]);
+
+    // Still momentaries after the disconnect, and the socket may already be gone.
+    core.press(&mut wire, fire).unwrap();
+    core.press(&mut wire, intake).unwrap();
+    take_booleans(&mut wire);
+    wire.down = true;
+    core.on_disconnect(&mut wire).unwrap();
+    wire.down = false;
+    core.release_all_momentaries(&mut wire).unwrap();
+    assert!(wire.take_binary().is_empty());
+}
+
+#[test]
+fn close_releases_momentaries() {
+    let (mut core, mut wire, fire, _) = held();
+    core.close(&mut wire);
+    assert_eq!(take_booleans(&mut wire), [(fire, false)]);
+    assert_eq!(core.press(&mut wire, fire).unwrap_err(), "connection closed");
+    assert!(wire.take_binary().is_empty());
+}
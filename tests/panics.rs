@@ -0,0 +1,67 @@
+//! Panics while processing frames, which only unwind outside wasm32-unknown-unknown, so these run
+//! natively with `cargo test`.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{announce, Wire};
+use nt4_wasm::{ConnectionCore, ConnectionEvent, Nt4Data, QuarantinedInput, TrajectoryOptions, WatchSpec};
+use serde_json::json;
+
+/// Panics on an int value of 13 as a stand-in for a bug deep in processing.
+fn fragile() -> Wire {
+    let hook = |event: &ConnectionEvent| {
+        if let ConnectionEvent::Value { data: Nt4Data::Int(13), .. } = event {
+            panic!("unlucky value");
+        }
+    };
+    Wire { hook: Some(hook), ..Default::default() }
+}
+
+/// The int values delivered since the last call.
+fn take_values(wire: &mut Wire) -> Vec<i64> {
+    let values = wire.take_events().into_iter().filter_map(|x| match x {
+        ConnectionEvent::Value { data: Nt4Data::Int(x), .. } => Some(x),
+        _ => None,
+    });
+    values.collect()
+}
+
+fn value(timestamp: i64, x: i64) -> Vec<u8> {
+    common::value(1, timestamp, 2, x)
+}
+
+#[test]
+fn panics_become_internal_errors() {
+    let mut core = ConnectionCore::new();
+    let mut sink = fragile();
+    announce(&mut core, &mut sink, "/x", 1, "int", json!({}));
+    announce(&mut core, &mut sink, "/pose", 2, "double[]", json!({}));
+    sink.take_events();
+
+    let crafted = value(100, 13);
+    let error = core.on_binary(&mut sink, &crafted).unwrap_err();
+    assert_eq!(error, "internal error: unlucky value");
+    let quarantined = core.quarantined();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].input, QuarantinedInput::Binary(crafted.into()));
+
+    // The next frame is processed as usual.
+    core.on_binary(&mut sink, &value(200, 14)).unwrap();
+    assert_eq!(take_values(&mut sink), [14]);
+
+    // Extreme timestamps no longer overflow on their way through watches and trajectories.
+    let spec = serde_json::from_value::<WatchSpec>(
+        json!({"kind": "within_after_change", "trigger": "/x", "topic": "/x", "op": ">", "value": 0, "within_ms": 1e18}),
+    )
+    .unwrap();
+    core.add_watch("extreme", spec).unwrap();
+    core.track_trajectory("/pose", TrajectoryOptions { max_age_s: Some(1e9), ..Default::default() });
+    core.on_binary(&mut sink, &value(i64::MIN, 15)).unwrap();
+    core.on_binary(&mut sink, &value(i64::MAX, 16)).unwrap();
+    core.on_binary(&mut sink, &common::value(2, i64::MIN, 17, [1.0, 2.0, 0.5])).unwrap();
+    core.snapshot().unwrap();
+    assert_eq!(core.quarantined().len(), 1);
+    core.clear_quarantine();
+    assert!(core.quarantined().is_empty());
+}
@@ -0,0 +1,141 @@
+//! The scenario files under `tests/scenarios`, run natively against a `ConnectionCore` the way
+//! `Nt4Scenario` runs them against a connection in the browser.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::Wire;
+use nt4_wasm::{ConnectionCore, ConnectionEvent, Scenario};
+use serde_json::{json, Value};
+
+const PID_TUNING: &str = include_str!("scenarios/pid_tuning.json");
+const MATCH: &str = include_str!("scenarios/match.json");
+
+#[derive(Debug, Clone, PartialEq)]
+enum Seen {
+    Ready,
+    Unready,
+    Announce(String),
+    Unannounce(String),
+    Properties(String),
+    Value(String, i64, Value),
+}
+
+/// What the connection delivered, in order, values by topic name.
+fn seen(wire: &Wire) -> Vec<Seen> {
+    let mut names = BTreeMap::new();
+    let seen = wire.events.iter().filter_map(|x| match x {
+        ConnectionEvent::Ready => Some(Seen::Ready),
+        ConnectionEvent::Unready => Some(Seen::Unready),
+        ConnectionEvent::Announce { id, topic } => {
+            names.insert(*id, topic.name.to_string());
+            Some(Seen::Announce(topic.name.to_string()))
+        },
+        ConnectionEvent::Unannounce { name, .. } => Some(Seen::Unannounce(name.clone())),
+        ConnectionEvent::PropertiesChanged { name, .. } => Some(Seen::Properties(name.clone())),
+        ConnectionEvent::Value { topic_id, timestamp, data, .. } => {
+            Some(Seen::Value(names[topic_id].clone(), *timestamp, serde_json::to_value(data).unwrap()))
+        },
+        _ => None,
+    });
+    seen.collect()
+}
+
+fn values(seen: &[Seen], name: &str) -> Vec<(i64, Value)> {
+    let values = seen.iter().filter_map(|x| match x {
+        Seen::Value(topic, timestamp, data) if topic == name => Some((*timestamp, data.clone())),
+        _ => None,
+    });
+    values.collect()
+}
+
+fn run(spec: &str) -> Vec<Seen> {
+    let mut scenario = Scenario::from_json(spec).unwrap();
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    scenario.run_until(&mut core, &mut wire, scenario.end_us()).unwrap();
+    assert!(scenario.finished());
+    seen(&wire)
+}
+
+#[test]
+fn pid_tuning() {
+    let mut scenario = Scenario::from_json(PID_TUNING).unwrap();
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    assert_eq!(scenario.end_us(), 2_780_000);
+
+    // Everything at 0: the connection, five topics and the first values of four.
+    assert_eq!(scenario.run_until(&mut core, &mut wire, 999_999).unwrap(), 10);
+    assert_eq!(seen(&wire)[0], Seen::Ready);
+    assert!(core.is_ready());
+    assert_eq!(values(&seen(&wire), "/Tuning/Arm/kP"), [(0, json!(2.0))]);
+
+    // The first step response, 40 positions 20 ms apart.
+    scenario.run_until(&mut core, &mut wire, 1_999_999).unwrap();
+    let positions = values(&seen(&wire), "/Arm/Position");
+    assert_eq!(positions.len(), 41);
+    assert_eq!(positions[1].0, 1_000_000);
+    assert_eq!(positions[40].0, 1_780_000);
+    let peak = positions.iter().filter_map(|(_, x)| x.as_f64()).fold(0.0, f64::max);
+    assert!(peak > 90.0, "the first step overshoots");
+    assert_eq!(values(&seen(&wire), "/Tuning/Arm/kD"), [(0, json!(0.0)), (1_900_000, json!(0.15))]);
+    assert!(seen(&wire).contains(&Seen::Properties("/Tuning/Arm/kD".to_string())));
+
+    scenario.run_until(&mut core, &mut wire, i64::MAX).unwrap();
+    assert!(scenario.finished());
+    assert_eq!(scenario.run_until(&mut core, &mut wire, i64::MAX).unwrap(), 0);
+    assert_eq!(values(&seen(&wire), "/Arm/Position").last(), Some(&(2_780_000, json!(44.86))));
+
+    // The same file gives the same values on every run.
+    assert_eq!(run(PID_TUNING), seen(&wire));
+}
+
+#[test]
+fn match_with_brownout() {
+    let seen = run(MATCH);
+    let readiness: Vec<&Seen> = seen.iter().filter(|x| matches!(x, Seen::Ready | Seen::Unready)).collect();
+    assert_eq!(readiness, [&Seen::Ready, &Seen::Unready, &Seen::Ready]);
+
+    // Poses of the second half of auto come while disconnected, and are dropped. The one at 2 s
+    // is in a step before the disconnect, so it makes it.
+    let poses = values(&seen, "/Robot/Pose");
+    assert_eq!(poses.len(), 26);
+    assert_eq!(poses.last().unwrap().0, 2_000_000);
+
+    // The topics are announced again on reconnect, along with the one announced while away.
+    let announces = |name: &str| seen.iter().filter(|x| **x == Seen::Announce(name.to_string())).count();
+    assert_eq!(announces("/Robot/Mode"), 2);
+    assert_eq!(announces("/Robot/Climber"), 1);
+    assert!(seen.contains(&Seen::Unannounce("/Robot/Climber".to_string())));
+    assert_eq!(values(&seen, "/Robot/Climber").len(), 6);
+
+    let modes: Vec<Value> = values(&seen, "/Robot/Mode").into_iter().map(|(_, x)| x).collect();
+    assert_eq!(modes, [json!("disabled"), json!("auto"), json!("teleop"), json!("disabled")]);
+}
+
+#[test]
+fn invalid_scenarios() {
+    let error = |spec: Value| Scenario::from_json(&spec.to_string()).unwrap_err();
+    assert_eq!(
+        error(json!({"steps": [{"t_us": 0, "kind": "value", "name": "/x", "value": 1}]})),
+        "step 0: /x is not announced at 0 µs"
+    );
+    assert_eq!(
+        error(json!({"steps": [
+            {"t_us": 0, "kind": "announce", "name": "/x", "type": "int"},
+            {"t_us": 10, "kind": "values", "name": "/x", "every_us": 10, "values": [1, "two"]},
+        ]})),
+        "step 1: invalid int value for /x: invalid type: string \"two\", expected i64"
+    );
+    assert_eq!(
+        error(json!({"steps": [
+            {"t_us": 0, "kind": "announce", "name": "/x", "type": "int"},
+            {"t_us": 5, "kind": "announce", "name": "/x", "type": "double"},
+        ]})),
+        "step 1: /x is already announced"
+    );
+    assert!(error(json!({"steps": [{"t_us": 0, "kind": "teleport"}]})).starts_with("invalid scenario: "));
+}
@@ -0,0 +1,296 @@
+//! `ServerCore` against `ConnectionCore` clients, and `Nt4Server` over a real socket. Run with
+//! `cargo test --features server,tcp-transport`.
+#![cfg(feature = "server")]
+
+mod common;
+
+use common::Wire;
+use nt4_wasm::{
+    ClientId, Conformance, ConnectionCore, ConnectionEvent, Nt4Data, Nt4TypeId, Outgoing, Properties, ServerCore,
+    ServerFrame, SubscriptionOptions,
+};
+
+/// A `ConnectionCore` connected to a `ServerCore` without a socket.
+struct Client {
+    id: ClientId,
+    core: ConnectionCore,
+    sink: Wire,
+}
+
+impl Client {
+    fn connect(server: &mut ServerCore, name: &str) -> Self {
+        Self { id: server.connect(name), core: ConnectionCore::new(), sink: Wire::default() }
+    }
+
+    /// Passes frames both ways until neither side has anything left to send.
+    fn pump(&mut self, server: &mut ServerCore) {
+        loop {
+            let outgoing = std::mem::take(&mut self.sink.sent);
+            for frame in &outgoing {
+                match frame {
+                    Outgoing::Text(data) => server.on_text(self.id, data).unwrap(),
+                    Outgoing::Binary(data) => server.on_binary(self.id, data).unwrap(),
+                }
+            }
+            let frames = server.take_frames(self.id);
+            if outgoing.is_empty() && frames.is_empty() {
+                return;
+            }
+            for frame in frames {
+                match frame {
+                    ServerFrame::Text(data) => self.core.on_text(&mut self.sink, &data).unwrap(),
+                    ServerFrame::Binary(data) => self.core.on_binary(&mut self.sink, &data).unwrap(),
+                }
+            }
+        }
+    }
+
+    fn take_events(&mut self) -> Vec<ConnectionEvent> {
+        self.sink.take_events()
+    }
+}
+
+fn prefix_options() -> SubscriptionOptions {
+    SubscriptionOptions { periodic: std::time::Duration::from_millis(100), all: false, topicsonly: false, prefix: true }
+}
+
+#[test]
+fn publish_subscribe() {
+    let mut server = ServerCore::new();
+    let mut publisher = Client::connect(&mut server, "publisher");
+    let mut subscriber = Client::connect(&mut server, "subscriber");
+
+    subscriber.core.timesync(&mut subscriber.sink).unwrap();
+    subscriber.core.subscribe(&mut subscriber.sink, "/arm/", prefix_options()).unwrap();
+    subscriber.pump(&mut server);
+    assert!(matches!(subscriber.take_events()[..], [ConnectionEvent::Ready]));
+    assert!(subscriber.core.is_ready());
+
+    let pubuid = publisher.core.publish(&mut publisher.sink, "/arm/angle", Nt4TypeId::Double, Properties::default()).unwrap();
+    publisher.pump(&mut server);
+    subscriber.pump(&mut server);
+    let ConnectionEvent::Announce { id, topic } = &subscriber.take_events()[0] else {
+        panic!("expected an announce");
+    };
+    assert_eq!((&*topic.name, topic.ty), ("/arm/angle", Nt4TypeId::Double));
+    let id = *id;
+
+    publisher.core.send_data(&mut publisher.sink, pubuid, Nt4Data::Double(1.25), None).unwrap();
+    publisher.pump(&mut server);
+    subscriber.pump(&mut server);
+    match &subscriber.take_events()[..] {
+        [ConnectionEvent::Value { topic_id, data: Nt4Data::Double(x), .. }] => assert_eq!((*topic_id, *x), (id, 1.25)),
+        events => panic!("expected a value, got {:?}", events),
+    }
+
+    // A late subscriber is announced the topic with its last value.
+    let mut late = Client::connect(&mut server, "late");
+    late.core.subscribe(&mut late.sink, "/arm/angle", SubscriptionOptions { prefix: false, ..prefix_options() }).unwrap();
+    late.pump(&mut server);
+    assert!(matches!(late.take_events()[..], [ConnectionEvent::Announce { .. }, ConnectionEvent::Value { .. }]));
+
+    // The topic goes with its last publisher.
+    server.disconnect(publisher.id);
+    subscriber.pump(&mut server);
+    assert!(matches!(&subscriber.take_events()[..], [ConnectionEvent::Unannounce { name, .. }] if name == "/arm/angle"));
+}
+
+#[test]
+fn retained_topics_outlive_publishers() {
+    let mut server = ServerCore::new();
+    let mut client = Client::connect(&mut server, "client");
+    let properties = Properties { retained: true, ..Default::default() };
+    let pubuid = client.core.publish(&mut client.sink, "/kept", Nt4TypeId::Int, properties).unwrap();
+    client.core.send_data(&mut client.sink, pubuid, Nt4Data::Int(7), None).unwrap();
+    client.core.unpublish(&mut client.sink, pubuid).unwrap();
+    client.pump(&mut server);
+
+    let mut other = Client::connect(&mut server, "other");
+    other.core.subscribe(&mut other.sink, "/kept", SubscriptionOptions { prefix: false, ..prefix_options() }).unwrap();
+    other.pump(&mut server);
+    let events = other.take_events();
+    assert!(matches!(events[..], [ConnectionEvent::Announce { .. }, ConnectionEvent::Value { data: Nt4Data::Int(7), .. }]));
+}
+
+#[test]
+fn demo_topics() {
+    let mut server = ServerCore::with_demo_topics();
+    let mut client = Client::connect(&mut server, "client");
+    client.core.subscribe(&mut client.sink, "/demo/", prefix_options()).unwrap();
+    client.pump(&mut server);
+    let events = client.take_events();
+    let announced = events.iter().filter(|x| matches!(x, ConnectionEvent::Announce { .. })).count();
+    let values = events.iter().filter(|x| matches!(x, ConnectionEvent::Value { .. })).count();
+    assert_eq!((announced, values), (4, 4));
+    assert!(server.set_local("/demo/sine", Nt4Data::Int(1)).is_err());
+    assert!(server.set_local("/elsewhere", Nt4Data::Int(1)).is_err());
+}
+
+fn send_all(server: &mut ServerCore, client: ClientId, outgoing: Vec<Outgoing>) {
+    for frame in outgoing {
+        match frame {
+            Outgoing::Text(data) => server.on_text(client, &data).unwrap(),
+            Outgoing::Binary(data) => server.on_binary(client, &data).unwrap(),
+        }
+    }
+}
+
+/// Runs `conformance` as `client` of `server` until it is finished or stalls, passing what the
+/// server sends through `tamper` first.
+fn run_conformance(
+    server: &mut ServerCore,
+    client: ClientId,
+    conformance: &mut Conformance,
+    mut tamper: impl FnMut(ServerFrame) -> Option<ServerFrame>,
+) {
+    let mut outgoing = conformance.start().unwrap();
+    loop {
+        send_all(server, client, std::mem::take(&mut outgoing));
+        let frames = server.take_frames(client);
+        if conformance.is_finished() || frames.is_empty() {
+            return;
+        }
+        for frame in frames.into_iter().filter_map(&mut tamper) {
+            outgoing.extend(match frame {
+                ServerFrame::Text(data) => conformance.on_text(&data).unwrap(),
+                ServerFrame::Binary(data) => conformance.on_binary(&data).unwrap(),
+            });
+        }
+    }
+}
+
+/// A client that sees the topics of conformance runs come and go.
+fn watch_conformance(server: &mut ServerCore) -> Client {
+    let mut watcher = Client::connect(server, "watcher");
+    watcher.core.subscribe(&mut watcher.sink, "/nt4-wasm/conformance/", prefix_options()).unwrap();
+    watcher.pump(server);
+    watcher
+}
+
+/// How many conformance topics were (announced, unannounced) since the last call.
+fn announces(watcher: &mut Client, server: &mut ServerCore) -> (usize, usize) {
+    watcher.pump(server);
+    let events = watcher.take_events();
+    let announced = events.iter().filter(|x| matches!(x, ConnectionEvent::Announce { .. })).count();
+    let unannounced = events.iter().filter(|x| matches!(x, ConnectionEvent::Unannounce { .. })).count();
+    (announced, unannounced)
+}
+
+#[test]
+fn conformance() {
+    let mut server = ServerCore::new();
+    let mut watcher = watch_conformance(&mut server);
+    let client = server.connect("conformance");
+    let mut conformance = Conformance::new();
+    run_conformance(&mut server, client, &mut conformance, Some);
+    let report = conformance.report();
+    assert!(conformance.is_finished() && report.passed, "{:?}", report);
+    // The run cleans up after itself.
+    assert_eq!(announces(&mut watcher, &mut server), (10, 10));
+}
+
+#[test]
+fn conformance_failures() {
+    let mut server = ServerCore::new();
+    let mut watcher = watch_conformance(&mut server);
+    let client = server.connect("conformance");
+    let mut conformance = Conformance::new();
+    // A server that announces floats as doubles, rounds doubles and never acknowledges properties.
+    run_conformance(&mut server, client, &mut conformance, |frame| match frame {
+        ServerFrame::Text(data) if data.contains(r#""method":"properties""#) => None,
+        ServerFrame::Text(data) => Some(ServerFrame::Text(data.replace(r#""type":"float""#, r#""type":"double""#))),
+        ServerFrame::Binary(data) => match rmp_serde::from_slice::<(i32, i64, u8, f64)>(&data) {
+            Ok((id, timestamp, 1, x)) if id >= 0 => {
+                Some(ServerFrame::Binary(rmp_serde::to_vec(&(id, timestamp, 1_u8, (x * 1e6).round() / 1e6)).unwrap()))
+            },
+            _ => Some(ServerFrame::Binary(data)),
+        },
+    });
+    assert!(!conformance.is_finished());
+    let outgoing = conformance.on_timeout().unwrap();
+    assert!(conformance.is_finished());
+    let report = conformance.report();
+    let failed: Vec<(&str, &str)> =
+        report.checks.iter().filter(|x| !x.passed).map(|x| (x.name.as_str(), x.detail.as_str())).collect();
+    assert_eq!(failed, [
+        ("announce float", "announced as double"),
+        ("echo double", "got Double(-0.3)"),
+        // Taken as a double, the float no longer matches what was sent.
+        ("echo float", "got Double(1.4999999853326784e-10)"),
+        ("properties ack", "timed out after 5000 ms"),
+    ]);
+    assert!(!report.passed && report.checks.len() == 22);
+
+    // Timing out cleans up too.
+    assert_eq!(announces(&mut watcher, &mut server), (10, 0));
+    send_all(&mut server, client, outgoing);
+    assert_eq!(announces(&mut watcher, &mut server), (0, 10));
+}
+
+#[cfg(feature = "tcp-transport")]
+#[test]
+fn websocket() {
+    use nt4_wasm::{Nt4Event, Nt4Server, Nt4TcpClient};
+
+    let server = Nt4Server::bind("127.0.0.1:0", ServerCore::with_demo_topics()).unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || server.serve());
+
+    let mut client = Nt4TcpClient::connect(&addr, "test").unwrap();
+    client.timesync().unwrap();
+    client.subscribe("/demo/counter", SubscriptionOptions { prefix: false, ..prefix_options() }).unwrap();
+    let (mut ready, mut announced, mut valued) = (false, false, false);
+    while !(ready && announced && valued) {
+        for event in client.read().unwrap() {
+            match event {
+                Nt4Event::Ready => ready = true,
+                Nt4Event::Announce { topic, .. } => announced |= &*topic.name == "/demo/counter",
+                Nt4Event::Value { data: Nt4Data::Int(_), .. } => valued = true,
+                _ => {},
+            }
+        }
+    }
+    client.close().unwrap();
+}
+
+/// Text frames from `Nt4TcpClient` are bare messages, as `Nt4Connection` sends them.
+#[cfg(feature = "tcp-transport")]
+#[test]
+// The handshake callback has to return tungstenite's `ErrorResponse` as is.
+#[allow(clippy::result_large_err)]
+fn tcp_text_frames() {
+    use nt4_wasm::{check_client_frame, Nt4TcpClient};
+    use tungstenite::{handshake::server::Response, http::HeaderValue, Message};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept_hdr(stream, |_: &_, mut response: Response| {
+            let protocol = HeaderValue::from_static("networktables.first.wpi.edu");
+            response.headers_mut().insert("Sec-WebSocket-Protocol", protocol);
+            Ok(response)
+        })
+        .unwrap();
+        let mut frames = Vec::new();
+        while let Ok(message) = socket.read() {
+            if let Message::Text(text) = message {
+                frames.push(text.to_string());
+            }
+        }
+        frames
+    });
+
+    let mut client = Nt4TcpClient::connect(&addr, "test").unwrap();
+    let subuid = client.subscribe("/demo", prefix_options()).unwrap();
+    let pubuid = client.publish("/dashboard/x", Nt4TypeId::Double, Properties::default()).unwrap();
+    client.unsubscribe(subuid).unwrap();
+    client.unpublish(pubuid).unwrap();
+    client.close().unwrap();
+
+    let frames = server.join().unwrap();
+    assert_eq!(frames.len(), 4);
+    for frame in frames {
+        check_client_frame(&frame).unwrap_or_else(|x| panic!("{}: {}", x, frame));
+    }
+}
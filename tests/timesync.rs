@@ -0,0 +1,85 @@
+//! Timesync responses and server time resets crafted to break the offset math, which must be
+//! rejected while keeping the offset from the last good response.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{announce, request, response, value, Wire};
+use nt4_wasm::{timesync_offset, ConnectionCore, ConnectionEvent, TimeResetOptions};
+use serde_json::json;
+
+fn ready(wire: &Wire) -> usize {
+    wire.events.iter().filter(|x| matches!(x, ConnectionEvent::Ready)).count()
+}
+
+#[test]
+fn adversarial_timesync_responses() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let local_time = request(&mut core, &mut wire);
+    core.on_binary(&mut wire, &response(local_time + 5_000_000, local_time)).unwrap();
+    let offset = core.offset();
+    // Off by half the round trip, which is well under a second here.
+    assert!((offset - 5_000_000).abs() < 1_000_000, "{}", offset);
+    assert_eq!(ready(&wire), 1);
+
+    // Server times at the ends of the range, and echoed local times that would overflow the round trip.
+    for (server_time, echoed) in [(i64::MAX, None), (i64::MIN, None), (5_000_000, Some(i64::MIN))] {
+        let local_time = request(&mut core, &mut wire);
+        let error = core.on_binary(&mut wire, &response(server_time, echoed.unwrap_or(local_time))).unwrap_err();
+        assert!(error.contains("is implausibly far from local time"), "{}", error);
+        assert_eq!(core.offset(), offset);
+    }
+
+    // An offset of exactly i64::MIN, whose absolute value does not fit. The clock cannot be set
+    // through the core, so the arithmetic is checked directly: a 2 ms round trip, 1 ms each way.
+    for (local_time, server_time, now) in [(0, i64::MIN, 0), (1_000, i64::MIN + 2_000, 3_000)] {
+        let error = timesync_offset(local_time, server_time, now).unwrap_err();
+        assert!(error.contains("is implausibly far from local time"), "{}", error);
+    }
+
+    // An echo from the future: a local time we have not reached yet.
+    for echoed in [local_time + 60_000_000, i64::MAX] {
+        request(&mut core, &mut wire);
+        let error = core.on_binary(&mut wire, &response(local_time + 5_000_000, echoed)).unwrap_err();
+        assert!(error.contains("is after the current time"), "{}", error);
+        assert_eq!(core.offset(), offset);
+    }
+    assert_eq!(ready(&wire), 1);
+
+    // The connection keeps working.
+    let local_time = request(&mut core, &mut wire);
+    core.on_binary(&mut wire, &response(local_time + 7_000_000, local_time)).unwrap();
+    assert!((core.offset() - 7_000_000).abs() < 1_000_000, "{}", core.offset());
+    assert_eq!(ready(&wire), 2);
+}
+
+#[test]
+fn implausible_server_time_reset() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    core.set_server_time_reset_options(TimeResetOptions { min_topics: 1, ..Default::default() });
+    announce(&mut core, &mut wire, "/a", 1, "int", json!({}));
+    let local_time = request(&mut core, &mut wire);
+    core.on_binary(&mut wire, &response(local_time + 5_000_000, local_time)).unwrap();
+    core.on_binary(&mut wire, &value(1, 100_000_000, 2, 0)).unwrap();
+    let offset = core.offset();
+    wire.take_events();
+
+    // Values stamped at the start of the range jump back far enough to look like a reset.
+    for timestamp in [i64::MIN, i64::MIN + 1_000] {
+        core.on_binary(&mut wire, &value(1, timestamp, 2, 0)).unwrap();
+        let warning = format!("ignoring a server time reset to an implausible {} µs", timestamp);
+        assert_eq!(wire.take_warnings(), [warning]);
+        assert!(!wire.events.iter().any(|x| matches!(x, ConnectionEvent::ServerTimeReset { .. })));
+        assert!(wire.take_binary().is_empty());
+        assert_eq!(core.offset(), offset);
+        core.on_binary(&mut wire, &value(1, 100_000_000, 2, 0)).unwrap();
+        wire.take_events();
+    }
+
+    // The new offset lands on exactly i64::MIN only if the clock reads the right microsecond, so
+    // that case goes through the bounds the reset shares with timesync.
+    let now = 5_000_000;
+    assert!(timesync_offset(now, i64::MIN + now, now).is_err());
+}
@@ -0,0 +1,75 @@
+//! Trajectories of a pose topic: decimation by distance and rotation, pruning by point count and
+//! age, and clearing when teleop starts.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{announce, value, Wire};
+use nt4_wasm::{ConnectionCore, TrajectoryOptions};
+use serde_json::json;
+
+const POSE: i32 = 1;
+const CONTROL_WORD: i32 = 2;
+
+fn pose(core: &mut ConnectionCore, seconds: i64, pose: [f64; 3]) {
+    core.on_binary(&mut Wire::default(), &value(POSE, seconds * 1_000_000, 17, pose)).unwrap();
+}
+
+fn control_word(core: &mut ConnectionCore, seconds: i64, word: i64) {
+    core.on_binary(&mut Wire::default(), &value(CONTROL_WORD, seconds * 1_000_000, 2, word)).unwrap();
+}
+
+/// The timestamps in seconds and the poses of the trail, headings in degrees.
+fn trail(core: &ConnectionCore) -> Vec<(f64, [f64; 3])> {
+    let trajectory = core.trajectory("/Field/Robot").unwrap();
+    let poses = trajectory.poses();
+    let round = |x: f64| (x * 1e6).round() / 1e6;
+    let points = trajectory.timestamps().into_iter().zip(poses.chunks(3));
+    points.map(|(t, x)| (t / 1e6, [round(x[0]), round(x[1]), round(x[2].to_degrees())])).collect()
+}
+
+#[test]
+fn decimation_and_pruning() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    announce(&mut core, &mut wire, "/Field/Robot", POSE, "double[]", json!({}));
+    announce(&mut core, &mut wire, "/FMSInfo/FMSControlData", CONTROL_WORD, "int", json!({}));
+    let options = TrajectoryOptions {
+        max_points: 4,
+        min_distance_m: 0.5,
+        min_rotation_rad: 20_f64.to_radians(),
+        max_age_s: Some(10.0),
+        degrees: true,
+        clear_on_teleop: true,
+    };
+    core.track_trajectory("/Field/Robot", options);
+
+    // Too little movement or turning adds nothing, including a turn across ±180°.
+    pose(&mut core, 0, [0.0, 0.0, 170.0]);
+    pose(&mut core, 1, [0.3, 0.0, 170.0]);
+    pose(&mut core, 2, [0.3, 0.0, -175.0]);
+    assert_eq!(trail(&core), [(0.0, [0.0, 0.0, 170.0])]);
+    pose(&mut core, 3, [0.3, 0.3, -160.0]);
+    pose(&mut core, 4, [0.7, 0.3, -160.0]);
+    assert_eq!(trail(&core), [(0.0, [0.0, 0.0, 170.0]), (3.0, [0.3, 0.3, -160.0])]);
+    pose(&mut core, 5, [0.9, 0.3, -160.0]);
+    assert_eq!(trail(&core).len(), 3);
+
+    // At most four points, the oldest going first.
+    pose(&mut core, 6, [2.0, 0.3, -160.0]);
+    pose(&mut core, 7, [3.0, 0.3, -160.0]);
+    assert_eq!(trail(&core).iter().map(|x| x.0).collect::<Vec<_>>(), [3.0, 5.0, 6.0, 7.0]);
+
+    // Nothing older than ten seconds before the newest point.
+    pose(&mut core, 16, [4.0, 0.3, -160.0]);
+    assert_eq!(trail(&core).iter().map(|x| x.0).collect::<Vec<_>>(), [6.0, 7.0, 16.0]);
+
+    // Teleop starting, enabled and neither autonomous nor test, clears the trail once.
+    control_word(&mut core, 17, 0b011);
+    assert_eq!(trail(&core).len(), 3);
+    control_word(&mut core, 18, 0b001);
+    assert!(trail(&core).is_empty());
+    pose(&mut core, 19, [5.0, 0.3, -160.0]);
+    control_word(&mut core, 20, 0b001);
+    assert_eq!(trail(&core), [(19.0, [5.0, 0.3, -160.0])]);
+}
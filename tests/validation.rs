@@ -0,0 +1,129 @@
+//! Topic validators on values we send: non-finite numbers, repeated topics in one atomic message,
+//! and validators that went through a JSON export and import.
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use common::Wire;
+use nt4_wasm::{AtomicEntry, ConnectionCore, Nt4Data, Nt4TypeId, Properties, ValidationMode, Validator};
+
+/// The values of every frame sent since the last call, in order, all doubles here.
+fn take_values(wire: &mut Wire) -> Vec<f64> {
+    let mut values = Vec::new();
+    for message in wire.take_binary() {
+        let mut rest = &message[..];
+        while !rest.is_empty() {
+            let (_, _, _, data): (i32, i64, u8, f64) = rmp_serde::from_read(&mut rest).unwrap();
+            values.push(data);
+        }
+    }
+    values
+}
+
+fn publish(core: &mut ConnectionCore, wire: &mut Wire, name: &str, ty: Nt4TypeId) -> i32 {
+    core.publish(wire, name, ty, Properties::default()).unwrap()
+}
+
+#[test]
+fn non_finite_values() {
+    let bounds = [
+        Validator { min: Some(0.0), ..Validator::default() },
+        Validator { max: Some(1.0), ..Validator::default() },
+        Validator { max_rate_of_change_per_s: Some(1.0), ..Validator::default() },
+    ];
+    for mode in [ValidationMode::Reject, ValidationMode::Clamp] {
+        for validator in bounds.iter() {
+            let mut core = ConnectionCore::new();
+            let mut wire = Wire::default();
+            let x = publish(&mut core, &mut wire, "/x", Nt4TypeId::Double);
+            let xs = publish(&mut core, &mut wire, "/xs", Nt4TypeId::DoubleArray);
+            core.set_topic_validator("/x", Validator { mode, ..validator.clone() });
+            core.set_topic_validator("/xs", Validator { mode, ..validator.clone() });
+            // A finite value first, so a rate of change check has something to compare against.
+            core.send_data(&mut wire, x, Nt4Data::Double(0.5), None).unwrap();
+            core.send_data(&mut wire, xs, Nt4Data::DoubleArray(vec![0.5, 0.5]), None).unwrap();
+            wire.take_binary();
+
+            for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+                let error = core.send_data(&mut wire, x, Nt4Data::Double(value), None).unwrap_err();
+                assert_eq!(error, format!("{} at index 0 is not a finite number, as \"/x\" requires", value));
+                let error = core.send_data(&mut wire, xs, Nt4Data::DoubleArray(vec![0.5, value]), None).unwrap_err();
+                assert_eq!(error, format!("{} at index 1 is not a finite number, as \"/xs\" requires", value));
+            }
+            assert!(wire.take_binary().is_empty(), "{:?} {:?}", mode, validator);
+        }
+    }
+
+    // Without a numeric bound or rate limit there is nothing to compare them to.
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let x = publish(&mut core, &mut wire, "/x", Nt4TypeId::Double);
+    core.set_topic_validator("/x", Validator::default());
+    core.send_data(&mut wire, x, Nt4Data::Double(f64::INFINITY), None).unwrap();
+    assert_eq!(take_values(&mut wire), [f64::INFINITY]);
+}
+
+#[test]
+fn atomic_entries_for_one_topic() {
+    for mode in [ValidationMode::Reject, ValidationMode::Clamp] {
+        let mut core = ConnectionCore::new();
+        let mut wire = Wire::default();
+        let x = publish(&mut core, &mut wire, "/x", Nt4TypeId::Double);
+        let validator = Validator { max_rate_of_change_per_s: Some(10.0), mode, ..Validator::default() };
+        core.set_topic_validator("/x", validator);
+        core.send_data(&mut wire, x, Nt4Data::Double(0.0), None).unwrap();
+        take_values(&mut wire);
+        // Long enough that each entry alone is within the rate of the last value sent.
+        std::thread::sleep(Duration::from_millis(200));
+
+        // Both entries share a timestamp, so the second may not differ from the first at all.
+        let entries = vec![
+            AtomicEntry { topic_id: x, data: Nt4Data::Double(1.5) },
+            AtomicEntry { topic_id: x, data: Nt4Data::Double(-1.5) },
+        ];
+        let result = core.send_atomic(&mut wire, entries, None);
+        match mode {
+            ValidationMode::Reject => {
+                let error = result.unwrap_err();
+                assert!(error.ends_with("-1.5 at index 0 changes from 1.5 faster than 10/s for \"/x\""), "{}", error);
+                assert!(wire.take_binary().is_empty());
+            }
+            ValidationMode::Clamp => {
+                result.unwrap();
+                assert_eq!(take_values(&mut wire), [1.5, 1.5]);
+            }
+        }
+    }
+}
+
+#[test]
+fn exported_validators() {
+    let mut core = ConnectionCore::new();
+    let mut wire = Wire::default();
+    let x = publish(&mut core, &mut wire, "/x", Nt4TypeId::Double);
+    core.set_topic_validator(
+        "/x",
+        Validator { min: Some(0.0), max: Some(1.0), mode: ValidationMode::Clamp, ..Validator::default() },
+    );
+    let json = core.export_validators().unwrap();
+    assert_eq!(json, r#"{"/x":{"min":0.0,"max":1.0,"mode":"clamp"}}"#);
+
+    let mut imported = ConnectionCore::new();
+    let mut imported_wire = Wire::default();
+    let y = publish(&mut imported, &mut imported_wire, "/x", Nt4TypeId::Double);
+    imported.import_validators(&json).unwrap();
+    assert_eq!(imported.export_validators().unwrap(), json);
+    for (core, wire, id) in [(&mut core, &mut wire, x), (&mut imported, &mut imported_wire, y)] {
+        core.send_data(wire, id, Nt4Data::Double(2.0), None).unwrap();
+        assert_eq!(take_values(wire), [1.0]);
+        let error = core.send_data(wire, id, Nt4Data::Double(f64::NAN), None).unwrap_err();
+        assert_eq!(error, "NaN at index 0 is not a finite number, as \"/x\" requires");
+    }
+
+    assert!(imported.import_validators(r#"{"/x": {"min": "low"}}"#).is_err());
+    assert_eq!(imported.export_validators().unwrap(), json);
+    imported.import_validators("{}").unwrap();
+    imported.send_data(&mut imported_wire, y, Nt4Data::Double(f64::NAN), None).unwrap();
+}